@@ -0,0 +1,11 @@
+import os
+import sys
+
+print("Content-Type: text/plain")
+print()
+
+# Affiche les variables CGI reçues puis le corps lu sur l'entrée standard
+for key in sorted(os.environ):
+    print(f"{key}={os.environ[key]}")
+print()
+print(sys.stdin.read(), end="")
@@ -0,0 +1,4 @@
+#!/bin/sh
+echo "Location: /index.html"
+echo
+while :; do echo "ignored"; done
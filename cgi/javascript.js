@@ -0,0 +1,3 @@
+console.log("Content-Type: text/html");
+console.log();
+console.log("Hello world!");
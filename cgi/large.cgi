@@ -0,0 +1,4 @@
+#!/bin/sh
+echo "Content-Type: application/octet-stream"
+echo
+head -c 16777216 /dev/zero
@@ -0,0 +1,5 @@
+#!/bin/sh
+echo "Content-Type: text/plain"
+echo
+echo "$(ulimit -n) $(ulimit -t) $(ulimit -v) $(pwd)"
+ls /proc/self/fd
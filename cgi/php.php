@@ -0,0 +1,3 @@
+<?php
+header("Content-Type: text/html");
+echo "Hello world!\n";
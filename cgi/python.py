@@ -1 +1,3 @@
+print("Content-Type: text/html")
+print()
 print("Hello world!")
@@ -0,0 +1,3 @@
+puts "Content-Type: text/html"
+puts
+puts "Hello world!"
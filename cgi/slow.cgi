@@ -0,0 +1,5 @@
+#!/bin/sh
+sleep 30
+echo "Content-Type: text/plain"
+echo
+echo "done"
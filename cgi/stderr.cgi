@@ -0,0 +1,5 @@
+#!/bin/sh
+echo "warning: $QUERY_STRING" >&2
+echo "Content-Type: text/plain"
+echo
+echo "done"
@@ -0,0 +1,6 @@
+#!/bin/sh
+echo "Content-Type: text/plain"
+echo
+echo "first"
+sleep 1
+echo "second"
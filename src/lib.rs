@@ -16,22 +16,32 @@ pub mod server {
 
     use crate::server::config::route::Route;
     use crate::type_aliases::{Path, Port};
+    use std::time::Duration;
 
     #[derive(Clone, Debug)]
     pub struct ServerConfig<'a> {
         pub host: &'a str,
         pub ports: Vec<Port>,
+        pub bind_addresses: Vec<BindAddress<'a>>, // Adresses d'écoute ; vide : l'adresse de `host`
+        pub listen_options: ListenOptions,
         pub custom_error_path: Option<Path<'a>>,
         pub body_size_limit: usize,
+        pub trace_enabled: bool,
+        pub cgi_max_processes: usize,
+        pub shutdown_grace_period: Duration, // Attente des requêtes en cours à l'arrêt du serveur
+        pub tls: Vec<Tls<'a>>, // Ports servis en HTTPS
+        pub proxy_protocol: Vec<Port>, // Ports dont les connexions commencent par un en-tête PROXY
+        pub unix_sockets: Vec<UnixSocket<'a>>, // Sockets Unix servis en plus des ports
         pub routes: Vec<Route<'a>>,
     }
 
     pub mod route {
-        use crate::server::Cgi;
         use crate::server::config::ServerConfig;
+        use crate::server::{Cgi, CgiSandbox, ClientAuth, EventStreamHandler, Gateway, WebSocketHandler};
         use crate::type_aliases::{Bytes, FileExtension, Path};
         use http::{Method, Request, Response, StatusCode};
         use std::collections::HashMap;
+        use std::time::Duration;
 
         pub type HandlerFunc =
             fn(req: &Request<Bytes>, conf: &ServerConfig) -> Result<Response<Bytes>, StatusCode>;
@@ -51,8 +61,17 @@ pub mod server {
             pub root_path: Option<Path<'a>>,
             pub default_if_url_is_dir: Option<Path<'a>>, // TODO: Implement
             pub default_if_request_is_dir: Option<Path<'a>>, // TODO: Implement
-            pub cgi_def: Option<HashMap<FileExtension<'a>, Cgi>>,
+            pub cgi_def: Option<HashMap<FileExtension<'a>, Cgi<'a>>>,
+            pub cgi_dirs: Option<Vec<Path<'a>>>, // Répertoires dont les scripts peuvent être exécutés
+            pub cgi_pass_env: Option<Vec<&'a str>>, // Variables du serveur transmises aux scripts
+            pub cgi_timeout: Option<Duration>,   // Durée maximale d'exécution d'un script
+            pub cgi_sandbox: Option<CgiSandbox>, // Utilisateur et limites de ressources des scripts
+            pub gateway: Option<Gateway<'a>>,    // Serveur d'application qui traite les requêtes
+            pub websocket: Option<WebSocketHandler>, // Gestionnaire des connexions WebSocket
+            pub event_stream: Option<EventStreamHandler>, // Flux d'événements (text/event-stream)
+            pub client_certificate: Option<ClientAuth>, // Certificat client exigé ou accepté (HTTPS)
             pub list_directory: bool,
+            pub webdav: bool, // PROPFIND, PROPPATCH, MKCOL, COPY, MOVE, LOCK, UNLOCK
         }
     }
     pub mod handle;
@@ -65,11 +84,9 @@ pub mod server {
     use std::io::Read;
 
     // use crate::server::config::ServerConfig;
-    use mio::net::{TcpListener, TcpStream};
+    use mio::net::TcpListener;
     use mio::{Events, Interest, Poll, Token};
     use std::collections::HashMap;
-    use std::net::SocketAddr;
-
     use std::sync::Arc;
     pub mod requests;
 
@@ -78,8 +95,40 @@ pub mod server {
     pub use responses::*;
     pub mod methods;
     pub use methods::*;
+    pub mod patch;
+    pub use patch::*;
+    pub mod webdav;
+    pub use webdav::*;
     pub mod cgi;
     pub use cgi::*;
+    pub mod upstream;
+    pub use upstream::*;
+    pub mod fastcgi;
+    pub use fastcgi::*;
+    pub mod scgi;
+    pub use scgi::*;
+    pub mod proxy;
+    pub use proxy::*;
+    pub mod balancer;
+    pub use balancer::*;
+    pub mod websocket;
+    pub use websocket::*;
+    pub mod event_stream;
+    pub use event_stream::*;
+    pub mod tls;
+    pub use tls::*;
+    pub mod proxy_protocol;
+    pub use proxy_protocol::*;
+    pub mod sockets;
+    pub use sockets::*;
+    pub mod activation;
+    pub use activation::*;
+    pub mod signals;
+    pub use signals::*;
+    pub mod hpack;
+    pub use hpack::*;
+    pub mod http2;
+    pub use http2::*;
     pub mod routes;
     pub use routes::*;
     pub mod start;
@@ -93,25 +142,27 @@ pub mod server {
 
     #[derive(Debug)]
     pub struct Server<'a> {
-        pub listeners: Vec<TcpListener>,
+        pub listeners: Vec<(ServerSocket, Transport)>,
         pub config: ServerConfig<'a>,
     }
 
     impl<'a> Server<'a> {
-        pub fn new(listeners: Vec<TcpListener>, config: ServerConfig<'a>) -> Self {
+        pub fn new(listeners: Vec<(ServerSocket, Transport)>, config: ServerConfig<'a>) -> Self {
             Self { listeners, config }
         }
     }
 
     #[derive(Debug)]
     pub struct Listener<'a> {
-        pub listener: TcpListener,
+        pub listener: ServerSocket,
         pub token: Token,
         pub config: Arc<ServerConfig<'a>>,
+        pub transport: Transport,
+        pub proxy_protocol: bool,
     }
 
     impl Listener<'_> {
-        pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        pub fn accept(&self) -> io::Result<ClientSocket> {
             self.listener.accept()
         }
     }
@@ -0,0 +1,284 @@
+use mio::net::{TcpListener, UnixListener};
+use socket2::Socket;
+use std::env;
+use std::ffi::{CString, OsString};
+use std::io;
+use std::net::SocketAddr;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStringExt;
+use std::path::Path;
+
+// Premier descripteur transmis par systemd (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+// Variable qui désigne au nouveau binaire le processus qu'il remplace
+const UPGRADE_PID: &str = "LOCALHOST_UPGRADE_PID";
+
+/// # InheritedSockets
+///
+/// Sockets d'écoute ouverts avant le lancement du serveur et décrits par `LISTEN_FDS`,
+/// `LISTEN_PID` et `LISTEN_FDNAMES` : activation par systemd, ou ancien processus lors d'une
+/// mise à jour du binaire. Ils remplacent les sockets que le serveur aurait liés aux mêmes
+/// adresses.
+#[derive(Debug, Default)]
+pub struct InheritedSockets {
+    tcp: Vec<std::net::TcpListener>,
+    unix: Vec<(std::os::unix::net::UnixListener, Option<String>)>, // Avec son nom transmis
+}
+
+impl InheritedSockets {
+    // Fonction pour reprendre les sockets transmis au processus ; les variables qui les décrivent
+    // sont retirées pour que les scripts CGI n'en héritent pas
+    pub fn from_env() -> Self {
+        let pid = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok());
+        let count = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<RawFd>().ok());
+        let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+
+        let mut sockets = Self::default();
+        let (Some(pid), Some(count)) = (pid, count) else {
+            return sockets;
+        };
+        if pid != std::process::id() {
+            return sockets;
+        }
+        let mut names = names.split(':').map(str::to_string);
+        for fd in LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count) {
+            sockets.adopt(fd, names.next());
+        }
+        sockets
+    }
+
+    fn adopt(&mut self, fd: RawFd, name: Option<String>) {
+        // Les sockets transmis ne doivent pas passer aux scripts CGI
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            eprintln!("Error: inherited descriptor {fd} is not open");
+            return;
+        }
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        match socket.local_addr() {
+            Ok(address) if address.is_unix() => {
+                self.unix.push((OwnedFd::from(socket).into(), name))
+            }
+            Ok(address) if address.as_socket().is_some() => {
+                self.tcp.push(OwnedFd::from(socket).into())
+            }
+            _ => eprintln!("Error: inherited descriptor {fd} is not a listening socket"),
+        }
+    }
+
+    // Fonction pour reprendre le socket TCP lié à cette adresse
+    pub fn take_tcp(&mut self, address: SocketAddr) -> Option<io::Result<TcpListener>> {
+        let position = self
+            .tcp
+            .iter()
+            .position(|listener| listener.local_addr().is_ok_and(|local| local == address))?;
+        let listener = self.tcp.remove(position);
+        Some(
+            listener
+                .set_nonblocking(true)
+                .map(|_| TcpListener::from_std(listener)),
+        )
+    }
+
+    // Fonction pour reprendre le socket Unix transmis sous ce chemin, ou lié à ce chemin ou à ce
+    // nom abstrait (`@nom`). Le binaire remplacé transmet le chemin configuré : l'adresse d'un
+    // socket qu'il a créé reste celle de son répertoire privé
+    pub fn take_unix(&mut self, path: &str) -> Option<io::Result<UnixListener>> {
+        let position = self.unix.iter().position(|(listener, name)| {
+            if name.as_deref() == Some(path) {
+                return true;
+            }
+            let Ok(address) = listener.local_addr() else {
+                return false;
+            };
+            match path.strip_prefix('@') {
+                Some(name) => abstract_name(&address) == Some(name.as_bytes()),
+                None => address.as_pathname() == Some(Path::new(path)),
+            }
+        })?;
+        let (listener, _) = self.unix.remove(position);
+        Some(
+            listener
+                .set_nonblocking(true)
+                .map(|_| UnixListener::from_std(listener)),
+        )
+    }
+
+    // Adresses des sockets transmis qu'aucun serveur n'a repris ; ils sont fermés
+    pub fn unused(&self) -> Vec<String> {
+        let tcp = self.tcp.iter().filter_map(|l| l.local_addr().ok());
+        let unix = self.unix.iter().filter_map(|(l, _)| l.local_addr().ok());
+        tcp.map(|address| address.to_string())
+            .chain(unix.map(|address| format!("unix:{address:?}")))
+            .collect()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_name(address: &std::os::unix::net::SocketAddr) -> Option<&[u8]> {
+    use std::os::linux::net::SocketAddrExt;
+    address.as_abstract_name()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_name(_: &std::os::unix::net::SocketAddr) -> Option<&[u8]> {
+    None
+}
+
+// Fonction pour prévenir l'ancien processus, lors d'une mise à jour du binaire, que ce processus
+// accepte les connexions : il cesse alors d'en accepter et s'arrête une fois ses connexions
+// terminées
+pub fn finish_upgrade() {
+    let pid = env::var(UPGRADE_PID).ok().and_then(|pid| pid.parse().ok());
+    env::remove_var(UPGRADE_PID);
+    // L'ancien processus est le parent ; la variable n'est pas prise en compte sinon
+    if let Some(pid) = pid.filter(|&pid| pid == unsafe { libc::getppid() }) {
+        println!("Upgrade: asking the previous process {pid} to stop");
+        unsafe { libc::kill(pid, libc::SIGQUIT) };
+    }
+}
+
+// Fonction pour lancer le binaire du serveur, éventuellement mis à jour, en lui transmettant les
+// sockets d'écoute à partir du descripteur 3, avec leurs noms dans `LISTEN_FDNAMES` ; retourne
+// le PID du nouveau processus
+pub fn spawn_upgrade(sockets: &[(RawFd, &str)]) -> io::Result<libc::pid_t> {
+    let fds: Vec<RawFd> = sockets.iter().map(|(fd, _)| *fd).collect();
+    let names: Vec<&str> = sockets.iter().map(|(_, name)| *name).collect();
+    let program = c_string(env::current_exe()?.into_os_string())?;
+    let args = env::args_os()
+        .map(c_string)
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut vars = env::vars_os()
+        .filter(|(name, _)| {
+            let name = name.to_string_lossy();
+            !name.starts_with("LISTEN_") && name != UPGRADE_PID
+        })
+        .map(|(mut name, value)| {
+            name.push("=");
+            name.push(value);
+            c_string(name)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    vars.push(c_string(format!("LISTEN_FDS={}", fds.len()).into())?);
+    vars.push(c_string(
+        format!("LISTEN_FDNAMES={}", names.join(":")).into(),
+    )?);
+    vars.push(c_string(
+        format!("{UPGRADE_PID}={}", std::process::id()).into(),
+    )?);
+
+    // Le PID du nouveau processus n'est connu qu'après fork : il est écrit à une place réservée
+    let mut listen_pid = *b"LISTEN_PID=0000000000\0";
+    let listen_pid = listen_pid.as_mut_ptr();
+    let argv = pointers(&args, None);
+    let envp = pointers(&vars, Some(listen_pid.cast_const().cast()));
+    let mut moved = fds.to_vec();
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => unsafe { exec_upgrade(&program, &argv, &envp, listen_pid, &fds, &mut moved) },
+        pid => Ok(pid),
+    }
+}
+
+fn c_string(value: OsString) -> io::Result<CString> {
+    CString::new(value.into_vec()).map_err(io::Error::other)
+}
+
+// Tableau de pointeurs terminé par un pointeur nul, comme execve les attend
+fn pointers(strings: &[CString], first: Option<*const libc::c_char>) -> Vec<*const libc::c_char> {
+    let pointers = strings.iter().map(|string| string.as_ptr());
+    first
+        .into_iter()
+        .chain(pointers)
+        .chain([std::ptr::null()])
+        .collect()
+}
+
+// Processus enfant, juste après fork : seules des fonctions sûres dans un processus à plusieurs
+// fils d'exécution sont appelées, sans allocation
+unsafe fn exec_upgrade(
+    program: &CString,
+    argv: &[*const libc::c_char],
+    envp: &[*const libc::c_char],
+    listen_pid: *mut u8,
+    fds: &[RawFd],
+    moved: &mut [RawFd],
+) -> ! {
+    // PID du processus, écrit après `LISTEN_PID=`
+    let mut pid = libc::getpid() as u32;
+    let mut digits = [0u8; 10];
+    let mut length = 0;
+    loop {
+        digits[length] = b'0' + (pid % 10) as u8;
+        length += 1;
+        pid /= 10;
+        if pid == 0 {
+            break;
+        }
+    }
+    for i in 0..length {
+        *listen_pid.add(11 + i) = digits[length - 1 - i];
+    }
+    *listen_pid.add(11 + length) = 0;
+
+    // Placer les sockets à partir de 3 sans écraser ceux qui restent à déplacer : les copies
+    // intermédiaires sont fermées par execve
+    let count = fds.len() as RawFd;
+    for (fd, copy) in fds.iter().zip(moved.iter_mut()) {
+        *copy = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, LISTEN_FDS_START + count);
+        if *copy == -1 {
+            libc::_exit(127);
+        }
+    }
+    for (target, copy) in (LISTEN_FDS_START..).zip(moved.iter()) {
+        if libc::dup2(*copy, target) == -1 {
+            libc::_exit(127);
+        }
+    }
+
+    libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr());
+    libc::_exit(127)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{bind_unix_socket, UnixSocket};
+    use std::fs;
+    use std::os::fd::IntoRawFd;
+
+    #[test]
+    fn unix_sockets_are_found_by_their_transmitted_name() {
+        // Le chemin configuré peut lui-même ressembler à un répertoire privé
+        let directory = format!("./target/activation-{}/.12-3", std::process::id());
+        fs::create_dir_all(&directory).unwrap();
+        let path = format!("{directory}/server.sock");
+        let socket = UnixSocket {
+            path: &path,
+            ..Default::default()
+        };
+        let listener = bind_unix_socket(&socket).unwrap();
+        let listener =
+            unsafe { std::os::unix::net::UnixListener::from_raw_fd(listener.into_raw_fd()) };
+
+        // L'adresse du socket ne donne pas son chemin
+        let bound = listener.local_addr().unwrap();
+        assert_ne!(bound.as_pathname(), Some(Path::new(&path)));
+        let mut inherited = InheritedSockets::default();
+        inherited.unix.push((listener, Some("unknown".to_string())));
+        assert!(inherited.take_unix(&path).is_none());
+
+        inherited.unix[0].1 = Some(path.clone());
+        assert!(inherited.take_unix(&path).unwrap().is_ok());
+        assert!(inherited.unused().is_empty());
+        fs::remove_dir_all(format!("./target/activation-{}", std::process::id())).unwrap();
+    }
+}
@@ -0,0 +1,679 @@
+use crate::log;
+use crate::log::*;
+use crate::server::{
+    Address, Addresses, Bytes, Endpoint, Gateway, Proxy, ServerConfig, UpstreamStream, BUFFER_SIZE,
+};
+use http::header::COOKIE;
+use http::Request;
+use mio::{Interest, Registry, Token};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// # VIRTUAL_NODES
+///
+/// Nombre de points placés sur l'anneau du hachage cohérent pour chaque unité de poids.
+const VIRTUAL_NODES: u32 = 100;
+
+/// # Upstream
+///
+/// Serveur mandaté d'une route, avec son poids dans la répartition des requêtes :
+/// un serveur de poids 3 reçoit trois fois plus de requêtes qu'un serveur de poids 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Upstream<'a> {
+    pub address: Address<'a>,
+    pub weight: u32,
+}
+
+impl<'a> Upstream<'a> {
+    // Fonction pour créer un serveur de poids 1
+    pub const fn new(address: Address<'a>) -> Upstream<'a> {
+        Upstream { address, weight: 1 }
+    }
+}
+
+/// # Balance
+///
+/// Stratégie de choix du serveur mandaté : à tour de rôle, le moins de requêtes en cours,
+/// ou hachage cohérent de l'adresse du client ou de la valeur d'un cookie (un client est
+/// toujours servi par le même serveur tant que celui-ci est disponible).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Balance<'a> {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    IpHash,
+    CookieHash(&'a str),
+}
+
+/// # Ejection
+///
+/// Détection passive des pannes : un serveur qui échoue `max_fails` fois de suite
+/// (connexion refusée, réponse invalide ou trop lente) est écarté pendant `duration`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ejection {
+    pub max_fails: u32,
+    pub duration: Duration,
+}
+
+/// # HealthCheck
+///
+/// Vérification active : toutes les `interval`, une requête `GET path` est envoyée à chaque
+/// serveur ; celui-ci est écarté tant qu'il ne répond pas par un statut 2xx ou 3xx dans `timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HealthCheck<'a> {
+    pub path: &'a str,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+// État des groupes de serveurs, partagé par toutes les requêtes et les vérifications actives
+static POOLS: Mutex<BTreeMap<Vec<Endpoint>, Pool>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug)]
+struct Member {
+    endpoint: Endpoint,
+    weight: i64,
+    current: i64,
+    active: usize,
+    fails: u32,
+    ejected_until: Option<Instant>,
+    healthy: bool,
+}
+
+impl Member {
+    fn is_available(&self) -> bool {
+        self.healthy && self.ejected_until.is_none()
+    }
+}
+
+#[derive(Debug)]
+struct Pool {
+    members: Vec<Member>,
+    ring: Vec<(u64, usize)>,
+    ejection: Option<Ejection>,
+}
+
+impl Pool {
+    fn new(proxy: &Proxy) -> Pool {
+        let members: Vec<Member> = proxy
+            .upstreams
+            .iter()
+            .map(|upstream| Member {
+                endpoint: Endpoint::from(&upstream.address),
+                weight: upstream.weight.max(1) as i64,
+                current: 0,
+                active: 0,
+                fails: 0,
+                ejected_until: None,
+                healthy: true,
+            })
+            .collect();
+
+        let mut ring = Vec::new();
+        for (index, member) in members.iter().enumerate() {
+            for node in 0..member.weight as u32 * VIRTUAL_NODES {
+                let point = format!("{}#{node}", member.endpoint);
+                ring.push((hash(point.as_bytes()), index));
+            }
+        }
+        ring.sort_unstable();
+
+        Pool {
+            members,
+            ring,
+            ejection: proxy.ejection,
+        }
+    }
+
+    // Fonction pour réintégrer les serveurs dont l'éviction a pris fin
+    fn refresh(&mut self, now: Instant) {
+        let max_fails = self.ejection.map_or(1, |ejection| ejection.max_fails);
+        for member in &mut self.members {
+            if member.ejected_until.is_some_and(|until| now >= until) {
+                member.ejected_until = None;
+                // Un seul nouvel échec suffit à l'écarter de nouveau
+                member.fails = max_fails.saturating_sub(1);
+                log!(
+                    LogFileType::Server,
+                    format!("Upstream {} is back in rotation", member.endpoint)
+                );
+            }
+        }
+    }
+
+    fn pick(&mut self, balance: &Balance, request: &Request<Bytes>) -> Option<usize> {
+        let available: Vec<usize> = (0..self.members.len())
+            .filter(|&index| self.members[index].is_available())
+            .collect();
+        match balance {
+            Balance::RoundRobin => self.round_robin(&available),
+            Balance::LeastConnections => {
+                let members = &self.members;
+                // Comparer `active / weight` sans division
+                let load = |a: usize, b: usize| members[a].active as i64 * members[b].weight;
+                let least: Vec<usize> = available
+                    .iter()
+                    .copied()
+                    .filter(|&i| available.iter().all(|&j| load(i, j) <= load(j, i)))
+                    .collect();
+                self.round_robin(&least)
+            }
+            Balance::IpHash => self.consistent_hash(&client_ip(request)),
+            Balance::CookieHash(name) => match cookie(request, name) {
+                Some(value) => self.consistent_hash(value),
+                None => self.consistent_hash(&client_ip(request)),
+            },
+        }
+    }
+
+    // Round-robin pondéré « lisse » : les tours d'un serveur lourd sont intercalés, pas groupés
+    fn round_robin(&mut self, candidates: &[usize]) -> Option<usize> {
+        let total: i64 = candidates.iter().map(|&i| self.members[i].weight).sum();
+        let mut best: Option<usize> = None;
+        for &index in candidates {
+            let member = &mut self.members[index];
+            member.current += member.weight;
+            let current = member.current;
+            if best.is_none_or(|best| current > self.members[best].current) {
+                best = Some(index);
+            }
+        }
+        let best = best?;
+        self.members[best].current -= total;
+        Some(best)
+    }
+
+    // Premier serveur disponible après le point de la clé sur l'anneau
+    fn consistent_hash(&self, key: &str) -> Option<usize> {
+        let point = hash(key.as_bytes());
+        let start = self.ring.partition_point(|&(node, _)| node < point);
+        self.ring[start..]
+            .iter()
+            .chain(&self.ring[..start])
+            .map(|&(_, index)| index)
+            .find(|&index| self.members[index].is_available())
+    }
+
+    fn report(&mut self, index: usize, success: bool, now: Instant) {
+        let member = &mut self.members[index];
+        if success {
+            member.fails = 0;
+            return;
+        }
+        member.fails += 1;
+        let Some(ejection) = self.ejection else {
+            return;
+        };
+        if member.fails >= ejection.max_fails && member.ejected_until.is_none() {
+            member.ejected_until = Some(now + ejection.duration);
+            log!(
+                LogFileType::Server,
+                format!(
+                    "Upstream {} ejected for {}s after {} failures",
+                    member.endpoint,
+                    ejection.duration.as_secs_f32(),
+                    member.fails
+                )
+            );
+        }
+    }
+}
+
+/// # Lease
+///
+/// Requête en cours sur un serveur mandaté : compte pour la stratégie `LeastConnections`
+/// jusqu'à sa destruction, et transmet l'issue de la requête à la détection des pannes.
+#[derive(Debug)]
+pub struct Lease {
+    pool: Vec<Endpoint>,
+    index: usize,
+    reported: bool,
+}
+
+impl Lease {
+    // Fonction pour signaler l'issue de la requête ; seul le premier signalement compte
+    pub fn report(&mut self, success: bool) {
+        if std::mem::replace(&mut self.reported, true) {
+            return;
+        }
+        if let Some(pool) = pools().get_mut(&self.pool) {
+            pool.report(self.index, success, Instant::now());
+        }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some(pool) = pools().get_mut(&self.pool) {
+            let member = &mut pool.members[self.index];
+            member.active = member.active.saturating_sub(1);
+        }
+    }
+}
+
+fn pools() -> MutexGuard<'static, BTreeMap<Vec<Endpoint>, Pool>> {
+    POOLS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn pool_key(proxy: &Proxy) -> Vec<Endpoint> {
+    proxy
+        .upstreams
+        .iter()
+        .map(|upstream| Endpoint::from(&upstream.address))
+        .collect()
+}
+
+// Fonction pour choisir le serveur qui traite la requête ; `None` si aucun n'est disponible
+pub fn choose_upstream(proxy: &Proxy, request: &Request<Bytes>) -> Option<(usize, Lease)> {
+    let key = pool_key(proxy);
+    let mut pools = pools();
+    let pool = pools.entry(key.clone()).or_insert_with(|| Pool::new(proxy));
+    pool.refresh(Instant::now());
+
+    let index = pool.pick(&proxy.balance, request)?;
+    pool.members[index].active += 1;
+    Some((
+        index,
+        Lease {
+            pool: key,
+            index,
+            reported: false,
+        },
+    ))
+}
+
+/// # HealthProbes
+///
+/// Vérifications actives des serveurs mandatés, menées par la boucle d'événements sur des
+/// connexions non bloquantes : elles s'arrêtent avec elle.
+#[derive(Debug, Default)]
+pub struct HealthProbes {
+    targets: Vec<Target>,
+}
+
+// Serveur vérifié, avec la vérification en cours
+#[derive(Debug)]
+struct Target {
+    proxy: Proxy<'static>,
+    index: usize,
+    check: HealthCheck<'static>,
+    next: Instant,
+    probe: Option<Probe>,
+}
+
+// Requête de vérification : elle est envoyée une fois la connexion établie, puis la ligne de
+// statut de la réponse est attendue
+#[derive(Debug)]
+struct Probe {
+    token: Token,
+    stream: UpstreamStream,
+    outgoing: Bytes,
+    received: Bytes,
+    deadline: Instant,
+}
+
+impl HealthProbes {
+    // Fonction pour ajouter les serveurs des routes qui demandent une vérification active
+    pub fn add(&mut self, config: &ServerConfig<'static>) {
+        for route in &config.routes {
+            let Some(Gateway::Proxy(proxy)) = route.settings.as_ref().and_then(|s| s.gateway)
+            else {
+                continue;
+            };
+            let Some(check) = proxy.health_check else {
+                continue;
+            };
+            self.targets
+                .extend((0..proxy.upstreams.len()).map(|index| Target {
+                    proxy,
+                    index,
+                    check,
+                    next: Instant::now(),
+                    probe: None,
+                }));
+        }
+    }
+
+    pub fn owns(&self, token: Token) -> bool {
+        self.targets.iter().any(|target| target.owns(token))
+    }
+
+    // Instant de la prochaine vérification, ou de l'abandon d'une vérification en cours
+    pub fn wake_at(&self) -> Option<Instant> {
+        let wake_at = |target: &Target| target.probe.as_ref().map_or(target.next, |p| p.deadline);
+        self.targets.iter().map(wake_at).min()
+    }
+
+    // Fonction pour abandonner les vérifications trop lentes et lancer celles qui sont dues
+    pub fn handle_timeout(&mut self, registry: &Registry, token_id: &mut usize) {
+        let now = Instant::now();
+        for target in &mut self.targets {
+            if target
+                .probe
+                .as_ref()
+                .is_some_and(|probe| now >= probe.deadline)
+            {
+                let timeout = io::Error::new(ErrorKind::TimedOut, "health check timed out");
+                target.finish(registry, Err(timeout));
+            }
+            if target.probe.is_none() && now >= target.next {
+                target.next = now + target.check.interval;
+                if let Err(e) = target.start(registry, token_id, now) {
+                    target.finish(registry, Err(e));
+                }
+            }
+        }
+    }
+
+    // Fonction pour faire avancer la vérification dont la connexion est prête
+    pub fn handle_event(&mut self, registry: &Registry, token: Token) {
+        let Some(target) = self.targets.iter_mut().find(|target| target.owns(token)) else {
+            return;
+        };
+        let Some(probe) = target.probe.as_mut() else {
+            return;
+        };
+        match probe.advance() {
+            Ok(None) => {}
+            Ok(Some(status)) => target.finish(registry, Ok(status)),
+            Err(e) => target.finish(registry, Err(e)),
+        }
+    }
+
+    // Fonction pour arrêter les vérifications lorsque le processus cesse de servir
+    pub fn stop(&mut self, registry: &Registry) {
+        for mut target in self.targets.drain(..) {
+            if let Some(mut probe) = target.probe.take() {
+                let _ = registry.deregister(&mut probe.stream);
+            }
+        }
+    }
+}
+
+impl Target {
+    fn owns(&self, token: Token) -> bool {
+        self.probe
+            .as_ref()
+            .is_some_and(|probe| probe.token == token)
+    }
+
+    // Fonction pour ouvrir la connexion de vérification ; elle est établie par la boucle
+    fn start(&mut self, registry: &Registry, token_id: &mut usize, now: Instant) -> io::Result<()> {
+        let address = &self.proxy.upstreams[self.index].address;
+        let mut stream = UpstreamStream::connect(&Endpoint::from(address))?;
+        let token = Token(*token_id);
+        *token_id += 1;
+        registry.register(&mut stream, token, Interest::READABLE | Interest::WRITABLE)?;
+
+        let host = match address {
+            Address::Tcp(host) => host,
+            Address::Unix(_) => "localhost",
+        };
+        let outgoing = format!(
+            "GET {} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: localhost-health-check\r\n\
+             Connection: close\r\n\r\n",
+            self.check.path
+        );
+        self.probe = Some(Probe {
+            token,
+            stream,
+            outgoing: outgoing.into_bytes(),
+            received: Bytes::new(),
+            deadline: now + self.check.timeout,
+        });
+        Ok(())
+    }
+
+    // Fonction pour fermer la connexion de vérification et noter l'état du serveur
+    fn finish(&mut self, registry: &Registry, result: io::Result<u16>) {
+        if let Some(mut probe) = self.probe.take() {
+            let _ = registry.deregister(&mut probe.stream);
+        }
+        set_health(&self.proxy, self.index, result);
+    }
+}
+
+impl Probe {
+    // Fonction pour envoyer la requête puis lire la réponse ; renvoie son statut une fois la
+    // ligne de statut reçue
+    fn advance(&mut self) -> io::Result<Option<u16>> {
+        if !self.stream.is_connected()? {
+            return Ok(None);
+        }
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut buf = [0; BUFFER_SIZE];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return status(&self.received, true),
+                Ok(n) => self.received.extend(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    return status(&self.received, false)
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+            if let Some(status) = status(&self.received, false)? {
+                return Ok(Some(status));
+            }
+        }
+    }
+}
+
+// Fonction pour lire le statut de la réponse de vérification, une fois sa première ligne reçue
+fn status(received: &[u8], closed: bool) -> io::Result<Option<u16>> {
+    let invalid = || io::Error::new(ErrorKind::InvalidData, "invalid status line");
+    let Some(end) = received.iter().position(|&byte| byte == b'\n') else {
+        return match closed || received.len() > BUFFER_SIZE {
+            true => Err(invalid()),
+            false => Ok(None),
+        };
+    };
+    String::from_utf8_lossy(&received[..end])
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .map(Some)
+        .ok_or_else(invalid)
+}
+
+fn set_health(proxy: &Proxy, index: usize, result: io::Result<u16>) {
+    let healthy = matches!(result, Ok(200..=399));
+    let mut pools = pools();
+    let pool = pools
+        .entry(pool_key(proxy))
+        .or_insert_with(|| Pool::new(proxy));
+    let member = &mut pool.members[index];
+    if member.healthy == healthy {
+        return;
+    }
+    member.healthy = healthy;
+    let detail = match result {
+        Ok(code) => format!("status {code}"),
+        Err(e) => e.to_string(),
+    };
+    log!(
+        LogFileType::Server,
+        format!(
+            "Health check: upstream {} is {} ({detail})",
+            member.endpoint,
+            if healthy { "up" } else { "down" }
+        )
+    );
+}
+
+fn client_ip(request: &Request<Bytes>) -> String {
+    request
+        .extensions()
+        .get::<Addresses>()
+        .map(|addresses| addresses.remote.ip().to_string())
+        .unwrap_or_default()
+}
+
+fn cookie<'r>(request: &'r Request<Bytes>, name: &str) -> Option<&'r str> {
+    request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// Hachage FNV-1a, stable d'une version du compilateur à l'autre, suivi d'un mélange final
+// pour que des clés voisines (adresses d'un même réseau) tombent loin sur l'anneau
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn proxy(upstreams: &'static [Upstream<'static>], balance: Balance<'static>) -> Proxy<'static> {
+        Proxy {
+            upstreams,
+            balance,
+            ejection: Some(Ejection {
+                max_fails: 2,
+                duration: Duration::from_secs(10),
+            }),
+            health_check: None,
+            path: None,
+            timeout: None,
+        }
+    }
+
+    fn request(ip: &str, cookie: Option<&str>) -> Request<Bytes> {
+        let mut builder = Request::builder();
+        if let Some(cookie) = cookie {
+            builder = builder.header(COOKIE, cookie);
+        }
+        let mut request = builder.body(Bytes::new()).unwrap();
+        let remote: SocketAddr = format!("{ip}:4000").parse().unwrap();
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        request.extensions_mut().insert(Addresses { remote, local });
+        request
+    }
+
+    const UPSTREAMS: [Upstream; 3] = [
+        Upstream {
+            address: Address::Tcp("10.0.0.1:80"),
+            weight: 3,
+        },
+        Upstream::new(Address::Tcp("10.0.0.2:80")),
+        Upstream::new(Address::Tcp("10.0.0.3:80")),
+    ];
+
+    #[test]
+    fn weighted_round_robin_is_smooth() {
+        let proxy = proxy(&UPSTREAMS, Balance::RoundRobin);
+        let mut pool = Pool::new(&proxy);
+        let request = request("192.0.2.1", None);
+        let picks: Vec<_> = (0..10)
+            .map(|_| pool.pick(&proxy.balance, &request).unwrap())
+            .collect();
+        assert_eq!(picks, [0, 1, 0, 2, 0, 0, 1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn least_connections_respects_weights() {
+        let proxy = proxy(&UPSTREAMS, Balance::LeastConnections);
+        let mut pool = Pool::new(&proxy);
+        let request = request("192.0.2.1", None);
+        pool.members[0].active = 2;
+        pool.members[1].active = 1;
+        // 2 / 3 < 1 / 1 : le serveur le plus lourd reste le moins chargé
+        pool.members[2].active = 1;
+        assert_eq!(pool.pick(&proxy.balance, &request), Some(0));
+        pool.members[0].active = 4;
+        let picks: Vec<_> = (0..2)
+            .map(|_| pool.pick(&proxy.balance, &request).unwrap())
+            .collect();
+        assert_eq!(picks, [1, 2]);
+    }
+
+    #[test]
+    fn hashing_is_sticky_and_consistent() {
+        let proxy = proxy(&UPSTREAMS, Balance::CookieHash("session"));
+        let mut pool = Pool::new(&proxy);
+        let first = request("192.0.2.1", Some("a=b; session=42"));
+        let chosen = pool.pick(&proxy.balance, &first).unwrap();
+        let again = request("198.51.100.7", Some("session=42"));
+        assert_eq!(pool.pick(&proxy.balance, &again), Some(chosen));
+
+        // Sans cookie, l'adresse du client sert de clé
+        let clients: Vec<_> = (1..=50)
+            .map(|n| request(&format!("192.0.2.{n}"), None))
+            .collect();
+        let before: Vec<_> = clients
+            .iter()
+            .map(|r| pool.pick(&Balance::IpHash, r).unwrap())
+            .collect();
+        assert!((0..3).all(|index| before.contains(&index)));
+
+        // Seuls les clients du serveur écarté changent de serveur
+        pool.members[1].healthy = false;
+        for (client, before) in clients.iter().zip(before) {
+            let after = pool.pick(&Balance::IpHash, client).unwrap();
+            assert!(after == before || before == 1);
+            assert_ne!(after, 1);
+        }
+    }
+
+    #[test]
+    fn failures_eject_then_readmit() {
+        let proxy = proxy(&UPSTREAMS[1..], Balance::RoundRobin);
+        let mut pool = Pool::new(&proxy);
+        let request = request("192.0.2.1", None);
+        let now = Instant::now();
+
+        pool.report(0, false, now);
+        pool.report(0, true, now);
+        pool.report(0, false, now);
+        assert!(pool.members[0].is_available());
+        pool.report(0, false, now);
+        assert!(!pool.members[0].is_available());
+        assert_eq!(pool.pick(&proxy.balance, &request), Some(1));
+        assert_eq!(pool.pick(&proxy.balance, &request), Some(1));
+
+        pool.refresh(now + Duration::from_secs(10));
+        assert!(pool.members[0].is_available());
+        pool.report(0, false, now);
+        assert!(!pool.members[0].is_available());
+
+        pool.members[1].healthy = false;
+        assert_eq!(pool.pick(&proxy.balance, &request), None);
+    }
+
+    #[test]
+    fn probe_status_line() {
+        assert_eq!(status(b"HTTP/1.1 204 No Content\r\n", false).unwrap(), Some(204));
+        assert_eq!(status(b"HTTP/1.1 503 Unavail", false).unwrap(), None);
+        assert!(status(b"HTTP/1.1 503 Unavail", true).is_err());
+        assert!(status(b"garbage\r\n", false).is_err());
+    }
+}
@@ -1,33 +1,167 @@
 use crate::log;
 use crate::log::*;
 use crate::server::path::add_root_to_path;
-use crate::server::{get_route, Bytes, ServerConfig, StatusCode};
-use crate::type_aliases::FileExtension;
+use crate::server::{get_route, Addresses, Bytes, Route, ServerConfig, StatusCode, TlsSession};
 use http::header::*;
+use http::response::Builder;
 use http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
-use std::env;
-use std::process::Command;
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{env, fs};
 
-// Enumération pour définir les types de scripts CGI supportés
-#[derive(Clone, Debug)]
-pub enum Cgi {
-    PHP,
-    Python,
+pub use output::*;
+pub use process::*;
+
+/// Valeur de `SERVER_SOFTWARE`
+pub const SERVER_SOFTWARE: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// # Cgi
+///
+/// Manière de lancer les scripts associés à une extension de fichier dans `cgi_def`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cgi<'a> {
+    // Interpréteur (nom dans le PATH ou chemin) et ses arguments, suivis du chemin du script
+    Interpreter {
+        program: &'a str,
+        args: &'a [&'a str],
+    },
+    // Fichier exécutable lancé directement, par exemple grâce à sa ligne shebang
+    Executable,
 }
 
-// Fonction pour vérifier si une requête est destinée à un script CGI
-pub fn is_cgi_request(path: &str) -> bool {
-    path.contains("/cgi/")
+// Interpréteurs courants
+impl<'a> Cgi<'a> {
+    pub const PHP: Cgi<'a> = Cgi::interpreter("php");
+    pub const PYTHON: Cgi<'a> = Cgi::interpreter("python3");
+    pub const JAVASCRIPT: Cgi<'a> = Cgi::interpreter("node");
+    pub const RUBY: Cgi<'a> = Cgi::interpreter("ruby");
+    pub const PERL: Cgi<'a> = Cgi::interpreter("perl");
+
+    // Fonction pour définir un interpréteur sans argument
+    pub const fn interpreter(program: &'a str) -> Cgi<'a> {
+        Cgi::Interpreter { program, args: &[] }
+    }
+}
+
+/// # CgiSandbox
+///
+/// Restrictions appliquées aux scripts CGI d'une route : utilisateur et groupe sous lesquels
+/// ils s'exécutent (le serveur doit alors être lancé en root), et limites de ressources.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CgiSandbox {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub cpu_time: Option<Duration>, // RLIMIT_CPU, en secondes
+    pub memory: Option<u64>,        // RLIMIT_AS, en octets
+    pub open_files: Option<u64>,    // RLIMIT_NOFILE
+}
+
+// Fonction pour vérifier si une requête est destinée à un script CGI de la route
+pub fn is_cgi_request(route: &Route, url_path: &str) -> bool {
+    let cgi_def = match route.settings.as_ref().and_then(|s| s.cgi_def.as_ref()) {
+        Some(cgi_def) => cgi_def,
+        None => return false,
+    };
+    locate_script(route, url_path)
+        .is_some_and(|script| cgi_def.contains_key(script.extension.as_str()))
 }
 
 // En-têtes standards à inclure dans la réponse
 const STANDARD_HEADERS: [HeaderName; 1] = [TRANSFER_ENCODING];
 
-// Fonction principale pour exécuter un script CGI
-pub fn execute_cgi_script(
+/// # DEFAULT_PASS_ENV
+///
+/// Variables d'environnement du serveur transmises aux scripts CGI lorsque la route
+/// ne définit pas `cgi_pass_env`.
+pub const DEFAULT_PASS_ENV: [&str; 1] = ["PATH"];
+
+/// # DEFAULT_CGI_TIMEOUT
+///
+/// Durée maximale d'exécution d'un script CGI lorsque la route ne définit pas `cgi_timeout`.
+pub const DEFAULT_CGI_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// # CgiEnv
+///
+/// Variables méta-données (RFC 3875, section 4.1) transmises à un script CGI.
+pub type CgiEnv = BTreeMap<String, String>;
+
+// Emplacement d'un script CGI déduit de l'URL de la requête
+#[derive(Debug)]
+pub struct Script {
+    pub name: String,
+    pub filename: String,
+    pub path_info: Option<String>,
+    pub extension: String,
+}
+
+impl Script {
+    // Fonction pour vérifier si le script produit une réponse non analysée (non-parsed header)
+    pub fn is_nph(&self) -> bool {
+        self.name
+            .rsplit('/')
+            .next()
+            .is_some_and(|name| name.starts_with("nph-"))
+    }
+}
+
+// Fonction pour trouver le script dans le chemin de la requête.
+// Exemple : /cgi/python.py/path/to/file -> SCRIPT_NAME: /cgi/python.py, PATH_INFO: /path/to/file
+pub fn locate_script(route: &Route, url_path: &str) -> Option<Script> {
+    let mut name = String::new();
+
+    for segment in url_path.split('/').filter(|s| !s.is_empty()) {
+        name.push('/');
+        name.push_str(segment);
+
+        let filename = add_root_to_path(route, &name);
+        if Path::new(&filename).is_file() {
+            let path_info = url_path[name.len()..].to_string();
+            let extension = segment
+                .rsplit_once('.')
+                .map(|(_, ext)| ext)
+                .unwrap_or_default();
+            return Some(Script {
+                extension: extension.to_string(),
+                filename,
+                path_info: (!path_info.is_empty()).then_some(path_info),
+                name,
+            });
+        }
+    }
+    None
+}
+
+// Fonction pour trouver le script d'une requête transmise à un serveur d'application.
+// Le script peut n'exister que sur ce serveur : le chemin entier est alors son nom.
+pub fn gateway_script(route: &Route, url_path: &str) -> Script {
+    locate_script(route, url_path).unwrap_or_else(|| Script {
+        name: url_path.to_string(),
+        filename: add_root_to_path(route, url_path),
+        path_info: None,
+        extension: url_path
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_string())
+            .unwrap_or_default(),
+    })
+}
+
+// Script CGI prêt à être lancé
+pub struct PreparedScript {
+    pub command: Command,
+    pub script: Script,
+    pub timeout: Duration,
+}
+
+// Fonction pour préparer la commande d'un script CGI à partir de la requête
+pub fn prepare_cgi_script(
     req: &Request<Bytes>,
     config: &ServerConfig,
-) -> Result<Response<Bytes>, StatusCode> {
+) -> Result<PreparedScript, StatusCode> {
     // Récupérer la route correspondant à la requête
     let route = match get_route(req, config) {
         Ok(route) => route,
@@ -41,78 +175,204 @@ pub fn execute_cgi_script(
     };
 
     // Vérifier si un script CGI est défini pour cette route
-    if settings.cgi_def.is_none() {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    // Construire le chemin complet du script CGI
-    let full_path = add_root_to_path(&route, req.uri().path());
-    let body = match String::from_utf8(req.body().clone()) {
-        Ok(b) => b,
-        Err(_) => return Err(StatusCode::BAD_REQUEST),
+    let cgi_def = match &settings.cgi_def {
+        Some(cgi_def) => cgi_def,
+        None => return Err(StatusCode::BAD_REQUEST),
     };
 
-    // Extraire l'extension du fichier pour déterminer le type de script CGI
-    let extension = full_path.split('.').rev().collect::<Vec<&str>>()[0].trim_end();
-
-    let mut file_extension = String::new();
-
-    for ch in extension.chars() {
-        if ch.is_alphanumeric() {
-            file_extension.push(ch);
-        } else {
-            break;
+    // Trouver le script et son extension pour déterminer le type de script CGI
+    let script = match locate_script(&route, req.uri().path()) {
+        Some(script) => script,
+        None => {
+            log!(
+                LogFileType::Server,
+                format!("Error: CGI script not found {}", req.uri().path())
+            );
+            return Err(StatusCode::NOT_FOUND);
         }
-    }
-
-    let path = full_path
-        .split(&format!(".{file_extension}"))
-        .collect::<Vec<&str>>()[0]
-        .to_string();
-
-    let path = format!("{path}.{file_extension}");
-    add_env_variables(req, config, file_extension.as_str());
+    };
 
     // Vérifier si l'extension du fichier est associée à un script CGI
-    let (command, arguments) = match settings
-        .cgi_def
-        .clone()
-        .unwrap()
-        .get(file_extension.as_str())
-    {
-        Some(cgi_type) => match cgi_type {
-            Cgi::PHP => ("php", vec![path, body]),
-            Cgi::Python => ("python3", vec![path, body]),
-        },
-
+    let cgi = match cgi_def.get(script.extension.as_str()) {
+        Some(cgi) => cgi,
         None => {
             log!(
                 LogFileType::Server,
-                format!("Error: CGI not found {}", path)
+                format!("Error: CGI not found {}", script.filename)
             );
             return Err(StatusCode::NOT_FOUND);
         }
     };
 
-    // Exécuter le script CGI et capturer sa sortie
-    let body = match Command::new(command).args(arguments).output() {
-        Ok(output) => output.stdout,
-        Err(e) => {
+    // N'exécuter que les scripts situés dans un répertoire autorisé
+    if !is_allowed_script(&route, &script) {
+        log!(
+            LogFileType::Server,
+            format!(
+                "Error: CGI script outside allowed directories {}",
+                script.filename
+            )
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Les variables sont définies uniquement pour le processus enfant
+    let mut env = CgiEnv::new();
+    pass_server_env(&mut env, settings.cgi_pass_env.as_deref());
+    add_env_variables(&mut env, req, config, &route, &script);
+
+    // Le chemin absolu reste valide depuis le répertoire du script
+    let filename = fs::canonicalize(&script.filename).map_err(|_| StatusCode::NOT_FOUND)?;
+    let mut command = match cgi {
+        Cgi::Interpreter { program, args } => {
+            let mut command = Command::new(program);
+            command.args(*args).arg(&filename);
+            command
+        }
+        Cgi::Executable if is_executable(&script.filename) => Command::new(&filename),
+        Cgi::Executable => {
             log!(
                 LogFileType::Server,
-                format!("Error executing CGI script: {}", e)
+                format!("Error: CGI script is not executable {}", script.filename)
             );
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(StatusCode::FORBIDDEN);
+        }
+    };
+
+    // Le script est placé dans son propre groupe de processus pour pouvoir l'arrêter entièrement
+    command
+        .env_clear()
+        .envs(env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+
+    // Exécuter le script depuis son répertoire (RFC 3875, section 7.2)
+    if let Some(dir) = filename.parent() {
+        command.current_dir(dir);
+    }
+    sandbox(&mut command, settings.cgi_sandbox.unwrap_or_default());
+
+    Ok(PreparedScript {
+        command,
+        script,
+        timeout: settings.cgi_timeout.unwrap_or(DEFAULT_CGI_TIMEOUT),
+    })
+}
+
+// Fonction pour appliquer au processus enfant l'utilisateur et les limites de la route
+fn sandbox(command: &mut Command, sandbox: CgiSandbox) {
+    if let Some(gid) = sandbox.gid {
+        command.gid(gid);
+    }
+    if let Some(uid) = sandbox.uid {
+        command.uid(uid);
+    }
+
+    // Exécuté dans l'enfant juste avant `exec` : seuls des appels système sont permis
+    let limit = |resource, value: Option<u64>, extra| -> std::io::Result<()> {
+        let Some(value) = value else {
+            return Ok(());
+        };
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value.saturating_add(extra) as libc::rlim_t,
+        };
+        match unsafe { libc::setrlimit(resource, &limit) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    };
+    let cpu_time = sandbox.cpu_time.map(|time| time.as_secs().max(1));
+    unsafe {
+        command.pre_exec(move || {
+            // SIGXCPU à la limite douce, puis SIGKILL une seconde plus tard
+            limit(libc::RLIMIT_CPU, cpu_time, 1)?;
+            limit(libc::RLIMIT_AS, sandbox.memory, 0)?;
+            limit(libc::RLIMIT_NOFILE, sandbox.open_files, 0)?;
+            close_inherited_fds();
+            Ok(())
+        });
+    }
+}
+
+// Fonction pour fermer à l'exécution du script les descripteurs hérités du serveur
+// (listeners, connexions des clients) qui ne seraient pas marqués `FD_CLOEXEC`
+fn close_inherited_fds() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let flags = libc::CLOSE_RANGE_CLOEXEC;
+        if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, flags) == 0 {
+            return;
+        }
+    }
+    let max = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) }.clamp(0, 65536) as libc::c_int;
+    for fd in 3..max {
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
         }
+    }
+}
+
+// Fonction pour vérifier que le script se trouve dans l'un des répertoires autorisés de la route.
+// Sans `cgi_dirs`, seuls les scripts sous le répertoire de la route sont autorisés.
+fn is_allowed_script(route: &Route, script: &Script) -> bool {
+    let root = add_root_to_path(route, route.url_path);
+    let dirs = match route.settings.as_ref().and_then(|s| s.cgi_dirs.as_ref()) {
+        Some(dirs) => dirs.iter().map(|dir| dir.to_string()).collect(),
+        None => vec![root],
     };
 
-    // Construire la réponse HTTP
+    // Comparer les chemins canoniques pour écarter les `..` et les liens symboliques
+    let filename = match fs::canonicalize(&script.filename) {
+        Ok(filename) => filename,
+        Err(_) => return false,
+    };
+    dirs.iter()
+        .filter_map(|dir| fs::canonicalize(dir).ok())
+        .any(|dir| filename.starts_with(dir))
+}
+
+// Fonction pour vérifier si un fichier peut être exécuté directement
+fn is_executable(filename: &str) -> bool {
+    fs::metadata(filename).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+// Fonction principale pour exécuter un script CGI jusqu'à la fin de sa sortie
+pub fn execute_cgi_script(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let prepared = prepare_cgi_script(req, config)?;
+    let script_name = prepared.script.name.clone();
+    let is_nph = prepared.script.is_nph();
+
+    // Exécuter le script CGI, transmettre le corps sur son entrée standard et capturer sa sortie
+    let process = CgiProcess::spawn(prepared, req, String::new(), config)?;
+    let output = process.wait_output()?;
+
+    // Les scripts `nph-` produisent eux-mêmes la réponse HTTP complète
+    if is_nph {
+        return Response::builder()
+            .extension(RawResponse)
+            .body(output)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    cgi_response(output, response_builder(req, config)).inspect_err(|_| {
+        log!(
+            LogFileType::Server,
+            format!("Error: Malformed output from CGI script {}", script_name)
+        );
+    })
+}
+
+// Fonction pour créer la réponse HTTP à compléter avec la sortie du script
+fn response_builder(req: &Request<Bytes>, config: &ServerConfig) -> Builder {
     let mut resp = Response::builder()
         .version(req.version())
-        .header(HOST, config.host)
-        .status(StatusCode::OK)
-        .header(CONTENT_TYPE, "text/html")
-        .header(CONTENT_LENGTH, body.len());
+        .header(HOST, config.host);
 
     // Ajouter les en-têtes standards à la réponse
     for (key, value) in req.headers() {
@@ -120,67 +380,1024 @@ pub fn execute_cgi_script(
             resp = resp.header(key, value);
         }
     }
+    resp
+}
+
+// Fonction pour démarrer le processus d'un script CGI
+fn spawn(command: &mut Command, script: &Script) -> Result<Child, StatusCode> {
+    command.spawn().map_err(|e| {
+        log!(
+            LogFileType::Server,
+            format!("Error executing CGI script {}: {}", script.name, e)
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// Fonction pour arrêter un script CGI et tous les processus qu'il a lancés
+fn kill_group(child: &mut Child) {
+    // Le groupe de processus a pour identifiant celui du script (`process_group(0)`)
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+// Fonction pour journaliser la sortie d'erreur d'un script CGI, ligne par ligne
+pub fn log_stderr(name: &str, stderr: &[u8]) {
+    for line in String::from_utf8_lossy(stderr).lines() {
+        if !line.trim().is_empty() {
+            log!(
+                LogFileType::Server,
+                format!("CGI script {}: {}", name, line)
+            );
+        }
+    }
+}
+
+/// # Slot
+///
+/// Place réservée parmi les scripts CGI exécutés en même temps, libérée à sa destruction.
+#[derive(Debug)]
+pub struct Slot;
 
-    let response = resp
-        .body(body)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+static RUNNING_SCRIPTS: AtomicUsize = AtomicUsize::new(0);
 
-    Ok(response)
+impl Slot {
+    // Fonction pour réserver une place, ou 503 si la limite est atteinte
+    pub fn acquire(max: usize) -> Result<Slot, StatusCode> {
+        if RUNNING_SCRIPTS.fetch_add(1, Ordering::SeqCst) >= max {
+            RUNNING_SCRIPTS.fetch_sub(1, Ordering::SeqCst);
+            log!(
+                LogFileType::Server,
+                format!("Error: Too many CGI scripts running (limit {max})")
+            );
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        Ok(Slot)
+    }
 }
 
-// Fonction pour ajouter des variables d'environnement nécessaires au script CGI
-fn add_env_variables(req: &Request<Bytes>, config: &ServerConfig, file_extension: FileExtension) {
-    add_http_variables(req.headers());
-    if let Some(query) = req.uri().query() {
-        env::set_var("QUERY_STRING", query);
+impl Drop for Slot {
+    fn drop(&mut self) {
+        RUNNING_SCRIPTS.fetch_sub(1, Ordering::SeqCst);
     }
+}
 
-    env::set_var("REQUEST_METHOD", req.method().to_string());
-    env::set_var("SERVER_NAME", config.host);
+// Fonction pour transmettre les variables d'environnement du serveur autorisées pour la route
+fn pass_server_env(env: &mut CgiEnv, pass_env: Option<&[&str]>) {
+    for key in pass_env.unwrap_or(&DEFAULT_PASS_ENV) {
+        if let Ok(value) = env::var(key) {
+            env.insert(key.to_string(), value);
+        }
+    }
+}
 
-    if let Some(port) = req.uri().port_u16() {
-        env::set_var("SERVER_PORT", format!("{port}"));
+// Fonction pour ajouter les variables méta-données du script CGI (RFC 3875, section 4.1)
+pub fn add_env_variables(
+    env: &mut CgiEnv,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    route: &Route,
+    script: &Script,
+) {
+    let mut set = |key: &str, value: String| {
+        env.insert(key.to_string(), value);
+    };
+
+    set("GATEWAY_INTERFACE", "CGI/1.1".to_string());
+    set("SERVER_SOFTWARE", SERVER_SOFTWARE.to_string());
+    set("SERVER_NAME", config.host.to_string());
+    set("SERVER_PROTOCOL", format!("{:?}", req.version()));
+    set("REQUEST_METHOD", req.method().to_string());
+    set("REQUEST_URI", req.uri().to_string());
+    set(
+        "QUERY_STRING",
+        req.uri().query().unwrap_or_default().to_string(),
+    );
+    set("SCRIPT_NAME", script.name.clone());
+    set(
+        "SCRIPT_FILENAME",
+        fs::canonicalize(&script.filename)
+            .map(|p| p.display().to_string())
+            .unwrap_or(script.filename.clone()),
+    );
+
+    if let Some(path_info) = &script.path_info {
+        set("PATH_INFO", path_info.clone());
+        // Chemin que `PATH_INFO` désignerait sous la racine de la route
+        let translated = add_root_to_path(route, path_info);
+        let translated = env::current_dir()
+            .unwrap_or_default()
+            .join(translated.trim_start_matches("./"));
+        set("PATH_TRANSLATED", translated.display().to_string());
     }
 
-    env::set_var("SERVER_SOFTWARE", "Rust v1.74.0");
+    // Adresses de la connexion
+    let addresses = req.extensions().get::<Addresses>();
+    if let Some(Addresses { remote, local }) = addresses {
+        set("REMOTE_ADDR", remote.ip().to_string());
+        set("REMOTE_HOST", remote.ip().to_string());
+        set("REMOTE_PORT", remote.port().to_string());
+        set("SERVER_ADDR", local.ip().to_string());
+        set("SERVER_PORT", local.port().to_string());
+    } else if let Some(port) = req.uri().port_u16() {
+        set("SERVER_PORT", port.to_string());
+    }
 
-    let path = req
-        .uri()
-        .path()
-        .split(file_extension)
-        .collect::<Vec<&str>>();
+    // Paramètres de la connexion chiffrée, sous les noms utilisés par mod_ssl
+    match req.extensions().get::<TlsSession>() {
+        Some(session) => {
+            set("HTTPS", "on".to_string());
+            set("REQUEST_SCHEME", "https".to_string());
+            set("SSL_PROTOCOL", session.protocol.clone());
+            set("SSL_CIPHER", session.cipher.clone());
+            if let Some(name) = &session.server_name {
+                set("SSL_TLS_SNI", name.clone());
+            }
+            match &session.client_certificate {
+                Some(certificate) => {
+                    set("SSL_CLIENT_VERIFY", "SUCCESS".to_string());
+                    set("SSL_CLIENT_S_DN", certificate.subject.clone());
+                    if let Some(name) = &certificate.common_name {
+                        set("SSL_CLIENT_S_DN_CN", name.clone());
+                    }
+                    set("SSL_CLIENT_I_DN", certificate.issuer.clone());
+                    set("SSL_CLIENT_M_SERIAL", certificate.serial.clone());
+                    set("SSL_CLIENT_V_START", certificate.not_before.clone());
+                    set("SSL_CLIENT_V_END", certificate.not_after.clone());
+                    set("SSL_CLIENT_FINGERPRINT", certificate.fingerprint.clone());
+                }
+                None => set("SSL_CLIENT_VERIFY", "NONE".to_string()),
+            }
+        }
+        None => set("REQUEST_SCHEME", "http".to_string()),
+    }
 
-    // Exemple : localhost:8080/cgi/python.py/path/to/file -> PATH_INFO: /path/to/file
-    if contains_path_info(path.clone()) {
-        env::set_var("PATH_INFO", path[1]);
+    // Le corps est transmis sur l'entrée standard
+    if !req.body().is_empty() {
+        set("CONTENT_LENGTH", req.body().len().to_string());
     }
+
+    add_http_variables(env, req.headers());
 }
 
 // Fonction pour ajouter les variables d'environnement HTTP
-fn add_http_variables(headers: &HeaderMap<HeaderValue>) {
-    for (key, v) in headers {
-        let value = v.to_str().unwrap_or_default();
+fn add_http_variables(env: &mut CgiEnv, headers: &HeaderMap<HeaderValue>) {
+    for key in headers.keys() {
+        let value = headers
+            .get_all(key)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(if *key == COOKIE { "; " } else { ", " });
         if value.is_empty() {
             continue;
         }
+
         match *key {
-            ACCEPT => env::set_var("HTTP_ACCEPT", value),
-            CONTENT_LENGTH => env::set_var("CONTENT_LENGTH", value),
-            CONTENT_TYPE => env::set_var("CONTENT_TYPE", value),
-            ACCEPT_CHARSET => env::set_var("HTTP_ACCEPT_CHARSET", value),
-            ACCEPT_ENCODING => env::set_var("HTTP_ACCEPT_ENCODING", value),
-            ACCEPT_LANGUAGE => env::set_var("HTTP_ACCEPT_LANGUAGE", value),
-            FORWARDED => env::set_var("HTTP_FORWARDED", value),
-            HOST => env::set_var("HTTP_HOST", value),
-            PROXY_AUTHORIZATION => env::set_var("HTTP_PROXY_AUTHORIZATION", value),
-            USER_AGENT => env::set_var("HTTP_USER_AGENT", value),
-            COOKIE => env::set_var("COOKIE", value),
-            _ => {}
+            CONTENT_TYPE => {
+                env.insert("CONTENT_TYPE".to_string(), value);
+            }
+            // Géré à partir du corps effectivement reçu
+            CONTENT_LENGTH => {}
+            // Les identifiants ne sont pas transmis au script, seulement le type d'authentification
+            AUTHORIZATION => {
+                if let Some(scheme) = value.split_whitespace().next() {
+                    env.insert("AUTH_TYPE".to_string(), scheme.to_string());
+                }
+            }
+            PROXY_AUTHORIZATION => {}
+            _ => {
+                let name = key.as_str().to_ascii_uppercase().replace('-', "_");
+                env.insert(format!("HTTP_{name}"), value);
+            }
+        }
+    }
+}
+
+pub mod output {
+    use super::*;
+    use crate::server::{format_response, without_body, MAX_HEAD_SIZE, OUTPUT_LIMIT};
+    use http::{Method, Version};
+
+    /// # LocalRedirect
+    ///
+    /// Extension d'une réponse CGI demandant au serveur de traiter la requête à un autre
+    /// chemin local (RFC 3875, section 6.2.2).
+    #[derive(Clone, Debug)]
+    pub struct LocalRedirect(pub String);
+
+    /// # RawResponse
+    ///
+    /// Extension d'une réponse dont le corps est déjà un message HTTP complet, envoyé tel quel.
+    #[derive(Clone, Copy, Debug)]
+    pub struct RawResponse;
+
+    // En-têtes gérés par le serveur et jamais repris de la sortie du script
+    const SERVER_HEADERS: [HeaderName; 3] = [CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING];
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|w| w == needle)
+    }
+
+    // Fonction pour trouver la ligne vide qui termine les en-têtes : (fin des en-têtes, début du corps)
+    pub fn header_end(output: &[u8]) -> Option<(usize, usize)> {
+        let crlf = find(output, b"\r\n\r\n").map(|i| (i, i + 4));
+        let lf = find(output, b"\n\n").map(|i| (i, i + 2));
+        match (crlf, lf) {
+            (Some(crlf), Some(lf)) => Some(if crlf.0 <= lf.0 { crlf } else { lf }),
+            (crlf, lf) => crlf.or(lf),
+        }
+    }
+
+    // Fonction pour analyser les lignes `Nom: valeur` de la section d'en-têtes
+    pub fn parse_headers(section: &[u8]) -> Result<Vec<(HeaderName, HeaderValue)>, StatusCode> {
+        let section = std::str::from_utf8(section).map_err(|_| StatusCode::BAD_GATEWAY)?;
+        let mut headers = Vec::new();
+
+        for line in section.split('\n').map(|line| line.trim_end_matches('\r')) {
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once(':').ok_or(StatusCode::BAD_GATEWAY)?;
+            let name = HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| StatusCode::BAD_GATEWAY)?;
+            let value = HeaderValue::from_str(value.trim()).map_err(|_| StatusCode::BAD_GATEWAY)?;
+            headers.push((name, value));
+        }
+        Ok(headers)
+    }
+
+    // Fonction pour lire le champ `Status: 404 Not Found`
+    fn parse_status(value: &HeaderValue) -> Result<StatusCode, StatusCode> {
+        value
+            .to_str()
+            .ok()
+            .and_then(|v| v.split_whitespace().next())
+            .and_then(|code| code.parse::<u16>().ok())
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .ok_or(StatusCode::BAD_GATEWAY)
+    }
+
+    // En-têtes analysés de la sortie d'un script
+    pub struct OutputHead {
+        pub response: Builder,
+        // Chemin local si les en-têtes ne contiennent qu'un champ `Location` vers le serveur
+        pub local_location: Option<String>,
+    }
+
+    // Fonction pour appliquer la section d'en-têtes d'un script à la réponse (RFC 3875, section 6)
+    pub fn output_head(section: &[u8], mut resp: Builder) -> Result<OutputHead, StatusCode> {
+        let headers = parse_headers(section)?;
+
+        let mut status = None;
+        let mut location = None;
+        let mut has_content_type = false;
+
+        for (name, value) in &headers {
+            match name.as_str() {
+                "status" => status = Some(parse_status(value)?),
+                "location" => location = value.to_str().ok().map(str::to_string),
+                "content-type" => has_content_type = true,
+                _ => {}
+            }
+            if name != "status" && !SERVER_HEADERS.contains(name) {
+                resp = resp.header(name, value);
+            }
+        }
+
+        // La réponse doit contenir au moins un de ces champs
+        if status.is_none() && location.is_none() && !has_content_type {
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+
+        // Redirection vers le client : 302 si le script ne précise pas de statut
+        let code = status.unwrap_or(if location.is_some() {
+            StatusCode::FOUND
+        } else {
+            StatusCode::OK
+        });
+
+        Ok(OutputHead {
+            response: resp.status(code),
+            local_location: location.filter(|l| l.starts_with('/') && headers.len() == 1),
+        })
+    }
+
+    // Fonction pour construire la réponse HTTP à partir de la sortie complète d'un script
+    pub fn cgi_response(output: Bytes, resp: Builder) -> Result<Response<Bytes>, StatusCode> {
+        // Une sortie sans ligne vide n'est valide que si elle ne contient que des en-têtes
+        let (end, body_start) = header_end(&output).unwrap_or((output.len(), output.len()));
+        let head = output_head(&output[..end], resp)?;
+        let body = output[body_start..].to_vec();
+
+        // Redirection locale : un chemin seul, sans autre champ ni corps
+        if let Some(path) = head.local_location.filter(|_| body.is_empty()) {
+            return Response::builder()
+                .extension(LocalRedirect(path))
+                .body(vec![])
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        head.response
+            .header(CONTENT_LENGTH, body.len())
+            .body(body)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    // Progression d'un script CGI après un événement sur l'un de ses tubes
+    #[derive(Debug)]
+    pub enum CgiProgress {
+        // Le script est toujours en cours ; octets à envoyer au client
+        Running(Bytes),
+        // La sortie du script est terminée ; derniers octets à envoyer au client
+        Done(Bytes),
+        // Le script demande au serveur de traiter la requête à un autre chemin
+        LocalRedirect(String),
+    }
+
+    // Avancement de la réponse envoyée au client
+    #[derive(Debug)]
+    enum Head {
+        // Les en-têtes du script ne sont pas encore complets
+        Pending,
+        // Redirection locale possible : la sortie est conservée jusqu'à la fin du script ;
+        // `body_start` est la position du corps dans la sortie conservée
+        Buffered { body_start: usize },
+        // Les en-têtes ont été envoyés, le corps est transmis au fur et à mesure
+        Sent { chunked: bool },
+        // Script `nph-` : la sortie est transmise telle quelle
+        Raw,
+    }
+
+    /// # Relay
+    ///
+    /// Transforme au fil de l'eau la sortie d'un script (CGI ou passerelle) en réponse HTTP :
+    /// les en-têtes sont analysés dès qu'ils sont complets, puis le corps est transmis en chunks.
+    #[derive(Debug)]
+    pub struct Relay {
+        head: Head,
+        output: Bytes,
+    }
+
+    // Résultat de la transformation d'une partie de la sortie
+    enum Consumed {
+        Bytes(Bytes),
+        LocalRedirect(String),
+    }
+
+    impl Relay {
+        // Fonction pour créer un relais ; `raw` pour une sortie déjà au format HTTP (`nph-`)
+        pub fn new(raw: bool) -> Relay {
+            Relay {
+                head: if raw { Head::Raw } else { Head::Pending },
+                output: Bytes::new(),
+            }
+        }
+
+        // Vrai si une partie de la réponse a déjà été envoyée au client
+        pub fn has_responded(&self) -> bool {
+            matches!(self.head, Head::Sent { .. } | Head::Raw)
+        }
+
+        // Fonction pour transmettre une partie de la sortie ; `closed` à la fin de la sortie
+        pub fn feed(
+            &mut self,
+            request: &Request<Bytes>,
+            data: Bytes,
+            closed: bool,
+            config: &ServerConfig,
+        ) -> Result<CgiProgress, StatusCode> {
+            Ok(match self.consume(request, data, closed, config)? {
+                Consumed::Bytes(bytes) if closed => CgiProgress::Done(bytes),
+                Consumed::Bytes(bytes) => CgiProgress::Running(bytes),
+                Consumed::LocalRedirect(path) => CgiProgress::LocalRedirect(path),
+            })
+        }
+
+        // Fonction pour transformer la sortie du script selon l'avancement de la réponse
+        fn consume(
+            &mut self,
+            request: &Request<Bytes>,
+            data: Bytes,
+            closed: bool,
+            config: &ServerConfig,
+        ) -> Result<Consumed, StatusCode> {
+            let is_head = request.method() == Method::HEAD;
+
+            match self.head {
+                Head::Raw => Ok(Consumed::Bytes(data)),
+                Head::Sent { chunked } => {
+                    let mut bytes = Bytes::new();
+                    if is_head {
+                        return Ok(Consumed::Bytes(bytes));
+                    }
+                    if !chunked {
+                        return Ok(Consumed::Bytes(data));
+                    }
+                    if !data.is_empty() {
+                        bytes.extend(format!("{:X}\r\n", data.len()).as_bytes());
+                        bytes.extend(data);
+                        bytes.extend(b"\r\n");
+                    }
+                    if closed {
+                        bytes.extend(b"0\r\n\r\n"); // Fin des chunks
+                    }
+                    Ok(Consumed::Bytes(bytes))
+                }
+                Head::Pending | Head::Buffered { .. } => {
+                    self.output.extend(data);
+
+                    // La sortie conservée est limitée : les en-têtes comme ceux d'une requête, et
+                    // le corps d'une redirection locale, qui sera ignoré, comme la sortie en
+                    // attente d'un client lent
+                    let oversized = match self.head {
+                        Head::Buffered { body_start } => {
+                            self.output.len() - body_start > OUTPUT_LIMIT
+                        }
+                        _ => {
+                            let end = header_end(&self.output).map(|(end, _)| end);
+                            end.unwrap_or(self.output.len()) > MAX_HEAD_SIZE
+                        }
+                    };
+                    if oversized {
+                        log!(
+                            LogFileType::Server,
+                            "Error: Script output kept before the response is too large"
+                                .to_string()
+                        );
+                        return Err(StatusCode::BAD_GATEWAY);
+                    }
+
+                    // À la fin du script, la sortie complète est analysée d'un coup
+                    if closed {
+                        let output = std::mem::take(&mut self.output);
+                        let mut response = cgi_response(output, response_builder(request, config))?;
+                        if let Some(LocalRedirect(path)) = response.extensions().get() {
+                            return Ok(Consumed::LocalRedirect(path.clone()));
+                        }
+                        // La connexion est fermée à la fin de la réponse
+                        let close = HeaderValue::from_static("close");
+                        response.headers_mut().insert(CONNECTION, close);
+                        let response = if is_head {
+                            without_body(response)
+                        } else {
+                            response
+                        };
+                        return Ok(Consumed::Bytes(format_response(response)));
+                    }
+
+                    let Some((end, body_start)) = header_end(&self.output) else {
+                        return Ok(Consumed::Bytes(Bytes::new()));
+                    };
+                    if matches!(self.head, Head::Buffered { .. }) {
+                        return Ok(Consumed::Bytes(Bytes::new()));
+                    }
+
+                    let head = output_head(&self.output[..end], response_builder(request, config))?;
+                    if head.local_location.is_some() {
+                        // Le corps déjà reçu compte dans la limite de la sortie conservée
+                        self.head = Head::Buffered { body_start };
+                        return self.consume(request, Bytes::new(), closed, config);
+                    }
+
+                    // La longueur du corps est inconnue : découpage en chunks en HTTP/1.1,
+                    // fin du corps à la fermeture de la connexion sinon. La connexion est
+                    // fermée dans les deux cas
+                    let chunked = request.version() == Version::HTTP_11;
+                    let mut response = head.response;
+                    if let Some(headers) = response.headers_mut() {
+                        headers.remove(TRANSFER_ENCODING);
+                        if chunked {
+                            headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+                        }
+                        headers.insert(CONNECTION, HeaderValue::from_static("close"));
+                    }
+                    let response = response
+                        .body(Bytes::new())
+                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+                    self.head = Head::Sent { chunked };
+                    let body = self.output.split_off(body_start);
+                    self.output.clear();
+
+                    let mut bytes = format_response(response);
+                    if let Consumed::Bytes(body) = self.consume(request, body, closed, config)? {
+                        bytes.extend(body);
+                    }
+                    Ok(Consumed::Bytes(bytes))
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn parse(output: &str) -> Result<Response<Bytes>, StatusCode> {
+            cgi_response(Bytes::from(output), Response::builder())
+        }
+
+        #[test]
+        fn document_response() {
+            let resp = parse("Content-Type: text/plain\r\nSet-Cookie: a=b\r\n\r\nhello").unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()[CONTENT_TYPE], "text/plain");
+            assert_eq!(resp.headers()[SET_COOKIE], "a=b");
+            assert_eq!(resp.headers()[CONTENT_LENGTH], "5");
+            assert_eq!(resp.body(), b"hello");
+        }
+
+        #[test]
+        fn status_and_lf_line_endings() {
+            let resp =
+                parse("Status: 404 Not Found\nContent-Type: text/html\n\n<h1>404</h1>\n\nend")
+                    .unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert!(resp.headers().get("status").is_none());
+            assert_eq!(resp.body(), b"<h1>404</h1>\n\nend");
+        }
+
+        #[test]
+        fn redirects() {
+            let resp = parse("Location: /index.html\n\n").unwrap();
+            assert_eq!(
+                resp.extensions().get::<LocalRedirect>().unwrap().0,
+                "/index.html"
+            );
+
+            let resp = parse("Location: https://example.com/\n\n").unwrap();
+            assert_eq!(resp.status(), StatusCode::FOUND);
+            assert_eq!(resp.headers()[LOCATION], "https://example.com/");
+
+            let resp =
+                parse("Status: 301\nLocation: /moved\nContent-Type: text/html\n\nMoved").unwrap();
+            assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+            assert!(resp.extensions().get::<LocalRedirect>().is_none());
+        }
+
+        #[test]
+        fn malformed_output() {
+            assert_eq!(
+                parse("Hello world!\n").unwrap_err(),
+                StatusCode::BAD_GATEWAY
+            );
+            assert_eq!(
+                parse("X-Only: header\n\nbody").unwrap_err(),
+                StatusCode::BAD_GATEWAY
+            );
+            assert_eq!(
+                parse("Status: abc\n\n").unwrap_err(),
+                StatusCode::BAD_GATEWAY
+            );
+            assert_eq!(parse("").unwrap_err(), StatusCode::BAD_GATEWAY);
+        }
+
+        fn relay(relay: &mut Relay, request: &Request<Bytes>, data: &str, closed: bool) -> String {
+            let config = &crate::server::config::server_config()[0];
+            match relay
+                .feed(request, Bytes::from(data), closed, config)
+                .unwrap()
+            {
+                CgiProgress::Running(bytes) | CgiProgress::Done(bytes) => {
+                    String::from_utf8(bytes).unwrap()
+                }
+                CgiProgress::LocalRedirect(path) => format!("redirect {path}"),
+            }
+        }
+
+        #[test]
+        fn relay_streams_chunks() {
+            let request = Request::builder().body(Bytes::new()).unwrap();
+            let mut output = Relay::new(false);
+
+            // Rien n'est envoyé avant la fin des en-têtes
+            assert_eq!(
+                relay(&mut output, &request, "Content-Type: text/", false),
+                ""
+            );
+            assert!(!output.has_responded());
+
+            let head = relay(&mut output, &request, "plain\n\nfirst", false);
+            assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+            assert!(head.contains("transfer-encoding: chunked\r\n"), "{head}");
+            assert!(head.contains("connection: close\r\n"), "{head}");
+            assert!(head.ends_with("\r\n\r\n5\r\nfirst\r\n"), "{head}");
+            assert!(output.has_responded());
+
+            assert_eq!(
+                relay(&mut output, &request, "second", true),
+                "6\r\nsecond\r\n0\r\n\r\n"
+            );
+        }
+
+        #[test]
+        fn relay_without_chunks() {
+            // En HTTP/1.0, la fin du corps est signalée par la fermeture de la connexion
+            let request = Request::builder()
+                .version(Version::HTTP_10)
+                .body(Bytes::new())
+                .unwrap();
+            let mut output = Relay::new(false);
+            let head = relay(
+                &mut output,
+                &request,
+                "Content-Type: text/plain\n\nbody",
+                false,
+            );
+            assert!(!head.contains("transfer-encoding"), "{head}");
+            assert!(head.ends_with("\r\n\r\nbody"), "{head}");
+            assert_eq!(relay(&mut output, &request, "", true), "");
+
+            // Les scripts `nph-` écrivent eux-mêmes la réponse
+            let mut output = Relay::new(true);
+            let raw = "HTTP/1.0 204 No Content\r\n\r\n";
+            assert_eq!(relay(&mut output, &request, raw, true), raw);
+        }
+
+        #[test]
+        fn relay_buffers_local_redirects() {
+            let request = Request::builder().body(Bytes::new()).unwrap();
+            let mut output = Relay::new(false);
+            assert_eq!(
+                relay(&mut output, &request, "Location: /index.html\n\n", false),
+                ""
+            );
+            assert!(!output.has_responded());
+            assert_eq!(
+                relay(&mut output, &request, "", true),
+                "redirect /index.html"
+            );
+        }
+
+        #[test]
+        fn relay_limits_the_output_it_keeps() {
+            let request = Request::builder().body(Bytes::new()).unwrap();
+            let config = &crate::server::config::server_config()[0];
+
+            // Des en-têtes sans fin
+            let mut output = Relay::new(false);
+            let head = format!("X-Long: {}", "a".repeat(MAX_HEAD_SIZE));
+            let progress = output.feed(&request, Bytes::from(head), false, config);
+            assert_eq!(progress.unwrap_err(), StatusCode::BAD_GATEWAY);
+
+            // Le corps d'une redirection locale, reçu avec les en-têtes ou après eux
+            let body = "a".repeat(OUTPUT_LIMIT);
+            let mut output = Relay::new(false);
+            assert_eq!(relay(&mut output, &request, "Location: /a\n\n", false), "");
+            assert_eq!(relay(&mut output, &request, &body, false), "");
+            let progress = output.feed(&request, Bytes::from("a"), false, config);
+            assert_eq!(progress.unwrap_err(), StatusCode::BAD_GATEWAY);
+
+            let mut output = Relay::new(false);
+            let redirect = format!("Location: /a\n\n{body}a");
+            let progress = output.feed(&request, Bytes::from(redirect), false, config);
+            assert_eq!(progress.unwrap_err(), StatusCode::BAD_GATEWAY);
         }
     }
 }
 
-// Fonction pour vérifier si le chemin contient des informations de chemin supplémentaires
-fn contains_path_info(path: Vec<&str>) -> bool {
-    path.len() == 2
+pub mod process {
+    use super::*;
+    use crate::server::BUFFER_SIZE;
+    use mio::unix::pipe::{Receiver, Sender};
+    use mio::{Events, Interest, Poll, Registry, Token};
+    use std::io;
+    use std::io::{ErrorKind, Read, Write};
+
+    /// # CgiProcess
+    ///
+    /// Script CGI en cours d'exécution dont les tubes sont surveillés par la boucle
+    /// d'événements : le corps de la requête est écrit sur stdin quand le tube est prêt,
+    /// stdout est transmis au client dès qu'il est produit et stderr est journalisé.
+    #[derive(Debug)]
+    pub struct CgiProcess {
+        child: Child,
+        script: Script,
+        request: Request<Bytes>,
+        request_head: String,
+        stdin: Option<(Token, Sender)>,
+        stdout: Option<(Token, Receiver)>,
+        stderr: Option<(Token, Receiver)>,
+        written: usize,
+        errors: Bytes,
+        relay: Relay,
+        // La sortie disponible n'a pas été lue : le client n'avait pas reçu la précédente
+        output_paused: bool,
+        deadline: Instant,
+        _slot: Slot,
+    }
+
+    impl CgiProcess {
+        // Fonction pour lancer le script avec des tubes non bloquants
+        pub fn spawn(
+            prepared: PreparedScript,
+            request: &Request<Bytes>,
+            request_head: String,
+            config: &ServerConfig,
+        ) -> Result<CgiProcess, StatusCode> {
+            let slot = Slot::acquire(config.cgi_max_processes)?;
+            let PreparedScript {
+                mut command,
+                script,
+                timeout,
+            } = prepared;
+            let mut child = spawn(&mut command, &script)?;
+
+            let stdin = child.stdin.take().map(Sender::from);
+            let stdout = child.stdout.take().map(Receiver::from);
+            let stderr = child.stderr.take().map(Receiver::from);
+            let (Some(stdin), Some(stdout), Some(stderr)) = (stdin, stdout, stderr) else {
+                kill_group(&mut child);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            };
+
+            let nonblocking = stdin
+                .set_nonblocking(true)
+                .and(stdout.set_nonblocking(true))
+                .and(stderr.set_nonblocking(true));
+            if let Err(e) = nonblocking {
+                log!(LogFileType::Server, format!("Error: CGI pipes: {e}"));
+                kill_group(&mut child);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+
+            Ok(CgiProcess {
+                child,
+                relay: Relay::new(script.is_nph()),
+                script,
+                request: request.clone(),
+                request_head,
+                stdin: Some((Token(0), stdin)),
+                stdout: Some((Token(0), stdout)),
+                stderr: Some((Token(0), stderr)),
+                written: 0,
+                errors: Bytes::new(),
+                output_paused: false,
+                deadline: Instant::now() + timeout,
+                _slot: slot,
+            })
+        }
+
+        // Fonction pour enregistrer les tubes du script, et renvoyer les jetons attribués
+        pub fn register(
+            &mut self,
+            registry: &Registry,
+            token_id: &mut usize,
+        ) -> io::Result<Vec<Token>> {
+            let mut next_token = || {
+                let token = Token(*token_id);
+                *token_id += 1;
+                token
+            };
+            let mut tokens = Vec::new();
+
+            // Sans corps, l'entrée standard est fermée tout de suite
+            if self.request.body().is_empty() {
+                self.stdin = None;
+            }
+            if let Some((token, stdin)) = self.stdin.as_mut() {
+                *token = next_token();
+                registry.register(stdin, *token, Interest::WRITABLE)?;
+                tokens.push(*token);
+            }
+            for (token, pipe) in [self.stdout.as_mut(), self.stderr.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                *token = next_token();
+                registry.register(pipe, *token, Interest::READABLE)?;
+                tokens.push(*token);
+            }
+            Ok(tokens)
+        }
+
+        // Fonction pour retirer de la boucle d'événements les tubes encore ouverts
+        pub fn deregister(&mut self, registry: &Registry) {
+            if let Some((_, mut stdin)) = self.stdin.take() {
+                let _ = registry.deregister(&mut stdin);
+            }
+            for (_, mut pipe) in [self.stdout.take(), self.stderr.take()]
+                .into_iter()
+                .flatten()
+            {
+                let _ = registry.deregister(&mut pipe);
+            }
+        }
+
+        // Fonction pour traiter un événement sur l'un des tubes du script ; `paused` laisse la
+        // sortie dans son tube jusqu'à l'appel de `resume_output`
+        pub fn handle_event(
+            &mut self,
+            registry: &Registry,
+            token: Token,
+            paused: bool,
+            config: &ServerConfig,
+        ) -> Result<CgiProgress, StatusCode> {
+            if self.stdin.as_ref().is_some_and(|(t, _)| *t == token) {
+                self.write_input(registry);
+            }
+            if self.stderr.as_ref().is_some_and(|(t, _)| *t == token) {
+                self.read_errors(registry);
+            }
+            if self.stdout.as_ref().is_some_and(|(t, _)| *t == token) {
+                if paused {
+                    self.output_paused = true;
+                    return Ok(CgiProgress::Running(Bytes::new()));
+                }
+                return self.read_output(registry, config);
+            }
+            Ok(CgiProgress::Running(Bytes::new()))
+        }
+
+        // Fonction pour lire la sortie laissée dans le tube pendant la pause ; aucun nouvel
+        // événement ne la signalera
+        pub fn resume_output(
+            &mut self,
+            registry: &Registry,
+            config: &ServerConfig,
+        ) -> Option<Result<CgiProgress, StatusCode>> {
+            if !std::mem::take(&mut self.output_paused) {
+                return None;
+            }
+            Some(self.read_output(registry, config))
+        }
+
+        // Fonction pour exécuter le script jusqu'à la fin de sa sortie, renvoyée telle qu'il l'a
+        // écrite : ses tubes sont surveillés par une boucle d'événements propre à l'appel
+        pub fn wait_output(mut self) -> Result<Bytes, StatusCode> {
+            let mut poll = Poll::new().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let mut events = Events::with_capacity(8);
+            let mut token_id = 0;
+            self.register(poll.registry(), &mut token_id)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            let mut output = Bytes::new();
+            while self.stdout.is_some() {
+                let timeout = self.deadline.saturating_duration_since(Instant::now());
+                if timeout.is_zero() {
+                    log!(
+                        LogFileType::Server,
+                        format!("Error: CGI script {} timed out", self.script.name)
+                    );
+                    // Le groupe de processus est arrêté à la destruction
+                    return Err(StatusCode::GATEWAY_TIMEOUT);
+                }
+                match poll.poll(&mut events, Some(timeout)) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+                }
+                for event in events.iter() {
+                    let token = event.token();
+                    if self.stdin.as_ref().is_some_and(|(t, _)| *t == token) {
+                        self.write_input(poll.registry());
+                    }
+                    if self.stderr.as_ref().is_some_and(|(t, _)| *t == token) {
+                        self.read_errors(poll.registry());
+                    }
+                    if self.stdout.as_ref().is_some_and(|(t, _)| *t == token) {
+                        output.extend(self.read_stdout(poll.registry())?.0);
+                    }
+                }
+            }
+            self.deregister(poll.registry());
+            Ok(output)
+        }
+
+        // Instant auquel le script est arrêté s'il n'a pas terminé
+        pub fn deadline(&self) -> Instant {
+            self.deadline
+        }
+
+        // Vrai si une partie de la réponse a déjà été envoyée au client
+        pub fn has_responded(&self) -> bool {
+            self.relay.has_responded()
+        }
+
+        pub fn script(&self) -> &Script {
+            &self.script
+        }
+
+        pub fn request(&self) -> &Request<Bytes> {
+            &self.request
+        }
+
+        pub fn request_head(&self) -> &str {
+            &self.request_head
+        }
+
+        // Fonction pour écrire le corps de la requête tant que le tube l'accepte
+        fn write_input(&mut self, registry: &Registry) {
+            let Some((_, stdin)) = self.stdin.as_mut() else {
+                return;
+            };
+            let body = self.request.body();
+            while self.written < body.len() {
+                match stdin.write(&body[self.written..]) {
+                    Ok(n) => self.written += n,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    // Un script peut ne pas lire son entrée : un tube fermé n'est pas une erreur
+                    Err(_) => break,
+                }
+            }
+
+            // Fermer l'entrée standard pour signaler la fin du corps
+            if let Some((_, mut stdin)) = self.stdin.take() {
+                let _ = registry.deregister(&mut stdin);
+            }
+        }
+
+        // Fonction pour journaliser les lignes complètes écrites sur stderr
+        fn read_errors(&mut self, registry: &Registry) {
+            let Some((_, stderr)) = self.stderr.as_mut() else {
+                return;
+            };
+            let closed = drain(stderr, &mut self.errors).unwrap_or(true);
+
+            let complete = match self.errors.iter().rposition(|&b| b == b'\n') {
+                _ if closed => self.errors.len(),
+                Some(i) => i + 1,
+                None => 0,
+            };
+            let lines: Bytes = self.errors.drain(..complete).collect();
+            log_stderr(&self.script.name, &lines);
+
+            if closed {
+                if let Some((_, mut stderr)) = self.stderr.take() {
+                    let _ = registry.deregister(&mut stderr);
+                }
+            }
+        }
+
+        // Fonction pour lire la sortie disponible et produire les octets à envoyer au client
+        fn read_output(
+            &mut self,
+            registry: &Registry,
+            config: &ServerConfig,
+        ) -> Result<CgiProgress, StatusCode> {
+            if self.stdout.is_none() {
+                return Ok(CgiProgress::Done(Bytes::new()));
+            }
+            let (data, closed) = match self.read_stdout(registry) {
+                Ok(read) => read,
+                // Fermer la connexion signale au client une réponse incomplète
+                Err(_) if self.has_responded() => return Ok(CgiProgress::Done(Bytes::new())),
+                Err(code) => return Err(code),
+            };
+            self.relay.feed(&self.request, data, closed, config)
+        }
+
+        // Fonction pour lire la sortie disponible du script ; vrai si elle est terminée
+        fn read_stdout(&mut self, registry: &Registry) -> Result<(Bytes, bool), StatusCode> {
+            let Some((_, stdout)) = self.stdout.as_mut() else {
+                return Ok((Bytes::new(), true));
+            };
+            let mut data = Bytes::new();
+            let closed = drain(stdout, &mut data).map_err(|e| {
+                log!(
+                    LogFileType::Server,
+                    format!("Error reading CGI script {}: {e}", self.script.name)
+                );
+                StatusCode::BAD_GATEWAY
+            })?;
+
+            if closed {
+                if let Some((_, mut stdout)) = self.stdout.take() {
+                    let _ = registry.deregister(&mut stdout);
+                }
+                // Journaliser ce qui reste sur stderr
+                self.read_errors(registry);
+            }
+            Ok((data, closed))
+        }
+    }
+
+    impl Drop for CgiProcess {
+        fn drop(&mut self) {
+            // Arrêter le script s'il tourne encore, et éviter les processus zombies
+            match self.child.try_wait() {
+                Ok(Some(_)) => {}
+                _ => kill_group(&mut self.child),
+            }
+        }
+    }
+
+    // Fonction pour lire tout ce qui est disponible sur un tube ; renvoie vrai s'il est fermé
+    fn drain(pipe: &mut Receiver, buffer: &mut Bytes) -> io::Result<bool> {
+        let mut chunk = [0; BUFFER_SIZE];
+        loop {
+            match pipe.read(&mut chunk) {
+                Ok(0) => return Ok(true),
+                Ok(n) => buffer.extend(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use config::route::Settings;
 use http::StatusCode;
 use std::collections::HashMap;
+use std::time::Duration;
 
 // Importation des modules nécessaires
 pub use crate::server::*;
@@ -15,12 +16,44 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
         // Ports sur lesquels le serveur écoutera. Ajoutez ou supprimez des ports selon vos besoins.
         ports: vec![8080, 8081],
 
+        // Adresses IP sur lesquelles les ports sont liés, par exemple 'vec![BindAddress { ip: "::",
+        // v6_only: Some(false) }]' pour IPv4 et IPv6 sur toutes les interfaces. Laissez vide pour
+        // l'adresse de 'host', qui reste le nom annoncé du serveur.
+        bind_addresses: vec![],
+
+        // File d'attente, SO_REUSEADDR, SO_REUSEPORT et TCP_NODELAY des sockets d'écoute.
+        listen_options: ListenOptions::default(),
+
         // Chemin pour les pages d'erreur personnalisées. Définissez sur 'Some(path)' pour activer, ou laissez 'None' pour la gestion des erreurs par défaut.
         custom_error_path: None,
 
         // Taille maximale autorisée pour les corps de requête en octets. Ajustez selon vos besoins.
         body_size_limit: 1000000000024,
 
+        // Activer la méthode TRACE. Désactivée par défaut, car elle renvoie la requête reçue au client.
+        trace_enabled: false,
+
+        // Nombre maximal de scripts CGI exécutés en même temps. Au-delà, le serveur répond 503.
+        cgi_max_processes: 16,
+
+        // Durée accordée aux requêtes en cours à l'arrêt du serveur (SIGTERM, SIGINT, SIGQUIT).
+        // Les connexions encore ouvertes sont ensuite fermées et les scripts CGI arrêtés.
+        shutdown_grace_period: Duration::from_secs(30),
+
+        // Ports servis en HTTPS, par exemple 'vec![Tls { port: 8443, certificates: vec![Certificate {
+        // server_names: vec!["localhost"], chain_path: "certs/cert.pem", key_path: "certs/key.pem" }],
+        // min_version: TlsVersion::Tls12, cipher_suites: None, redirect_from: Some(8080),
+        // client_ca_path: None }]'. Avec 'client_ca_path', le port demande un certificat aux clients.
+        tls: vec![],
+
+        // Ports placés derrière un répartiteur de charge qui envoie l'en-tête PROXY (v1 ou v2) de
+        // HAProxy. Les adresses du client d'origine remplacent alors celles de la connexion.
+        proxy_protocol: vec![],
+        // Sockets Unix servis avec les mêmes routes, par exemple 'vec![UnixSocket { path:
+        // "/run/localhost.sock", mode: Some(0o660), uid: None, gid: Some(33), proxy_protocol: false }]'.
+        // Un chemin commençant par '@' désigne un socket abstrait (Linux), sans fichier.
+        unix_sockets: vec![],
+
         // Configuration des routes individuelles sur le serveur.
         routes: vec![
             Route {
@@ -54,11 +87,48 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                     // Configuration pour les scripts CGI.
                     cgi_def: Some(HashMap::from([
                         // Associez les extensions de fichier aux gestionnaires CGI. Ajoutez ou supprimez des mappages selon vos besoins.
+                        // Un interpréteur peut aussi être défini avec son chemin et ses arguments,
+                        // par exemple `Cgi::Interpreter { program: "/usr/bin/php-cgi", args: &["-q"] }`.
                         ("php", Cgi::PHP),
-                        ("py", Cgi::Python),
+                        ("py", Cgi::PYTHON),
+                        ("js", Cgi::JAVASCRIPT),
+                        // Les fichiers exécutables sont lancés directement, selon leur ligne shebang.
+                        ("cgi", Cgi::Executable),
                     ])),
+                    // Répertoires dont les scripts peuvent être exécutés. 'None' autorise uniquement le répertoire de la route.
+                    cgi_dirs: Some(vec!["./cgi"]),
+                    // Variables d'environnement du serveur transmises aux scripts. 'None' transmet uniquement PATH.
+                    cgi_pass_env: None,
+                    // Durée maximale d'exécution d'un script avant son arrêt (504). 'None' utilise 30 secondes.
+                    cgi_timeout: Some(Duration::from_secs(10)),
+                    // Utilisateur ('uid', 'gid', le serveur doit être lancé en root) et limites de
+                    // ressources des scripts. 'None' les exécute sans restriction.
+                    cgi_sandbox: Some(CgiSandbox {
+                        uid: None,
+                        gid: None,
+                        cpu_time: Some(Duration::from_secs(10)),
+                        // Espace d'adressage : node réserve à lui seul plusieurs Go de mémoire virtuelle
+                        memory: None,
+                        open_files: Some(64),
+                    }),
+                    // Serveur d'application qui traite toutes les requêtes de la route, par exemple
+                    // 'Some(Gateway::FastCgi(Address::Unix("/run/php/php-fpm.sock")))' ; aussi
+                    // 'Gateway::Scgi', 'Gateway::Uwsgi', ou 'Gateway::Proxy(Proxy { upstreams:
+                    // &[Upstream::new(Address::Tcp("127.0.0.1:3000"))], balance: Balance::RoundRobin,
+                    // ejection: None, health_check: None, path: Some("/"), timeout: None })'.
+                    gateway: None,
+                    // Gestionnaire des requêtes 'Upgrade: websocket' de la route, par exemple
+                    // 'Some(chat)' avec 'fn chat(socket: &mut WebSocket, event: WebSocketEvent)'.
+                    websocket: None,
+                    // Gestionnaire des requêtes GET servies en 'text/event-stream', par exemple
+                    // 'Some(updates)' avec 'fn updates(request: &Request<Bytes>, sink: EventSink)'.
+                    event_stream: None,
+                    // Certificat client vérifié : 'Some(ClientAuth::Required)' répond 403 sans
+                    // certificat, 'Some(ClientAuth::Optional)' le transmet seulement s'il est présenté.
+                    client_certificate: None,
                     // Activez l'affichage du contenu du répertoire pour cette route. Définissez sur 'false' pour désactiver.
                     list_directory: true,
+                    webdav: false,
                     // Paramètres CGI supplémentaires peuvent être configurés ici.
                     // Laissez 'None' pour les valeurs par défaut ou spécifiez pour personnaliser le comportement.
                     http_redirections: None,
@@ -79,7 +149,16 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -93,7 +172,16 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                     default_if_url_is_dir: Some("/dir.html"),
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -107,7 +195,16 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                     default_if_url_is_dir: Some("/does-not-exist-mate"),
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -127,7 +224,16 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: true,
+                    webdav: false,
                 }),
             },
         ],
@@ -0,0 +1,302 @@
+use crate::server::{raw_header_values, Bytes, StatusCode};
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::{HeaderName, Request, Response};
+use mio::{Token, Waker};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// # EVENT_STREAM_KEEP_ALIVE
+///
+/// Durée sans écriture après laquelle un commentaire est envoyé sur un flux d'événements,
+/// pour que les proxys et le client ne ferment pas la connexion.
+pub const EVENT_STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// # EVENT_STREAM_BACKLOG
+///
+/// Octets publiés et pas encore envoyés au-delà desquels un flux d'événements est fermé : le
+/// client qui ne suit pas se reconnecte avec `Last-Event-ID`, et l'application voit ses
+/// publications échouer.
+pub const EVENT_STREAM_BACKLOG: usize = 256 * 1024;
+
+/// # WAKE_TOKEN
+///
+/// Jeton du `Waker` qui réveille la boucle d'événements lorsqu'un événement est publié.
+pub const WAKE_TOKEN: Token = Token(usize::MAX);
+
+/// # EventStreamHandler
+///
+/// Gestionnaire des requêtes `GET` d'une route de flux d'événements (`text/event-stream`) :
+/// il reçoit le `EventSink` de la connexion, qu'il peut conserver ou confier à un autre fil
+/// d'exécution pour publier des événements au fil du temps.
+pub type EventStreamHandler = fn(request: &Request<Bytes>, sink: EventSink);
+
+/// # Event
+///
+/// Événement envoyé au client : `data` sur une ou plusieurs lignes, avec un type, un
+/// identifiant (renvoyé par le client dans `Last-Event-ID` à la reconnexion) et un délai
+/// de reconnexion facultatifs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Event<'a> {
+    pub event: Option<&'a str>,
+    pub data: &'a str,
+    pub id: Option<&'a str>,
+    pub retry: Option<Duration>,
+}
+
+impl<'a> Event<'a> {
+    // Fonction pour créer un événement sans type ni identifiant
+    pub const fn data(data: &'a str) -> Event<'a> {
+        Event {
+            event: None,
+            data,
+            id: None,
+            retry: None,
+        }
+    }
+
+    // Fonction pour encoder l'événement ; les retours à la ligne des champs d'une ligne sont retirés
+    pub fn encode(&self) -> Bytes {
+        let single_line = |value: &str| value.replace(['\r', '\n', '\0'], "");
+        let mut out = String::new();
+        if let Some(event) = self.event {
+            out.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(id) = self.id {
+            out.push_str(&format!("id: {}\n", single_line(id)));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            out.push_str(&format!("data: {line}\n"));
+        }
+        out.push('\n');
+        out.into_bytes()
+    }
+}
+
+// File d'attente partagée entre la connexion et ses `EventSink`
+#[derive(Debug, Default)]
+struct Shared {
+    pending: Bytes,
+    closing: bool,
+    disconnected: bool,
+    waker: Option<Arc<Waker>>,
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// # EventSink
+///
+/// Extrémité d'un flux d'événements par laquelle l'application publie ; elle peut être
+/// clonée et envoyée à d'autres fils d'exécution. Les publications échouent une fois la
+/// connexion fermée.
+#[derive(Clone, Debug)]
+pub struct EventSink {
+    shared: Arc<Mutex<Shared>>,
+    last_event_id: Option<Arc<str>>,
+}
+
+impl EventSink {
+    // Identifiant du dernier événement reçu par le client avant sa reconnexion
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    // Fonction pour publier un événement ; faux si la connexion est fermée, ou si le client a
+    // trop de retard pour le recevoir
+    pub fn send(&self, event: &Event) -> bool {
+        self.push(event.encode())
+    }
+
+    // Fonction pour envoyer un commentaire, ignoré par le client
+    pub fn comment(&self, text: &str) -> bool {
+        let lines = text.replace("\r\n", "\n");
+        let lines = lines.split(['\r', '\n']).map(|line| format!(": {line}\n"));
+        self.push(format!("{}\n", lines.collect::<String>()).into_bytes())
+    }
+
+    // Fonction pour fermer la connexion une fois les événements en attente envoyés
+    pub fn close(&self) {
+        let mut shared = lock(&self.shared);
+        shared.closing = true;
+        if let Some(waker) = &shared.waker {
+            let _ = waker.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let shared = lock(&self.shared);
+        shared.closing || shared.disconnected
+    }
+
+    fn push(&self, bytes: Bytes) -> bool {
+        let mut shared = lock(&self.shared);
+        if shared.closing || shared.disconnected {
+            return false;
+        }
+        // Le flux se termine après ce que le client n'a pas encore reçu
+        let overflow = shared.pending.len() + bytes.len() > EVENT_STREAM_BACKLOG;
+        match overflow {
+            true => shared.closing = true,
+            false => shared.pending.extend(bytes),
+        }
+        if let Some(waker) = &shared.waker {
+            let _ = waker.wake();
+        }
+        !overflow
+    }
+}
+
+/// # EventStream
+///
+/// Extrémité d'un flux d'événements tenue par la connexion : la boucle d'événements y
+/// récupère ce qui a été publié et y ajoute les commentaires de maintien de la connexion.
+#[derive(Debug)]
+pub struct EventStream {
+    shared: Arc<Mutex<Shared>>,
+    last_write: Instant,
+}
+
+impl EventStream {
+    // Fonction pour créer un flux d'événements et l'extrémité remise à l'application
+    pub fn channel(last_event_id: Option<String>) -> (EventStream, EventSink) {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let sink = EventSink {
+            shared: Arc::clone(&shared),
+            last_event_id: last_event_id.map(Arc::from),
+        };
+        let stream = EventStream {
+            shared,
+            last_write: Instant::now(),
+        };
+        (stream, sink)
+    }
+
+    // Fonction pour réveiller la boucle d'événements à chaque publication
+    pub fn attach(&self, waker: Arc<Waker>) {
+        lock(&self.shared).waker = Some(waker);
+    }
+
+    // Fonction pour récupérer les octets à écrire ; vrai si l'application a fermé le flux
+    pub fn take_pending(&mut self, now: Instant) -> (Bytes, bool) {
+        let mut shared = lock(&self.shared);
+        let pending = std::mem::take(&mut shared.pending);
+        if !pending.is_empty() {
+            self.last_write = now;
+        }
+        (pending, shared.closing)
+    }
+
+    // Fonction pour ajouter un commentaire si rien n'a été écrit depuis longtemps ; des octets
+    // encore en attente suffisent
+    pub fn keep_alive(&self, now: Instant) -> bool {
+        if now.duration_since(self.last_write) < EVENT_STREAM_KEEP_ALIVE {
+            return false;
+        }
+        let mut shared = lock(&self.shared);
+        if shared.pending.is_empty() {
+            shared.pending.extend(b": keep-alive\n\n");
+        }
+        true
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        lock(&self.shared).disconnected = true;
+    }
+}
+
+// Fonction pour ouvrir le flux d'événements demandé par le client
+pub fn event_stream(
+    request: &Request<Bytes>,
+    handler: EventStreamHandler,
+) -> Result<(Response<Bytes>, EventStream), StatusCode> {
+    // L'identifiant est sensible à la casse : il est lu tel qu'il a été reçu
+    let last_event_id = HeaderName::from_static("last-event-id");
+    let last_event_id = raw_header_values(request, &last_event_id)
+        .into_iter()
+        .next();
+    let (stream, sink) = EventStream::channel(last_event_id);
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .version(request.version())
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Bytes::new())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    handler(request, sink);
+    Ok((response, stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_encoded() {
+        let event = Event {
+            event: Some("update"),
+            data: "first\nsecond\r\nthird",
+            id: Some("4\n2"),
+            retry: Some(Duration::from_secs(3)),
+        };
+        assert_eq!(
+            event.encode(),
+            b"event: update\nid: 42\nretry: 3000\ndata: first\ndata: second\ndata: third\n\n"
+        );
+        assert_eq!(Event::data("").encode(), b"data: \n\n");
+    }
+
+    #[test]
+    fn sink_feeds_the_stream_until_closed() {
+        let (mut stream, sink) = EventStream::channel(Some("7".to_string()));
+        assert_eq!(sink.last_event_id(), Some("7"));
+
+        let start = Instant::now();
+        assert!(sink.send(&Event::data("a")));
+        assert!(sink.comment("ping"));
+        assert_eq!(
+            stream.take_pending(start),
+            (b"data: a\n\n: ping\n\n".to_vec(), false)
+        );
+
+        assert!(!stream.keep_alive(start + Duration::from_secs(1)));
+        assert!(stream.keep_alive(start + EVENT_STREAM_KEEP_ALIVE));
+        assert_eq!(stream.take_pending(start).0, b": keep-alive\n\n");
+
+        sink.close();
+        assert!(!sink.send(&Event::data("b")));
+        assert_eq!(stream.take_pending(start), (Bytes::new(), true));
+
+        let (stream, sink) = EventStream::channel(None);
+        drop(stream);
+        assert!(sink.is_closed());
+    }
+
+    #[test]
+    fn slow_clients_are_disconnected() {
+        let (mut stream, sink) = EventStream::channel(None);
+        let data = "x".repeat(1000);
+        let mut sent = 0;
+        while sink.send(&Event::data(&data)) {
+            sent += 1;
+        }
+        assert_eq!(
+            sent,
+            EVENT_STREAM_BACKLOG / Event::data(&data).encode().len()
+        );
+        assert!(sink.is_closed());
+        assert!(!sink.comment("late"));
+
+        // Les événements acceptés sont envoyés avant la fermeture
+        let (pending, closing) = stream.take_pending(Instant::now());
+        assert!(pending.len() <= EVENT_STREAM_BACKLOG);
+        assert!(pending.ends_with(b"x\n\n"));
+        assert!(closing);
+    }
+}
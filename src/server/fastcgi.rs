@@ -0,0 +1,702 @@
+use crate::log;
+use crate::log::*;
+use crate::server::route::Route;
+use crate::server::{
+    log_stderr, Address, Bytes, CgiEnv, CgiProgress, Endpoint, GatewayTarget, Relay, ServerConfig,
+    StatusCode, UpstreamStream, BUFFER_SIZE,
+};
+use http::Request;
+use mio::{Interest, Registry, Token};
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::time::Instant;
+
+pub use record::*;
+
+/// # DEFAULT_MAX_REQUESTS
+///
+/// Nombre de requêtes multiplexées sur une connexion lorsque le serveur accepte le
+/// multiplexage sans préciser `FCGI_MAX_REQS`.
+const DEFAULT_MAX_REQUESTS: usize = 16;
+
+/// # FastCgiRequest
+///
+/// Requête d'un client transmise à un serveur FastCGI (par exemple php-fpm), dont la
+/// réponse est relayée au client au fil de l'eau, comme la sortie d'un script CGI.
+#[derive(Debug)]
+pub struct FastCgiRequest {
+    endpoint: Endpoint,
+    name: String,
+    request: Request<Bytes>,
+    request_head: String,
+    env: CgiEnv,
+    relay: Relay,
+    errors: Bytes,
+    deadline: Instant,
+}
+
+impl FastCgiRequest {
+    // Fonction pour préparer la requête à transmettre au serveur FastCGI de la route
+    pub fn prepare(
+        route: &Route,
+        address: &Address,
+        request: &Request<Bytes>,
+        request_head: String,
+        config: &ServerConfig,
+    ) -> FastCgiRequest {
+        let target = GatewayTarget::new(route, request, config);
+
+        FastCgiRequest {
+            endpoint: Endpoint::from(address),
+            name: target.name,
+            request: request.clone(),
+            request_head,
+            env: target.env,
+            relay: Relay::new(false),
+            errors: Bytes::new(),
+            deadline: target.deadline,
+        }
+    }
+
+    // Instant auquel la requête est abandonnée si la réponse n'est pas terminée
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    // Vrai si une partie de la réponse a déjà été envoyée au client
+    pub fn has_responded(&self) -> bool {
+        self.relay.has_responded()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn request(&self) -> &Request<Bytes> {
+        &self.request
+    }
+
+    pub fn request_head(&self) -> &str {
+        &self.request_head
+    }
+
+    // Fonction pour encoder la requête sous l'identifiant attribué par la connexion
+    fn encode(&self, request_id: u16) -> Bytes {
+        let mut records = Bytes::new();
+        begin_request(request_id, KEEP_CONN).encode(&mut records);
+
+        let params = encode_pairs(self.env.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        encode_stream(PARAMS, request_id, &params, &mut records);
+        encode_stream(STDIN, request_id, self.request.body(), &mut records);
+        records
+    }
+
+    // Fonction pour relayer au client ce que le serveur FastCGI a envoyé pour cette requête
+    pub fn receive(
+        &mut self,
+        delivery: Delivery,
+        config: &ServerConfig,
+    ) -> Result<CgiProgress, StatusCode> {
+        match delivery {
+            Delivery::Output {
+                stdout,
+                stderr,
+                ended,
+            } => {
+                // Journaliser les lignes complètes de stderr, et le reste à la fin de la requête
+                self.errors.extend(stderr);
+                let complete = match self.errors.iter().rposition(|&b| b == b'\n') {
+                    _ if ended => self.errors.len(),
+                    Some(i) => i + 1,
+                    None => 0,
+                };
+                let lines: Bytes = self.errors.drain(..complete).collect();
+                log_stderr(&self.name, &lines);
+
+                self.relay.feed(&self.request, stdout, ended, config)
+            }
+            Delivery::Failed => {
+                log!(
+                    LogFileType::Server,
+                    format!(
+                        "Error: FastCGI server {} failed for {}",
+                        self.endpoint, self.name
+                    )
+                );
+                if self.has_responded() {
+                    // Fermer la connexion signale au client une réponse incomplète
+                    return Ok(CgiProgress::Done(Bytes::new()));
+                }
+                Err(StatusCode::BAD_GATEWAY)
+            }
+        }
+    }
+}
+
+/// # Delivery
+///
+/// Ce que le serveur FastCGI a envoyé pour une requête depuis le dernier événement.
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    Output {
+        stdout: Bytes,
+        stderr: Bytes,
+        ended: bool,
+    },
+    // La connexion au serveur a échoué avant la fin de la requête
+    Failed,
+}
+
+/// # FastCgiClient
+///
+/// Connexions ouvertes vers les serveurs FastCGI. Une connexion est réutilisée pour les
+/// requêtes suivantes (`FCGI_KEEP_CONN`), et plusieurs requêtes y sont multiplexées si le
+/// serveur l'annonce en réponse à `FCGI_GET_VALUES`.
+#[derive(Debug, Default)]
+pub struct FastCgiClient {
+    connections: HashMap<Token, Upstream>,
+    requests: HashMap<Token, (Token, u16)>, // Connexion client -> (connexion FastCGI, identifiant)
+}
+
+// Connexion vers un serveur FastCGI
+#[derive(Debug)]
+struct Upstream {
+    endpoint: Endpoint,
+    stream: UpstreamStream,
+    connected: bool,
+    outgoing: Bytes,
+    incoming: Bytes,
+    requests: HashMap<u16, Option<Token>>, // `None` pour une requête abandonnée par le client
+    max_requests: usize,
+    next_id: u16,
+}
+
+impl FastCgiClient {
+    // Vrai si le jeton correspond à une connexion vers un serveur FastCGI
+    pub fn owns(&self, token: Token) -> bool {
+        self.connections.contains_key(&token)
+    }
+
+    // Fonction pour envoyer la requête d'un client, sur une connexion existante si possible
+    pub fn submit(
+        &mut self,
+        registry: &Registry,
+        token_id: &mut usize,
+        client: Token,
+        request: &FastCgiRequest,
+    ) -> Vec<(Token, Delivery)> {
+        let available = self
+            .connections
+            .iter()
+            .find(|(_, upstream)| {
+                upstream.endpoint == request.endpoint
+                    && upstream.requests.len() < upstream.max_requests
+            })
+            .map(|(token, _)| *token);
+
+        let token = match available {
+            Some(token) => token,
+            None => match Upstream::connect(&request.endpoint, registry, token_id) {
+                Ok((token, upstream)) => {
+                    self.connections.insert(token, upstream);
+                    token
+                }
+                Err(e) => {
+                    log!(
+                        LogFileType::Server,
+                        format!(
+                            "Error connecting to FastCGI server {}: {e}",
+                            request.endpoint
+                        )
+                    );
+                    return vec![(client, Delivery::Failed)];
+                }
+            },
+        };
+
+        let upstream = self.connections.get_mut(&token).expect("connection exists");
+        let request_id = upstream.next_request_id();
+        upstream.requests.insert(request_id, Some(client));
+        upstream.outgoing.extend(request.encode(request_id));
+        self.requests.insert(client, (token, request_id));
+
+        if upstream.connected {
+            if let Err(e) = upstream.flush() {
+                return self.close(registry, token, e);
+            }
+        }
+        Vec::new()
+    }
+
+    // Fonction pour traiter un événement sur une connexion FastCGI
+    pub fn handle_event(&mut self, registry: &Registry, token: Token) -> Vec<(Token, Delivery)> {
+        let Some(upstream) = self.connections.get_mut(&token) else {
+            return Vec::new();
+        };
+
+        match upstream.process() {
+            Ok((deliveries, false)) => {
+                self.forget_ended(&deliveries);
+                deliveries
+            }
+            Ok((mut deliveries, true)) => {
+                self.forget_ended(&deliveries);
+                let closed = io::Error::new(ErrorKind::UnexpectedEof, "connection closed");
+                deliveries.extend(self.close(registry, token, closed));
+                deliveries
+            }
+            Err(e) => self.close(registry, token, e),
+        }
+    }
+
+    // Fonction pour abandonner la requête d'un client qui n'attend plus la réponse
+    pub fn abort(&mut self, client: Token) {
+        let Some((token, request_id)) = self.requests.remove(&client) else {
+            return;
+        };
+        let Some(upstream) = self.connections.get_mut(&token) else {
+            return;
+        };
+        if let Some(slot) = upstream.requests.get_mut(&request_id) {
+            // La requête occupe la connexion jusqu'à ce que le serveur confirme sa fin
+            *slot = None;
+            Record::new(ABORT_REQUEST, request_id, Bytes::new()).encode(&mut upstream.outgoing);
+            if upstream.connected {
+                let _ = upstream.flush();
+            }
+        }
+    }
+
+    // Fonction pour oublier les requêtes terminées
+    fn forget_ended(&mut self, deliveries: &[(Token, Delivery)]) {
+        for (client, delivery) in deliveries {
+            if matches!(delivery, Delivery::Output { ended: true, .. }) {
+                self.requests.remove(client);
+            }
+        }
+    }
+
+    // Fonction pour fermer une connexion, en signalant l'échec des requêtes en cours
+    fn close(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        error: io::Error,
+    ) -> Vec<(Token, Delivery)> {
+        let Some(mut upstream) = self.connections.remove(&token) else {
+            return Vec::new();
+        };
+        let _ = registry.deregister(&mut upstream.stream);
+
+        let pending = upstream
+            .requests
+            .into_values()
+            .flatten()
+            .collect::<Vec<_>>();
+        if !pending.is_empty() {
+            log!(
+                LogFileType::Server,
+                format!("Error: FastCGI server {}: {error}", upstream.endpoint)
+            );
+        }
+        pending
+            .into_iter()
+            .map(|client| {
+                self.requests.remove(&client);
+                (client, Delivery::Failed)
+            })
+            .collect()
+    }
+}
+
+impl Upstream {
+    // Fonction pour ouvrir une connexion et demander si le serveur accepte le multiplexage
+    fn connect(
+        endpoint: &Endpoint,
+        registry: &Registry,
+        token_id: &mut usize,
+    ) -> io::Result<(Token, Upstream)> {
+        let mut stream = UpstreamStream::connect(endpoint)?;
+        let token = Token(*token_id);
+        *token_id += 1;
+        registry.register(&mut stream, token, Interest::READABLE | Interest::WRITABLE)?;
+
+        let mut outgoing = Bytes::new();
+        let values = encode_pairs([("FCGI_MPXS_CONNS", ""), ("FCGI_MAX_REQS", "")]);
+        Record::new(GET_VALUES, 0, values).encode(&mut outgoing);
+
+        Ok((
+            token,
+            Upstream {
+                endpoint: endpoint.clone(),
+                stream,
+                connected: false,
+                outgoing,
+                incoming: Bytes::new(),
+                requests: HashMap::new(),
+                max_requests: 1, // Jusqu'à la réponse à `FCGI_GET_VALUES`
+                next_id: 0,
+            },
+        ))
+    }
+
+    // Fonction pour choisir un identifiant de requête libre sur la connexion
+    fn next_request_id(&mut self) -> u16 {
+        loop {
+            self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+            if !self.requests.contains_key(&self.next_id) {
+                return self.next_id;
+            }
+        }
+    }
+
+    // Fonction pour écrire les enregistrements en attente tant que la connexion les accepte
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Fonction pour écrire, lire et décoder ce qui est disponible ; vrai si le serveur a fermé
+    fn process(&mut self) -> io::Result<(Vec<(Token, Delivery)>, bool)> {
+        if !self.connected {
+            if !self.stream.is_connected()? {
+                return Ok((Vec::new(), false));
+            }
+            self.connected = true;
+        }
+        self.flush()?;
+
+        let mut closed = false;
+        let mut chunk = [0; BUFFER_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) => self.incoming.extend(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut deliveries = Vec::new();
+        while let Some(record) = Record::parse(&mut self.incoming)? {
+            self.receive(record, &mut deliveries);
+        }
+        Ok((deliveries, closed))
+    }
+
+    // Fonction pour traiter un enregistrement reçu du serveur
+    fn receive(&mut self, record: Record, deliveries: &mut Vec<(Token, Delivery)>) {
+        if record.kind == GET_VALUES_RESULT {
+            let values = decode_pairs(&record.content);
+            let value = |name: &str| values.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+            if value("FCGI_MPXS_CONNS").is_some_and(|v| v == "1") {
+                self.max_requests = value("FCGI_MAX_REQS")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_MAX_REQUESTS)
+                    .max(1);
+            }
+            return;
+        }
+
+        // Les requêtes abandonnées par leur client sont ignorées jusqu'à leur fin
+        let Some(&slot) = self.requests.get(&record.request_id) else {
+            return;
+        };
+        if record.kind == END_REQUEST {
+            self.requests.remove(&record.request_id);
+        }
+        let Some(client) = slot else {
+            return;
+        };
+
+        match record.kind {
+            STDOUT => output(deliveries, client).0.extend(record.content),
+            STDERR => output(deliveries, client).1.extend(record.content),
+            END_REQUEST => match end_request_status(&record.content) {
+                Some(REQUEST_COMPLETE) => *output(deliveries, client).2 = true,
+                status => {
+                    log!(
+                        LogFileType::Server,
+                        format!(
+                            "Error: FastCGI server {} refused the request ({status:?})",
+                            self.endpoint
+                        )
+                    );
+                    // Un serveur qui ne multiplexe pas n'accepte qu'une requête par connexion
+                    if status == Some(CANT_MPX_CONN) {
+                        self.max_requests = 1;
+                    }
+                    deliveries.push((client, Delivery::Failed));
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+// Fonction pour trouver (ou ajouter) la sortie à transmettre à un client
+fn output(
+    deliveries: &mut Vec<(Token, Delivery)>,
+    client: Token,
+) -> (&mut Bytes, &mut Bytes, &mut bool) {
+    let index = match deliveries.iter().position(|(token, delivery)| {
+        *token == client && matches!(delivery, Delivery::Output { .. })
+    }) {
+        Some(index) => index,
+        None => {
+            deliveries.push((
+                client,
+                Delivery::Output {
+                    stdout: Bytes::new(),
+                    stderr: Bytes::new(),
+                    ended: false,
+                },
+            ));
+            deliveries.len() - 1
+        }
+    };
+    match &mut deliveries[index].1 {
+        Delivery::Output {
+            stdout,
+            stderr,
+            ended,
+        } => (stdout, stderr, ended),
+        Delivery::Failed => unreachable!("only outputs are looked up"),
+    }
+}
+
+pub mod record {
+    use crate::server::Bytes;
+    use std::io;
+    use std::io::ErrorKind;
+
+    pub const VERSION_1: u8 = 1;
+    pub const HEADER_LEN: usize = 8;
+    pub const MAX_CONTENT_LEN: usize = 65535;
+
+    // Types d'enregistrements
+    pub const BEGIN_REQUEST: u8 = 1;
+    pub const ABORT_REQUEST: u8 = 2;
+    pub const END_REQUEST: u8 = 3;
+    pub const PARAMS: u8 = 4;
+    pub const STDIN: u8 = 5;
+    pub const STDOUT: u8 = 6;
+    pub const STDERR: u8 = 7;
+    pub const DATA: u8 = 8;
+    pub const GET_VALUES: u8 = 9;
+    pub const GET_VALUES_RESULT: u8 = 10;
+    pub const UNKNOWN_TYPE: u8 = 11;
+
+    // Rôle, option de `BEGIN_REQUEST` et statuts de `END_REQUEST`
+    pub const RESPONDER: u16 = 1;
+    pub const KEEP_CONN: u8 = 1;
+    pub const REQUEST_COMPLETE: u8 = 0;
+    pub const CANT_MPX_CONN: u8 = 1;
+    pub const OVERLOADED: u8 = 2;
+    pub const UNKNOWN_ROLE: u8 = 3;
+
+    /// # Record
+    ///
+    /// Enregistrement du protocole FastCGI 1.0.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Record {
+        pub kind: u8,
+        pub request_id: u16,
+        pub content: Bytes,
+    }
+
+    impl Record {
+        pub fn new(kind: u8, request_id: u16, content: Bytes) -> Record {
+            Record {
+                kind,
+                request_id,
+                content,
+            }
+        }
+
+        // Fonction pour encoder l'enregistrement, complété à un multiple de 8 octets
+        pub fn encode(&self, out: &mut Bytes) {
+            let len = self.content.len().min(MAX_CONTENT_LEN);
+            let padding = (8 - len % 8) % 8;
+
+            out.extend([VERSION_1, self.kind]);
+            out.extend(self.request_id.to_be_bytes());
+            out.extend((len as u16).to_be_bytes());
+            out.extend([padding as u8, 0]);
+            out.extend(&self.content[..len]);
+            out.resize(out.len() + padding, 0);
+        }
+
+        // Fonction pour extraire le premier enregistrement complet du tampon
+        pub fn parse(buffer: &mut Bytes) -> io::Result<Option<Record>> {
+            if buffer.len() < HEADER_LEN {
+                return Ok(None);
+            }
+            if buffer[0] != VERSION_1 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Unsupported FastCGI version",
+                ));
+            }
+            let len = u16::from_be_bytes([buffer[4], buffer[5]]) as usize;
+            let total = HEADER_LEN + len + buffer[6] as usize;
+            if buffer.len() < total {
+                return Ok(None);
+            }
+
+            let record = Record {
+                kind: buffer[1],
+                request_id: u16::from_be_bytes([buffer[2], buffer[3]]),
+                content: buffer[HEADER_LEN..HEADER_LEN + len].to_vec(),
+            };
+            buffer.drain(..total);
+            Ok(Some(record))
+        }
+    }
+
+    // Fonction pour créer l'enregistrement qui ouvre une requête
+    pub fn begin_request(request_id: u16, flags: u8) -> Record {
+        let mut content = RESPONDER.to_be_bytes().to_vec();
+        content.extend([flags, 0, 0, 0, 0, 0]);
+        Record::new(BEGIN_REQUEST, request_id, content)
+    }
+
+    // Fonction pour lire le statut de protocole d'un enregistrement `END_REQUEST`
+    pub fn end_request_status(content: &[u8]) -> Option<u8> {
+        content.get(4).copied()
+    }
+
+    // Fonction pour découper un flux en enregistrements, terminé par un enregistrement vide
+    pub fn encode_stream(kind: u8, request_id: u16, data: &[u8], out: &mut Bytes) {
+        for chunk in data.chunks(MAX_CONTENT_LEN) {
+            Record::new(kind, request_id, chunk.to_vec()).encode(out);
+        }
+        Record::new(kind, request_id, Bytes::new()).encode(out);
+    }
+
+    // Longueur d'un nom ou d'une valeur : un octet jusqu'à 127, quatre octets au-delà
+    fn encode_length(len: usize, out: &mut Bytes) {
+        if len < 0x80 {
+            out.push(len as u8);
+        } else {
+            out.extend((len as u32 | 0x8000_0000).to_be_bytes());
+        }
+    }
+
+    fn decode_length(content: &[u8], at: &mut usize) -> Option<usize> {
+        let first = *content.get(*at)?;
+        if first < 0x80 {
+            *at += 1;
+            return Some(first as usize);
+        }
+        let bytes = content.get(*at..*at + 4)?;
+        *at += 4;
+        Some((u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x7fff_ffff) as usize)
+    }
+
+    // Fonction pour encoder des paires nom-valeur (`PARAMS`, `GET_VALUES`)
+    pub fn encode_pairs<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Bytes {
+        let mut out = Bytes::new();
+        for (name, value) in pairs {
+            encode_length(name.len(), &mut out);
+            encode_length(value.len(), &mut out);
+            out.extend(name.as_bytes());
+            out.extend(value.as_bytes());
+        }
+        out
+    }
+
+    // Fonction pour décoder des paires nom-valeur, en s'arrêtant à la première incomplète
+    pub fn decode_pairs(content: &[u8]) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        let mut at = 0;
+        while at < content.len() {
+            let (Some(name_len), Some(value_len)) = (
+                decode_length(content, &mut at),
+                decode_length(content, &mut at),
+            ) else {
+                break;
+            };
+            let (Some(name), Some(value)) = (
+                content.get(at..at + name_len),
+                content.get(at + name_len..at + name_len + value_len),
+            ) else {
+                break;
+            };
+            at += name_len + value_len;
+            pairs.push((
+                String::from_utf8_lossy(name).to_string(),
+                String::from_utf8_lossy(value).to_string(),
+            ));
+        }
+        pairs
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn records_round_trip() {
+            let mut buffer = Bytes::new();
+            Record::new(STDOUT, 7, b"hello".to_vec()).encode(&mut buffer);
+            begin_request(7, KEEP_CONN).encode(&mut buffer);
+            assert_eq!(buffer.len(), 16 + 16);
+
+            let record = Record::parse(&mut buffer).unwrap().unwrap();
+            assert_eq!(record, Record::new(STDOUT, 7, b"hello".to_vec()));
+            let record = Record::parse(&mut buffer).unwrap().unwrap();
+            assert_eq!(record.kind, BEGIN_REQUEST);
+            assert_eq!(record.content, [0, 1, 1, 0, 0, 0, 0, 0]);
+            assert!(buffer.is_empty());
+
+            // Incomplete records wait for more bytes; other versions are rejected
+            let mut partial = vec![VERSION_1, STDOUT, 0, 1, 0, 4, 0, 0, b'a'];
+            assert_eq!(Record::parse(&mut partial).unwrap(), None);
+            let mut invalid = vec![2, STDOUT, 0, 1, 0, 0, 0, 0];
+            assert!(Record::parse(&mut invalid).is_err());
+        }
+
+        #[test]
+        fn streams_are_split_and_terminated() {
+            let mut out = Bytes::new();
+            encode_stream(STDIN, 1, &vec![b'x'; MAX_CONTENT_LEN + 1], &mut out);
+
+            let mut lengths = Vec::new();
+            while let Some(record) = Record::parse(&mut out).unwrap() {
+                lengths.push(record.content.len());
+            }
+            assert_eq!(lengths, [MAX_CONTENT_LEN, 1, 0]);
+        }
+
+        #[test]
+        fn name_value_pairs() {
+            let long = "v".repeat(300);
+            let pairs = encode_pairs([("SCRIPT_NAME", "/index.php"), ("LONG", long.as_str())]);
+            assert_eq!(&pairs[..2], [11, 10]);
+            assert_eq!(
+                decode_pairs(&pairs),
+                [
+                    ("SCRIPT_NAME".to_string(), "/index.php".to_string()),
+                    ("LONG".to_string(), long.clone())
+                ]
+            );
+            assert!(decode_pairs(&pairs[..pairs.len() - 1]).len() == 1);
+        }
+    }
+}
@@ -1,171 +1,876 @@
 use crate::log;
 use crate::log::*;
-use crate::server::errors::error;
+use crate::server::errors::{error, method_not_allowed};
 use crate::server::handle_method;
 use crate::server::path::add_root_to_path;
 use crate::server::redirections::redirect;
-use crate::server::safe::get;
+use crate::server::safe::{get, server_options};
 use crate::server::*;
+use http::header::{
+    CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, SEC_WEBSOCKET_VERSION, TRANSFER_ENCODING,
+};
+use http::{HeaderValue, Version};
+use mio::Registry;
 use serve::*;
 use std::path::Path;
+use std::time::Instant;
 
 const KB: usize = 1024;
 pub const BUFFER_SIZE: usize = KB;
 
-// Fonction principale pour gérer une connexion client
-pub fn handle_connection(stream: &mut TcpStream, config: &ServerConfig) -> io::Result<()> {
+/// # OUTPUT_LIMIT
+///
+/// Octets en attente d'envoi au-delà desquels une connexion cesse de produire sa réponse
+/// (sortie du script CGI, événements publiés, requêtes suivantes) jusqu'à ce que le client
+/// en ait reçu une partie.
+pub const OUTPUT_LIMIT: usize = 64 * KB;
+
+/// # MAX_LOCAL_REDIRECTS
+///
+/// Nombre maximal de redirections locales successives produites par des scripts CGI.
+const MAX_LOCAL_REDIRECTS: usize = 10;
+
+// Nombre de redirections locales déjà suivies pour une requête
+#[derive(Clone, Copy, Debug, Default)]
+struct LocalRedirects(usize);
+
+/// # Outcome
+///
+/// Suite à donner à une connexion après le traitement d'un événement. Pour la réponse d'un flux
+/// HTTP/2, `Close` termine seulement cette réponse.
+#[derive(Debug)]
+pub enum Outcome {
+    // La réponse est envoyée : fermer la connexion
+    Close,
+    // La réponse est envoyée : passer à la requête suivante de la connexion
+    KeepAlive,
+    // Attendre les prochains événements du script CGI en cours
+    Wait,
+    // Surveiller les tubes d'un nouveau script CGI
+    Cgi(Box<CgiProcess>),
+    // Transmettre la requête à un serveur FastCGI
+    FastCgi(Box<FastCgiRequest>),
+    // Surveiller la connexion d'une requête SCGI, uwsgi ou mandatée
+    Upstream(Box<UpstreamRequest>),
+    // La connexion est passée au protocole WebSocket
+    WebSocket(Box<WebSocket>),
+    // La connexion transmet les événements publiés par l'application
+    EventStream(Box<EventStream>),
+    // La connexion est passée à HTTP/2
+    Http2(Box<Http2Connection>),
+}
+
+// Réponse immédiate ou script CGI dont la sortie sera transmise au fil de l'eau
+enum Reply {
+    Response(Response<Bytes>),
+    Cgi(Box<CgiProcess>),
+    FastCgi(Box<FastCgiRequest>),
+    Upstream(Box<UpstreamRequest>),
+    WebSocket(Response<Bytes>, Box<WebSocket>),
+    EventStream(Response<Bytes>, Box<EventStream>),
+}
+
+impl From<Response<Bytes>> for Reply {
+    fn from(response: Response<Bytes>) -> Self {
+        Reply::Response(response)
+    }
+}
+
+// Requête HTTP/1 lue sur la connexion
+enum Parsed {
+    // Une requête complète : en-tête et corps tel qu'il a été transmis
+    Request(String, Bytes),
+    // La requête n'est pas encore arrivée en entier
+    Incomplete,
+    // Le client a fermé la connexion
+    Closed,
+    // La requête ne peut pas être délimitée : répondre par une erreur et fermer
+    Invalid(StatusCode),
+}
+
+/// # Sink
+///
+/// Destination d'une réponse produite au fil de l'eau (script CGI, serveur d'application,
+/// flux d'événements) : la connexion HTTP/1 elle-même, ou un flux d'une connexion HTTP/2 sur
+/// lequel la réponse part en trames.
+pub enum Sink<'a> {
+    Http1(&'a mut ClientStream),
+    Http2(&'a mut ClientStream, &'a mut Http2Connection, u32),
+}
+
+impl Sink<'_> {
+    // Octets de la réponse qui attendent que le client les reçoive
+    fn pending_output(&self) -> usize {
+        match self {
+            Sink::Http1(stream) => stream.pending_output(),
+            Sink::Http2(stream, connection, stream_id) => {
+                stream.pending_output() + connection.pending_data(*stream_id)
+            }
+        }
+    }
+
+    // Faux une fois la réponse du flux terminée, ou annulée par le client
+    pub fn is_open(&self) -> bool {
+        match self {
+            Sink::Http1(_) => true,
+            Sink::Http2(_, connection, stream_id) => connection.awaits_response(*stream_id),
+        }
+    }
+
+    // Fonction pour envoyer une partie de la réponse HTTP/1 écrite par une passerelle ; `done` à
+    // la fin de sa sortie
+    fn relay(&mut self, request: &Request<Bytes>, bytes: &[u8], done: bool) -> io::Result<()> {
+        match self {
+            Sink::Http1(stream) => send_bytes(stream, bytes),
+            Sink::Http2(stream, connection, stream_id) => {
+                let head_only = request.method() == Method::HEAD;
+                connection.relay(*stream_id, bytes, done, head_only);
+                send_bytes(stream, &connection.take_outgoing())
+            }
+        }
+    }
+
+    // Fonction pour envoyer une partie du corps de la réponse ; `end` à la fin du corps
+    fn send_body(&mut self, bytes: &[u8], end: bool) -> io::Result<()> {
+        match self {
+            Sink::Http1(stream) => send_bytes(stream, bytes),
+            Sink::Http2(stream, connection, stream_id) => {
+                connection.send_data(*stream_id, bytes, end);
+                send_bytes(stream, &connection.take_outgoing())
+            }
+        }
+    }
+
+    // Fonction pour répondre par une erreur, après laquelle la connexion HTTP/1 est fermée ; une
+    // réponse HTTP/2 déjà commencée est annulée
+    fn fail(&mut self, response: Response<Bytes>) -> io::Result<()> {
+        match self {
+            Sink::Http1(stream) => serve_response(stream, closing(response)),
+            Sink::Http2(stream, connection, stream_id) => {
+                connection.fail(*stream_id, response);
+                send_bytes(stream, &connection.take_outgoing())
+            }
+        }
+    }
+}
+
+// Fonction principale pour gérer une connexion client : les requêtes reçues à la suite sont
+// traitées l'une après l'autre, et leurs réponses envoyées dans le même ordre
+pub fn handle_connection(stream: &mut ClientStream, config: &ServerConfig) -> io::Result<Outcome> {
+    loop {
+        // Les requêtes suivantes attendent que le client reçoive les réponses précédentes
+        if stream.pending_output() >= OUTPUT_LIMIT {
+            return Ok(Outcome::Wait);
+        }
+        match handle_request(stream, config)? {
+            Outcome::KeepAlive => continue,
+            outcome => return Ok(outcome),
+        }
+    }
+}
+
+// Fonction pour répondre à la prochaine requête reçue sur la connexion
+fn handle_request(stream: &mut ClientStream, config: &ServerConfig) -> io::Result<Outcome> {
+    // Passer à HTTP/2 si le client l'a négocié par ALPN ou commence par la préface
+    match speaks_http2(stream) {
+        Some(true) => {
+            let connection = Http2Connection::new(config.body_size_limit);
+            return Ok(Outcome::Http2(Box::new(connection)));
+        }
+        Some(false) => {}
+        None => return Ok(Outcome::Wait),
+    }
+
     // Analyser la requête HTTP
-    let request_parts = unsafe { parse_http_request(stream) }.map_err(|_| io::Error::from_raw_os_error(35))?;
-    let request = get_request(config, request_parts.clone())
-        .map_err(|e| serve_response(stream, error(e, config)))
-        .unwrap_or_else(|_| Default::default());
+    let request_parts = match parse_http_request(stream, config)? {
+        Parsed::Request(head, body) => (head, body),
+        Parsed::Incomplete => return Ok(Outcome::Wait),
+        Parsed::Closed => return Ok(Outcome::Close),
+        Parsed::Invalid(code) => {
+            serve_response(stream, closing(failure(code, config)))?;
+            return Ok(Outcome::Close);
+        }
+    };
+    let mut request = match get_request(config, request_parts.clone()) {
+        Ok(request) => request,
+        Err(code) => {
+            serve_response(stream, closing(failure(code, config)))?;
+            return Ok(Outcome::Close);
+        }
+    };
+    add_connection_details(stream, &mut request);
+
+    // Le port HTTP d'un port HTTPS redirige toutes les requêtes
+    if let Some(port) = stream.https_redirect() {
+        serve_response(stream, closing(https_redirect(&request, port, config)))?;
+        return Ok(Outcome::Close);
+    }
+    if let Err(code) = check_client_certificate(&mut request, config) {
+        serve_response(stream, closing(failure(code, config)))?;
+        return Ok(Outcome::Close);
+    }
+
+    // Passer à HTTP/2 en clair si le client le demande : la réponse part sur le flux 1
+    let upgrade =
+        h2c_upgrade(&request).filter(|_| request.extensions().get::<TlsSession>().is_none());
+    if let Some(settings) = upgrade {
+        let limit = config.body_size_limit;
+        if let Some(connection) = Http2Connection::upgraded(&settings, request_parts.clone(), limit)
+        {
+            serve_response(stream, h2c_switching_protocols())?;
+            return Ok(Outcome::Http2(Box::new(connection)));
+        }
+    }
+
+    let reply = respond(&request, request_parts, config);
+    send_reply(Sink::Http1(stream), &request, reply)
+}
+
+// Fonction pour ajouter à la requête les adresses et la session TLS de la connexion
+fn add_connection_details(stream: &ClientStream, request: &mut Request<Bytes>) {
+    if let (Ok(remote), Ok(local)) = (stream.peer_addr(), stream.local_addr()) {
+        request.extensions_mut().insert(Addresses { remote, local });
+    }
+    if let Some(session) = stream.tls_session() {
+        request.extensions_mut().insert(session);
+    }
+}
+
+// Fonction pour savoir si le client parle HTTP/2 ; `None` tant que la préface est incomplète
+fn speaks_http2(stream: &ClientStream) -> Option<bool> {
+    if stream.https_redirect().is_some() {
+        return Some(false);
+    }
+    if let Some(protocol) = stream.alpn_protocol() {
+        return Some(protocol == b"h2");
+    }
+    // En clair, un client qui sait que le serveur parle HTTP/2 commence par la préface
+    let mut preface = [0; HTTP2_PREFACE.len()];
+    match stream.peek(&mut preface).unwrap_or(0) {
+        0 => Some(false),
+        n if preface[..n] != HTTP2_PREFACE[..n] => Some(false),
+        n if n < HTTP2_PREFACE.len() => None,
+        _ => Some(true),
+    }
+}
+
+// Fonction pour traiter les trames reçues sur une connexion HTTP/2 et répondre aux requêtes ;
+// les réponses produites au fil de l'eau sont ajoutées à `started` avec leur flux
+pub fn handle_http2_event(
+    stream: &mut ClientStream,
+    connection: &mut Http2Connection,
+    config: &ServerConfig,
+    started: &mut Vec<(u32, Outcome)>,
+) -> io::Result<Outcome> {
+    let (data, closed) = read_available(stream)?;
+    for Http2Request { stream_id, parts } in connection.receive(&data) {
+        let request = parts.and_then(|parts| Ok((get_request(config, parts.clone())?, parts)));
+        let (mut request, parts) = match request {
+            Ok(request) => request,
+            Err(code) => {
+                connection.send_response(stream_id, failure(code, config), false);
+                continue;
+            }
+        };
+        add_connection_details(stream, &mut request);
+        if let Err(code) = check_client_certificate(&mut request, config) {
+            connection.send_response(stream_id, failure(code, config), false);
+            continue;
+        }
+        let reply = respond(&request, parts, config);
+        match send_reply(Sink::Http2(stream, connection, stream_id), &request, reply)? {
+            Outcome::Close => {}
+            outcome => started.push((stream_id, outcome)),
+        }
+    }
+    send_bytes(stream, &connection.take_outgoing())?;
+    match closed || connection.is_finished() {
+        true => Ok(Outcome::Close),
+        false => Ok(Outcome::Wait),
+    }
+}
+
+// Fonction pour annoncer au client la fermeture d'une connexion HTTP/2 inactive
+pub fn handle_http2_timeout(
+    stream: &mut ClientStream,
+    connection: &mut Http2Connection,
+) -> io::Result<()> {
+    connection.go_away();
+    send_bytes(stream, &connection.take_outgoing())
+}
+
+// Fonction pour annuler un flux HTTP/2 dont la réponse ne sera pas terminée
+pub fn cancel_http2_stream(
+    stream: &mut ClientStream,
+    connection: &mut Http2Connection,
+    stream_id: u32,
+) -> io::Result<()> {
+    connection.cancel(stream_id);
+    send_bytes(stream, &connection.take_outgoing())
+}
+
+// Fonction pour traiter un événement sur les tubes du script CGI d'une connexion
+pub fn handle_cgi_event(
+    sink: Sink,
+    process: &mut CgiProcess,
+    registry: &Registry,
+    token: Token,
+    config: &ServerConfig,
+) -> io::Result<Outcome> {
+    // La sortie du script reste dans son tube tant que le client ne reçoit pas la précédente
+    let paused = sink.pending_output() >= OUTPUT_LIMIT;
+    let progress = process.handle_event(registry, token, paused, config);
+    relay_cgi_progress(sink, process, progress, config)
+}
+
+// Fonction pour reprendre la lecture de la sortie du script une fois le client rattrapé
+pub fn resume_cgi_output(
+    sink: Sink,
+    process: &mut CgiProcess,
+    registry: &Registry,
+    config: &ServerConfig,
+) -> Option<io::Result<Outcome>> {
+    if sink.pending_output() >= OUTPUT_LIMIT {
+        return None;
+    }
+    let progress = process.resume_output(registry, config)?;
+    Some(relay_cgi_progress(sink, process, progress, config))
+}
+
+fn relay_cgi_progress(
+    sink: Sink,
+    process: &CgiProcess,
+    progress: Result<CgiProgress, StatusCode>,
+    config: &ServerConfig,
+) -> io::Result<Outcome> {
+    let (request, head) = (process.request(), process.request_head());
+    relay_progress(
+        sink,
+        progress,
+        request,
+        head,
+        &process.script().name,
+        config,
+    )
+}
+
+// Fonction pour transmettre au client ce que le serveur FastCGI a envoyé pour sa requête
+pub fn handle_fastcgi_output(
+    sink: Sink,
+    request: &mut FastCgiRequest,
+    delivery: Delivery,
+    config: &ServerConfig,
+) -> io::Result<Outcome> {
+    let progress = request.receive(delivery, config);
+    let name = request.name();
+    relay_progress(
+        sink,
+        progress,
+        request.request(),
+        request.request_head(),
+        name,
+        config,
+    )
+}
+
+// Fonction pour relayer la réponse d'un serveur SCGI, uwsgi ou mandaté après un événement
+pub fn handle_upstream_event(
+    sink: Sink,
+    request: &mut UpstreamRequest,
+    config: &ServerConfig,
+) -> io::Result<Outcome> {
+    let progress = request.handle_event(config);
+    let name = request.name();
+    relay_progress(
+        sink,
+        progress,
+        request.request(),
+        request.request_head(),
+        name,
+        config,
+    )
+}
+
+// Fonction pour transmettre au serveur mandaté ce que le client envoie dans un tunnel
+pub fn handle_tunnel_input(
+    stream: &mut ClientStream,
+    request: &mut UpstreamRequest,
+    config: &ServerConfig,
+) -> io::Result<Outcome> {
+    let (data, closed) = read_available(stream)?;
+    request.forward(&data);
+    match handle_upstream_event(Sink::Http1(stream), request, config)? {
+        _ if closed => Ok(Outcome::Close),
+        outcome => Ok(outcome),
+    }
+}
+
+// Fonction pour traiter les trames reçues sur une connexion WebSocket et envoyer les réponses
+pub fn handle_websocket_event(
+    stream: &mut ClientStream,
+    socket: &mut WebSocket,
+) -> io::Result<Outcome> {
+    let (data, closed) = read_available(stream)?;
+    socket.receive(&data);
+    if closed {
+        socket.peer_closed();
+    }
+    send_bytes(stream, &socket.take_outgoing())?;
+    match closed || socket.is_finished() {
+        true => Ok(Outcome::Close),
+        false => Ok(Outcome::Wait),
+    }
+}
+
+// Fonction pour surveiller la connexion d'un flux d'événements, que le client peut fermer
+pub fn handle_event_stream_input(
+    stream: &mut ClientStream,
+    events: &mut EventStream,
+) -> io::Result<Outcome> {
+    // Le client n'a rien à envoyer : ce qu'il écrit est ignoré
+    match read_available(stream)? {
+        (_, true) => Ok(Outcome::Close),
+        // La connexion a pu redevenir prête à recevoir les événements retenus
+        (_, false) => flush_event_stream(Sink::Http1(stream), events),
+    }
+}
+
+// Fonction pour envoyer au client les événements publiés depuis le dernier envoi
+pub fn flush_event_stream(mut sink: Sink, events: &mut EventStream) -> io::Result<Outcome> {
+    // Les événements restent dans la file de l'application tant que le client est en retard
+    if sink.pending_output() >= OUTPUT_LIMIT {
+        return Ok(Outcome::Wait);
+    }
+    let (pending, closing) = events.take_pending(Instant::now());
+    sink.send_body(&pending, closing)?;
+    match closing || !sink.is_open() {
+        true => Ok(Outcome::Close),
+        false => Ok(Outcome::Wait),
+    }
+}
+
+// Fonction pour lire tout ce que le client a envoyé ; vrai s'il a fermé la connexion
+fn read_available(stream: &mut ClientStream) -> io::Result<(Bytes, bool)> {
+    let mut data = Bytes::new();
+    let mut chunk = [0; BUFFER_SIZE];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok((data, true)),
+            Ok(n) => data.extend(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok((data, false)),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Fonction pour envoyer la sortie d'un script au client, ou suivre sa redirection locale
+fn relay_progress(
+    mut sink: Sink,
+    progress: Result<CgiProgress, StatusCode>,
+    request: &Request<Bytes>,
+    request_head: &str,
+    name: &str,
+    config: &ServerConfig,
+) -> io::Result<Outcome> {
+    match progress {
+        Ok(CgiProgress::Running(bytes)) => {
+            sink.relay(request, &bytes, false)?;
+            // Le client a pu annuler le flux HTTP/2, ou la réponse à `HEAD` être complète
+            match sink.is_open() {
+                true => Ok(Outcome::Wait),
+                false => Ok(Outcome::Close),
+            }
+        }
+        Ok(CgiProgress::Done(bytes)) => {
+            sink.relay(request, &bytes, true)?;
+            Ok(Outcome::Close)
+        }
+        Ok(CgiProgress::LocalRedirect(location)) => {
+            let request_parts = (request_head.to_string(), Bytes::new());
+            let reply = local_redirect(request, request_parts, &location, config);
+            send_reply(sink, request, reply)
+        }
+        Err(code) => {
+            log!(
+                LogFileType::Server,
+                format!("Error: Invalid response from {}", name)
+            );
+            sink.fail(failure(code, config))?;
+            Ok(Outcome::Close)
+        }
+    }
+}
+
+// Fonction pour répondre à une connexion dont le script CGI a dépassé sa durée d'exécution
+pub fn handle_cgi_timeout(
+    sink: Sink,
+    process: &CgiProcess,
+    config: &ServerConfig,
+) -> io::Result<()> {
+    let name = &process.script().name;
+    gateway_timeout(sink, "CGI script", name, process.has_responded(), config)
+}
+
+// Fonction pour répondre à une connexion dont la requête FastCGI a dépassé sa durée
+pub fn handle_fastcgi_timeout(
+    sink: Sink,
+    request: &FastCgiRequest,
+    config: &ServerConfig,
+) -> io::Result<()> {
+    let responded = request.has_responded();
+    gateway_timeout(sink, "FastCGI request", request.name(), responded, config)
+}
+
+// Fonction pour répondre à une connexion dont la requête SCGI, uwsgi ou mandatée a dépassé sa durée
+pub fn handle_upstream_timeout(
+    sink: Sink,
+    request: &mut UpstreamRequest,
+    config: &ServerConfig,
+) -> io::Result<()> {
+    request.report(false);
+    let responded = request.has_responded();
+    gateway_timeout(sink, request.kind(), request.name(), responded, config)
+}
+
+fn gateway_timeout(
+    mut sink: Sink,
+    kind: &str,
+    name: &str,
+    has_responded: bool,
+    config: &ServerConfig,
+) -> io::Result<()> {
+    log!(
+        LogFileType::Server,
+        format!("Error: {} {} timed out", kind, name)
+    );
+    // Une réponse déjà commencée est interrompue par la fermeture de la connexion, ou
+    // l'annulation de son flux HTTP/2
+    if has_responded && matches!(sink, Sink::Http1(_)) {
+        return Ok(());
+    }
+    sink.fail(error(StatusCode::GATEWAY_TIMEOUT, config))
+}
+
+// Fonction pour envoyer la réponse, ou laisser la boucle d'événements suivre le script CGI
+fn send_reply(sink: Sink, request: &Request<Bytes>, reply: Reply) -> io::Result<Outcome> {
+    let stream = match sink {
+        Sink::Http1(stream) => stream,
+        Sink::Http2(stream, connection, stream_id) => {
+            return send_http2_reply(stream, connection, stream_id, request, reply);
+        }
+    };
+    match reply {
+        Reply::Response(mut response) => {
+            // Annoncer au client si la connexion reste ouverte après la réponse
+            let keep_alive = is_persistent(request, &response);
+            match (keep_alive, request.version()) {
+                (true, Version::HTTP_10) => {
+                    let value = HeaderValue::from_static("keep-alive");
+                    response.headers_mut().insert(CONNECTION, value);
+                }
+                (false, Version::HTTP_11) => response = closing(response),
+                _ => {}
+            }
+            // HEAD suit exactement le même traitement que GET : seul le corps est retiré à l'envoi
+            match request.method() == Method::HEAD {
+                true => serve_response(stream, without_body(response))?,
+                false => serve_response(stream, response)?,
+            }
+            if keep_alive {
+                return Ok(Outcome::KeepAlive);
+            }
+        }
+        Reply::Cgi(process) => return Ok(Outcome::Cgi(process)),
+        Reply::FastCgi(request) => return Ok(Outcome::FastCgi(request)),
+        Reply::Upstream(request) => return Ok(Outcome::Upstream(request)),
+        Reply::WebSocket(response, mut socket) => {
+            serve_response(stream, response)?;
+            socket.open();
+            // Les premières trames du client ont pu arriver avec la requête
+            return match handle_websocket_event(stream, &mut socket)? {
+                Outcome::Wait => Ok(Outcome::WebSocket(socket)),
+                outcome => Ok(outcome),
+            };
+        }
+        Reply::EventStream(response, mut events) => {
+            serve_response(stream, response)?;
+            // Envoyer les événements publiés par le gestionnaire lors de l'ouverture
+            return match flush_event_stream(Sink::Http1(stream), &mut events)? {
+                Outcome::Wait => Ok(Outcome::EventStream(events)),
+                outcome => Ok(outcome),
+            };
+        }
+    }
+    Ok(Outcome::Close)
+}
+
+// Fonction pour envoyer la réponse sur son flux HTTP/2, ou laisser la boucle d'événements suivre
+// ce qui la produit ; `Close` lorsque le flux n'attend plus rien
+fn send_http2_reply(
+    stream: &mut ClientStream,
+    connection: &mut Http2Connection,
+    stream_id: u32,
+    request: &Request<Bytes>,
+    reply: Reply,
+) -> io::Result<Outcome> {
+    match reply {
+        Reply::Response(response) => {
+            let head_only = request.method() == Method::HEAD;
+            connection.send_response(stream_id, response, head_only);
+        }
+        Reply::Cgi(process) => return Ok(Outcome::Cgi(process)),
+        Reply::FastCgi(request) => return Ok(Outcome::FastCgi(request)),
+        Reply::Upstream(request) => return Ok(Outcome::Upstream(request)),
+        // WebSocket sur HTTP/2 (RFC 8441) n'est pas pris en charge : le client refait la
+        // requête en HTTP/1.1
+        Reply::WebSocket(..) => connection.require_http1(stream_id),
+        Reply::EventStream(response, mut events) => {
+            connection.send_head(stream_id, &response);
+            let sink = Sink::Http2(stream, connection, stream_id);
+            return match flush_event_stream(sink, &mut events)? {
+                Outcome::Wait => Ok(Outcome::EventStream(events)),
+                outcome => Ok(outcome),
+            };
+        }
+    }
+    send_bytes(stream, &connection.take_outgoing())?;
+    Ok(Outcome::Close)
+}
+
+// Fonction pour savoir si la connexion reste ouverte après la réponse : le client doit l'accepter
+// et la fin de la réponse doit se déduire de ses en-têtes plutôt que de la fermeture
+fn is_persistent(request: &Request<Bytes>, response: &Response<Bytes>) -> bool {
+    let accepted = match request.version() {
+        Version::HTTP_11 => !has_token(request.headers(), CONNECTION, "close"),
+        Version::HTTP_10 => has_token(request.headers(), CONNECTION, "keep-alive"),
+        _ => false,
+    };
+    let headers = response.headers();
+    let status = response.status();
+    let delimited = request.method() == Method::HEAD
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || headers.contains_key(CONTENT_LENGTH)
+        // Un corps chunked vide est envoyé sans son dernier chunk
+        || (has_token(headers, TRANSFER_ENCODING, "chunked") && !response.body().is_empty());
+    accepted
+        && delimited
+        && !has_token(headers, CONNECTION, "close")
+        && response.extensions().get::<RawResponse>().is_none()
+}
+
+// Fonction pour produire la réponse à une requête, quel que soit le type de route
+fn respond(
+    request: &Request<Bytes>,
+    request_parts: (String, Bytes),
+    config: &ServerConfig,
+) -> Reply {
+    // Répondre à `OPTIONS *`, qui ne correspond à aucune route
+    if request.method() == Method::OPTIONS && request.uri() == "*" {
+        return server_options(request, config)
+            .unwrap_or_else(|code| failure(code, config))
+            .into();
+    }
 
     // Obtenir la route correspondant à la requête
-    let route = match get_route(&request, config) {
+    let route = match get_route(request, config) {
         Ok(route) => route,
 
         // Gérer les redirections
         Err((code, path)) if code.is_redirection() => {
-            return serve_response(stream, redirect(code, config, request.version(), path));
+            return redirect(code, config, request.version(), path).into();
         }
 
-        // Gérer les erreurs
-        Err((code, _)) => {
-            log!(LogFileType::Server, format!("Error: {}", &code));
-            return serve_response(stream, error(code, config));
+        // Indiquer les méthodes autorisées
+        Err((StatusCode::METHOD_NOT_ALLOWED, allow)) => {
+            log!(
+                LogFileType::Server,
+                format!("Error: {}", StatusCode::METHOD_NOT_ALLOWED)
+            );
+            return method_not_allowed(config, allow).into();
         }
+
+        // Gérer les erreurs
+        Err((code, _)) => return failure(code, config).into(),
     };
 
+    // Passer au protocole WebSocket si la route l'accepte
+    let websocket = route.settings.as_ref().and_then(|s| s.websocket);
+    if let Some(handler) = websocket.filter(|_| is_websocket_request(request)) {
+        return match websocket_handshake(request, handler, config) {
+            Ok((response, socket)) => Reply::WebSocket(response, Box::new(socket)),
+            Err(StatusCode::UPGRADE_REQUIRED) => {
+                let mut response = failure(StatusCode::UPGRADE_REQUIRED, config);
+                let version = HeaderValue::from_static("13");
+                response
+                    .headers_mut()
+                    .insert(SEC_WEBSOCKET_VERSION, version);
+                response.into()
+            }
+            Err(code) => failure(code, config).into(),
+        };
+    }
+
+    // Ouvrir un flux d'événements pour les requêtes GET de la route
+    let handler = route.settings.as_ref().and_then(|s| s.event_stream);
+    if let Some(handler) = handler.filter(|_| request.method() == Method::GET) {
+        return match event_stream(request, handler) {
+            Ok((response, events)) => Reply::EventStream(response, Box::new(events)),
+            Err(code) => failure(code, config).into(),
+        };
+    }
+
     // Utiliser le gestionnaire associé à la route
     if let Some(handler) = route.handler {
-        return match handler(&request, config) {
-            Ok(response) => serve_response(stream, response),
-            Err(code) => {
-                log!(LogFileType::Server, format!("Error: {}", &code));
-                serve_response(stream, error(code, config))
+        return handler(request, config)
+            .unwrap_or_else(|code| failure(code, config))
+            .into();
+    }
+
+    // Transmettre la requête au serveur d'application de la route
+    if let Some(gateway) = route.settings.as_ref().and_then(|s| s.gateway) {
+        let head = request_parts.0;
+        let upstream = match gateway {
+            Gateway::FastCgi(address) => {
+                let request = FastCgiRequest::prepare(&route, &address, request, head, config);
+                return Reply::FastCgi(Box::new(request));
+            }
+            Gateway::Scgi(address) => {
+                scgi_request(&route, Protocol::Scgi, &address, request, head, config)
             }
+            Gateway::Uwsgi(address) => {
+                scgi_request(&route, Protocol::Uwsgi, &address, request, head, config)
+            }
+            Gateway::Proxy(proxy) => proxy_request(&route, &proxy, request, head, config),
         };
+        return upstream
+            .map(|request| Reply::Upstream(Box::new(request)))
+            .unwrap_or_else(|code| failure(code, config).into());
     }
 
     let path = &add_root_to_path(&route, request.uri().path());
 
     // Vérifier si le chemin est un répertoire et si un fichier par défaut est spécifié
-    if Path::new(&path).is_dir() && route.settings.is_some() {
-        let settings = route.settings.as_ref().unwrap();
-
+    let is_dir_listing = Path::new(&path).is_dir() && !is_webdav_method(request.method());
+    if let Some(settings) = route.settings.as_ref().filter(|_| is_dir_listing) {
         // Servir le fichier par défaut si activé dans la configuration
         if let Some(default_file) = settings.default_if_url_is_dir {
             let default_path = &add_root_to_path(&route, default_file);
-            let new_head = replace_path_in_request(request_parts.0, request.uri().path(), default_path);
+            let new_head =
+                replace_path_in_request(request_parts.0, request.uri().path(), default_path);
             let request_parts = (new_head, request_parts.1);
-            let request = match get_request(config, request_parts) {
-                Ok(r) => r,
-                Err(code) => {
-                    log!(LogFileType::Server, code.to_string());
-                    return serve_response(stream, error(code, config));
-                }
-            };
-
-            return match get(&request, config) {
-                Ok(resp) => serve_response(stream, resp),
-                Err(e) => serve_response(stream, error(e, config)),
-            };
+            return get_request(config, request_parts)
+                .and_then(|request| get(&request, config))
+                .unwrap_or_else(|code| failure(code, config))
+                .into();
         }
 
         // Lister le contenu du répertoire si activé
         return if settings.list_directory {
-            serve_directory_contents(stream, path)
+            directory_contents(path)
+                .unwrap_or_else(|_| failure(StatusCode::NOT_FOUND, config))
+                .into()
         } else {
-            serve_response(stream, error(StatusCode::NOT_FOUND, config))
+            error(StatusCode::NOT_FOUND, config).into()
         };
     }
 
     // Vérifier si la requête est destinée à un script CGI
-    if is_cgi_request(path) {
-        return match execute_cgi_script(&request, config) {
-            Ok(resp) => serve_response(stream, resp),
-            Err(code) => {
-                log!(LogFileType::Server, format!("Error: {}", &code));
-                serve_response(stream, error(code, config))
-            }
-        };
+    if is_cgi_request(&route, request.uri().path()) {
+        return prepare_cgi_script(request, config)
+            .and_then(|prepared| CgiProcess::spawn(prepared, request, request_parts.0, config))
+            .map(|process| Reply::Cgi(Box::new(process)))
+            .unwrap_or_else(|code| failure(code, config).into());
     }
 
     // Gérer la méthode HTTP
-    match handle_method(&route, &request, config) {
-        Ok(response) => serve_response(stream, response),
-        Err(code) => {
-            log!(LogFileType::Server, format!("Error: {}", &code));
-            serve_response(stream, error(code, config))
-        }
-    }
+    handle_method(&route, request, config)
+        .unwrap_or_else(|code| failure(code, config))
+        .into()
 }
 
-// Fonction pour analyser une requête HTTP
-unsafe fn parse_http_request(stream: &mut TcpStream) -> Result<(String, Vec<u8>), u32> {
-    let mut buffer = [0; BUFFER_SIZE];
-    let mut head = String::new();
-    let mut body = Vec::new();
+// Fonction pour traiter la requête au chemin indiqué par une redirection locale CGI
+fn local_redirect(
+    request: &Request<Bytes>,
+    request_parts: (String, Bytes),
+    location: &str,
+    config: &ServerConfig,
+) -> Reply {
+    let redirects = request
+        .extensions()
+        .get::<LocalRedirects>()
+        .copied()
+        .unwrap_or_default();
+    if redirects.0 >= MAX_LOCAL_REDIRECTS {
+        return failure(StatusCode::INTERNAL_SERVER_ERROR, config).into();
+    }
 
-    // Lire l'en-tête et les premiers octets du corps
-    loop {
-        let bytes_read = stream.read(&mut buffer).map_err(|_| line!())?;
+    // La nouvelle requête est un GET sans corps vers le chemin indiqué par le script
+    let mut redirected = match Request::builder()
+        .method(Method::GET)
+        .uri(location)
+        .version(request.version())
+        .body(Bytes::new())
+    {
+        Ok(redirected) => redirected,
+        Err(_) => return failure(StatusCode::INTERNAL_SERVER_ERROR, config).into(),
+    };
+    *redirected.headers_mut() = request.headers().clone();
+    for header in [CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING] {
+        redirected.headers_mut().remove(header);
+    }
+    *redirected.extensions_mut() = request.extensions().clone();
+    redirected
+        .extensions_mut()
+        .insert(LocalRedirects(redirects.0 + 1));
+
+    // Remplacer la ligne de requête en conservant les en-têtes d'origine
+    let (_, headers) = request_parts.0.split_once("\r\n").unwrap_or_default();
+    let head = format!("GET {} {:?}\r\n{}", location, request.version(), headers);
+    respond(&redirected, (head, Bytes::new()), config)
+}
 
-        if bytes_read == 0 {
-            return Ok((head, body));
-        }
+// Fonction pour annoncer au client que la connexion sera fermée après la réponse
+fn closing(mut response: Response<Bytes>) -> Response<Bytes> {
+    let close = HeaderValue::from_static("close");
+    response.headers_mut().insert(CONNECTION, close);
+    response
+}
 
-        match String::from_utf8(buffer[..bytes_read].to_vec()) {
-            Ok(chunk) => {
-                if let Some(index) = chunk.find("\r\n\r\n") {
-                    // Séparer l'en-tête et le corps lorsque le double CRLF est trouvé
-                    head.push_str(&chunk[..index]);
-                    body.extend(&buffer[index + 4..bytes_read]);
-                    break;
-                } else {
-                    // Si aucun double CRLF trouvé, ajouter le morceau entier à l'en-tête
-                    head.push_str(&chunk);
-                }
-            }
-            Err(_) => {
-                let rest;
-                unsafe {
-                    rest = String::from_utf8_unchecked(buffer.to_vec());
-                }
-                let index = rest.find("\r\n\r\n").unwrap_or(0);
-                head.push_str(rest.split_at(index).0);
-                if index == 0 {
-                    body.extend(&buffer[index..bytes_read]);
-                } else {
-                    body.extend(&buffer[index + 4..bytes_read]);
-                }
-                break;
-            }
-        }
-        // Vider le buffer
-    }
+// Fonction pour journaliser une erreur et créer la réponse correspondante
+fn failure(code: StatusCode, config: &ServerConfig) -> Response<Bytes> {
+    log!(LogFileType::Server, format!("Error: {}", &code));
+    error(code, config)
+}
 
-    loop {
-        let bytes_read = match stream.read(&mut buffer) {
-            Ok(b) => b,
-            Err(_) => return Ok((head, body)),
-        };
-        body.extend(buffer);
-        if bytes_read < BUFFER_SIZE {
-            break;
+// Fonction pour lire la prochaine requête ; les octets qui la suivent sont conservés pour
+// les requêtes suivantes
+fn parse_http_request(stream: &mut ClientStream, config: &ServerConfig) -> io::Result<Parsed> {
+    let (data, closed) = read_available(stream)?;
+    match split_request(&data, config.body_size_limit) {
+        Ok(Some((head, body, used))) => {
+            stream.unread(&data[used..]);
+            Ok(Parsed::Request(head, body))
+        }
+        // Une requête interrompue par la fermeture ne reçoit pas de réponse
+        Ok(None) if closed => Ok(Parsed::Closed),
+        Ok(None) => {
+            stream.unread(&data);
+            Ok(Parsed::Incomplete)
         }
+        Err(code) => Ok(Parsed::Invalid(code)),
     }
-
-    Ok((head, body))
 }
 
 // Fonction pour remplacer le chemin dans une requête
 fn replace_path_in_request(head: String, path: &str, default_path: &str) -> String {
-    return if let Some(stripped_path) = path.strip_prefix('.') {
+    if let Some(stripped_path) = path.strip_prefix('.') {
         head.replacen(stripped_path, &default_path[1..], 1)
     } else {
         head.replacen(path, &default_path[1..], 1)
-    };
+    }
 }
 
 #[cfg(test)]
@@ -205,40 +910,26 @@ mod tests {
 
 mod serve {
     use crate::server::format_response;
+    use crate::server::ClientStream;
     use crate::type_aliases::Bytes;
-    use http::header::CONTENT_TYPE;
+    use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
     use http::{Response, StatusCode};
-    use mio::net::TcpStream;
-    use std::io::Write;
     use std::path::Path;
     use std::{fs, io};
 
     // Fonction pour envoyer une réponse au client
-    pub fn serve_response(stream: &mut TcpStream, response: Response<Bytes>) -> io::Result<()> {
-        let formatted_response = format_response(response.clone());
-        let total_size = formatted_response.len();
-        let mut written_size = 0;
-
-        while written_size < total_size {
-            match stream.write(&formatted_response[written_size..]) {
-                Ok(0) => {
-                    break; // Plus de données à écrire
-                }
-                Ok(n) => {
-                    written_size += n;
-                }
-                Err(e) if e.kind() != io::ErrorKind::WouldBlock => {
-                    return Err(e); // Erreur n'est pas WouldBlock, retourner l'erreur.
-                }
-                _ => {} // Événement est maintenant bloquant, réessayer plus tard.
-            }
-        }
+    pub fn serve_response(stream: &mut ClientStream, response: Response<Bytes>) -> io::Result<()> {
+        send_bytes(stream, &format_response(response))
+    }
 
-        stream.flush()
+    // Fonction pour écrire des octets sur la connexion ; ceux qu'elle n'accepte pas encore sont
+    // envoyés par la boucle d'événements quand elle redevient prête
+    pub fn send_bytes(stream: &mut ClientStream, formatted_response: &[u8]) -> io::Result<()> {
+        stream.send(formatted_response)
     }
 
-    // Fonction pour servir le contenu d'un répertoire
-    pub fn serve_directory_contents(stream: &mut TcpStream, path: &str) -> io::Result<()> {
+    // Fonction pour construire la page listant le contenu d'un répertoire
+    pub fn directory_contents(path: &str) -> io::Result<Response<Bytes>> {
         // S'assurer que le chemin ne se termine pas par un slash
         let trimmed_path = path.trim_end_matches('/');
 
@@ -268,12 +959,11 @@ mod serve {
             })
         );
 
-        let response = Response::builder()
+        Response::builder()
             .status(StatusCode::OK)
             .header(CONTENT_TYPE, "text/html")
+            .header(CONTENT_LENGTH, body.len())
             .body(Bytes::from(body))
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Could not build response"))?;
-
-        serve_response(stream, response)
+            .map_err(|_| io::Error::other("Could not build response"))
     }
 }
@@ -0,0 +1,738 @@
+use crate::type_aliases::Bytes;
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+/// # HEADER_TABLE_SIZE
+///
+/// Taille de la table dynamique du décodeur : la valeur par défaut de HTTP/2, que le
+/// serveur n'augmente pas.
+pub const HEADER_TABLE_SIZE: usize = 4096;
+
+/// # HeaderField
+///
+/// Nom et valeur d'un en-tête, tels qu'ils circulent dans un bloc HPACK.
+pub type HeaderField = (Bytes, Bytes);
+
+/// # CompressionError
+///
+/// Bloc d'en-têtes impossible à décoder ; la connexion HTTP/2 ne peut plus être utilisée.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressionError;
+
+// Table statique (RFC 7541, annexe A)
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// # HpackDecoder
+///
+/// Décodeur des blocs d'en-têtes reçus sur une connexion : il tient la table dynamique
+/// que le client alimente d'un bloc à l'autre.
+#[derive(Debug)]
+pub struct HpackDecoder {
+    entries: VecDeque<HeaderField>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for HpackDecoder {
+    fn default() -> Self {
+        HpackDecoder {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: HEADER_TABLE_SIZE,
+        }
+    }
+}
+
+impl HpackDecoder {
+    // Fonction pour décoder un bloc d'en-têtes complet ; `None` si la liste dépasse
+    // `max_list_size`, comptée comme SETTINGS_MAX_HEADER_LIST_SIZE (RFC 7540, section 6.5.2).
+    // Le bloc est alors décodé jusqu'au bout sans garder ses en-têtes, pour que la table
+    // dynamique reste celle du client.
+    pub fn decode(
+        &mut self,
+        block: &[u8],
+        max_list_size: usize,
+    ) -> Result<Option<Vec<HeaderField>>, CompressionError> {
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        let mut pos = 0;
+        while let Some(&first) = block.get(pos) {
+            let field = if first & 0x80 != 0 {
+                // Champ indexé, copié seulement s'il est gardé
+                let index = integer(block, &mut pos, 7)?;
+                let (name, value) = self.field(index)?;
+                list_size += name.len() + value.len() + 32;
+                if list_size <= max_list_size {
+                    fields.push((name.to_vec(), value.to_vec()));
+                }
+                continue;
+            } else if first & 0x40 != 0 {
+                // Littéral ajouté à la table dynamique
+                let field = self.literal(block, &mut pos, 6)?;
+                self.insert(field.clone());
+                field
+            } else if first & 0x20 != 0 {
+                // Changement de taille de la table, seulement en tête de bloc
+                let size = integer(block, &mut pos, 5)?;
+                if list_size > 0 || size > HEADER_TABLE_SIZE {
+                    return Err(CompressionError);
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // Littéral sans indexation, ou jamais indexé
+                self.literal(block, &mut pos, 4)?
+            };
+            list_size += field.0.len() + field.1.len() + 32;
+            if list_size <= max_list_size {
+                fields.push(field);
+            }
+        }
+        Ok((list_size <= max_list_size).then_some(fields))
+    }
+
+    fn field(&self, index: usize) -> Result<(&[u8], &[u8]), CompressionError> {
+        match index {
+            0 => Err(CompressionError),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes(), value.as_bytes()))
+            }
+            _ => self
+                .entries
+                .get(index - 62)
+                .map(|(name, value)| (name.as_slice(), value.as_slice()))
+                .ok_or(CompressionError),
+        }
+    }
+
+    fn literal(
+        &self,
+        block: &[u8],
+        pos: &mut usize,
+        prefix: u8,
+    ) -> Result<HeaderField, CompressionError> {
+        let name = match integer(block, pos, prefix)? {
+            0 => string(block, pos)?,
+            index => self.field(index)?.0.to_vec(),
+        };
+        Ok((name, string(block, pos)?))
+    }
+
+    fn insert(&mut self, field: HeaderField) {
+        let size = field.0.len() + field.1.len() + 32;
+        self.evict(size);
+        // Une entrée plus grande que la table la vide sans y être ajoutée
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front(field);
+        }
+    }
+
+    // Fonction pour retirer les entrées les plus anciennes jusqu'à libérer la place demandée
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.entries.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + 32;
+        }
+    }
+}
+
+// Fonction pour ajouter un en-tête à un bloc, sans l'indexer : le serveur n'utilise pas
+// de table dynamique pour ses réponses
+pub fn encode_header(block: &mut Bytes, name: &[u8], value: &[u8]) {
+    let position = |exact: bool| {
+        STATIC_TABLE
+            .iter()
+            .position(|&(n, v)| n.as_bytes() == name && (!exact || v.as_bytes() == value))
+    };
+    if let Some(index) = position(true) {
+        encode_integer(block, 0x80, 7, index + 1);
+        return;
+    }
+    match position(false) {
+        Some(index) => encode_integer(block, 0x00, 4, index + 1),
+        None => {
+            block.push(0x00);
+            encode_string(block, name);
+        }
+    }
+    encode_string(block, value);
+}
+
+// Fonction pour lire un entier dont les premiers bits partagent l'octet du type de champ
+fn integer(block: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, CompressionError> {
+    let mask = (1 << prefix) - 1;
+    let first = *block.get(*pos).ok_or(CompressionError)?;
+    *pos += 1;
+    let mut value = (first & mask) as usize;
+    if value < mask as usize {
+        return Ok(value);
+    }
+    for shift in (0..28).step_by(7) {
+        let byte = *block.get(*pos).ok_or(CompressionError)?;
+        *pos += 1;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(CompressionError)
+}
+
+fn encode_integer(block: &mut Bytes, flags: u8, prefix: u8, value: usize) {
+    let mask = (1 << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | mask as u8);
+    let mut rest = value - mask;
+    while rest >= 0x80 {
+        block.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    block.push(rest as u8);
+}
+
+// Fonction pour lire une chaîne, éventuellement compressée par le code de Huffman
+fn string(block: &[u8], pos: &mut usize) -> Result<Bytes, CompressionError> {
+    let huffman = block.get(*pos).ok_or(CompressionError)? & 0x80 != 0;
+    let len = integer(block, pos, 7)?;
+    let data = block.get(*pos..*pos + len).ok_or(CompressionError)?;
+    *pos += len;
+    match huffman {
+        true => huffman_decode(data),
+        false => Ok(data.to_vec()),
+    }
+}
+
+// Fonction pour écrire une chaîne, compressée seulement si elle y gagne
+fn encode_string(block: &mut Bytes, data: &[u8]) {
+    let bits: usize = data
+        .iter()
+        .map(|&byte| HUFFMAN_CODES[byte as usize].1 as usize)
+        .sum();
+    let compressed = bits.div_ceil(8);
+    if compressed < data.len() {
+        encode_integer(block, 0x80, 7, compressed);
+        huffman_encode(block, data);
+    } else {
+        encode_integer(block, 0x00, 7, data.len());
+        block.extend(data);
+    }
+}
+
+fn huffman_encode(block: &mut Bytes, data: &[u8]) {
+    let (mut bits, mut pending) = (0u64, 0u32);
+    for &byte in data {
+        let (code, len) = HUFFMAN_CODES[byte as usize];
+        bits = (bits << len) | code as u64;
+        pending += len as u32;
+        while pending >= 8 {
+            pending -= 8;
+            block.push((bits >> pending) as u8);
+        }
+        bits &= (1 << pending) - 1;
+    }
+    // Compléter le dernier octet avec le début du symbole EOS
+    if pending > 0 {
+        block.push(((bits << (8 - pending)) as u8) | (0xff >> pending));
+    }
+}
+
+// Marque des feuilles de l'arbre de décodage
+const LEAF: u16 = 0x8000;
+const EOS: u16 = 256;
+
+// Arbre binaire du code de Huffman : les deux suivants de chaque nœud, ou un symbole
+fn huffman_tree() -> &'static [[u16; 2]] {
+    static TREE: OnceLock<Vec<[u16; 2]>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut tree = vec![[0u16; 2]];
+        for (symbol, &(code, len)) in HUFFMAN_CODES.iter().enumerate() {
+            let mut node = 0;
+            for shift in (0..len).rev() {
+                let bit = ((code >> shift) & 1) as usize;
+                if shift == 0 {
+                    tree[node][bit] = LEAF | symbol as u16;
+                    break;
+                }
+                if tree[node][bit] == 0 {
+                    tree.push([0; 2]);
+                    tree[node][bit] = (tree.len() - 1) as u16;
+                }
+                node = tree[node][bit] as usize;
+            }
+        }
+        tree
+    })
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Bytes, CompressionError> {
+    let tree = huffman_tree();
+    let mut out = Bytes::new();
+    let (mut node, mut depth, mut ones) = (0, 0, true);
+    for &byte in data {
+        for shift in (0..8).rev() {
+            let bit = (byte >> shift) & 1;
+            depth += 1;
+            ones &= bit == 1;
+            let next = tree[node][bit as usize];
+            if next & LEAF == 0 {
+                node = next as usize;
+                continue;
+            }
+            if next & !LEAF == EOS {
+                return Err(CompressionError);
+            }
+            out.push((next & !LEAF) as u8);
+            (node, depth, ones) = (0, 0, true);
+        }
+    }
+    // Le remplissage fait moins d'un octet et ne contient que des 1
+    if depth > 7 || !ones {
+        return Err(CompressionError);
+    }
+    Ok(out)
+}
+
+// Code de Huffman de chaque octet, puis du symbole EOS (RFC 7541, annexe B)
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: usize = 64 * 1024;
+
+    fn hex(text: &str) -> Bytes {
+        let digits: Vec<u8> = text.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<HeaderField> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn rfc_examples_with_huffman() {
+        // RFC 7541, annexe C.4 : trois requêtes successives partageant la table dynamique
+        let mut decoder = HpackDecoder::default();
+        let first = hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff");
+        assert_eq!(
+            decoder.decode(&first, LIMIT),
+            Ok(Some(fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])))
+        );
+        let second = hex("8286 84be 5886 a8eb 1064 9cbf");
+        assert_eq!(
+            decoder.decode(&second, LIMIT).unwrap().unwrap()[4],
+            fields(&[("cache-control", "no-cache")])[0]
+        );
+        let third = hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf");
+        assert_eq!(
+            decoder.decode(&third, LIMIT),
+            Ok(Some(fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])))
+        );
+        assert_eq!(decoder.size, 164);
+    }
+
+    #[test]
+    fn table_size_updates_evict_entries() {
+        let mut decoder = HpackDecoder::default();
+        let literal = hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572");
+        decoder.decode(&literal, LIMIT).unwrap();
+        assert_eq!(decoder.decode(&hex("be"), LIMIT).unwrap().unwrap().len(), 1);
+
+        // Réduire la table à zéro la vide ; l'entrée n'est plus accessible
+        assert_eq!(decoder.decode(&hex("20"), LIMIT), Ok(Some(vec![])));
+        assert_eq!(decoder.decode(&hex("be"), LIMIT), Err(CompressionError));
+        assert_eq!(
+            decoder.decode(&hex("3fe2 1f"), LIMIT),
+            Err(CompressionError)
+        );
+        assert_eq!(decoder.decode(&hex("82 20"), LIMIT), Err(CompressionError));
+    }
+
+    #[test]
+    fn header_lists_over_the_limit_are_dropped() {
+        let mut decoder = HpackDecoder::default();
+        // Un littéral indexé de 132 octets, puis dix références à cette entrée
+        let mut block = hex("4001 6163");
+        block.extend([b'v'; 99]);
+        block.extend([0xbe; 10]);
+
+        assert_eq!(decoder.decode(&block, 11 * 132 - 1), Ok(None));
+        // La table dynamique a tout de même reçu l'entrée
+        assert_eq!(decoder.decode(&hex("be"), 132).unwrap().unwrap().len(), 1);
+        assert_eq!(decoder.decode(&block, 11 * 132).unwrap().unwrap().len(), 11);
+    }
+
+    #[test]
+    fn encoded_headers_are_decoded() {
+        let mut block = Bytes::new();
+        encode_header(&mut block, b":status", b"200");
+        encode_header(&mut block, b":status", b"418");
+        encode_header(&mut block, b"content-type", b"text/html; charset=utf-8");
+        encode_header(&mut block, b"x-request-id", &[b'a'; 300]);
+        encode_header(&mut block, b"x-raw", &[0, 255, 10]);
+        assert_eq!(block[0], 0x88);
+
+        let decoded = HpackDecoder::default()
+            .decode(&block, LIMIT)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded[1], fields(&[(":status", "418")])[0]);
+        assert_eq!(decoded[2].1, b"text/html; charset=utf-8");
+        assert_eq!(decoded[3].1, vec![b'a'; 300]);
+        assert_eq!(decoded[4], (b"x-raw".to_vec(), vec![0, 255, 10]));
+    }
+
+    #[test]
+    fn invalid_huffman_padding() {
+        // Remplissage de plus de sept bits, puis remplissage avec un 0
+        assert_eq!(huffman_decode(&[0xff, 0xff]), Err(CompressionError));
+        assert_eq!(huffman_decode(&[0x1e]), Err(CompressionError));
+        assert_eq!(huffman_decode(&[0x1f]), Ok(b"a".to_vec()));
+    }
+}
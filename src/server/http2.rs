@@ -0,0 +1,1143 @@
+use crate::server::{
+    encode_header, header_end, parse_headers, raw_header_values, Bytes, HpackDecoder, StatusCode,
+    MAX_HEAD_SIZE,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use http::header::{CONNECTION, TRANSFER_ENCODING, UPGRADE};
+use http::{HeaderMap, HeaderName, Request, Response, Version};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// # HTTP2_PREFACE
+///
+/// Préface envoyée par le client au début d'une connexion HTTP/2 (RFC 9113, section 3.4).
+pub const HTTP2_PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// # HTTP2_IDLE_TIMEOUT
+///
+/// Durée au-delà de laquelle une connexion HTTP/2 sans requête en cours est fermée.
+pub const HTTP2_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Types de trames
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Drapeaux
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// Codes d'erreur
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const CANCEL: u32 = 0x8;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+const HTTP_1_1_REQUIRED: u32 = 0xd;
+
+// Paramètres de `SETTINGS`
+const ENABLE_PUSH: u16 = 0x2;
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// Fenêtre de contrôle de flux initiale, et plus grande fenêtre autorisée
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+// Taille maximale des trames reçues, et par défaut des trames envoyées
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+
+// Nombre de requêtes qu'un client peut avoir en cours sur une connexion
+const MAX_STREAMS: usize = 100;
+
+// Taille maximale d'un bloc d'en-têtes réparti sur plusieurs trames
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+// En-têtes propres à une connexion HTTP/1, interdits en HTTP/2
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// # Http2Request
+///
+/// Requête complète reçue sur un flux, sous la forme lue sur une connexion HTTP/1 (en-tête
+/// et corps, pour `get_request`), ou l'erreur à renvoyer au client sur ce flux.
+#[derive(Debug)]
+pub struct Http2Request {
+    pub stream_id: u32,
+    pub parts: Result<(String, Bytes), StatusCode>,
+}
+
+// Flux d'une connexion, de la réception de la requête à l'envoi de la fin de sa réponse
+#[derive(Debug, Default)]
+struct Stream {
+    head: String,
+    body: Bytes,
+    remote_closed: bool, // Le client a fini d'envoyer sa requête
+    dispatched: bool,    // La requête a été transmise pour être traitée
+    send_window: i64,
+    receive_window: i64,
+    pending: Bytes,     // Corps de la réponse en attente de crédit
+    headers_sent: bool, // Les en-têtes de la réponse sont envoyés
+    ended: bool,        // La fin de la réponse est en file : le flux se termine avec `pending`
+    relayed: Bytes,     // Sortie d'une passerelle pas encore transmise
+    chunked: bool,      // Le corps relayé est découpé en chunks
+}
+
+/// # Http2Connection
+///
+/// Connexion HTTP/2 établie avec un client : les trames reçues sont validées, les en-têtes
+/// décodés et les requêtes complètes remises au serveur ; les réponses sont découpées en
+/// trames dans la limite des fenêtres de contrôle de flux annoncées par le client.
+#[derive(Debug)]
+pub struct Http2Connection {
+    max_body_size: usize,
+    buffer: Bytes,
+    outgoing: Bytes,
+    preface_received: bool,
+    settings_received: bool,
+    decoder: HpackDecoder,
+    streams: BTreeMap<u32, Stream>,
+    last_stream_id: u32,
+    header_block: Option<(u32, u8, Bytes)>, // Flux, drapeaux et bloc en attente de CONTINUATION
+    send_window: i64,
+    receive_window: i64,
+    initial_window: i64, // Fenêtre initiale des flux, fixée par le client
+    max_frame_size: usize,
+    requests: Vec<Http2Request>,
+    go_away_sent: bool,
+    go_away_received: bool,
+    headers_refused: bool, // Un bloc d'en-têtes trop grand a déjà reçu une réponse 431
+}
+
+impl Http2Connection {
+    pub fn new(max_body_size: usize) -> Self {
+        let mut connection = Http2Connection {
+            max_body_size,
+            buffer: Bytes::new(),
+            outgoing: Bytes::new(),
+            preface_received: false,
+            settings_received: false,
+            decoder: HpackDecoder::default(),
+            streams: BTreeMap::new(),
+            last_stream_id: 0,
+            header_block: None,
+            send_window: DEFAULT_WINDOW,
+            receive_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: DEFAULT_FRAME_SIZE,
+            requests: Vec::new(),
+            go_away_sent: false,
+            go_away_received: false,
+            headers_refused: false,
+        };
+
+        // La préface du serveur : ses paramètres, avant toute autre trame
+        let mut settings = Bytes::new();
+        for (id, value) in [
+            (MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32),
+            (ENABLE_PUSH, 0),
+            // Les en-têtes d'une requête sont limités comme en HTTP/1
+            (MAX_HEADER_LIST_SIZE, MAX_HEAD_SIZE as u32),
+        ] {
+            settings.extend(id.to_be_bytes());
+            settings.extend(value.to_be_bytes());
+        }
+        connection.frame(SETTINGS, 0, 0, &settings);
+        connection
+    }
+
+    // Fonction pour reprendre une requête HTTP/1.1 passée à HTTP/2 (`Upgrade: h2c`) : elle est
+    // remise avec les requêtes suivantes, et sa réponse sera envoyée sur le flux 1
+    pub fn upgraded(
+        settings: &[u8],
+        request_parts: (String, Bytes),
+        max_body_size: usize,
+    ) -> Option<Self> {
+        let mut connection = Http2Connection::new(max_body_size);
+        if !settings.len().is_multiple_of(6) || connection.apply_settings(settings).is_err() {
+            return None;
+        }
+        let stream = Stream {
+            remote_closed: true,
+            dispatched: true,
+            send_window: connection.initial_window,
+            receive_window: DEFAULT_WINDOW,
+            ..Stream::default()
+        };
+        connection.streams.insert(1, stream);
+        connection.last_stream_id = 1;
+        connection.requests.push(Http2Request {
+            stream_id: 1,
+            parts: Ok(request_parts),
+        });
+        Some(connection)
+    }
+
+    // Fonction pour traiter les octets reçus du client ; renvoie les requêtes complètes
+    pub fn receive(&mut self, data: &[u8]) -> Vec<Http2Request> {
+        self.buffer.extend(data);
+        if !self.preface_received {
+            let len = self.buffer.len().min(HTTP2_PREFACE.len());
+            if self.buffer[..len] != HTTP2_PREFACE[..len] {
+                self.send_go_away(PROTOCOL_ERROR);
+            } else if len == HTTP2_PREFACE.len() {
+                self.buffer.drain(..len);
+                self.preface_received = true;
+            }
+        }
+
+        while self.preface_received && !self.go_away_sent && self.buffer.len() >= 9 {
+            let len = u32::from_be_bytes([0, self.buffer[0], self.buffer[1], self.buffer[2]]);
+            let len = len as usize;
+            if len > DEFAULT_FRAME_SIZE {
+                self.send_go_away(FRAME_SIZE_ERROR);
+                break;
+            }
+            if self.buffer.len() < 9 + len {
+                break;
+            }
+            let frame: Bytes = self.buffer.drain(..9 + len).collect();
+            let stream_id = u32::from_be_bytes([frame[5], frame[6], frame[7], frame[8]]);
+            self.dispatch(frame[3], frame[4], stream_id & 0x7fff_ffff, &frame[9..]);
+        }
+        std::mem::take(&mut self.requests)
+    }
+
+    // Fonction pour envoyer la réponse à la requête d'un flux ; sans corps pour `HEAD`
+    pub fn send_response(&mut self, stream_id: u32, response: Response<Bytes>, head_only: bool) {
+        let (head, body) = response.into_parts();
+        let body = if head_only { Bytes::new() } else { body };
+        self.send_fields(stream_id, head.status, &head.headers, body.is_empty());
+        self.send_data(stream_id, &body, true);
+    }
+
+    // Fonction pour envoyer les en-têtes d'une réponse dont le corps suivra au fil de l'eau
+    pub fn send_head(&mut self, stream_id: u32, response: &Response<Bytes>) {
+        self.send_fields(stream_id, response.status(), response.headers(), false);
+    }
+
+    // Fonction pour envoyer une partie du corps d'une réponse ; `end_stream` à la fin du corps
+    pub fn send_data(&mut self, stream_id: u32, data: &[u8], end_stream: bool) {
+        // Le flux a pu être annulé par le client pendant le traitement
+        let Some(stream) = self.streams.get_mut(&stream_id).filter(|s| !s.ended) else {
+            return;
+        };
+        stream.pending.extend(data);
+        stream.ended = end_stream;
+        match stream.pending.is_empty() {
+            true if end_stream => {
+                self.frame(DATA, END_STREAM, stream_id, &[]);
+                self.finish(stream_id);
+            }
+            true => {}
+            false => self.flush(),
+        }
+    }
+
+    // Fonction pour transmettre sur un flux la réponse HTTP/1 produite par une passerelle
+    // (script CGI, serveur d'application) : son en-tête devient un bloc HEADERS, et son corps,
+    // décodé s'il est découpé en chunks, des trames DATA ; `done` à la fin de sa sortie
+    pub fn relay(&mut self, stream_id: u32, output: &[u8], done: bool, head_only: bool) {
+        let Some(stream) = self.streams.get_mut(&stream_id).filter(|s| !s.ended) else {
+            return;
+        };
+        stream.relayed.extend(output);
+        if !stream.headers_sent {
+            let head = match take_response_head(&mut stream.relayed) {
+                Ok(Some(head)) => head,
+                Ok(None) if !done => return,
+                _ => return self.reset(stream_id, INTERNAL_ERROR),
+            };
+            let (status, headers) = head;
+            stream.chunked = headers
+                .get_all(TRANSFER_ENCODING)
+                .iter()
+                .any(|value| value.as_bytes().eq_ignore_ascii_case(b"chunked"));
+            self.send_fields(stream_id, status, &headers, head_only);
+            if head_only {
+                return;
+            }
+        }
+
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        let (body, finished) = match stream.chunked {
+            true => match take_chunks(&mut stream.relayed) {
+                Some(chunks) => chunks,
+                None => return self.reset(stream_id, INTERNAL_ERROR),
+            },
+            false => (std::mem::take(&mut stream.relayed), false),
+        };
+        // Un corps découpé en chunks doit se terminer par le dernier chunk
+        if done && stream.chunked && !finished {
+            return self.reset(stream_id, INTERNAL_ERROR);
+        }
+        self.send_data(stream_id, &body, done || finished);
+    }
+
+    // Fonction pour répondre par une erreur sur un flux ; une réponse déjà commencée est annulée
+    pub fn fail(&mut self, stream_id: u32, response: Response<Bytes>) {
+        match self
+            .streams
+            .get(&stream_id)
+            .map(|stream| stream.headers_sent)
+        {
+            Some(false) => self.send_response(stream_id, response, false),
+            Some(true) => self.reset(stream_id, INTERNAL_ERROR),
+            None => {}
+        }
+    }
+
+    // Fonction pour annuler un flux dont la réponse ne sera pas terminée
+    pub fn cancel(&mut self, stream_id: u32) {
+        if self.awaits_response(stream_id) {
+            self.reset(stream_id, CANCEL);
+        }
+    }
+
+    // Vrai tant que la fin de la réponse d'un flux reste à envoyer, et que le client ne l'a pas annulé
+    pub fn awaits_response(&self, stream_id: u32) -> bool {
+        self.streams
+            .get(&stream_id)
+            .is_some_and(|stream| !stream.ended)
+    }
+
+    // Octets du corps d'une réponse en attente de crédit
+    pub fn pending_data(&self, stream_id: u32) -> usize {
+        self.streams
+            .get(&stream_id)
+            .map_or(0, |stream| stream.pending.len())
+    }
+
+    // Fonction pour demander au client de refaire la requête d'un flux en HTTP/1.1
+    pub fn require_http1(&mut self, stream_id: u32) {
+        self.reset(stream_id, HTTP_1_1_REQUIRED);
+    }
+
+    // Fonction pour annoncer la fermeture de la connexion au client
+    pub fn go_away(&mut self) {
+        self.send_go_away(NO_ERROR);
+    }
+
+    // Vrai lorsque la connexion peut être fermée
+    pub fn is_finished(&self) -> bool {
+        self.go_away_sent || (self.go_away_received && self.streams.is_empty())
+    }
+
+    // Fonction pour récupérer les trames à écrire sur la connexion
+    pub fn take_outgoing(&mut self) -> Bytes {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    fn dispatch(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+        // Un bloc d'en-têtes commencé doit être terminé avant toute autre trame
+        let continued = self.header_block.as_ref().map(|(id, _, _)| *id);
+        if continued.is_some_and(|id| kind != CONTINUATION || id != stream_id) {
+            return self.send_go_away(PROTOCOL_ERROR);
+        }
+        // La préface du client se termine par ses paramètres
+        if !self.settings_received && (kind != SETTINGS || flags & ACK != 0) {
+            return self.send_go_away(PROTOCOL_ERROR);
+        }
+
+        match kind {
+            DATA => self.on_data(flags, stream_id, payload),
+            HEADERS => self.on_headers(flags, stream_id, payload),
+            PRIORITY if stream_id == 0 => self.send_go_away(PROTOCOL_ERROR),
+            PRIORITY if payload.len() != 5 => self.reset(stream_id, FRAME_SIZE_ERROR),
+            RST_STREAM if stream_id == 0 || stream_id > self.last_stream_id => {
+                self.send_go_away(PROTOCOL_ERROR)
+            }
+            RST_STREAM if payload.len() != 4 => self.send_go_away(FRAME_SIZE_ERROR),
+            RST_STREAM => {
+                self.streams.remove(&stream_id);
+            }
+            SETTINGS => self.on_settings(flags, stream_id, payload),
+            PUSH_PROMISE => self.send_go_away(PROTOCOL_ERROR),
+            PING if stream_id != 0 => self.send_go_away(PROTOCOL_ERROR),
+            PING if payload.len() != 8 => self.send_go_away(FRAME_SIZE_ERROR),
+            PING if flags & ACK == 0 => self.frame(PING, ACK, 0, payload),
+            GOAWAY if stream_id != 0 => self.send_go_away(PROTOCOL_ERROR),
+            GOAWAY => self.go_away_received = true,
+            WINDOW_UPDATE => self.on_window_update(stream_id, payload),
+            CONTINUATION => self.on_continuation(flags, payload),
+            // Les trames de type inconnu et les PRIORITY sont ignorées
+            _ => {}
+        }
+    }
+
+    fn on_data(&mut self, flags: u8, stream_id: u32, payload: &[u8]) {
+        if stream_id == 0 || stream_id > self.last_stream_id {
+            return self.send_go_away(PROTOCOL_ERROR);
+        }
+        // Le remplissage compte dans les fenêtres de contrôle de flux
+        let len = payload.len() as i64;
+        if len > self.receive_window {
+            return self.send_go_away(FLOW_CONTROL_ERROR);
+        }
+        self.receive_window -= len;
+        if self.receive_window < DEFAULT_WINDOW / 2 {
+            let increment = DEFAULT_WINDOW - self.receive_window;
+            self.frame(WINDOW_UPDATE, 0, 0, &(increment as u32).to_be_bytes());
+            self.receive_window = DEFAULT_WINDOW;
+        }
+        let Some(data) = unpad(flags, payload) else {
+            return self.send_go_away(PROTOCOL_ERROR);
+        };
+
+        // Les trames d'un flux fermé ou annulé par le serveur sont ignorées
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        if stream.remote_closed {
+            return self.reset(stream_id, STREAM_CLOSED);
+        }
+        if len > stream.receive_window {
+            return self.reset(stream_id, FLOW_CONTROL_ERROR);
+        }
+        stream.receive_window -= len;
+        stream.remote_closed = flags & END_STREAM != 0;
+        let mut update = None;
+        if !stream.remote_closed && stream.receive_window < DEFAULT_WINDOW / 2 {
+            update = Some(DEFAULT_WINDOW - stream.receive_window);
+            stream.receive_window = DEFAULT_WINDOW;
+        }
+
+        if !stream.dispatched {
+            stream.body.extend(data);
+            // Un corps trop grand reçoit sa réponse sans attendre la fin de la requête
+            if stream.body.len() > self.max_body_size {
+                stream.dispatched = true;
+                stream.body = Bytes::new();
+                self.requests.push(Http2Request {
+                    stream_id,
+                    parts: Err(StatusCode::PAYLOAD_TOO_LARGE),
+                });
+            }
+        }
+        if let Some(increment) = update {
+            let increment = (increment as u32).to_be_bytes();
+            self.frame(WINDOW_UPDATE, 0, stream_id, &increment);
+        }
+        if flags & END_STREAM != 0 {
+            self.complete(stream_id);
+        }
+    }
+
+    fn on_headers(&mut self, flags: u8, stream_id: u32, payload: &[u8]) {
+        // Les flux ouverts par le client ont des identifiants impairs
+        if stream_id.is_multiple_of(2) {
+            return self.send_go_away(PROTOCOL_ERROR);
+        }
+        let Some(mut block) = unpad(flags, payload) else {
+            return self.send_go_away(PROTOCOL_ERROR);
+        };
+        if flags & PRIORITY_FLAG != 0 {
+            let Some(rest) = block.get(5..) else {
+                return self.send_go_away(FRAME_SIZE_ERROR);
+            };
+            block = rest;
+        }
+        match flags & END_HEADERS {
+            0 => self.header_block = Some((stream_id, flags, block.to_vec())),
+            _ => self.on_header_block(stream_id, flags, block),
+        }
+    }
+
+    fn on_continuation(&mut self, flags: u8, payload: &[u8]) {
+        let Some((stream_id, first_flags, mut block)) = self.header_block.take() else {
+            return self.send_go_away(PROTOCOL_ERROR);
+        };
+        block.extend(payload);
+        if block.len() > MAX_HEADER_BLOCK {
+            return self.send_go_away(ENHANCE_YOUR_CALM);
+        }
+        match flags & END_HEADERS {
+            0 => self.header_block = Some((stream_id, first_flags, block)),
+            _ => self.on_header_block(stream_id, first_flags, &block),
+        }
+    }
+
+    // Fonction pour traiter un bloc d'en-têtes complet : une nouvelle requête ou ses trailers
+    fn on_header_block(&mut self, stream_id: u32, flags: u8, block: &[u8]) {
+        // Le bloc est décodé même si le flux est refusé, pour garder la table dynamique
+        let Ok(fields) = self.decoder.decode(block, MAX_HEAD_SIZE) else {
+            return self.send_go_away(COMPRESSION_ERROR);
+        };
+        let end_stream = flags & END_STREAM != 0;
+        let Some(fields) = fields else {
+            return self.refuse_header_block(stream_id, end_stream);
+        };
+
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            // Les trailers terminent la requête ; leur contenu n'est pas transmis
+            if stream.remote_closed {
+                return self.reset(stream_id, STREAM_CLOSED);
+            }
+            if !end_stream {
+                return self.reset(stream_id, PROTOCOL_ERROR);
+            }
+            stream.remote_closed = true;
+            return self.complete(stream_id);
+        }
+        // Flux déjà terminé : rien à faire
+        if stream_id <= self.last_stream_id {
+            return;
+        }
+        self.last_stream_id = stream_id;
+        if self.go_away_received || self.streams.len() >= MAX_STREAMS {
+            return self.reset(stream_id, REFUSED_STREAM);
+        }
+        let Some(head) = request_head(&fields) else {
+            return self.reset(stream_id, PROTOCOL_ERROR);
+        };
+
+        let stream = Stream {
+            head,
+            send_window: self.initial_window,
+            receive_window: DEFAULT_WINDOW,
+            remote_closed: end_stream,
+            ..Stream::default()
+        };
+        self.streams.insert(stream_id, stream);
+        if end_stream {
+            self.complete(stream_id);
+        }
+    }
+
+    // Fonction pour écarter un bloc d'en-têtes plus grand que la limite annoncée : une nouvelle
+    // requête reçoit une réponse 431, des trailers annulent leur flux, et un client qui
+    // recommence voit sa connexion fermée
+    fn refuse_header_block(&mut self, stream_id: u32, end_stream: bool) {
+        if std::mem::replace(&mut self.headers_refused, true) {
+            return self.send_go_away(ENHANCE_YOUR_CALM);
+        }
+        if self.streams.contains_key(&stream_id) {
+            return self.reset(stream_id, ENHANCE_YOUR_CALM);
+        }
+        if stream_id <= self.last_stream_id {
+            return;
+        }
+        self.last_stream_id = stream_id;
+        if self.go_away_received || self.streams.len() >= MAX_STREAMS {
+            return self.reset(stream_id, REFUSED_STREAM);
+        }
+
+        // Le reste de la requête n'est pas attendu
+        let stream = Stream {
+            send_window: self.initial_window,
+            receive_window: DEFAULT_WINDOW,
+            remote_closed: end_stream,
+            dispatched: true,
+            ..Stream::default()
+        };
+        self.streams.insert(stream_id, stream);
+        self.requests.push(Http2Request {
+            stream_id,
+            parts: Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+        });
+    }
+
+    fn on_settings(&mut self, flags: u8, stream_id: u32, payload: &[u8]) {
+        if stream_id != 0 {
+            return self.send_go_away(PROTOCOL_ERROR);
+        }
+        if flags & ACK != 0 {
+            if !payload.is_empty() {
+                self.send_go_away(FRAME_SIZE_ERROR);
+            }
+            return;
+        }
+        if !payload.len().is_multiple_of(6) {
+            return self.send_go_away(FRAME_SIZE_ERROR);
+        }
+        if let Err(code) = self.apply_settings(payload) {
+            return self.send_go_away(code);
+        }
+        self.settings_received = true;
+        self.frame(SETTINGS, ACK, 0, &[]);
+        self.flush();
+    }
+
+    // Fonction pour appliquer les paramètres du client
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), u32> {
+        for setting in payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                ENABLE_PUSH if value > 1 => return Err(PROTOCOL_ERROR),
+                INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW {
+                        return Err(FLOW_CONTROL_ERROR);
+                    }
+                    // La différence s'applique aux fenêtres des flux déjà ouverts
+                    let delta = value - self.initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(FLOW_CONTROL_ERROR);
+                        }
+                    }
+                    self.initial_window = value;
+                }
+                MAX_FRAME_SIZE if !(16_384..=16_777_215).contains(&value) => {
+                    return Err(PROTOCOL_ERROR);
+                }
+                MAX_FRAME_SIZE => self.max_frame_size = value as usize,
+                // La table dynamique n'est pas utilisée pour les réponses
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, stream_id: u32, payload: &[u8]) {
+        let Ok(increment) = <[u8; 4]>::try_from(payload) else {
+            return self.send_go_away(FRAME_SIZE_ERROR);
+        };
+        let increment = (u32::from_be_bytes(increment) & 0x7fff_ffff) as i64;
+        if stream_id == 0 {
+            if increment == 0 {
+                return self.send_go_away(PROTOCOL_ERROR);
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW {
+                return self.send_go_away(FLOW_CONTROL_ERROR);
+            }
+        } else if stream_id > self.last_stream_id {
+            return self.send_go_away(PROTOCOL_ERROR);
+        } else if let Some(stream) = self.streams.get_mut(&stream_id) {
+            if increment == 0 {
+                return self.reset(stream_id, PROTOCOL_ERROR);
+            }
+            stream.send_window += increment;
+            if stream.send_window > MAX_WINDOW {
+                return self.reset(stream_id, FLOW_CONTROL_ERROR);
+            }
+        }
+        self.flush();
+    }
+
+    // Fonction pour transmettre la requête d'un flux que le client a fini d'envoyer
+    fn complete(&mut self, stream_id: u32) {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        if stream.dispatched {
+            return;
+        }
+        stream.dispatched = true;
+        let mut head = std::mem::take(&mut stream.head);
+        let body = std::mem::take(&mut stream.body);
+        // La taille du corps est donnée par les trames : l'indiquer comme en HTTP/1
+        let has_length = head.lines().any(|line| line.starts_with("content-length:"));
+        if !has_length && !body.is_empty() {
+            head.push_str(&format!("\r\ncontent-length: {}", body.len()));
+        }
+        self.requests.push(Http2Request {
+            stream_id,
+            parts: Ok((head, body)),
+        });
+    }
+
+    // Fonction pour envoyer les en-têtes de la réponse d'un flux, sans ceux propres à HTTP/1
+    fn send_fields(&mut self, stream_id: u32, status: StatusCode, headers: &HeaderMap, end: bool) {
+        let Some(stream) = self.streams.get_mut(&stream_id).filter(|s| !s.headers_sent) else {
+            return;
+        };
+        stream.headers_sent = true;
+        stream.ended = end;
+        let mut block = Bytes::new();
+        encode_header(&mut block, b":status", status.as_str().as_bytes());
+        for (name, value) in headers.iter() {
+            if !CONNECTION_HEADERS.contains(&name.as_str()) {
+                encode_header(&mut block, name.as_str().as_bytes(), value.as_bytes());
+            }
+        }
+        self.send_headers(stream_id, &block, end);
+        if end {
+            self.finish(stream_id);
+        }
+    }
+
+    // Fonction pour envoyer un bloc d'en-têtes, découpé selon la taille maximale des trames
+    fn send_headers(&mut self, stream_id: u32, block: &[u8], end_stream: bool) {
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.frame(kind, flags, stream_id, chunk);
+            (kind, flags) = (CONTINUATION, 0);
+        }
+        if block.is_empty() {
+            self.frame(HEADERS, flags | END_HEADERS, stream_id, &[]);
+        }
+    }
+
+    // Fonction pour envoyer les corps en attente, dans la limite des fenêtres du client
+    fn flush(&mut self) {
+        let ids: Vec<u32> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| !stream.pending.is_empty())
+            .map(|(id, _)| *id)
+            .collect();
+        for stream_id in ids {
+            while let Some(stream) = self.streams.get_mut(&stream_id) {
+                let size = stream
+                    .pending
+                    .len()
+                    .min(self.max_frame_size)
+                    .min(stream.send_window.max(0) as usize)
+                    .min(self.send_window.max(0) as usize);
+                if size == 0 {
+                    break;
+                }
+                let chunk: Bytes = stream.pending.drain(..size).collect();
+                stream.send_window -= size as i64;
+                self.send_window -= size as i64;
+                let end_stream = stream.pending.is_empty() && stream.ended;
+                let flags = if end_stream { END_STREAM } else { 0 };
+                self.frame(DATA, flags, stream_id, &chunk);
+                if end_stream {
+                    self.finish(stream_id);
+                }
+            }
+        }
+    }
+
+    // Fonction pour oublier un flux dont la réponse est entièrement envoyée
+    fn finish(&mut self, stream_id: u32) {
+        let Some(stream) = self.streams.remove(&stream_id) else {
+            return;
+        };
+        // Le reste d'une requête qui a déjà sa réponse n'est pas attendu
+        if !stream.remote_closed {
+            self.frame(RST_STREAM, 0, stream_id, &NO_ERROR.to_be_bytes());
+        }
+    }
+
+    // Fonction pour annuler un flux
+    fn reset(&mut self, stream_id: u32, code: u32) {
+        self.streams.remove(&stream_id);
+        self.frame(RST_STREAM, 0, stream_id, &code.to_be_bytes());
+    }
+
+    // Fonction pour fermer la connexion, après une violation du protocole ou à l'arrêt
+    fn send_go_away(&mut self, code: u32) {
+        if self.go_away_sent {
+            return;
+        }
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend(code.to_be_bytes());
+        self.frame(GOAWAY, 0, 0, &payload);
+        self.go_away_sent = true;
+        self.buffer.clear();
+    }
+
+    fn frame(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+        self.outgoing
+            .extend(&(payload.len() as u32).to_be_bytes()[1..]);
+        self.outgoing.extend([kind, flags]);
+        self.outgoing.extend(stream_id.to_be_bytes());
+        self.outgoing.extend(payload);
+    }
+}
+
+// Fonction pour retirer le remplissage d'une trame DATA ou HEADERS
+fn unpad(flags: u8, payload: &[u8]) -> Option<&[u8]> {
+    if flags & PADDED == 0 {
+        return Some(payload);
+    }
+    let (&padding, rest) = payload.split_first()?;
+    rest.len()
+        .checked_sub(padding as usize)
+        .map(|end| &rest[..end])
+}
+
+// Fonction pour retirer de la sortie d'une passerelle l'en-tête de sa réponse, après les réponses
+// intermédiaires (1xx) qui ne sont pas transmises ; `None` tant qu'il est incomplet
+fn take_response_head(output: &mut Bytes) -> Result<Option<(StatusCode, HeaderMap)>, ()> {
+    loop {
+        let Some((end, body_start)) = header_end(output) else {
+            return Ok(None);
+        };
+        let head: Bytes = output.drain(..body_start).collect();
+        let head = &head[..end];
+        let line_end = head.iter().position(|&b| b == b'\n').unwrap_or(head.len());
+        let status_line = std::str::from_utf8(&head[..line_end]).map_err(|_| ())?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| StatusCode::from_bytes(code.as_bytes()).ok())
+            .filter(|_| status_line.starts_with("HTTP/"))
+            .ok_or(())?;
+        if status.is_informational() {
+            continue;
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in parse_headers(&head[line_end..]).map_err(|_| ())? {
+            headers.append(name, value);
+        }
+        return Ok(Some((status, headers)));
+    }
+}
+
+// Fonction pour décoder les chunks complets d'un corps ; vrai une fois le dernier chunk reçu,
+// `None` si le découpage est invalide
+fn take_chunks(output: &mut Bytes) -> Option<(Bytes, bool)> {
+    let mut body = Bytes::new();
+    loop {
+        let Some(line_end) = output.windows(2).position(|w| w == b"\r\n") else {
+            return Some((body, false));
+        };
+        let line = std::str::from_utf8(&output[..line_end]).ok()?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        // Les trailers du dernier chunk ne sont pas transmis
+        if size == 0 {
+            output.clear();
+            return Some((body, true));
+        }
+        let end = (line_end + 2).checked_add(size)?.checked_add(2)?;
+        if output.len() < end {
+            return Some((body, false));
+        }
+        body.extend(&output[line_end + 2..end - 2]);
+        output.drain(..end);
+    }
+}
+
+// Fonction pour écrire les en-têtes reçus comme l'en-tête d'une requête HTTP/1
+fn request_head(fields: &[(Bytes, Bytes)]) -> Option<String> {
+    let (mut method, mut path, mut scheme, mut authority) = (None, None, None, None);
+    let (mut headers, mut cookies) = (Vec::new(), Vec::new());
+    for (name, value) in fields {
+        let name = std::str::from_utf8(name).ok()?;
+        let value = std::str::from_utf8(value).ok()?;
+        if value.contains(['\r', '\n', '\0']) {
+            return None;
+        }
+
+        // Les pseudo-en-têtes précèdent les en-têtes et ne sont pas répétés
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "path" => &mut path,
+                "scheme" => &mut scheme,
+                "authority" => &mut authority,
+                _ => return None,
+            };
+            if !headers.is_empty() || !cookies.is_empty() || slot.replace(value).is_some() {
+                return None;
+            }
+            continue;
+        }
+        let valid = HeaderName::from_bytes(name.as_bytes()).is_ok();
+        if !valid || name.bytes().any(|b| b.is_ascii_uppercase()) {
+            return None;
+        }
+        if CONNECTION_HEADERS.contains(&name) || (name == "te" && value != "trailers") {
+            return None;
+        }
+        // Les cookies peuvent être envoyés séparément ; ils sont réunis comme en HTTP/1
+        match name {
+            "cookie" => cookies.push(value),
+            _ => headers.push(format!("{name}: {value}")),
+        }
+    }
+
+    let (method, path) = (method?, path.filter(|path| !path.is_empty())?);
+    scheme?;
+    let mut head = format!("{method} {path} HTTP/2.0");
+    let has_host = headers.iter().any(|header| header.starts_with("host: "));
+    if let Some(authority) = authority.filter(|_| !has_host) {
+        head.push_str(&format!("\r\nhost: {authority}"));
+    }
+    if !cookies.is_empty() {
+        head.push_str(&format!("\r\ncookie: {}", cookies.join("; ")));
+    }
+    for header in headers {
+        head.push_str(&format!("\r\n{header}"));
+    }
+    Some(head)
+}
+
+// Fonction pour obtenir les paramètres HTTP/2 d'une requête qui demande `Upgrade: h2c`
+pub fn h2c_upgrade(request: &Request<Bytes>) -> Option<Bytes> {
+    let lists = |name| {
+        let values = request.headers().get_all(name).iter();
+        let values = values.filter_map(|value| value.to_str().ok());
+        values
+            .flat_map(|value| value.split(','))
+            .map(|token| token.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>()
+    };
+    let connection = lists(CONNECTION);
+    let upgrade = request.version() == Version::HTTP_11
+        && lists(UPGRADE).iter().any(|token| token == "h2c")
+        && connection.iter().any(|token| token == "upgrade")
+        && connection.iter().any(|token| token == "http2-settings");
+    if !upgrade {
+        return None;
+    }
+
+    // Les paramètres sont encodés en base64url, sensible à la casse
+    let name = HeaderName::from_static("http2-settings");
+    let settings = raw_header_values(request, &name);
+    let [settings] = settings.as_slice() else {
+        return None;
+    };
+    URL_SAFE_NO_PAD.decode(settings.trim_end_matches('=')).ok()
+}
+
+// Fonction pour créer la réponse qui accepte le passage à HTTP/2
+pub fn h2c_switching_protocols() -> Response<Bytes> {
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "Upgrade")
+        .header(UPGRADE, "h2c")
+        .body(Bytes::new())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Bytes {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend([kind, flags]);
+        frame.extend(stream_id.to_be_bytes());
+        frame.extend(payload);
+        frame
+    }
+
+    // Fonction pour découper les octets envoyés par le serveur en trames
+    fn frames(mut bytes: &[u8]) -> Vec<(u8, u8, u32, Bytes)> {
+        let mut frames = Vec::new();
+        while bytes.len() >= 9 {
+            let len = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize;
+            let id = u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+            frames.push((bytes[3], bytes[4], id, bytes[9..9 + len].to_vec()));
+            bytes = &bytes[9 + len..];
+        }
+        frames
+    }
+
+    fn request(stream_id: u32, fields: &[(&str, &str)], flags: u8) -> Bytes {
+        let mut block = Bytes::new();
+        for (name, value) in fields {
+            encode_header(&mut block, name.as_bytes(), value.as_bytes());
+        }
+        frame(HEADERS, flags | END_HEADERS, stream_id, &block)
+    }
+
+    fn open(settings: &[u8]) -> Http2Connection {
+        let mut connection = Http2Connection::new(1024);
+        let mut preface = HTTP2_PREFACE.to_vec();
+        preface.extend(frame(SETTINGS, 0, 0, settings));
+        assert!(connection.receive(&preface).is_empty());
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!(sent[0].0, SETTINGS);
+        assert_eq!(sent[1], (SETTINGS, ACK, 0, vec![]));
+        connection
+    }
+
+    const GET: [(&str, &str); 4] = [
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", "/index.html?q=1"),
+        (":authority", "localhost:8080"),
+    ];
+
+    #[test]
+    fn requests_are_rebuilt_as_http1_heads() {
+        let mut connection = open(&[]);
+        let mut fields = GET.to_vec();
+        fields.extend([("cookie", "a=1"), ("accept", "*/*"), ("cookie", "b=2")]);
+        let mut bytes = request(1, &fields, 0);
+        bytes.extend(frame(DATA, PADDED | END_STREAM, 1, b"\x02hi\0\0"));
+
+        let requests = connection.receive(&bytes);
+        assert_eq!(requests[0].stream_id, 1);
+        let (head, body) = requests[0].parts.clone().unwrap();
+        assert_eq!(
+            head,
+            "GET /index.html?q=1 HTTP/2.0\r\nhost: localhost:8080\r\ncookie: a=1; b=2\r\n\
+             accept: */*\r\ncontent-length: 2"
+        );
+        assert_eq!(body, b"hi");
+    }
+
+    #[test]
+    fn malformed_requests_are_reset() {
+        let mut connection = open(&[]);
+        let mut fields = GET.to_vec();
+        fields.push(("connection", "keep-alive"));
+        assert!(connection
+            .receive(&request(1, &fields, END_STREAM))
+            .is_empty());
+        let reset = (RST_STREAM, 0, 1, PROTOCOL_ERROR.to_be_bytes().to_vec());
+        assert_eq!(frames(&connection.take_outgoing()), vec![reset]);
+
+        // Un flux pair est une erreur de la connexion entière
+        assert!(connection.receive(&request(2, &GET, END_STREAM)).is_empty());
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!(sent[0].0, GOAWAY);
+        assert_eq!(sent[0].3[4..], PROTOCOL_ERROR.to_be_bytes());
+        assert!(connection.is_finished());
+    }
+
+    #[test]
+    fn oversized_header_lists_are_refused() {
+        // Une entrée de 132 octets dans la table dynamique, référencée jusqu'à dépasser la limite
+        let mut block = Bytes::new();
+        for (name, value) in GET {
+            encode_header(&mut block, name.as_bytes(), value.as_bytes());
+        }
+        block.extend([0x40, 1, b'a', 99]);
+        block.extend([b'v'; 99]);
+        block.extend(vec![0xbe; MAX_HEAD_SIZE / 132]);
+
+        let mut connection = open(&[]);
+        let requests = connection.receive(&frame(HEADERS, END_HEADERS | END_STREAM, 1, &block));
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].parts,
+            Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+        assert!(connection.take_outgoing().is_empty());
+
+        // Le client qui recommence est écarté
+        let block = vec![0xbe; MAX_HEAD_SIZE / 132 + 1];
+        let requests = connection.receive(&frame(HEADERS, END_HEADERS | END_STREAM, 3, &block));
+        assert!(requests.is_empty());
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!(sent[0].0, GOAWAY);
+        assert_eq!(sent[0].3[4..], ENHANCE_YOUR_CALM.to_be_bytes());
+    }
+
+    #[test]
+    fn responses_wait_for_flow_control_credit() {
+        // Fenêtre initiale de 10 octets pour chaque flux
+        let mut connection = open(&[0, 4, 0, 0, 0, 10]);
+        assert_eq!(connection.receive(&request(1, &GET, END_STREAM)).len(), 1);
+        let response = Response::builder()
+            .header("content-length", "25")
+            .header("transfer-encoding", "chunked")
+            .body(vec![b'x'; 25])
+            .unwrap();
+        connection.send_response(1, response, false);
+
+        let sent = frames(&connection.take_outgoing());
+        let mut decoder = HpackDecoder::default();
+        let fields = decoder.decode(&sent[0].3, MAX_HEAD_SIZE).unwrap().unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[1].0, b"content-length");
+        assert_eq!(sent[1], (DATA, 0, 1, vec![b'x'; 10]));
+        assert_eq!(sent.len(), 2);
+
+        connection.receive(&frame(WINDOW_UPDATE, 0, 1, &100u32.to_be_bytes()));
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!(sent, vec![(DATA, END_STREAM, 1, vec![b'x'; 15])]);
+        assert!(connection.streams.is_empty());
+    }
+
+    #[test]
+    fn gateway_output_is_relayed_as_frames() {
+        let mut connection = open(&[]);
+        assert_eq!(connection.receive(&request(1, &GET, END_STREAM)).len(), 1);
+        let head = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\ncontent-type: text/plain\r\n\
+                     transfer-encoding: chunked\r\n\r\n5\r\nhel";
+        connection.relay(1, head, false, false);
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!((sent[0].0, sent[0].1), (HEADERS, END_HEADERS));
+        let mut decoder = HpackDecoder::default();
+        let fields = decoder.decode(&sent[0].3, MAX_HEAD_SIZE).unwrap().unwrap();
+        assert_eq!(fields[0], (b":status".to_vec(), b"200".to_vec()));
+        assert_eq!(fields.len(), 2);
+        assert_eq!(sent.len(), 1);
+
+        connection.relay(1, b"lo\r\n3\r\n!!!\r\n0\r\n\r\n", true, false);
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!(sent, vec![(DATA, END_STREAM, 1, b"hello!!!".to_vec())]);
+        assert!(!connection.awaits_response(1));
+
+        // Sans corps pour `HEAD`, et une sortie sans en-tête complet annule le flux
+        assert_eq!(connection.receive(&request(3, &GET, END_STREAM)).len(), 1);
+        connection.relay(3, b"HTTP/1.1 200 OK\r\n\r\nbody", false, true);
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!(sent[0].1, END_STREAM | END_HEADERS);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(connection.receive(&request(5, &GET, END_STREAM)).len(), 1);
+        connection.relay(5, b"HTTP/1.1 200", true, false);
+        let reset = (RST_STREAM, 0, 5, INTERNAL_ERROR.to_be_bytes().to_vec());
+        assert_eq!(frames(&connection.take_outgoing()), vec![reset]);
+    }
+
+    #[test]
+    fn oversized_bodies_are_answered_early() {
+        let mut connection = open(&[]);
+        let mut fields = GET.to_vec();
+        fields[0] = (":method", "POST");
+        let mut bytes = request(1, &fields, 0);
+        bytes.extend(frame(DATA, 0, 1, &[0; 1025]));
+        let requests = connection.receive(&bytes);
+        assert_eq!(requests[0].parts, Err(StatusCode::PAYLOAD_TOO_LARGE));
+
+        let response = Response::builder().status(413).body(vec![]).unwrap();
+        connection.send_response(1, response, false);
+        let sent = frames(&connection.take_outgoing());
+        assert_eq!(sent[0].1, END_STREAM | END_HEADERS);
+        assert_eq!(sent[1], (RST_STREAM, 0, 1, NO_ERROR.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn upgrade_settings_are_decoded() {
+        let request = Request::builder()
+            .header("connection", "upgrade, http2-settings")
+            .header("upgrade", "h2c")
+            .header("http2-settings", "AAMAAABkAARAAAAAAAIAAAAA")
+            .body(Bytes::new())
+            .unwrap();
+        let settings = h2c_upgrade(&request).unwrap();
+        assert_eq!(settings.len(), 18);
+
+        let parts = ("GET / HTTP/1.1".to_string(), Bytes::new());
+        let mut connection = Http2Connection::upgraded(&settings, parts.clone(), 1024).unwrap();
+        assert_eq!(connection.streams[&1].send_window, 1 << 30);
+        let requests = connection.receive(&[]);
+        assert_eq!(requests[0].stream_id, 1);
+        assert_eq!(requests[0].parts, Ok(parts.clone()));
+        assert!(Http2Connection::upgraded(&settings[..5], parts, 1024).is_none());
+    }
+}
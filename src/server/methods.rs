@@ -2,7 +2,12 @@ use super::{Bytes, Method, Request, Response, Route, ServerConfig, StatusCode};
 use crate::log;
 use crate::log::*;
 use crate::server::content_type;
+use crate::server::errors::method_not_allowed;
 use crate::server::utils::{get_line, get_split_index};
+use crate::server::webdav::{
+    check_path, check_write_locks, forget_properties, handle_webdav, is_webdav_method,
+    is_webdav_route,
+};
 use http::header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE, HOST};
 use std::fs;
 use std::str::FromStr;
@@ -15,9 +20,39 @@ pub fn get_method(req: &str) -> Result<Method, StatusCode> {
     Method::from_str(method).map_err(|_| StatusCode::BAD_REQUEST)
 }
 
+// Fonction pour obtenir les méthodes autorisées d'une route, HEAD étant implicite avec GET
+pub fn allowed_methods(route: &Route, config: &ServerConfig) -> Vec<Method> {
+    let mut methods = route.methods.clone();
+    if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+        methods.push(Method::HEAD);
+    }
+    // TRACE n'est accepté que si le serveur l'active explicitement
+    if !config.trace_enabled {
+        methods.retain(|method| method != Method::TRACE);
+    }
+    methods
+}
+
+// Fonction pour formater une liste de méthodes pour l'en-tête `Allow`
+pub fn allow_header(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(|method| method.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
 // Fonction pour vérifier si une méthode est autorisée pour une route donnée
-pub fn method_is_allowed(method: &Method, route: &Route) -> bool {
-    route.methods.contains(method)
+pub fn method_is_allowed(method: &Method, route: &Route, config: &ServerConfig) -> bool {
+    allowed_methods(route, config).contains(method)
+}
+
+// Fonction pour vérifier si une méthode modifie la ressource ciblée
+fn is_write_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
 }
 
 // Fonction principale pour gérer les différentes méthodes HTTP
@@ -26,18 +61,36 @@ pub fn handle_method(
     req: &Request<Bytes>,
     config: &ServerConfig,
 ) -> Result<Response<Bytes>, StatusCode> {
+    // Refuser l'écriture hors de la racine ou sur une ressource verrouillée par WebDAV
+    if is_webdav_route(route) && is_write_method(req.method()) {
+        check_path(req.uri().path())?;
+        check_write_locks(route, req)?;
+    }
+
     match *req.method() {
         // Méthodes sécurisées
         Method::GET => safe::get(req, config),
         Method::OPTIONS => safe::options(route, req, config),
         Method::HEAD => safe::head(req, config),
+        // TRACE désactivé : la réponse 405 doit indiquer les méthodes autorisées
+        Method::TRACE if !method_is_allowed(req.method(), route, config) => {
+            log!(
+                LogFileType::Server,
+                format!("Error: {}", StatusCode::METHOD_NOT_ALLOWED)
+            );
+            let allow = allow_header(&allowed_methods(route, config));
+            Ok(method_not_allowed(config, allow))
+        }
         Method::TRACE => safe::trace(req, config),
 
         // Méthodes non sécurisées
         Method::POST => not_safe::post(req, config),
         Method::PUT => not_safe::put(req, config),
         Method::PATCH => not_safe::patch(req, config),
-        Method::DELETE => not_safe::delete(req, config),
+        Method::DELETE => not_safe::delete(req, config).inspect(|_| forget_properties(route, req)),
+
+        // Méthodes WebDAV
+        _ if is_webdav_method(req.method()) => handle_webdav(route, req, config),
         _ => {
             // Méthode non implémentée
             log!(
@@ -53,8 +106,14 @@ pub mod safe {
     use super::*;
     use crate::server::get_route;
     use crate::server::path::add_root_to_path;
-    use http::header::{TRANSFER_ENCODING, VIA};
-    use http::HeaderName;
+    use crate::server::RawHead;
+    use crate::server::{without_body, PATCH_FORMATS};
+    use http::header::{
+        ACCEPT_RANGES, AUTHORIZATION, COOKIE, MAX_FORWARDS, PROXY_AUTHORIZATION, SET_COOKIE,
+        TRANSFER_ENCODING,
+    };
+    use http::response::Builder;
+    use http::{HeaderMap, HeaderName};
 
     /// # STANDARD_HEADERS
     ///
@@ -91,97 +150,178 @@ pub mod safe {
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
     }
 
-    // Fonction pour gérer les requêtes HEAD
+    // Fonction pour gérer les requêtes HEAD : même réponse que GET, sans le corps
     pub fn head(
         req: &Request<Bytes>,
         config: &ServerConfig,
     ) -> Result<Response<Bytes>, StatusCode> {
-        let route = match get_route(req, config) {
-            Ok(route) => route,
-            Err((status, _)) => return Err(status),
-        };
-        let path = &add_root_to_path(&route, req.uri().path());
-        let metadata = fs::metadata(path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        Response::builder()
-            .version(req.version())
-            .header(HOST, config.host)
-            .status(StatusCode::OK)
-            .header(CONTENT_TYPE, content_type(path))
-            .header(CONTENT_LENGTH, metadata.len())
-            .body(vec![])
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        get(req, config).map(without_body)
     }
 
+    /// # SENSITIVE_HEADERS
+    ///
+    /// En-têtes dont la valeur est masquée dans l'écho renvoyé par TRACE.
+    pub(crate) const SENSITIVE_HEADERS: [HeaderName; 4] =
+        [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE];
+
     // Fonction pour gérer les requêtes TRACE
     pub fn trace(
         req: &Request<Bytes>,
         config: &ServerConfig,
     ) -> Result<Response<Bytes>, StatusCode> {
-        // Vérifier l'en-tête Max-Forwards
-        let max_forwards = req
-            .headers()
-            .get("Max-Forwards")
-            .and_then(|h| h.to_str().ok())
-            .and_then(|s| s.parse::<i32>().ok());
-
-        if max_forwards == Some(0) {
-            return Err(StatusCode::BAD_REQUEST);
-        }
-
-        // Mettre à jour l'en-tête Via
-        let existing_via = req.headers().get(VIA).map(|v| v.to_str().unwrap_or(""));
-
-        let via = if let Some(via_header) = existing_via {
-            format!("{}, {}", via_header, config.host)
-        } else {
-            config.host.to_string()
-        };
-
-        // Exclure les en-têtes sensibles et construire la chaîne de requête
-        let request_string = format!("{:?}\r\n", req)
-            .lines()
-            .filter(|line| !line.starts_with("Cookie:") && !line.starts_with("Authorization:"))
-            .collect::<Vec<_>>()
-            .join("\r\n");
+        // Le serveur d'origine est toujours le destinataire final, quelle que soit la valeur
+        // de Max-Forwards ; elle doit néanmoins être valide.
+        max_forwards(req.headers())?;
 
+        let body = Bytes::from(echo_request(req));
         Response::builder()
             .version(req.version())
             .header(HOST, config.host)
             .status(StatusCode::OK)
             .header(CONTENT_TYPE, "message/http")
-            .header("Via", via)
-            .body(Bytes::from(request_string))
+            .header(CONTENT_LENGTH, body.len())
+            .body(body)
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
     }
 
+    // Fonction pour reproduire la requête reçue au format message/http, en masquant les en-têtes sensibles
+    fn echo_request(req: &Request<Bytes>) -> String {
+        // Utiliser les octets reçus si disponibles, sinon reconstruire la requête
+        let head = match req.extensions().get::<RawHead>() {
+            Some(RawHead(head)) => head.clone(),
+            None => {
+                let mut head = format!("{} {} {:?}", req.method(), req.uri(), req.version());
+                for (key, value) in req.headers() {
+                    head.push_str(&format!(
+                        "\r\n{key}: {}",
+                        value.to_str().unwrap_or_default()
+                    ));
+                }
+                head
+            }
+        };
+
+        let mut lines = head
+            .trim_end_matches('\0')
+            .split("\r\n")
+            .collect::<Vec<_>>();
+        let request_line = lines.remove(0);
+        let fields = lines.into_iter().map(|line| match line.split_once(':') {
+            Some((name, _))
+                if SENSITIVE_HEADERS
+                    .iter()
+                    .any(|h| name.trim().eq_ignore_ascii_case(h.as_str())) =>
+            {
+                format!("{name}: [redacted]")
+            }
+            _ => line.to_string(),
+        });
+
+        std::iter::once(request_line.to_string())
+            .chain(fields)
+            .map(|line| line + "\r\n")
+            .collect::<String>()
+            + "\r\n"
+    }
+
+    // Fonction pour lire l'en-tête Max-Forwards (RFC 9110, section 7.6.2)
+    pub fn max_forwards(headers: &HeaderMap) -> Result<Option<u32>, StatusCode> {
+        match headers.get(MAX_FORWARDS) {
+            None => Ok(None),
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|v| v.trim().parse::<u32>().ok())
+                .map(Some)
+                .ok_or(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    // Fonction pour décrémenter Max-Forwards avant de transmettre une requête TRACE ou OPTIONS.
+    // Retourne `false` si la valeur est nulle : la requête ne doit pas être transmise.
+    pub fn forward_max_forwards(headers: &mut HeaderMap) -> Result<bool, StatusCode> {
+        match max_forwards(headers)? {
+            None => Ok(true),
+            Some(0) => Ok(false),
+            Some(n) => {
+                headers.insert(MAX_FORWARDS, (n - 1).into());
+                Ok(true)
+            }
+        }
+    }
+
     // Fonction pour gérer les requêtes OPTIONS
     pub fn options(
         route: &Route,
         req: &Request<Bytes>,
         config: &ServerConfig,
     ) -> Result<Response<Bytes>, StatusCode> {
-        let allowed_methods = route
-            .methods
+        let resp = Response::builder()
+            .version(req.version())
+            .header(HOST, config.host)
+            .status(StatusCode::OK)
+            .header(ALLOW, allow_header(&allowed_methods(route, config)))
+            .header(CONTENT_LENGTH, 0);
+
+        capabilities(resp, &[route])
+            .body(vec![]) // Corps vide pour OPTIONS
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    // Fonction pour gérer `OPTIONS *`, qui décrit les capacités de tout le serveur
+    pub fn server_options(
+        req: &Request<Bytes>,
+        config: &ServerConfig,
+    ) -> Result<Response<Bytes>, StatusCode> {
+        let mut methods = vec![Method::OPTIONS];
+        for method in config
+            .routes
             .iter()
-            .map(|method| method.as_str())
-            .collect::<Vec<&str>>()
-            .join(", ");
+            .flat_map(|r| allowed_methods(r, config))
+        {
+            if !methods.contains(&method) {
+                methods.push(method);
+            }
+        }
 
-        Response::builder()
+        let resp = Response::builder()
             .version(req.version())
             .header(HOST, config.host)
             .status(StatusCode::OK)
-            .header(ALLOW, allowed_methods)
-            .body(vec![]) // Corps vide pour OPTIONS
+            .header(ALLOW, allow_header(&methods))
+            .header(CONTENT_LENGTH, 0);
+
+        capabilities(resp, &config.routes.iter().collect::<Vec<_>>())
+            .body(vec![])
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
     }
+
+    // Fonction pour annoncer les fonctionnalités optionnelles activées sur les routes
+    fn capabilities(mut resp: Builder, routes: &[&Route]) -> Builder {
+        // Formats acceptés par PATCH
+        if routes.iter().any(|r| r.methods.contains(&Method::PATCH)) {
+            resp = resp.header("Accept-Patch", PATCH_FORMATS.join(", "));
+        }
+
+        // Les fichiers statiques sont servis en entier : les requêtes `Range` ne sont pas prises en charge
+        if routes.iter().any(|r| r.handler.is_none()) {
+            resp = resp.header(ACCEPT_RANGES, "none");
+        }
+
+        // Conformité WebDAV (classes 1 et 2)
+        if routes.iter().any(|r| is_webdav_route(r)) {
+            resp = resp.header("DAV", "1, 2");
+        }
+        resp
+    }
 }
 
 mod not_safe {
     use super::*;
+    use crate::server::errors::unsupported_patch_format;
     use crate::server::get_route;
     use crate::server::path::add_root_to_path;
+    use crate::server::{apply_patch, PATCH_FORMATS};
 
     // Fonction pour créer une réponse non sécurisée
     fn unsafe_response(path: &str, body: Bytes) -> Result<Response<Bytes>, StatusCode> {
@@ -252,9 +392,16 @@ mod not_safe {
             Err((status, _)) => return Err(status),
         };
         let path = &add_root_to_path(&route, req.uri().path());
-        let body = req.body().to_vec();
 
-        fs::metadata(path).map_err(|_| StatusCode::NOT_FOUND)?;
+        let current = fs::read(path).map_err(|_| StatusCode::NOT_FOUND)?;
+        let body = match apply_patch(current, req) {
+            Ok(body) => body,
+            // Indiquer au client les formats qu'il peut utiliser
+            Err(StatusCode::UNSUPPORTED_MEDIA_TYPE) => {
+                return Ok(unsupported_patch_format(config, PATCH_FORMATS.join(", ")));
+            }
+            Err(code) => return Err(code),
+        };
         fs::write(path, &body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
         unsafe_response(path, body)
     }
@@ -269,7 +416,12 @@ mod not_safe {
             Err((status, _)) => return Err(status),
         };
         let path = &add_root_to_path(&route, req.uri().path());
-        let body = fs::read(path).map_err(|_| StatusCode::NOT_FOUND)?;
+        let metadata = fs::metadata(path).map_err(|_| StatusCode::NOT_FOUND)?;
+        let body = if metadata.is_dir() {
+            Bytes::new()
+        } else {
+            fs::read(path).map_err(|_| StatusCode::NOT_FOUND)?
+        };
         if fs::remove_file(path).is_err() {
             fs::remove_dir_all(path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
         }
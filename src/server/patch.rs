@@ -0,0 +1,409 @@
+use crate::log;
+use crate::log::*;
+use crate::server::{Bytes, Request, StatusCode};
+use http::header::{CONTENT_RANGE, CONTENT_TYPE};
+use serde_json::{Map, Value};
+
+/// # PATCH_FORMATS
+///
+/// Types de médias acceptés par PATCH, annoncés dans l'en-tête `Accept-Patch`.
+pub const PATCH_FORMATS: [&str; 4] = [
+    MERGE_PATCH_JSON,
+    JSON_PATCH_JSON,
+    "application/octet-stream",
+    "text/plain",
+];
+
+const MERGE_PATCH_JSON: &str = "application/merge-patch+json";
+const JSON_PATCH_JSON: &str = "application/json-patch+json";
+
+// Mode d'application d'une requête PATCH
+#[derive(Debug, PartialEq)]
+pub enum PatchFormat {
+    /// RFC 7396
+    MergePatch,
+    /// RFC 6902
+    JsonPatch,
+    /// Mise à jour d'une plage d'octets décrite par `Content-Range`
+    ByteRange { first: usize, last: usize },
+    /// Ajout du corps à la fin du fichier
+    Append,
+}
+
+// Fonction pour déterminer le format de PATCH à partir des en-têtes de la requête
+pub fn patch_format(req: &Request<Bytes>) -> Result<PatchFormat, StatusCode> {
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match content_type.as_str() {
+        MERGE_PATCH_JSON => Ok(PatchFormat::MergePatch),
+        JSON_PATCH_JSON => Ok(PatchFormat::JsonPatch),
+        "application/octet-stream" | "text/plain" => match req.headers().get(CONTENT_RANGE) {
+            Some(range) => {
+                let range = range.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+                let (first, last) = parse_content_range(range).ok_or(StatusCode::BAD_REQUEST)?;
+                Ok(PatchFormat::ByteRange { first, last })
+            }
+            None => Ok(PatchFormat::Append),
+        },
+        _ => {
+            log!(
+                LogFileType::Server,
+                format!("Error: Unsupported patch format '{content_type}'")
+            );
+            Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        }
+    }
+}
+
+// Fonction pour appliquer le corps d'une requête PATCH au contenu actuel d'un fichier
+pub fn apply_patch(current: Bytes, req: &Request<Bytes>) -> Result<Bytes, StatusCode> {
+    let patch = req.body();
+
+    match patch_format(req)? {
+        PatchFormat::MergePatch => {
+            let mut target = parse_json(&current, StatusCode::CONFLICT)?;
+            let patch = parse_json(patch, StatusCode::BAD_REQUEST)?;
+            merge_patch(&mut target, &patch);
+            serde_json::to_vec(&target).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        PatchFormat::JsonPatch => {
+            let mut target = parse_json(&current, StatusCode::CONFLICT)?;
+            let patch = parse_json(patch, StatusCode::BAD_REQUEST)?;
+            json_patch(&mut target, &patch)?;
+            serde_json::to_vec(&target).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        PatchFormat::ByteRange { first, last } => byte_range(current, patch, first, last),
+        PatchFormat::Append => {
+            let mut body = current;
+            body.extend(patch);
+            Ok(body)
+        }
+    }
+}
+
+fn parse_json(bytes: &[u8], code: StatusCode) -> Result<Value, StatusCode> {
+    serde_json::from_slice(bytes).map_err(|_| code)
+}
+
+// Fonction pour analyser `Content-Range: bytes <first>-<last>/<total|*>`
+fn parse_content_range(range: &str) -> Option<(usize, usize)> {
+    let (unit, spec) = range.trim().split_once(' ')?;
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let (span, _total) = spec.split_once('/')?;
+    let (first, last) = span.split_once('-')?;
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+
+    if first > last {
+        return None;
+    }
+    Some((first, last))
+}
+
+// Fonction pour remplacer une plage d'octets du fichier
+fn byte_range(
+    mut body: Bytes,
+    patch: &[u8],
+    first: usize,
+    last: usize,
+) -> Result<Bytes, StatusCode> {
+    // Une plage qui couvre tout l'espace des positions ne peut pas être satisfaite
+    let length = last
+        .checked_sub(first)
+        .and_then(|n| n.checked_add(1))
+        .ok_or(StatusCode::RANGE_NOT_SATISFIABLE)?;
+    if patch.len() != length {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Il est possible d'écrire juste après la fin du fichier, mais pas de laisser un trou
+    if first > body.len() {
+        return Err(StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+    if last >= body.len() {
+        body.resize(last + 1, 0);
+    }
+
+    body[first..=last].copy_from_slice(patch);
+    Ok(body)
+}
+
+// Fonction pour appliquer un JSON Merge Patch (RFC 7396)
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+// Fonction pour appliquer un JSON Patch (RFC 6902)
+fn json_patch(target: &mut Value, patch: &Value) -> Result<(), StatusCode> {
+    let operations = patch.as_array().ok_or(StatusCode::BAD_REQUEST)?;
+
+    for operation in operations {
+        let op = string_member(operation, "op")?;
+        let path = string_member(operation, "path")?;
+
+        match op {
+            "add" => pointer::add(target, path, value_member(operation)?)?,
+            "remove" => {
+                pointer::remove(target, path)?;
+            }
+            "replace" => {
+                let value = value_member(operation)?;
+                *target.pointer_mut(path).ok_or(StatusCode::CONFLICT)? = value;
+            }
+            "move" => {
+                let from = string_member(operation, "from")?;
+                // Un emplacement ne peut pas être déplacé dans l'un de ses enfants
+                if path.starts_with(&format!("{from}/")) {
+                    return Err(StatusCode::CONFLICT);
+                }
+                let value = pointer::remove(target, from)?;
+                pointer::add(target, path, value)?;
+            }
+            "copy" => {
+                let from = string_member(operation, "from")?;
+                let value = target.pointer(from).ok_or(StatusCode::CONFLICT)?.clone();
+                pointer::add(target, path, value)?;
+            }
+            "test" => {
+                if target.pointer(path) != Some(&value_member(operation)?) {
+                    return Err(StatusCode::CONFLICT);
+                }
+            }
+            _ => return Err(StatusCode::BAD_REQUEST),
+        }
+    }
+    Ok(())
+}
+
+fn string_member<'a>(operation: &'a Value, key: &str) -> Result<&'a str, StatusCode> {
+    operation
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
+fn value_member(operation: &Value) -> Result<Value, StatusCode> {
+    operation
+        .get("value")
+        .cloned()
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
+mod pointer {
+    use super::*;
+
+    // Fonction pour séparer un JSON Pointer en pointeur parent et dernier segment
+    fn split(path: &str) -> Result<(&str, String), StatusCode> {
+        let index = path.rfind('/').ok_or(StatusCode::BAD_REQUEST)?;
+        let token = path[index + 1..].replace("~1", "/").replace("~0", "~");
+        Ok((&path[..index], token))
+    }
+
+    fn array_index(token: &str, len: usize) -> Result<usize, StatusCode> {
+        match token.parse::<usize>() {
+            Ok(i) if i <= len && (token == "0" || !token.starts_with('0')) => Ok(i),
+            _ => Err(StatusCode::CONFLICT),
+        }
+    }
+
+    pub fn add(target: &mut Value, path: &str, value: Value) -> Result<(), StatusCode> {
+        if path.is_empty() {
+            *target = value;
+            return Ok(());
+        }
+
+        let (parent, token) = split(path)?;
+        match target.pointer_mut(parent).ok_or(StatusCode::CONFLICT)? {
+            Value::Object(map) => {
+                map.insert(token, value);
+            }
+            Value::Array(array) if token == "-" => array.push(value),
+            Value::Array(array) => {
+                let i = array_index(&token, array.len())?;
+                array.insert(i, value);
+            }
+            _ => return Err(StatusCode::CONFLICT),
+        }
+        Ok(())
+    }
+
+    pub fn remove(target: &mut Value, path: &str) -> Result<Value, StatusCode> {
+        let (parent, token) = split(path)?;
+        match target.pointer_mut(parent).ok_or(StatusCode::CONFLICT)? {
+            Value::Object(map) => map.remove(&token).ok_or(StatusCode::CONFLICT),
+            Value::Array(array) => {
+                let i = array_index(&token, array.len())?;
+                if i == array.len() {
+                    return Err(StatusCode::CONFLICT);
+                }
+                Ok(array.remove(i))
+            }
+            _ => Err(StatusCode::CONFLICT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Method;
+
+    fn patch_request(content_type: &str, range: Option<&str>, body: &str) -> Request<Bytes> {
+        let mut req = Request::builder()
+            .method(Method::PATCH)
+            .uri("/file")
+            .header(CONTENT_TYPE, content_type);
+        if let Some(range) = range {
+            req = req.header(CONTENT_RANGE, range);
+        }
+        req.body(Bytes::from(body)).unwrap()
+    }
+
+    #[test]
+    fn unsupported_format() {
+        let req = patch_request("application/xml", None, "<a/>");
+        assert_eq!(
+            apply_patch(Bytes::new(), &req),
+            Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+    }
+
+    #[test]
+    fn append() {
+        let req = patch_request("text/plain; charset=utf-8", None, "line 2\n");
+        let body = apply_patch(Bytes::from("line 1\n"), &req).unwrap();
+        assert_eq!(body, b"line 1\nline 2\n");
+    }
+
+    #[test]
+    fn byte_range_update() {
+        let req = patch_request("application/octet-stream", Some("bytes 2-4/*"), "XYZ");
+        let body = apply_patch(Bytes::from("abcdefg"), &req).unwrap();
+        assert_eq!(body, b"abXYZfg");
+
+        // Étendre le fichier au-delà de sa fin
+        let req = patch_request("application/octet-stream", Some("bytes 3-5/6"), "XYZ");
+        let body = apply_patch(Bytes::from("abc"), &req).unwrap();
+        assert_eq!(body, b"abcXYZ");
+    }
+
+    #[test]
+    fn byte_range_errors() {
+        let req = patch_request("application/octet-stream", Some("bytes 2-4/*"), "XY");
+        assert_eq!(
+            apply_patch(Bytes::from("abcdefg"), &req),
+            Err(StatusCode::BAD_REQUEST)
+        );
+
+        let req = patch_request("application/octet-stream", Some("bytes 9-10/*"), "XY");
+        assert_eq!(
+            apply_patch(Bytes::from("abc"), &req),
+            Err(StatusCode::RANGE_NOT_SATISFIABLE)
+        );
+
+        let range = format!("bytes 0-{}/*", usize::MAX);
+        let req = patch_request("application/octet-stream", Some(&range), "XY");
+        assert_eq!(
+            apply_patch(Bytes::from("abc"), &req),
+            Err(StatusCode::RANGE_NOT_SATISFIABLE)
+        );
+
+        let req = patch_request("application/octet-stream", Some("lines 1-2"), "XY");
+        assert_eq!(
+            apply_patch(Bytes::from("abc"), &req),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn merge_patch_rfc7396() {
+        let target = r#"{"a":"b","c":{"d":"e","f":"g"}}"#;
+        let req = patch_request(MERGE_PATCH_JSON, None, r#"{"a":"z","c":{"f":null}}"#);
+        let body = apply_patch(Bytes::from(target), &req).unwrap();
+
+        let result: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(result, serde_json::json!({"a": "z", "c": {"d": "e"}}));
+    }
+
+    #[test]
+    fn merge_patch_on_non_json_file() {
+        let req = patch_request(MERGE_PATCH_JSON, None, r#"{"a":1}"#);
+        assert_eq!(
+            apply_patch(Bytes::from("plain text"), &req),
+            Err(StatusCode::CONFLICT)
+        );
+    }
+
+    #[test]
+    fn json_patch_rfc6902() {
+        let target = r#"{"foo":["bar","baz"],"qux":{"a/b":1}}"#;
+        let patch = r#"[
+            {"op":"test","path":"/qux/a~1b","value":1},
+            {"op":"add","path":"/foo/1","value":"qux"},
+            {"op":"remove","path":"/foo/0"},
+            {"op":"replace","path":"/qux","value":{}},
+            {"op":"copy","from":"/foo","path":"/copy"},
+            {"op":"move","from":"/copy","path":"/moved"},
+            {"op":"add","path":"/moved/-","value":"end"}
+        ]"#;
+        let req = patch_request(JSON_PATCH_JSON, None, patch);
+        let body = apply_patch(Bytes::from(target), &req).unwrap();
+
+        let result: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "foo": ["qux", "baz"],
+                "qux": {},
+                "moved": ["qux", "baz", "end"]
+            })
+        );
+    }
+
+    #[test]
+    fn json_patch_errors() {
+        let target = Bytes::from(r#"{"foo":1}"#);
+
+        let req = patch_request(
+            JSON_PATCH_JSON,
+            None,
+            r#"[{"op":"test","path":"/foo","value":2}]"#,
+        );
+        assert_eq!(apply_patch(target.clone(), &req), Err(StatusCode::CONFLICT));
+
+        let req = patch_request(JSON_PATCH_JSON, None, r#"[{"op":"remove","path":"/bar"}]"#);
+        assert_eq!(apply_patch(target.clone(), &req), Err(StatusCode::CONFLICT));
+
+        let req = patch_request(JSON_PATCH_JSON, None, r#"[{"op":"jump","path":"/foo"}]"#);
+        assert_eq!(
+            apply_patch(target.clone(), &req),
+            Err(StatusCode::BAD_REQUEST)
+        );
+
+        let req = patch_request(JSON_PATCH_JSON, None, r#"{"op":"add"}"#);
+        assert_eq!(apply_patch(target, &req), Err(StatusCode::BAD_REQUEST));
+    }
+}
@@ -0,0 +1,478 @@
+use crate::log;
+use crate::log::*;
+use crate::server::route::Route;
+use crate::server::{
+    choose_upstream, format_response, header_end, is_upgrade_request, parse_headers,
+    raw_header_values, Address, Addresses, Balance, Bytes, CgiProgress, Ejection, Exchange,
+    HealthCheck, Reader, ServerConfig, StatusCode, TlsSession, Upstream, UpstreamRequest,
+};
+use http::header::*;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response, Uri, Version};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// # DEFAULT_PROXY_TIMEOUT
+///
+/// Durée maximale d'une réponse mandatée lorsque la route ne définit pas `timeout`.
+pub const DEFAULT_PROXY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// # MAX_RESPONSE_HEAD
+///
+/// Taille maximale de l'en-tête d'une réponse mandatée.
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+/// # HOP_BY_HOP_HEADERS
+///
+/// En-têtes propres à une connexion, qui ne sont pas transmis d'un côté à l'autre
+/// (RFC 9110, section 7.6.1), en plus de ceux nommés par l'en-tête `Connection`.
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// # Proxy
+///
+/// Serveurs HTTP/1.1 (`host:port` ou socket Unix) auxquels une route transmet ses requêtes.
+/// `path` remplace le préfixe de la route dans le chemin transmis : avec la route `/api` et
+/// `path: Some("/")`, `/api/users` est transmis comme `/users`. Les requêtes sont réparties
+/// entre les serveurs selon `balance` ; `ejection` et `health_check` écartent les serveurs en panne.
+/// Le corps de la requête est reçu en entier, dans la limite de `body_size_limit`, avant d'être
+/// transmis : il n'est pas relayé au fil de l'eau.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Proxy<'a> {
+    pub upstreams: &'a [Upstream<'a>],
+    pub balance: Balance<'a>,
+    pub ejection: Option<Ejection>,
+    pub health_check: Option<HealthCheck<'a>>,
+    pub path: Option<&'a str>,
+    pub timeout: Option<Duration>,
+}
+
+// Fonction pour préparer la requête à transmettre à l'un des serveurs de la route
+pub fn proxy_request(
+    route: &Route,
+    proxy: &Proxy,
+    request: &Request<Bytes>,
+    request_head: String,
+    config: &ServerConfig,
+) -> Result<UpstreamRequest, StatusCode> {
+    // Le corps est conservé en mémoire jusqu'à son envoi
+    if request.body().len() > config.body_size_limit {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let Some((index, mut lease)) = choose_upstream(proxy, request) else {
+        log!(
+            LogFileType::Server,
+            format!("Error: no upstream available for {}", route.url_path)
+        );
+        return Err(StatusCode::BAD_GATEWAY);
+    };
+    let upstream = &proxy.upstreams[index].address;
+
+    let target = rewrite_path(route.url_path, proxy.path, request.uri());
+    let outgoing = encode_request(request, &target, upstream);
+    let exchange = Exchange {
+        name: target,
+        kind: "proxied request",
+        request: request.clone(),
+        request_head,
+        deadline: Instant::now() + proxy.timeout.unwrap_or(DEFAULT_PROXY_TIMEOUT),
+    };
+    match UpstreamRequest::connect(
+        upstream,
+        outgoing,
+        Reader::Http(ProxyRelay::default()),
+        exchange,
+    ) {
+        Ok(request) => Ok(request.with_lease(lease)),
+        Err(code) => {
+            lease.report(false);
+            Err(code)
+        }
+    }
+}
+
+// Fonction pour remplacer le préfixe de la route par celui du serveur mandaté
+pub fn rewrite_path(prefix: &str, replacement: Option<&str>, uri: &Uri) -> String {
+    let path = uri.path();
+    let mut target = match (replacement, path.strip_prefix(prefix.trim_end_matches('/'))) {
+        (Some(replacement), Some(rest)) => {
+            format!("{}{}", replacement.trim_end_matches('/'), rest)
+        }
+        _ => path.to_string(),
+    };
+    if !target.starts_with('/') {
+        target.insert(0, '/');
+    }
+    if let Some(query) = uri.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    target
+}
+
+// Fonction pour vérifier si un en-tête est propre à la connexion
+fn is_hop_by_hop(name: &HeaderName, headers: &HeaderMap) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name.as_str())
+        || headers.get_all(CONNECTION).iter().any(|value| {
+            value
+                .to_str()
+                .unwrap_or_default()
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case(name.as_str()))
+        })
+}
+
+// Fonction pour encoder la requête transmise au serveur mandaté
+pub fn encode_request(request: &Request<Bytes>, target: &str, upstream: &Address) -> Bytes {
+    // Un client HTTP/1.0 ne sait pas lire une réponse découpée en chunks
+    let version = match request.version() {
+        Version::HTTP_10 => "HTTP/1.0",
+        _ => "HTTP/1.1",
+    };
+    let mut head = format!("{} {} {}\r\n", request.method(), target, version);
+
+    let headers = request.headers();
+    for name in headers.keys() {
+        // Le corps a déjà été reçu en entier : sa longueur est connue
+        if is_hop_by_hop(name, headers) || [CONTENT_LENGTH, EXPECT].contains(name) {
+            continue;
+        }
+        if [FORWARDED.as_str(), "x-forwarded-for", "x-forwarded-proto"].contains(&name.as_str()) {
+            continue;
+        }
+        // Les valeurs sont transmises telles qu'elles ont été reçues (jetons, clés WebSocket)
+        for value in raw_header_values(request, name) {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    if !headers.contains_key(HOST) {
+        let host = match upstream {
+            Address::Tcp(address) => address,
+            Address::Unix(_) => "localhost",
+        };
+        head.push_str(&format!("host: {host}\r\n"));
+    }
+    for (name, value) in forwarded_headers(request) {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !request.body().is_empty() || headers.contains_key(CONTENT_LENGTH) {
+        head.push_str(&format!("content-length: {}\r\n", request.body().len()));
+    }
+    // Le changement de protocole demandé par le client (WebSocket) est transmis au serveur
+    match headers.get(UPGRADE).filter(|_| is_upgrade_request(request)) {
+        Some(protocol) => head.push_str(&format!(
+            "upgrade: {}\r\nconnection: upgrade\r\n\r\n",
+            protocol.to_str().unwrap_or_default()
+        )),
+        None => head.push_str("connection: close\r\n\r\n"),
+    }
+
+    let mut out = head.into_bytes();
+    out.extend(request.body());
+    out
+}
+
+// Fonction pour ajouter le client aux en-têtes `X-Forwarded-For`, `X-Forwarded-Proto`
+// et `Forwarded` (RFC 7239) reçus
+fn forwarded_headers(request: &Request<Bytes>) -> Vec<(&'static str, String)> {
+    let headers = request.headers();
+    let joined = |name: &str| {
+        let values = headers.get_all(name).iter();
+        let values = values
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>();
+        (!values.is_empty()).then(|| values.join(", "))
+    };
+    let append = |previous: Option<String>, value: String| match previous {
+        Some(previous) => format!("{previous}, {value}"),
+        None => value,
+    };
+
+    let client = request
+        .extensions()
+        .get::<Addresses>()
+        .map(|a| a.remote.ip());
+    let proto = match request.extensions().get::<TlsSession>() {
+        Some(_) => "https",
+        None => "http",
+    };
+    let node = match client {
+        Some(IpAddr::V6(ip)) => format!("\"[{ip}]\""),
+        Some(ip) => ip.to_string(),
+        None => "unknown".to_string(),
+    };
+    let mut forwarded = format!("for={node};proto={proto}");
+    if let Some(host) = headers.get(HOST).and_then(|host| host.to_str().ok()) {
+        forwarded.push_str(&format!(";host=\"{host}\""));
+    }
+
+    let mut fields = vec![("forwarded", append(joined(FORWARDED.as_str()), forwarded))];
+    if let Some(client) = client {
+        fields.push((
+            "x-forwarded-for",
+            append(joined("x-forwarded-for"), client.to_string()),
+        ));
+    }
+    fields.push(("x-forwarded-proto", proto.to_string()));
+    fields
+}
+
+/// # ProxyRelay
+///
+/// Transmet au client la réponse d'un serveur mandaté : l'en-tête est réécrit sans les
+/// en-têtes propres à la connexion, puis le corps est transmis tel quel.
+#[derive(Debug, Default)]
+pub struct ProxyRelay {
+    head: Bytes,
+    sent: bool,
+    tunnel: bool,
+}
+
+impl ProxyRelay {
+    // Vrai si l'en-tête de la réponse a déjà été envoyé au client
+    pub fn has_responded(&self) -> bool {
+        self.sent
+    }
+
+    // Vrai après une réponse `101 Switching Protocols` : les octets passent tels quels
+    pub fn is_tunnel(&self) -> bool {
+        self.tunnel
+    }
+
+    // Fonction pour transmettre une partie de la réponse ; `closed` à la fin de la réponse
+    pub fn feed(
+        &mut self,
+        request: &Request<Bytes>,
+        data: Bytes,
+        closed: bool,
+    ) -> Result<CgiProgress, StatusCode> {
+        let progress = |bytes| match closed {
+            true => CgiProgress::Done(bytes),
+            false => CgiProgress::Running(bytes),
+        };
+        if self.sent {
+            return Ok(progress(data));
+        }
+
+        self.head.extend(data);
+        let mut bytes = Bytes::new();
+        loop {
+            let Some((end, body_start)) = header_end(&self.head) else {
+                if closed || self.head.len() > MAX_RESPONSE_HEAD {
+                    return Err(StatusCode::BAD_GATEWAY);
+                }
+                return Ok(progress(bytes));
+            };
+
+            let (status, response) = response_head(&self.head[..end], request.version())?;
+            let rest = self.head.split_off(body_start);
+            bytes.extend(format_response(response));
+
+            // Les réponses intermédiaires (`100 Continue`, `103 Early Hints`) précèdent la réponse
+            let upgraded = status == StatusCode::SWITCHING_PROTOCOLS && is_upgrade_request(request);
+            if status.is_informational() && !upgraded {
+                self.head = rest;
+                continue;
+            }
+            self.sent = true;
+            self.tunnel = upgraded;
+            self.head.clear();
+            bytes.extend(rest);
+            return Ok(progress(bytes));
+        }
+    }
+}
+
+// Fonction pour réécrire l'en-tête d'une réponse mandatée pour le client
+fn response_head(
+    section: &[u8],
+    version: Version,
+) -> Result<(StatusCode, Response<Bytes>), StatusCode> {
+    let line_end = section
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(section.len());
+    let status_line =
+        std::str::from_utf8(&section[..line_end]).map_err(|_| StatusCode::BAD_GATEWAY)?;
+    if !status_line.starts_with("HTTP/") {
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| StatusCode::from_bytes(code.as_bytes()).ok())
+        .ok_or(StatusCode::BAD_GATEWAY)?;
+
+    let fields = parse_headers(&section[line_end..])?;
+    let mut received = HeaderMap::new();
+    for (name, value) in &fields {
+        received.append(name, value.clone());
+    }
+
+    let mut response = Response::builder().status(status).version(version);
+    for (name, value) in fields {
+        // Le corps est transmis tel quel : son découpage en chunks est conservé
+        if name == TRANSFER_ENCODING || !is_hop_by_hop(&name, &received) {
+            response = response.header(name, value);
+        }
+    }
+    if status == StatusCode::SWITCHING_PROTOCOLS {
+        if let Some(protocol) = received.get(UPGRADE) {
+            response = response.header(UPGRADE, protocol);
+        }
+        response = response.header(CONNECTION, HeaderValue::from_static("Upgrade"));
+    } else if !status.is_informational() {
+        response = response.header(CONNECTION, HeaderValue::from_static("close"));
+    }
+    let response = response
+        .body(Bytes::new())
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    Ok((status, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::config::server_config;
+    use crate::server::{resolve_gateways, resolved_address, Gateway};
+    use std::net::SocketAddr;
+
+    const UPSTREAMS: [Upstream; 1] = [Upstream::new(Address::Tcp("localhost:3999"))];
+
+    fn proxy() -> Proxy<'static> {
+        Proxy {
+            upstreams: &UPSTREAMS,
+            balance: Balance::RoundRobin,
+            ejection: None,
+            health_check: None,
+            path: None,
+            timeout: None,
+        }
+    }
+
+    fn request(uri: &str, headers: &[(&str, &str)], body: &str) -> Request<Bytes> {
+        let mut request = Request::builder().method("POST").uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut request = request.body(Bytes::from(body)).unwrap();
+        let remote: SocketAddr = "192.0.2.7:4000".parse().unwrap();
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        request.extensions_mut().insert(Addresses { remote, local });
+        request
+    }
+
+    #[test]
+    fn path_prefix_is_rewritten() {
+        let uri: Uri = "/api/users?id=1".parse().unwrap();
+        assert_eq!(rewrite_path("/api", Some("/"), &uri), "/users?id=1");
+        assert_eq!(rewrite_path("/api", Some("/v1/"), &uri), "/v1/users?id=1");
+        assert_eq!(rewrite_path("/api", None, &uri), "/api/users?id=1");
+        let uri: Uri = "/api".parse().unwrap();
+        assert_eq!(rewrite_path("/api", Some("/"), &uri), "/");
+    }
+
+    #[test]
+    fn forwarded_request() {
+        let request = request(
+            "/api/users",
+            &[
+                ("Host", "example.com"),
+                ("Connection", "close, X-Secret"),
+                ("X-Secret", "1"),
+                ("Keep-Alive", "timeout=5"),
+                ("X-Forwarded-For", "203.0.113.1"),
+                ("Accept", "*/*"),
+            ],
+            "a=b",
+        );
+        let out = encode_request(&request, "/users", &Address::Tcp("127.0.0.1:3000"));
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("POST /users HTTP/1.1\r\n"), "{out}");
+        assert!(out.contains("host: example.com\r\n"));
+        assert!(out.contains("accept: */*\r\n"));
+        assert!(out.contains("x-forwarded-for: 203.0.113.1, 192.0.2.7\r\n"));
+        assert!(out.contains("x-forwarded-proto: http\r\n"));
+        assert!(out.contains("forwarded: for=192.0.2.7;proto=http;host=\"example.com\"\r\n"));
+        assert!(out.contains("content-length: 3\r\n"));
+        assert!(out.ends_with("connection: close\r\n\r\na=b"));
+        for hop_by_hop in ["x-secret", "keep-alive", "connection: close, x-secret"] {
+            assert!(!out.contains(hop_by_hop), "{hop_by_hop} in {out}");
+        }
+    }
+
+    #[test]
+    fn response_is_relayed() {
+        let request = request("/", &[], "");
+        let mut relay = ProxyRelay::default();
+
+        let first = relay
+            .feed(
+                &request,
+                b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nKeep".to_vec(),
+                false,
+            )
+            .unwrap();
+        assert!(matches!(first, CgiProgress::Running(ref b) if b.starts_with(b"HTTP/1.1 100")));
+        assert!(!relay.has_responded());
+
+        let head = b"-Alive: timeout=5\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n";
+        let CgiProgress::Running(bytes) = relay.feed(&request, head.to_vec(), false).unwrap()
+        else {
+            panic!("response is not complete");
+        };
+        let bytes = String::from_utf8(bytes).unwrap();
+        assert!(bytes.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(bytes.contains("transfer-encoding: chunked\r\n"));
+        assert!(bytes.contains("connection: close\r\n"));
+        assert!(!bytes.contains("keep-alive"));
+        assert!(bytes.ends_with("\r\n\r\n3\r\nabc\r\n"));
+
+        let last = relay.feed(&request, b"0\r\n\r\n".to_vec(), true).unwrap();
+        assert!(matches!(last, CgiProgress::Done(ref b) if b == b"0\r\n\r\n"));
+
+        // A connection closed before the head is complete is a gateway error
+        let mut relay = ProxyRelay::default();
+        assert_eq!(
+            relay
+                .feed(&request, b"HTTP/1.1 200".to_vec(), true)
+                .unwrap_err(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[test]
+    fn upstream_addresses_are_resolved_once() {
+        let mut config = server_config().remove(0);
+        let route = config.routes.iter_mut().find(|r| r.settings.is_some());
+        route.unwrap().settings.as_mut().unwrap().gateway = Some(Gateway::Proxy(proxy()));
+
+        assert!(resolved_address("localhost:3999").is_err());
+        resolve_gateways(&config);
+        assert!(resolved_address("localhost:3999")
+            .unwrap()
+            .ip()
+            .is_loopback());
+        // Une adresse IP n'a pas besoin d'être résolue
+        assert_eq!(resolved_address("127.0.0.1:3000").unwrap().port(), 3000);
+    }
+
+    #[test]
+    fn bodies_over_the_limit_are_not_forwarded() {
+        let mut config = server_config().remove(0);
+        config.body_size_limit = 2;
+        let route = config.routes[0].clone();
+        let request = request("/api", &[], "a=b");
+        let result = proxy_request(&route, &proxy(), &request, String::new(), &config);
+        assert_eq!(result.err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+}
@@ -0,0 +1,236 @@
+use crate::server::Addresses;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// # PROXY_HEADER_MAX_SIZE
+///
+/// Taille maximale d'un en-tête PROXY : 16 octets fixes et 65535 octets d'adresses et
+/// d'extensions en version 2 (la version 1 tient en 107 octets).
+pub const PROXY_HEADER_MAX_SIZE: usize = 16 + u16::MAX as usize;
+
+// Longueur maximale d'une ligne PROXY v1, CRLF compris
+const V1_MAX_SIZE: usize = 107;
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// # ProxyHeader
+///
+/// En-tête PROXY (HAProxy, v1 texte ou v2 binaire) qu'un répartiteur de charge envoie au début
+/// de la connexion pour transmettre les adresses du client d'origine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProxyHeader {
+    // Adresses du client et de la destination d'origine ; `None` pour les connexions du
+    // répartiteur lui-même (`LOCAL`, `UNKNOWN`) ou les familles autres que TCP/UDP sur IP
+    pub addresses: Option<Addresses>,
+    // Nombre d'octets de l'en-tête, à retirer avant la requête
+    pub length: usize,
+}
+
+// Fonction pour lire l'en-tête PROXY au début des octets reçus ; `None` tant qu'il est incomplet
+pub fn parse_proxy_header(data: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    if data.starts_with(V1_PREFIX) {
+        return parse_v1(data);
+    }
+    if data.starts_with(V2_SIGNATURE) {
+        return parse_v2(data);
+    }
+    // Attendre la suite si les octets reçus commencent l'une des deux signatures
+    let prefix = |signature: &[u8]| signature.starts_with(&data[..data.len().min(signature.len())]);
+    match prefix(V1_PREFIX) || prefix(V2_SIGNATURE) {
+        true => Ok(None),
+        false => Err(invalid("missing PROXY protocol header")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// Version 1 : `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn parse_v1(data: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    let window = &data[..data.len().min(V1_MAX_SIZE)];
+    let Some(end) = window.windows(2).position(|w| w == b"\r\n") else {
+        return match data.len() >= V1_MAX_SIZE {
+            true => Err(invalid("PROXY protocol v1 line too long")),
+            false => Ok(None),
+        };
+    };
+    let line = std::str::from_utf8(&data[..end]).map_err(|_| invalid("invalid PROXY line"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let addresses = match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+        {
+            let ip = |address: &str| match (family, address.parse::<IpAddr>()) {
+                ("TCP4", Ok(ip @ IpAddr::V4(_))) | ("TCP6", Ok(ip @ IpAddr::V6(_))) => Ok(ip),
+                _ => Err(invalid("invalid PROXY address")),
+            };
+            let port = |port: &str| {
+                port.parse::<u16>()
+                    .map_err(|_| invalid("invalid PROXY port"))
+            };
+            Some(Addresses {
+                remote: SocketAddr::new(ip(source)?, port(source_port)?),
+                local: SocketAddr::new(ip(destination)?, port(destination_port)?),
+            })
+        }
+        _ => return Err(invalid("invalid PROXY line")),
+    };
+    Ok(Some(ProxyHeader {
+        addresses,
+        length: end + 2,
+    }))
+}
+
+// Version 2 : signature, version et commande, famille, longueur puis adresses et extensions
+fn parse_v2(data: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    if data.len() < 16 {
+        return Ok(None);
+    }
+    let (version, command, family) = (data[12] >> 4, data[12] & 0x0f, data[13]);
+    let length = 16 + u16::from_be_bytes([data[14], data[15]]) as usize;
+    if version != 2 || command > 1 {
+        return Err(invalid("unsupported PROXY protocol v2 command"));
+    }
+    let Some(payload) = data.get(16..length) else {
+        return Ok(None);
+    };
+
+    // `LOCAL` : connexion ouverte par le répartiteur lui-même, pour ses vérifications
+    let addresses = match (command, family >> 4) {
+        (0, _) => None,
+        // TCP ou UDP sur IPv4
+        (_, 0x1) => {
+            let bytes: &[u8; 12] = payload
+                .get(..12)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| invalid("truncated PROXY addresses"))?;
+            let ip = |at: usize| {
+                Ipv4Addr::from([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+            };
+            let port = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+            Some(Addresses {
+                remote: SocketAddr::new(ip(0).into(), port(8)),
+                local: SocketAddr::new(ip(4).into(), port(10)),
+            })
+        }
+        // TCP ou UDP sur IPv6
+        (_, 0x2) => {
+            let bytes: &[u8; 36] = payload
+                .get(..36)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| invalid("truncated PROXY addresses"))?;
+            let ip = |at: usize| {
+                let octets: [u8; 16] = bytes[at..at + 16].try_into().unwrap_or_default();
+                Ipv6Addr::from(octets)
+            };
+            let port = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+            Some(Addresses {
+                remote: SocketAddr::new(ip(0).into(), port(32)),
+                local: SocketAddr::new(ip(16).into(), port(34)),
+            })
+        }
+        // Famille non précisée ou sockets Unix : les adresses de la connexion sont conservées
+        _ => None,
+    };
+    Ok(Some(ProxyHeader { addresses, length }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(remote: &str, local: &str) -> Option<Addresses> {
+        Some(Addresses {
+            remote: remote.parse().unwrap(),
+            local: local.parse().unwrap(),
+        })
+    }
+
+    #[test]
+    fn v1_headers() {
+        let data = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n";
+        let header = parse_proxy_header(data).unwrap().unwrap();
+        assert_eq!(
+            header.addresses,
+            addresses("192.0.2.1:56324", "198.51.100.1:443")
+        );
+        assert_eq!(&data[header.length..], b"GET / HTTP/1.1\r\n");
+
+        let data = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        let header = parse_proxy_header(data).unwrap().unwrap();
+        assert_eq!(
+            header.addresses,
+            addresses("[2001:db8::1]:4000", "[2001:db8::2]:80")
+        );
+
+        let header = parse_proxy_header(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+        assert_eq!((header.addresses, header.length), (None, 15));
+    }
+
+    #[test]
+    fn v1_incomplete_and_invalid() {
+        for partial in [&b"PRO"[..], b"PROXY TCP4 192.0.2.1", b"\r\n\r\n\0"] {
+            assert!(parse_proxy_header(partial).unwrap().is_none());
+        }
+        for data in [
+            &b"GET / HTTP/1.1\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 192.0.2.2 1 2\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 1\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 1 70000\r\n",
+            &[b'P', b'R', b'O', b'X', b'Y', b' '].repeat(20),
+        ] {
+            assert!(parse_proxy_header(data).is_err(), "{data:?}");
+        }
+    }
+
+    fn v2(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x20 | command, family]);
+        data.extend((payload.len() as u16).to_be_bytes());
+        data.extend(payload);
+        data
+    }
+
+    #[test]
+    fn v2_headers() {
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        // Extension (TLV) ignorée
+        payload.extend([0x04, 0x00, 0x02, 0xab, 0xcd]);
+        let mut data = v2(1, 0x11, &payload);
+        data.extend(b"GET");
+        let header = parse_proxy_header(&data).unwrap().unwrap();
+        assert_eq!(
+            header.addresses,
+            addresses("192.0.2.1:56324", "198.51.100.1:443")
+        );
+        assert_eq!(&data[header.length..], b"GET");
+        for end in 0..header.length {
+            assert!(parse_proxy_header(&data[..end]).unwrap().is_none());
+        }
+
+        let mut payload = [0; 36];
+        payload[15] = 1;
+        payload[31] = 2;
+        payload[32..].copy_from_slice(&[0x0f, 0xa0, 0x00, 0x50]);
+        let header = parse_proxy_header(&v2(1, 0x21, &payload)).unwrap().unwrap();
+        assert_eq!(header.addresses, addresses("[::1]:4000", "[::2]:80"));
+
+        // LOCAL et sockets Unix : les adresses de la connexion sont conservées
+        let header = parse_proxy_header(&v2(0, 0x00, &[])).unwrap().unwrap();
+        assert_eq!((header.addresses, header.length), (None, 16));
+        let header = parse_proxy_header(&v2(1, 0x31, &[0; 216]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.addresses, None);
+    }
+
+    #[test]
+    fn v2_invalid() {
+        assert!(parse_proxy_header(&v2(2, 0x11, &[0; 12])).is_err());
+        assert!(parse_proxy_header(&v2(1, 0x11, &[0; 4])).is_err());
+        let mut data = v2(1, 0x11, &[0; 12]);
+        data[12] = 0x11;
+        assert!(parse_proxy_header(&data).is_err());
+    }
+}
@@ -1,5 +1,108 @@
 use crate::server::{Request, Route, ServerConfig, StatusCode};
 use crate::type_aliases::Bytes;
+use http::HeaderName;
+use std::net::SocketAddr;
+
+/// # RawHead
+///
+/// En-tête de la requête tel qu'il a été reçu, avant la normalisation des en-têtes.
+/// Disponible dans les extensions des requêtes construites par `get_request`.
+#[derive(Clone, Debug)]
+pub struct RawHead(pub String);
+
+// Fonction pour obtenir les valeurs d'un en-tête telles qu'elles ont été reçues, sans
+// la mise en minuscules appliquée par `get_request`
+pub fn raw_header_values(request: &Request<Bytes>, name: &HeaderName) -> Vec<String> {
+    let Some(RawHead(head)) = request.extensions().get::<RawHead>() else {
+        let values = request.headers().get_all(name).iter();
+        return values
+            .filter_map(|value| value.to_str().ok())
+            .map(String::from)
+            .collect();
+    };
+    head.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(key, _)| key.trim().eq_ignore_ascii_case(name.as_str()))
+        .map(|(_, value)| value.trim().to_string())
+        .collect()
+}
+
+/// # Addresses
+///
+/// Adresses du client et du serveur pour la connexion qui a reçu la requête.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Addresses {
+    pub remote: SocketAddr,
+    pub local: SocketAddr,
+}
+
+/// # MAX_HEAD_SIZE
+///
+/// Taille maximale de la ligne de requête et des en-têtes reçus sur une connexion HTTP/1.
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+// Fonction pour délimiter la première requête des octets reçus : en-tête, corps tel qu'il a été
+// transmis et nombre d'octets consommés ; `None` tant que la requête est incomplète
+pub fn split_request(
+    data: &[u8],
+    limit: usize,
+) -> Result<Option<(String, Bytes, usize)>, StatusCode> {
+    let Some(head_end) = data.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return match data.len() > MAX_HEAD_SIZE {
+            true => Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            false => Ok(None),
+        };
+    };
+    if head_end > MAX_HEAD_SIZE {
+        return Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let rest = &data[head_end + 4..];
+
+    // La longueur du corps est donnée par `Content-Length` ou par le découpage en chunks
+    let mut length = None;
+    let mut codings = Vec::new();
+    for (name, value) in head.lines().skip(1).filter_map(|line| line.split_once(':')) {
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            codings.extend(
+                value
+                    .split(',')
+                    .map(|coding| coding.trim().to_ascii_lowercase()),
+            );
+        } else if name.eq_ignore_ascii_case("content-length") {
+            let value = value
+                .parse::<usize>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+            if length.is_some_and(|length| length != value) {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            length = Some(value);
+        }
+    }
+    // RFC 9112 §6.1 et §6.3 : seul `chunked` est pris en charge, une seule fois et en dernier, et
+    // jamais avec `Content-Length` ; toute autre combinaison ferait lire le corps comme la
+    // requête suivante
+    let chunked = !codings.is_empty();
+    if codings
+        .iter()
+        .any(|coding| coding != "chunked" && !coding.is_empty())
+    {
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+    if chunked && (codings.len() > 1 || codings[0] != "chunked" || length.is_some()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let length = match chunked {
+        true => body::chunked_length(rest, limit)?,
+        false => match length.unwrap_or(0) {
+            length if length > limit => return Err(StatusCode::PAYLOAD_TOO_LARGE),
+            length => Some(length).filter(|&length| length <= rest.len()),
+        },
+    };
+    Ok(length.map(|length| (head, rest[..length].to_vec(), head_end + 4 + length)))
+}
 
 // Fonction pour construire une requête HTTP à partir des parties de la requête
 pub fn get_request(
@@ -16,7 +119,8 @@ pub fn get_request(
     let mut request_builder = http::Request::builder()
         .method(method)
         .uri(path)
-        .version(version);
+        .version(version)
+        .extension(RawHead(head.clone()));
 
     for header in headers::get_headers(head) {
         if let Some((key, value)) = headers::format_header(header) {
@@ -91,6 +195,14 @@ pub mod path {
         }
     }
 
+    /// `has_dot_segment` vérifie si le chemin contient un segment `.` ou `..`, même encodé (`%2e`)
+    pub fn has_dot_segment(path: &str) -> bool {
+        path.split('/').any(|segment| {
+            let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+            segment == "." || segment == ".."
+        })
+    }
+
     // Ajouter le chemin racine au chemin de la requête
     pub fn add_root_to_path(route: &Route, path: &str) -> String {
         if let Some(settings) = &route.settings {
@@ -117,8 +229,16 @@ pub mod path {
             let expected_path = "./foo".to_string();
             assert_eq!(add_root_to_path(&route, path), expected_path);
         }
-    }
 
+        #[test]
+        fn test_has_dot_segment() {
+            assert!(has_dot_segment("/dav/../etc"));
+            assert!(has_dot_segment("/dav/./a"));
+            assert!(has_dot_segment("/dav/%2E%2e/etc"));
+            assert!(has_dot_segment("/dav/.."));
+            assert!(!has_dot_segment("/dav/..a/.b/"));
+        }
+    }
 }
 
 pub mod version {
@@ -148,7 +268,6 @@ pub mod version {
             }
         }
     }
-
 }
 
 pub mod headers {
@@ -214,6 +333,54 @@ pub mod body {
         }
     }
 
+    // Fonction pour trouver la fin d'un corps en mode chunked, trailers compris ;
+    // `None` tant que le dernier chunk n'est pas arrivé
+    pub(crate) fn chunked_length(data: &[u8], limit: usize) -> Result<Option<usize>, StatusCode> {
+        let line_end = |from: usize| {
+            let rest = data.get(from..).unwrap_or_default();
+            rest.windows(2).position(|window| window == b"\r\n")
+        };
+        let (mut position, mut total) = (0, 0usize);
+        loop {
+            let Some(end) = line_end(position) else {
+                return Ok(None);
+            };
+            // Ignorer les extensions qui suivent la taille
+            let line = String::from_utf8_lossy(&data[position..position + end]);
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| StatusCode::BAD_REQUEST)?;
+            position += end + 2;
+
+            if size == 0 {
+                // Les trailers se terminent par une ligne vide
+                loop {
+                    let Some(end) = line_end(position) else {
+                        return Ok(None);
+                    };
+                    position += end + 2;
+                    if end == 0 {
+                        return Ok(Some(position));
+                    }
+                }
+            }
+
+            // Une taille annoncée peut dépasser tout ce que `usize` peut compter
+            total = total
+                .checked_add(size)
+                .filter(|total| *total <= limit)
+                .ok_or(StatusCode::PAYLOAD_TOO_LARGE)?;
+            let chunk_end = position
+                .checked_add(size)
+                .and_then(|end| end.checked_add(2))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            match data.get(chunk_end - 2..chunk_end) {
+                None => return Ok(None),
+                Some(b"\r\n") => position = chunk_end,
+                Some(_) => return Err(StatusCode::BAD_REQUEST),
+            }
+        }
+    }
+
     // Fonction pour obtenir le corps en mode chunked
     pub(crate) fn get_chunked_body(body: Bytes, limit: usize) -> Result<Bytes, StatusCode> {
         let mut result_body = Vec::new();
@@ -222,18 +389,19 @@ pub mod body {
         while !remaining_data.is_empty() {
             // Séparer à la première occurrence de CRLF
             if let Some((size_str, rest)) = split_once_str(remaining_data, b'\r', b'\n') {
-                // Analyser la taille du chunk
-                let chunk_size =
-                    match usize::from_str_radix(String::from_utf8_lossy(size_str).trim(), 16) {
-                        Ok(size) => size,
-                        Err(_) => {
-                            log!(
-                                LogFileType::Server,
-                                "Error: Failed to parse chunk size".to_string()
-                            );
-                            return Err(StatusCode::BAD_REQUEST);
-                        }
-                    };
+                // Analyser la taille du chunk, sans ses éventuelles extensions
+                let size_str = String::from_utf8_lossy(size_str);
+                let size_str = size_str.split(';').next().unwrap_or_default();
+                let chunk_size = match usize::from_str_radix(size_str.trim(), 16) {
+                    Ok(size) => size,
+                    Err(_) => {
+                        log!(
+                            LogFileType::Server,
+                            "Error: Failed to parse chunk size".to_string()
+                        );
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                };
 
                 // Vérifier la fin du corps en mode chunked
                 if chunk_size == 0 {
@@ -241,7 +409,7 @@ pub mod body {
                 }
 
                 // S'assurer qu'il y a suffisamment de données pour le chunk
-                if rest.len() < chunk_size + 2 {
+                if rest.len() < chunk_size.saturating_add(2) {
                     log!(
                         LogFileType::Server,
                         "Error: Not enough data for chunk".to_string()
@@ -317,5 +485,117 @@ pub mod utils {
             lines[0]
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: usize = 100;
+
+    #[test]
+    fn split_pipelined_requests() {
+        let data = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n\
+                     POST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /c";
+        let (head, body, used) = split_request(data, LIMIT).unwrap().unwrap();
+        assert_eq!(head, "GET /a HTTP/1.1\r\nHost: x");
+        assert!(body.is_empty());
+
+        let rest = &data[used..];
+        let (head, body, used) = split_request(rest, LIMIT).unwrap().unwrap();
+        assert_eq!(head, "POST /b HTTP/1.1\r\nContent-Length: 5");
+        assert_eq!(body, b"hello");
+        assert_eq!(&rest[used..], b"GET /c");
+        assert_eq!(split_request(&rest[used..], LIMIT), Ok(None));
+    }
+
+    #[test]
+    fn split_waits_for_the_whole_body() {
+        let data = b"PUT / HTTP/1.1\r\ncontent-length: 10\r\n\r\n12345";
+        assert_eq!(split_request(data, LIMIT), Ok(None));
+        let data = b"PUT / HTTP/1.1\r\nContent-Length: 101\r\n\r\n";
+        assert_eq!(
+            split_request(data, LIMIT),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        let data = b"PUT / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n";
+        assert_eq!(split_request(data, LIMIT), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn split_chunked_bodies() {
+        let body = b"5;ext=1\r\nhello\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let mut data = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        data.extend(body);
+        for end in 0..body.len() {
+            let partial = &data[..data.len() - body.len() + end];
+            assert_eq!(split_request(partial, LIMIT), Ok(None));
+        }
+        data.extend(b"GET");
+        let (_, raw, used) = split_request(&data, LIMIT).unwrap().unwrap();
+        assert_eq!(raw, body);
+        assert_eq!(&data[used..], b"GET");
+        assert_eq!(body::get_chunked_body(raw, LIMIT), Ok(b"hello".to_vec()));
+
+        assert_eq!(
+            body::chunked_length(b"zz\r\n", LIMIT),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            body::chunked_length(b"65\r\n", LIMIT),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        let overflowing = b"1\r\na\r\nffffffffffffffff\r\n";
+        assert_eq!(
+            body::chunked_length(overflowing, usize::MAX),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        assert_eq!(
+            body::chunked_length(&overflowing[5..], usize::MAX),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            body::get_chunked_body(overflowing[5..].to_vec(), usize::MAX),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn split_rejects_ambiguous_framing() {
+        let framing = |headers: &str| {
+            let data = format!("POST / HTTP/1.1\r\n{headers}\r\n\r\n0\r\n\r\n");
+            split_request(data.as_bytes(), LIMIT).map(|split| split.is_some())
+        };
+        assert_eq!(framing("Transfer-Encoding: Chunked"), Ok(true));
+        assert_eq!(
+            framing("Transfer-Encoding: gzip, chunked"),
+            Err(StatusCode::NOT_IMPLEMENTED)
+        );
+        assert_eq!(
+            framing("Transfer-Encoding: chunked, identity"),
+            Err(StatusCode::NOT_IMPLEMENTED)
+        );
+        assert_eq!(
+            framing("Transfer-Encoding: chunked\r\nTransfer-Encoding: identity"),
+            Err(StatusCode::NOT_IMPLEMENTED)
+        );
+        for headers in [
+            "Transfer-Encoding: chunked, chunked",
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked",
+            "Transfer-Encoding: ",
+            "Transfer-Encoding: chunked\r\nContent-Length: 5",
+            "Content-Length: 5\r\nTransfer-Encoding: chunked",
+        ] {
+            assert_eq!(framing(headers), Err(StatusCode::BAD_REQUEST), "{headers}");
+        }
+    }
 
+    #[test]
+    fn split_rejects_oversized_heads() {
+        let data = vec![b'a'; MAX_HEAD_SIZE + 1];
+        assert_eq!(
+            split_request(&data, LIMIT),
+            Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+    }
 }
@@ -1,10 +1,15 @@
-use crate::server::{Bytes, Response, ServerConfig, StatusCode, BUFFER_SIZE};
-use http::header::TRANSFER_ENCODING;
-use http::Version;
+use crate::server::{Bytes, RawResponse, Response, ServerConfig, StatusCode, BUFFER_SIZE};
+use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderValue, Version};
 use std::fs;
 
 // Fonction pour formater une réponse HTTP
 pub fn format_response(response: Response<Bytes>) -> Bytes {
+    // Le corps d'une réponse brute (script CGI `nph-`) est déjà un message HTTP complet
+    if response.extensions().get::<RawResponse>().is_some() {
+        return response.into_body();
+    }
+
     // Séparer la réponse en en-tête et corps
     let (head, body) = response.into_parts();
     let mut resp = Bytes::from(format!("{:?} {}\r\n", head.version, head.status));
@@ -28,7 +33,7 @@ pub fn format_response(response: Response<Bytes>) -> Bytes {
     } else {
         BUFFER_SIZE
     };
-    if is_chunked(head) {
+    if is_chunked(&head.headers) {
         for chunk in body.chunks(chunk_size) {
             resp.extend(format!("{:X}\r\n", chunk.len()).as_bytes());
             resp.extend(chunk);
@@ -42,9 +47,24 @@ pub fn format_response(response: Response<Bytes>) -> Bytes {
     resp
 }
 
+// Fonction pour retirer le corps d'une réponse à HEAD en conservant tous ses en-têtes
+pub fn without_body(mut response: Response<Bytes>) -> Response<Bytes> {
+    // Une réponse brute est envoyée telle que le script l'a produite
+    if response.extensions().get::<RawResponse>().is_some() {
+        return response;
+    }
+    let len = response.body().len();
+    let headers = response.headers_mut();
+    if !is_chunked(headers) && !headers.contains_key(CONTENT_LENGTH) {
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+    }
+    response.body_mut().clear();
+    response
+}
+
 // Fonction pour vérifier si la réponse est en mode chunked
-fn is_chunked(head: http::response::Parts) -> bool {
-    head.headers
+fn is_chunked(headers: &HeaderMap) -> bool {
+    headers
         .get_all(TRANSFER_ENCODING)
         .iter()
         .any(|value| value.to_str().unwrap_or_default().to_uppercase() == "CHUNKED")
@@ -149,7 +169,8 @@ pub mod redirections {
 
 pub mod errors {
     use super::*;
-    use http::header::{CONTENT_LENGTH, HOST};
+    use http::header::{ALLOW, CONTENT_LENGTH, HOST};
+    use http::HeaderValue;
 
     // Fonction pour créer une réponse d'erreur
     pub fn error(code: StatusCode, config: &ServerConfig) -> Response<Bytes> {
@@ -162,6 +183,24 @@ pub mod errors {
             .unwrap()
     }
 
+    // Fonction pour créer une réponse 405 avec l'en-tête `Allow` obligatoire
+    pub fn method_not_allowed(config: &ServerConfig, allow: String) -> Response<Bytes> {
+        let mut resp = error(StatusCode::METHOD_NOT_ALLOWED, config);
+        if let Ok(value) = HeaderValue::from_str(&allow) {
+            resp.headers_mut().insert(ALLOW, value);
+        }
+        resp
+    }
+
+    // Fonction pour créer une réponse 415 avec l'en-tête `Accept-Patch` (RFC 5789, section 2.2)
+    pub fn unsupported_patch_format(config: &ServerConfig, accept: String) -> Response<Bytes> {
+        let mut resp = error(StatusCode::UNSUPPORTED_MEDIA_TYPE, config);
+        if let Ok(value) = HeaderValue::from_str(&accept) {
+            resp.headers_mut().insert("Accept-Patch", value);
+        }
+        resp
+    }
+
     // Fonction pour générer le contenu HTML d'une page d'erreur
     fn generate_error_html(code: u16, name: &str) -> String {
         format!(
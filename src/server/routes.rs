@@ -1,8 +1,8 @@
 use crate::log;
 use crate::log::LogFileType;
-use crate::server::method_is_allowed;
 use crate::server::path::path_exists;
 use crate::server::redirections::is_redirect;
+use crate::server::{allow_header, allowed_methods};
 use crate::server::{Request, Route, ServerConfig, StatusCode};
 use crate::type_aliases::Bytes;
 
@@ -41,7 +41,8 @@ pub fn get_route<'a>(
     }
 
     // Vérifier si la méthode est autorisée pour cette route
-    if !method_is_allowed(req.method(), &route) {
+    let allowed = allowed_methods(&route, config);
+    if !allowed.contains(req.method()) {
         log!(
             LogFileType::Server,
             format!(
@@ -51,7 +52,8 @@ pub fn get_route<'a>(
             )
         );
 
-        return Err((StatusCode::METHOD_NOT_ALLOWED, "".to_string()));
+        // Les méthodes autorisées sont renvoyées pour l'en-tête `Allow`
+        return Err((StatusCode::METHOD_NOT_ALLOWED, allow_header(&allowed)));
     }
 
     Ok(route)
@@ -0,0 +1,122 @@
+use crate::server::route::Route;
+use crate::server::{
+    Address, Bytes, CgiEnv, Exchange, GatewayTarget, Reader, ServerConfig, StatusCode,
+    UpstreamRequest,
+};
+use http::Request;
+
+/// # Protocol
+///
+/// Protocoles des serveurs d'application à une requête par connexion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Scgi,
+    Uwsgi,
+}
+
+// Fonction pour préparer une requête SCGI ou uwsgi vers le serveur d'application de la route
+pub fn scgi_request(
+    route: &Route,
+    protocol: Protocol,
+    address: &Address,
+    request: &Request<Bytes>,
+    request_head: String,
+    config: &ServerConfig,
+) -> Result<UpstreamRequest, StatusCode> {
+    let target = GatewayTarget::new(route, request, config);
+    let outgoing = match protocol {
+        Protocol::Scgi => encode_scgi(&target.env, request.body()),
+        Protocol::Uwsgi => encode_uwsgi(&target.env, request.body())?,
+    };
+
+    let exchange = Exchange {
+        name: target.name,
+        kind: match protocol {
+            Protocol::Scgi => "SCGI request",
+            Protocol::Uwsgi => "uwsgi request",
+        },
+        request: request.clone(),
+        request_head,
+        deadline: target.deadline,
+    };
+    UpstreamRequest::connect(address, outgoing, Reader::cgi(), exchange)
+}
+
+// Fonction pour encoder une requête SCGI : en-têtes dans un netstring, suivis du corps
+pub fn encode_scgi(env: &CgiEnv, body: &[u8]) -> Bytes {
+    // CONTENT_LENGTH doit être le premier en-tête, même pour un corps vide
+    let mut headers = Bytes::new();
+    let length = body.len().to_string();
+    let variables = env.iter().filter(|(name, _)| *name != "CONTENT_LENGTH");
+    for (name, value) in [("CONTENT_LENGTH", length.as_str()), ("SCGI", "1")]
+        .into_iter()
+        .chain(variables.map(|(name, value)| (name.as_str(), value.as_str())))
+    {
+        headers.extend(name.as_bytes());
+        headers.push(0);
+        headers.extend(value.as_bytes());
+        headers.push(0);
+    }
+
+    let mut out = format!("{}:", headers.len()).into_bytes();
+    out.extend(headers);
+    out.push(b',');
+    out.extend(body);
+    out
+}
+
+// Fonction pour encoder une requête uwsgi : en-tête de 4 octets, variables, puis le corps
+pub fn encode_uwsgi(env: &CgiEnv, body: &[u8]) -> Result<Bytes, StatusCode> {
+    let too_large = |_| StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE;
+
+    let mut variables = Bytes::new();
+    for (name, value) in env {
+        for field in [name, value] {
+            variables.extend(u16::try_from(field.len()).map_err(too_large)?.to_le_bytes());
+            variables.extend(field.as_bytes());
+        }
+    }
+
+    let size = u16::try_from(variables.len()).map_err(too_large)?;
+    let mut out = vec![0]; // modifier1 : requête WSGI
+    out.extend(size.to_le_bytes());
+    out.push(0); // modifier2
+    out.extend(variables);
+    out.extend(body);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> CgiEnv {
+        CgiEnv::from([
+            ("CONTENT_LENGTH".to_string(), "3".to_string()),
+            ("REQUEST_METHOD".to_string(), "POST".to_string()),
+        ])
+    }
+
+    #[test]
+    fn scgi_netstring() {
+        let out = encode_scgi(&env(), b"a=b");
+        let headers = "CONTENT_LENGTH\x003\x00SCGI\x001\x00REQUEST_METHOD\x00POST\x00";
+        assert_eq!(out, format!("{}:{headers},a=b", headers.len()).into_bytes());
+    }
+
+    #[test]
+    fn uwsgi_packet() {
+        let out = encode_uwsgi(&env(), b"a=b").unwrap();
+        let size = u16::from_le_bytes([out[1], out[2]]) as usize;
+        assert_eq!((out[0], out[3]), (0, 0));
+        assert_eq!(&out[4..6], [14, 0]);
+        assert_eq!(&out[6..20], b"CONTENT_LENGTH");
+        assert_eq!(&out[4 + size..], b"a=b");
+
+        let huge = CgiEnv::from([("HTTP_X".to_string(), "x".repeat(70_000))]);
+        assert_eq!(
+            encode_uwsgi(&huge, b""),
+            Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+    }
+}
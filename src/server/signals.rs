@@ -0,0 +1,95 @@
+use mio::net::UnixStream;
+use mio::{Interest, Registry, Token};
+use std::io::{self, ErrorKind, Read};
+use std::os::fd::IntoRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// # SIGNAL_TOKEN
+///
+/// Jeton de la boucle d'événements réservé aux signaux reçus par le processus.
+pub const SIGNAL_TOKEN: Token = Token(usize::MAX - 1);
+
+// Extrémité d'écriture du tube des signaux, la seule chose que le gestionnaire utilise
+static SIGNAL_SENDER: AtomicI32 = AtomicI32::new(-1);
+
+/// # Signals
+///
+/// Signaux du processus transmis à la boucle d'événements par un tube : le gestionnaire
+/// n'écrit qu'un octet, et la boucle traite le signal comme tout autre événement.
+#[derive(Debug)]
+pub struct Signals {
+    receiver: UnixStream,
+}
+
+impl Signals {
+    // Fonction pour installer les gestionnaires des signaux et enregistrer le tube
+    pub fn install(registry: &Registry, signals: &[libc::c_int]) -> io::Result<Self> {
+        let (sender, mut receiver) = UnixStream::pair()?;
+        registry.register(&mut receiver, SIGNAL_TOKEN, Interest::READABLE)?;
+        let previous = SIGNAL_SENDER.swap(sender.into_raw_fd(), Ordering::SeqCst);
+        if previous >= 0 {
+            unsafe { libc::close(previous) };
+        }
+
+        for &signal in signals {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as usize;
+            action.sa_flags = libc::SA_RESTART;
+            if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Self { receiver })
+    }
+
+    // Fonction pour lire les signaux reçus depuis le dernier appel, dans l'ordre
+    pub fn received(&mut self) -> Vec<libc::c_int> {
+        let mut signals = Vec::new();
+        let mut buf = [0; 64];
+        loop {
+            match self.receiver.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => signals.extend(buf[..n].iter().map(|&signal| signal as libc::c_int)),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        signals
+    }
+}
+
+// Gestionnaire des signaux : seules des fonctions sûres dans un gestionnaire sont appelées
+extern "C" fn on_signal(signal: libc::c_int) {
+    let fd = SIGNAL_SENDER.load(Ordering::Relaxed);
+    if fd < 0 {
+        return;
+    }
+    // `write` peut modifier errno, que le code interrompu lit peut-être
+    let errno = unsafe { *libc::__errno_location() };
+    let byte = signal as u8;
+    unsafe {
+        libc::write(fd, (&byte as *const u8).cast(), 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mio::{Events, Poll};
+    use std::time::Duration;
+
+    #[test]
+    fn signals_wake_the_poll() {
+        let mut poll = Poll::new().unwrap();
+        let mut signals = Signals::install(poll.registry(), &[libc::SIGWINCH]).unwrap();
+        unsafe { libc::raise(libc::SIGWINCH) };
+
+        let mut events = Events::with_capacity(4);
+        poll.poll(&mut events, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(events.iter().any(|event| event.token() == SIGNAL_TOKEN));
+        assert_eq!(signals.received(), vec![libc::SIGWINCH]);
+        assert!(signals.received().is_empty());
+    }
+}
@@ -0,0 +1,456 @@
+use crate::server::ServerConfig;
+use crate::type_aliases::Path;
+use mio::event::Source;
+use mio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use mio::{Interest, Registry, Token};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// # BindAddress
+///
+/// Adresse IP sur laquelle un serveur lie ses ports : `"127.0.0.1"`, `"::1"`, ou `"0.0.0.0"` et
+/// `"::"` pour toutes les interfaces. Le nom annoncé du serveur (`host`) en est indépendant.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BindAddress<'a> {
+    pub ip: &'a str,
+    // IPV6_V6ONLY pour une adresse IPv6 : `Some(false)` accepte aussi les clients IPv4 sur `"::"`,
+    // `None` conserve la valeur du système
+    pub v6_only: Option<bool>,
+}
+
+/// # ListenOptions
+///
+/// Options des sockets d'écoute TCP d'un serveur.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListenOptions {
+    pub backlog: i32,        // Connexions en attente d'acceptation
+    pub reuse_address: bool, // SO_REUSEADDR : relancer le serveur sans attendre TIME_WAIT
+    pub reuse_port: bool,    // SO_REUSEPORT : plusieurs processus écoutent le même port
+    pub nodelay: bool,       // TCP_NODELAY sur les connexions acceptées
+}
+
+impl Default for ListenOptions {
+    fn default() -> Self {
+        Self {
+            backlog: 1024,
+            reuse_address: true,
+            reuse_port: false,
+            nodelay: false,
+        }
+    }
+}
+
+// Fonction pour créer un socket d'écoute TCP avec les options du serveur
+pub fn bind_tcp_socket(
+    address: SocketAddr,
+    v6_only: Option<bool>,
+    options: &ListenOptions,
+) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(options.reuse_address)?;
+    if options.reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    if let (SocketAddr::V6(_), Some(v6_only)) = (address, v6_only) {
+        socket.set_only_v6(v6_only)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(options.backlog)?;
+    Ok(TcpListener::from_std(socket.into()))
+}
+
+/// # UnixSocket
+///
+/// Socket Unix sur lequel un serveur écoute en plus de ses ports, par exemple pour le
+/// répartiteur de charge de la machine. Un chemin qui commence par `@` désigne un socket de
+/// l'espace de noms abstrait de Linux, sans fichier ni permissions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnixSocket<'a> {
+    pub path: Path<'a>,
+    pub mode: Option<u32>,    // Permissions du fichier, par exemple 0o660
+    pub uid: Option<u32>,     // Propriétaire du fichier (le serveur doit être lancé en root)
+    pub gid: Option<u32>,     // Groupe du fichier
+    pub proxy_protocol: bool, // Les connexions commencent par un en-tête PROXY
+}
+
+// Répertoires privés créés par ce processus pour lier ses sockets Unix
+static PRIVATE_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+// Fonction pour créer un socket Unix d'écoute et lui appliquer ses permissions
+pub fn bind_unix_socket(socket: &UnixSocket) -> io::Result<UnixListener> {
+    if let Some(name) = socket.path.strip_prefix('@') {
+        return bind_abstract(name);
+    }
+
+    // Le fichier laissé par une exécution précédente empêche de lier le chemin ; un serveur
+    // qui y répond encore est en revanche conservé
+    let metadata = fs::symlink_metadata(socket.path);
+    if metadata.is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        if std::os::unix::net::UnixStream::connect(socket.path).is_ok() {
+            return Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                "a server already listens on this socket",
+            ));
+        }
+        fs::remove_file(socket.path)?;
+    }
+
+    // Le socket est créé dans un répertoire que seul ce processus peut traverser, puis lié à
+    // son chemin une fois ses droits et son propriétaire fixés : il n'est jamais joignable
+    // avec les droits qu'aurait donnés l'umask
+    let path = std::path::Path::new(socket.path);
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, "invalid socket path");
+    let name = path.file_name().ok_or_else(invalid)?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    let private = parent.unwrap_or(std::path::Path::new(".")).join(format!(
+        ".{}-{}",
+        std::process::id(),
+        PRIVATE_DIRS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let staged = private.join(name);
+
+    let listener = bind_staged(socket, &staged);
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&private);
+    listener
+}
+
+// Fonction pour lier le socket dans le répertoire privé, fixer ses droits et le rendre
+// visible à son chemin ; un fichier déjà présent à ce chemin n'est jamais remplacé
+fn bind_staged(socket: &UnixSocket, staged: &std::path::Path) -> io::Result<UnixListener> {
+    let listener = UnixListener::bind(staged)?;
+    if let Some(mode) = socket.mode {
+        fs::set_permissions(staged, fs::Permissions::from_mode(mode))?;
+    }
+    if socket.uid.is_some() || socket.gid.is_some() {
+        std::os::unix::fs::chown(staged, socket.uid, socket.gid)?;
+    }
+    fs::hard_link(staged, socket.path)?;
+    Ok(listener)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> io::Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let listener = std::os::unix::net::UnixListener::bind_addr(&address)?;
+    listener.set_nonblocking(true)?;
+    Ok(UnixListener::from_std(listener))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_: &str) -> io::Result<UnixListener> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "abstract Unix sockets are only available on Linux",
+    ))
+}
+
+/// # ServerSocket
+///
+/// Socket d'écoute d'un serveur : un port TCP ou un socket Unix, avec le chemin configuré et
+/// l'indication que ce processus a lui-même créé le fichier du socket. Un socket hérité de
+/// systemd ou de l'ancien binaire garde son fichier à l'arrêt.
+#[derive(Debug)]
+pub enum ServerSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener, String, bool),
+}
+
+impl ServerSocket {
+    pub fn accept(&self) -> io::Result<ClientSocket> {
+        match self {
+            ServerSocket::Tcp(listener) => listener.accept().map(|(s, _)| ClientSocket::Tcp(s)),
+            ServerSocket::Unix(listener, ..) => {
+                listener.accept().map(|(s, _)| ClientSocket::Unix(s))
+            }
+        }
+    }
+
+    // Nom transmis au nouveau binaire dans `LISTEN_FDNAMES` : le chemin configuré d'un socket
+    // Unix, que l'adresse du socket ne donne pas, ou `unknown` comme systemd pour un socket sans
+    // nom. Un chemin contenant `:`, le séparateur des noms, ne peut pas être transmis
+    pub fn name(&self) -> &str {
+        match self {
+            ServerSocket::Unix(_, path, _) if !path.contains(':') => path,
+            _ => "unknown",
+        }
+    }
+
+    // Fonction pour savoir si les connexions acceptées commencent par un en-tête PROXY
+    pub fn expects_proxy_header(&self, config: &ServerConfig) -> bool {
+        match self {
+            ServerSocket::Tcp(listener) => listener
+                .local_addr()
+                .is_ok_and(|address| config.proxy_protocol.contains(&address.port())),
+            ServerSocket::Unix(_, path, _) => config
+                .unix_sockets
+                .iter()
+                .any(|socket| socket.path == path && socket.proxy_protocol),
+        }
+    }
+}
+
+impl AsRawFd for ServerSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            ServerSocket::Tcp(listener) => listener.as_raw_fd(),
+            ServerSocket::Unix(listener, ..) => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl Source for ServerSocket {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            ServerSocket::Tcp(listener) => listener.register(registry, token, interests),
+            ServerSocket::Unix(listener, ..) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            ServerSocket::Tcp(listener) => listener.reregister(registry, token, interests),
+            ServerSocket::Unix(listener, ..) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            ServerSocket::Tcp(listener) => listener.deregister(registry),
+            ServerSocket::Unix(listener, ..) => listener.deregister(registry),
+        }
+    }
+}
+
+/// # ClientSocket
+///
+/// Connexion acceptée sur un port TCP ou sur un socket Unix.
+#[derive(Debug)]
+pub enum ClientSocket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl ClientSocket {
+    // Adresse IP du client ; les sockets Unix n'en ont pas
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.peer_addr(),
+            ClientSocket::Unix(_) => Err(no_ip_address()),
+        }
+    }
+
+    // Adresse IP à laquelle le client s'est connecté
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.local_addr(),
+            ClientSocket::Unix(_) => Err(no_ip_address()),
+        }
+    }
+
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.peek(buf),
+            ClientSocket::Unix(stream) => {
+                let flags = libc::MSG_PEEK;
+                let buffer = buf.as_mut_ptr().cast();
+                match unsafe { libc::recv(stream.as_raw_fd(), buffer, buf.len(), flags) } {
+                    -1 => Err(io::Error::last_os_error()),
+                    received => Ok(received as usize),
+                }
+            }
+        }
+    }
+}
+
+fn no_ip_address() -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, "Unix sockets have no IP address")
+}
+
+impl Read for ClientSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.read(buf),
+            ClientSocket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ClientSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.write(buf),
+            ClientSocket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.flush(),
+            ClientSocket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Source for ClientSocket {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.register(registry, token, interests),
+            ClientSocket::Unix(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.reregister(registry, token, interests),
+            ClientSocket::Unix(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            ClientSocket::Tcp(stream) => stream.deregister(registry),
+            ClientSocket::Unix(stream) => stream.deregister(registry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    fn socket_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("localhost-{}-{name}", std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn reuse_port_shares_the_address() {
+        let options = ListenOptions {
+            reuse_port: true,
+            ..Default::default()
+        };
+        let first = bind_tcp_socket("127.0.0.1:0".parse().unwrap(), None, &options).unwrap();
+        let address = first.local_addr().unwrap();
+        assert!(bind_tcp_socket(address, None, &options).is_ok());
+        // Sans SO_REUSEPORT, le port reste réservé au premier socket
+        let error = bind_tcp_socket(address, None, &ListenOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AddrInUse);
+    }
+
+    #[test]
+    fn v6_only_controls_dual_stack() {
+        let options = ListenOptions::default();
+        let any = "[::]:0".parse().unwrap();
+        let dual = bind_tcp_socket(any, Some(false), &options).unwrap();
+        let port = dual.local_addr().unwrap().port();
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_ok());
+
+        let v6 = bind_tcp_socket(any, Some(true), &options).unwrap();
+        let port = v6.local_addr().unwrap().port();
+        assert!(std::net::TcpStream::connect(("::1", port)).is_ok());
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn stale_socket_files_are_replaced() {
+        let path = socket_path("stale.sock");
+        let socket = UnixSocket {
+            path: &path,
+            mode: Some(0o600),
+            ..Default::default()
+        };
+        drop(bind_unix_socket(&socket).unwrap());
+        // Le fichier reste après la fermeture du premier socket
+        let listener = bind_unix_socket(&socket).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+
+        // Un serveur actif n'est pas remplacé
+        let error = bind_unix_socket(&socket).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AddrInUse);
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sockets_are_linked_once_their_mode_is_set() {
+        let directory = socket_path("private");
+        fs::create_dir_all(&directory).unwrap();
+        let path = format!("{directory}/server.sock");
+        let socket = UnixSocket {
+            path: &path,
+            mode: Some(0o666),
+            ..Default::default()
+        };
+        let _listener = bind_unix_socket(&socket).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o666);
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+
+        // Le répertoire privé dans lequel le socket a été créé est supprimé
+        let entries: Vec<_> = fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn other_files_are_kept() {
+        let path = socket_path("regular");
+        fs::write(&path, "data").unwrap();
+        let socket = UnixSocket {
+            path: &path,
+            ..Default::default()
+        };
+        assert!(bind_unix_socket(&socket).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn abstract_sockets() {
+        let name = format!("@localhost-{}-abstract", std::process::id());
+        let socket = UnixSocket {
+            path: &name,
+            ..Default::default()
+        };
+        let listener = bind_unix_socket(&socket).unwrap();
+        let address = listener.local_addr().unwrap();
+        assert_eq!(address.as_abstract_namespace(), Some(&name.as_bytes()[1..]));
+    }
+}
@@ -1,8 +1,15 @@
+use std::io::{self, Write};
 use std::net::ToSocketAddrs;
 use std::process::exit;
+use std::sync::Arc;
 
-use crate::server::{Server, ServerState, TcpListener};
+use crate::log;
+use crate::log::LogFileType;
 use crate::server::config::ServerConfig;
+use crate::server::{
+    bind_tcp_socket, bind_unix_socket, finish_upgrade, tls_server_config, InheritedSockets,
+    ListenOptions, Server, ServerSocket, ServerState, TcpListener, Transport,
+};
 use crate::type_aliases::Port;
 
 // Fonction principale pour démarrer le serveur
@@ -13,17 +20,26 @@ pub fn start(configs: Vec<ServerConfig<'static>>) {
         exit(1);
     }
     let mut s = ServerState::init(servers);
-    loop {
+    finish_upgrade();
+    while s.is_running() {
         s.poll();
         s.handle_events();
     }
+    // Les journaux sont écrits à chaque message : seule la sortie standard reste à vider
+    log!(LogFileType::Server, "Server stopped".to_string());
+    let _ = io::stdout().flush();
 }
 
 // Fonction pour lier un port à une adresse IP
-fn bind_port(host: &str, port: &Port) -> Option<TcpListener> {
+fn bind_port(
+    host: &str,
+    port: &Port,
+    v6_only: Option<bool>,
+    options: &ListenOptions,
+    inherited: &mut InheritedSockets,
+) -> Option<TcpListener> {
     // Utiliser ToSocketAddrs pour résoudre le nom d'hôte en une adresse IP
-    let host_and_port = format!("{host}:{port}");
-    let mut addresses = match host_and_port.to_socket_addrs() {
+    let mut addresses = match (host, *port).to_socket_addrs() {
         Ok(addr) => addr,
         Err(e) => {
             eprintln!("Error resolving address {host}:{port}. {e}");
@@ -32,13 +48,18 @@ fn bind_port(host: &str, port: &Port) -> Option<TcpListener> {
     };
 
     if let Some(socket_addr) = addresses.next() {
-        return match TcpListener::bind(socket_addr) {
+        // Un socket transmis au lancement remplace celui que le serveur aurait lié
+        let listener = match inherited.take_tcp(socket_addr) {
+            Some(listener) => listener,
+            None => bind_tcp_socket(socket_addr, v6_only, options),
+        };
+        return match listener {
             Ok(listener) => {
-                println!("Server listening on {host_and_port}");
+                println!("Server listening on {socket_addr}");
                 Some(listener)
             }
             Err(e) => {
-                eprintln!("Error: {e}. Unable to listen to: {host_and_port}");
+                eprintln!("Error: {e}. Unable to listen to: {socket_addr}");
                 None
             }
         };
@@ -46,27 +67,91 @@ fn bind_port(host: &str, port: &Port) -> Option<TcpListener> {
     None
 }
 
+// Fonction pour lier un port à chaque adresse d'écoute du serveur ; sans adresse configurée,
+// à la première adresse de son nom
+fn bind_addresses(
+    config: &ServerConfig,
+    port: &Port,
+    inherited: &mut InheritedSockets,
+) -> Vec<TcpListener> {
+    let options = &config.listen_options;
+    if config.bind_addresses.is_empty() {
+        return bind_port(config.host, port, None, options, inherited)
+            .into_iter()
+            .collect();
+    }
+    config
+        .bind_addresses
+        .iter()
+        .filter_map(|address| bind_port(address.ip, port, address.v6_only, options, inherited))
+        .collect()
+}
+
 // Fonction pour obtenir les serveurs configurés
 pub fn get_servers(configs: Vec<ServerConfig<'static>>) -> Vec<Server<'static>> {
     let mut servers = Vec::new();
+    let mut inherited = InheritedSockets::from_env();
     for config in configs {
-        if config.ports.is_empty() {
+        if config.ports.is_empty() && config.tls.is_empty() && config.unix_sockets.is_empty() {
             eprintln!(
                 "Error: no ports are specified for this instance of {}",
                 config.host
             );
         }
 
-        let listeners = config
+        let mut listeners = config
             .ports
             .iter()
-            .filter_map(|port| bind_port(config.host, port))
+            .flat_map(|port| bind_addresses(&config, port, &mut inherited))
+            .map(|listener| (ServerSocket::Tcp(listener), Transport::Plain))
             .collect::<Vec<_>>();
 
+        // Ports HTTPS et ports HTTP qui y redirigent
+        for tls in &config.tls {
+            let tls_config = match tls_server_config(tls) {
+                Ok(tls_config) => tls_config,
+                Err(e) => {
+                    eprintln!("Error: {e}. Unable to serve HTTPS on port {}", tls.port);
+                    continue;
+                }
+            };
+            for listener in bind_addresses(&config, &tls.port, &mut inherited) {
+                let transport = Transport::Tls(Arc::clone(&tls_config));
+                listeners.push((ServerSocket::Tcp(listener), transport));
+            }
+            if let Some(port) = tls.redirect_from {
+                for listener in bind_addresses(&config, &port, &mut inherited) {
+                    let transport = Transport::RedirectToHttps(tls.port);
+                    listeners.push((ServerSocket::Tcp(listener), transport));
+                }
+            }
+        }
+
+        // Sockets Unix, servis en clair avec les mêmes routes
+        for socket in &config.unix_sockets {
+            // Seul un socket lié ici a un fichier que ce processus doit supprimer à l'arrêt
+            let (listener, created) = match inherited.take_unix(socket.path) {
+                Some(listener) => (listener, false),
+                None => (bind_unix_socket(socket), true),
+            };
+            match listener {
+                Ok(listener) => {
+                    println!("Server listening on unix:{}", socket.path);
+                    let path = socket.path.to_string();
+                    let listener = ServerSocket::Unix(listener, path, created);
+                    listeners.push((listener, Transport::Plain));
+                }
+                Err(e) => eprintln!("Error: {e}. Unable to listen to: unix:{}", socket.path),
+            }
+        }
+
         if !listeners.is_empty() {
             servers.push(Server::new(listeners, config));
         }
     }
+    for address in inherited.unused() {
+        eprintln!("Warning: inherited socket {address} matches no listener and is closed");
+    }
     servers
 }
 
@@ -74,18 +159,21 @@ pub fn get_servers(configs: Vec<ServerConfig<'static>>) -> Vec<Server<'static>>
 mod tests {
     use super::*;
     use crate::log::init_logs;
+    use std::time::Duration;
     #[test]
     fn test_bind_port() {
         // Invalid address
         let valid_port: Port = 8080;
         let invalid_addr = "foo";
-        assert!(bind_port(invalid_addr, &valid_port).is_none());
+        let options = ListenOptions::default();
+        let inherited = &mut InheritedSockets::default();
+        assert!(bind_port(invalid_addr, &valid_port, None, &options, inherited).is_none());
 
         init_logs();
         // Invalid ports
         let invalid_port: Port = 1;
         let valid_addr = "127.0.0.1";
-        assert!(bind_port(valid_addr, &invalid_port).is_none());
+        assert!(bind_port(valid_addr, &invalid_port, None, &options, inherited).is_none());
     }
 
     #[test]
@@ -95,6 +183,14 @@ mod tests {
             ports: vec![],
             custom_error_path: None,
             body_size_limit: 0,
+            trace_enabled: false,
+            cgi_max_processes: 16,
+            shutdown_grace_period: Duration::from_secs(30),
+            tls: vec![],
+            bind_addresses: vec![],
+            listen_options: ListenOptions::default(),
+            proxy_protocol: vec![],
+            unix_sockets: vec![],
             routes: vec![],
         };
         assert!(get_servers(vec![server_config]).is_empty());
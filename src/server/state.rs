@@ -1,8 +1,21 @@
-use super::{
-    Arc, Events, HashMap, Interest, Listener, Poll, Server, ServerConfig, TcpStream, Token,
-};
+use super::{Arc, Events, HashMap, Interest, Listener, Poll, Server, ServerConfig, Token};
 
 use crate::log::*;
+use crate::server::{
+    cancel_http2_stream, flush_event_stream, handle_cgi_event, handle_cgi_timeout,
+    handle_connection, handle_event_stream_input, handle_fastcgi_output, handle_fastcgi_timeout,
+    handle_http2_event, handle_http2_timeout, handle_tunnel_input, handle_upstream_event,
+    handle_upstream_timeout, handle_websocket_event, resolve_gateways, resume_cgi_output,
+    spawn_upgrade, CgiProcess, ClientSocket, ClientStream, Delivery, EventStream, FastCgiClient,
+    FastCgiRequest, HealthProbes, Http2Connection, Outcome, ServerSocket, Signals, Sink,
+    UpstreamRequest, WebSocket, HTTP2_IDLE_TIMEOUT, SIGNAL_TOKEN, WAKE_TOKEN,
+    WEBSOCKET_IDLE_TIMEOUT,
+};
+use mio::net::TcpStream;
+use mio::{Registry, Waker};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
 use std::io::ErrorKind;
 #[cfg(unix)]
 use std::os::fd::{AsRawFd, FromRawFd};
@@ -13,18 +26,150 @@ use std::time::Instant;
 
 pub const INITIAL_TOKEN_ID: usize = 0;
 
+// Attente maximale de la boucle d'événements
+const POLL_TIMEOUT: Duration = Duration::from_millis(5000);
+
+// Inactivité tolérée pendant la réception d'une requête HTTP
+const IDLE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+// Attente tolérée de la requête suivante sur une connexion persistante
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_millis(5000);
+
+// Ce qui produit au fil de l'eau la réponse d'une connexion HTTP/1 ou d'un flux HTTP/2
+#[derive(Default)]
+struct Gateways {
+    cgi: Option<Box<CgiProcess>>,
+    fastcgi: Option<Box<FastCgiRequest>>,
+    upstream: Option<Box<UpstreamRequest>>,
+    events: Option<Box<EventStream>>,
+}
+
+impl Gateways {
+    // Instant auquel le script ou la requête FastCGI en cours doit être abandonné
+    fn deadline(&self) -> Option<Instant> {
+        let cgi = self.cgi.as_ref().map(|process| process.deadline());
+        let fastcgi = self.fastcgi.as_ref().map(|request| request.deadline());
+        let upstream = self.upstream.as_ref().map(|request| request.deadline());
+        cgi.or(fastcgi).or(upstream)
+    }
+}
+
 struct Connection<'a> {
-    stream: TcpStream,
+    stream: ClientStream,
     config: Arc<ServerConfig<'a>>,
     last_activity: Instant,
+    gateways: Gateways,
+    websocket: Option<Box<WebSocket>>,
+    http2: Option<Box<Http2Connection>>,
+    // Réponses des flux HTTP/2 produites au fil de l'eau, avec le jeton qui les désigne auprès
+    // des tubes et des serveurs FastCGI
+    streams: BTreeMap<u32, (Token, Gateways)>,
+    // La connexion est surveillée en écriture : des octets attendent qu'elle redevienne prête
+    writable: bool,
+    // La réponse est terminée : la connexion est fermée une fois ses octets envoyés
+    closing: bool,
 }
 
 impl<'a> Connection<'a> {
-    fn new(stream: TcpStream, config: Arc<ServerConfig<'a>>) -> Self {
+    fn new(stream: ClientStream, config: Arc<ServerConfig<'a>>) -> Self {
         Self {
             stream,
             config,
             last_activity: Instant::now(),
+            gateways: Gateways::default(),
+            websocket: None,
+            http2: None,
+            streams: BTreeMap::new(),
+            writable: false,
+            closing: false,
+        }
+    }
+
+    // Fonction pour obtenir la destination d'une réponse produite au fil de l'eau, ce qui la
+    // produit et la configuration : la connexion HTTP/1, ou l'un de ses flux HTTP/2
+    fn exchange(
+        &mut self,
+        stream_id: Option<u32>,
+    ) -> Option<(Sink<'_>, &mut Gateways, &ServerConfig<'a>)> {
+        let Some(stream_id) = stream_id else {
+            return Some((
+                Sink::Http1(&mut self.stream),
+                &mut self.gateways,
+                &self.config,
+            ));
+        };
+        let http2 = self.http2.as_deref_mut()?;
+        let (_, gateways) = self.streams.get_mut(&stream_id)?;
+        let sink = Sink::Http2(&mut self.stream, http2, stream_id);
+        Some((sink, gateways, &self.config))
+    }
+
+    // Flux HTTP/2 dont la réponse est produite au fil de l'eau, avec le jeton qui la désigne
+    fn stream_owners(&self) -> Vec<(u32, Token)> {
+        let owners = self.streams.iter();
+        owners
+            .map(|(stream_id, (owner, _))| (*stream_id, *owner))
+            .collect()
+    }
+
+    // Durée d'inactivité après laquelle la connexion est fermée
+    fn idle_timeout(&self) -> Duration {
+        match (&self.websocket, &self.http2) {
+            (Some(_), _) => WEBSOCKET_IDLE_TIMEOUT,
+            (None, Some(_)) => HTTP2_IDLE_TIMEOUT,
+            (None, None) if self.stream.has_unread() => IDLE_TIMEOUT,
+            (None, None) => KEEP_ALIVE_TIMEOUT,
+        }
+    }
+
+    // Vrai si la connexion attend la requête suivante, sans rien en cours
+    fn is_between_requests(&self) -> bool {
+        self.gateways.deadline().is_none()
+            && self.websocket.is_none()
+            && self.gateways.events.is_none()
+            && self.http2.is_none()
+            && !self.stream.has_unread()
+            && !self.stream.has_pending_output()
+    }
+
+    // Instant auquel la boucle doit se réveiller pour cette connexion
+    fn wake_at(&self) -> Option<Instant> {
+        let wake_at = match (self.gateways.deadline(), &self.gateways.events) {
+            (Some(deadline), _) => Some(deadline),
+            // Les flux d'événements ne sont jamais inactifs
+            (None, Some(_)) => None,
+            (None, None) => Some(self.last_activity + self.idle_timeout()),
+        };
+        let streams = self.streams.values().filter_map(|(_, g)| g.deadline());
+        wake_at.into_iter().chain(streams).min()
+    }
+}
+
+// Tubes des scripts CGI, connexions vers les serveurs FastCGI et réveil des flux d'événements
+struct Backends {
+    pipes: HashMap<Token, Token>, // Tube d'un script CGI ou connexion en amont -> réponse
+    streams: HashMap<Token, (Token, u32)>, // Réponse d'un flux HTTP/2 -> connexion et flux
+    fastcgi: FastCgiClient,
+    health: HealthProbes, // Vérification active des serveurs mandatés
+    waker: Arc<Waker>,    // Réveille la boucle lorsqu'un événement est publié
+}
+
+impl Backends {
+    fn new(registry: &Registry) -> Self {
+        Self {
+            pipes: HashMap::new(),
+            streams: HashMap::new(),
+            fastcgi: FastCgiClient::default(),
+            health: HealthProbes::default(),
+            waker: Arc::new(Waker::new(registry, WAKE_TOKEN).expect("Failed to create Waker")),
+        }
+    }
+
+    // Connexion et éventuel flux HTTP/2 dont le jeton désigne la réponse
+    fn exchange(&self, owner: Token) -> (Token, Option<u32>) {
+        match self.streams.get(&owner) {
+            Some(&(token, stream_id)) => (token, Some(stream_id)),
+            None => (owner, None),
         }
     }
 }
@@ -35,6 +180,13 @@ pub struct ServerState<'a> {
     token_id: usize,
     listeners: Vec<Listener<'a>>,
     connections: HashMap<Token, Connection<'a>>,
+    backends: Backends,
+    signals: Option<Signals>,
+    // Durée accordée aux requêtes en cours à l'arrêt
+    grace_period: Duration,
+    // Les listeners sont fermés : le processus s'arrête une fois ses connexions terminées, et
+    // ferme à cet instant celles qui restent
+    draining: Option<Instant>,
 }
 
 impl ServerState<'_> {
@@ -44,48 +196,87 @@ impl ServerState<'_> {
         let mut token_id = INITIAL_TOKEN_ID;
         let mut listeners = Vec::new();
         let connections = HashMap::new();
+        let mut grace_period = Duration::ZERO;
+        let mut backends = Backends::new(poll.registry());
 
         // Enregistrer tous les listeners
         for server in servers {
+            resolve_gateways(&server.config);
+            backends.health.add(&server.config);
+            grace_period = grace_period.max(server.config.shutdown_grace_period);
             let config = Arc::new(server.config);
 
             server
                 .listeners
                 .into_iter()
                 .enumerate()
-                .for_each(|(id, mut listener)| {
+                .for_each(|(id, (mut listener, transport))| {
                     token_id += 1;
                     let token = Token(id);
                     poll.registry()
                         .register(&mut listener, token, Interest::READABLE)
                         .expect("Failed to register listener");
 
+                    let proxy_protocol = listener.expects_proxy_header(&config);
                     listeners.push(Listener {
                         listener,
                         token,
                         config: Arc::clone(&config),
+                        transport,
+                        proxy_protocol,
                     });
                 });
         }
 
+        // SIGUSR2 lance le nouveau binaire ; SIGQUIT, SIGTERM et SIGINT arrêtent le processus une
+        // fois les connexions terminées
+        let handled = [libc::SIGUSR2, libc::SIGQUIT, libc::SIGTERM, libc::SIGINT];
+        let signals = Signals::install(poll.registry(), &handled)
+            .map_err(|e| {
+                log!(
+                    LogFileType::Server,
+                    format!("Error installing signals: {e}")
+                )
+            })
+            .ok();
         ServerState {
             poll,
             events,
             token_id,
             listeners,
             connections,
+            backends,
+            signals,
+            grace_period,
+            draining: None,
         }
     }
 
     pub fn poll(&mut self) {
-        self.poll
-            .poll(&mut self.events, Some(Duration::from_millis(5000)))
-            .expect("Poll failed");
+        // Se réveiller à temps pour arrêter les scripts CGI qui dépassent leur durée et fermer
+        // les connexions inactives
+        let now = Instant::now();
+        let timeout = self
+            .connections
+            .values()
+            .filter_map(|conn| conn.wake_at())
+            .chain(self.backends.health.wake_at())
+            .chain(self.draining)
+            .map(|deadline| deadline.saturating_duration_since(now))
+            .fold(POLL_TIMEOUT, Duration::min);
+
+        match self.poll.poll(&mut self.events, Some(timeout)) {
+            Ok(()) => {}
+            // Un signal interrompt l'attente ; il est lu dans son tube au tour suivant
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => panic!("Poll failed: {e}"),
+        }
 
         self.handle_timeout();
     }
 
     pub fn handle_events(&mut self) {
+        let mut signaled = false;
         for event in self.events.iter() {
             for listener in &self.listeners {
                 while accept_connection(
@@ -95,26 +286,289 @@ impl ServerState<'_> {
                     &mut self.connections,
                 ) {}
             }
-            handle_existing_connection(&self.poll, event.token(), &mut self.connections);
+
+            let token = event.token();
+            if token == SIGNAL_TOKEN {
+                signaled = true;
+                continue;
+            }
+            if token == WAKE_TOKEN {
+                flush_event_streams(
+                    &self.poll,
+                    &mut self.token_id,
+                    &mut self.connections,
+                    &mut self.backends,
+                );
+                continue;
+            }
+            if self.backends.health.owns(token) {
+                let registry = self.poll.registry();
+                self.backends.health.handle_event(registry, token);
+                continue;
+            }
+            if self.backends.fastcgi.owns(token) {
+                let deliveries = self
+                    .backends
+                    .fastcgi
+                    .handle_event(self.poll.registry(), token);
+                deliver(
+                    &self.poll,
+                    &mut self.token_id,
+                    deliveries,
+                    &mut self.connections,
+                    &mut self.backends,
+                );
+                continue;
+            }
+
+            let http2 = self.connections.get(&token).map(|c| c.http2.is_some());
+            let outcome = match self.backends.pipes.get(&token) {
+                Some(&owner) => handle_cgi_pipe(
+                    &self.poll,
+                    token,
+                    owner,
+                    &mut self.connections,
+                    &self.backends,
+                )
+                .map(|outcome| (owner, outcome)),
+                None if http2 == Some(true) => {
+                    serve_http2(
+                        &self.poll,
+                        &mut self.token_id,
+                        token,
+                        &mut self.connections,
+                        &mut self.backends,
+                    );
+                    None
+                }
+                None => {
+                    let registry = self.poll.registry();
+                    handle_existing_connection(registry, token, &mut self.connections)
+                        .map(|outcome| (token, outcome))
+                }
+            };
+
+            if let Some((token, outcome)) = outcome {
+                settle(
+                    &self.poll,
+                    &mut self.token_id,
+                    token,
+                    outcome,
+                    &mut self.connections,
+                    &mut self.backends,
+                );
+            }
+        }
+
+        if signaled {
+            self.handle_signals();
+        }
+        // Fermer sans attendre les connexions qui viennent de terminer leur requête
+        if self.draining.is_some() {
+            self.handle_timeout();
+        }
+        self.watch_output();
+    }
+
+    // Fonction pour surveiller en écriture les connexions dont des octets attendent d'être
+    // envoyés, et cesser de le faire une fois qu'ils le sont
+    fn watch_output(&mut self) {
+        for (token, connection) in self.connections.iter_mut() {
+            let writable = connection.stream.has_pending_output();
+            if writable == connection.writable {
+                continue;
+            }
+            let interests = match writable {
+                true => Interest::READABLE | Interest::WRITABLE,
+                false => Interest::READABLE,
+            };
+            match self
+                .poll
+                .registry()
+                .reregister(&mut connection.stream, *token, interests)
+            {
+                Ok(()) => connection.writable = writable,
+                Err(e) => log!(LogFileType::Server, format!("Error: {e}")),
+            }
+        }
+    }
+
+    // Vrai tant que le processus accepte des connexions ou en sert encore
+    pub fn is_running(&self) -> bool {
+        self.draining.is_none() || !self.connections.is_empty()
+    }
+
+    fn handle_signals(&mut self) {
+        let Some(signals) = self.signals.as_mut() else {
+            return;
+        };
+        for signal in signals.received() {
+            match signal {
+                libc::SIGUSR2 => self.upgrade(),
+                // Le nouveau binaire a repris les sockets : leurs fichiers sont conservés
+                libc::SIGQUIT => self.drain(true),
+                libc::SIGTERM | libc::SIGINT => self.drain(false),
+                _ => {}
+            }
         }
     }
 
+    // Fonction pour lancer le nouveau binaire en lui transmettant les sockets d'écoute ; il
+    // demande à ce processus de s'arrêter une fois prêt
+    fn upgrade(&self) {
+        if self.draining.is_some() {
+            return;
+        }
+        let sockets = self.listeners.iter();
+        let sockets = sockets.map(|l| (l.listener.as_raw_fd(), l.listener.name()));
+        match spawn_upgrade(&sockets.collect::<Vec<_>>()) {
+            Ok(pid) => log!(
+                LogFileType::Server,
+                format!("Upgrade: started new process {pid}")
+            ),
+            Err(e) => log!(LogFileType::Server, format!("Error starting upgrade: {e}")),
+        }
+    }
+
+    // Fonction pour cesser d'accepter des connexions ; celles qui attendent une requête sont
+    // fermées, les autres le seront après leur réponse ou à la fin du délai de grâce
+    fn drain(&mut self, handover: bool) {
+        if self.draining.is_some() {
+            // Un second signal d'arrêt ferme aussitôt les connexions restantes
+            if !handover {
+                self.draining = Some(Instant::now());
+                self.handle_timeout();
+            }
+            return;
+        }
+        self.backends.health.stop(self.poll.registry());
+        for mut listener in self.listeners.drain(..) {
+            let _ = self.poll.registry().deregister(&mut listener.listener);
+            if let (ServerSocket::Unix(_, path, true), false) = (&listener.listener, handover) {
+                if !path.starts_with('@') {
+                    let _ = fs::remove_file(path);
+                }
+            }
+        }
+        self.draining = Some(Instant::now() + self.grace_period);
+        log!(
+            LogFileType::Server,
+            format!("Draining {} connections", self.connections.len())
+        );
+        self.handle_timeout();
+    }
+
     fn handle_timeout(&mut self) {
+        let registry = self.poll.registry();
+        self.backends
+            .health
+            .handle_timeout(registry, &mut self.token_id);
+
         let now = Instant::now();
-        let timeout_duration = Duration::from_millis(1000);
+        let mut expired = Vec::new();
+        // Connexions fermées une fois leur dernière réponse envoyée
+        let mut finished = Vec::new();
+        // Réponses des flux HTTP/2 terminées ou à relancer
+        let mut streams = Vec::new();
 
-        // Supprimer les connexions qui ont expiré du `connections` HashMap
-        self.connections.retain(|_, conn| {
-            if now.duration_since(conn.last_activity) > timeout_duration {
-                self.poll
-                    .registry()
-                    .deregister(&mut conn.stream)
-                    .expect("Failed to deregister stream due to timeout");
-                false
-            } else {
-                true
+        // À la fin du délai de grâce, toutes les connexions sont fermées
+        let forced = self.draining.is_some_and(|deadline| now >= deadline);
+        for (token, conn) in self.connections.iter_mut() {
+            for (stream_id, owner) in conn.stream_owners() {
+                let Some((sink, gateways, config)) = conn.exchange(Some(stream_id)) else {
+                    continue;
+                };
+                match gateways.deadline() {
+                    Some(deadline) if now >= deadline || forced => {
+                        let result = expire(sink, gateways, config);
+                        streams.push((owner, result.map(|()| Outcome::Close)));
+                    }
+                    Some(_) => {}
+                    None => match gateways.events.as_mut() {
+                        Some(_) if self.draining.is_some() => {
+                            streams.push((owner, Ok(Outcome::Close)))
+                        }
+                        Some(events) if events.keep_alive(now) => {
+                            streams.push((owner, flush_event_stream(sink, events)))
+                        }
+                        _ => {}
+                    },
+                }
+            }
+
+            // Une connexion HTTP/2 qui produit encore des réponses n'est pas inactive
+            let idle = conn.streams.is_empty()
+                && (now.duration_since(conn.last_activity) >= conn.idle_timeout()
+                    || (self.draining.is_some() && conn.is_between_requests()));
+            match conn.gateways.deadline() {
+                // Arrêter les scripts CGI qui ont dépassé leur durée d'exécution
+                Some(deadline) if now >= deadline || forced => {
+                    let (sink, gateways, config) = match conn.exchange(None) {
+                        Some(exchange) => exchange,
+                        None => continue,
+                    };
+                    let result = expire(sink, gateways, config);
+                    if let Err(e) = &result {
+                        let client = client_label(&conn.stream);
+                        log!(
+                            LogFileType::Client,
+                            format!("Error handling client {client}: {e}")
+                        );
+                    }
+                    match result.is_ok() && !forced {
+                        true => finished.push(*token),
+                        false => expired.push(*token),
+                    }
+                }
+                // Une connexion qui attend son script CGI n'est pas inactive
+                Some(_) => {}
+                // Un flux d'événements reste ouvert : un commentaire l'entretient
+                None => match conn.gateways.events.as_mut() {
+                    // Un flux d'événements ne se termine pas de lui-même
+                    Some(_) if self.draining.is_some() => expired.push(*token),
+                    Some(events) if events.keep_alive(now) => {
+                        match flush_event_stream(Sink::Http1(&mut conn.stream), events) {
+                            Ok(Outcome::Wait) => {}
+                            Ok(_) => finished.push(*token),
+                            Err(_) => expired.push(*token),
+                        }
+                    }
+                    Some(_) => {}
+                    None if idle || forced => {
+                        if let Some(http2) = conn.http2.as_mut() {
+                            let _ = handle_http2_timeout(&mut conn.stream, http2);
+                        }
+                        expired.push(*token);
+                    }
+                    None => {}
+                },
             }
-        });
+        }
+
+        for (owner, outcome) in streams {
+            settle(
+                &self.poll,
+                &mut self.token_id,
+                owner,
+                outcome,
+                &mut self.connections,
+                &mut self.backends,
+            );
+        }
+        // Supprimer les connexions qui ont expiré du `connections` HashMap
+        for token in expired {
+            close_connection(&self.poll, token, &mut self.connections, &mut self.backends);
+        }
+        for token in finished {
+            settle(
+                &self.poll,
+                &mut self.token_id,
+                token,
+                Ok(Outcome::Close),
+                &mut self.connections,
+                &mut self.backends,
+            );
+        }
     }
 }
 
@@ -125,21 +579,38 @@ fn accept_connection<'a>(
     connections: &mut HashMap<Token, Connection<'a>>,
 ) -> bool {
     match listener.accept() {
-        Ok((mut stream, _)) => {
-            let linger_duration = match std::env::consts::OS {
-                "macos" => Some(Duration::from_millis(100)),
-                _ => None,
-            };
+        Ok(stream) => {
+            // Les options de socket ne concernent que les connexions TCP
+            if let ClientSocket::Tcp(tcp) = &stream {
+                let linger_duration = match std::env::consts::OS {
+                    "macos" => Some(Duration::from_millis(100)),
+                    _ => None,
+                };
+
+                set_linger_option(tcp, linger_duration).expect("Failed to set linger option");
 
-            set_linger_option(&stream, linger_duration).expect("Failed to set linger option");
+                if let Err(e) = tcp.set_ttl(60) {
+                    log!(LogFileType::Server, format!("Error: {e}"));
+                }
 
-            if let Err(e) = stream.set_ttl(60) {
-                log!(LogFileType::Server, format!("Error: {e}"));
+                if listener.config.listen_options.nodelay {
+                    if let Err(e) = tcp.set_nodelay(true) {
+                        log!(LogFileType::Server, format!("Error: {e}"));
+                    }
+                }
             }
 
             let connection_token = Token(*token_id);
             *token_id += 1;
 
+            let transport = &listener.transport;
+            let mut stream = match ClientStream::new(stream, transport, listener.proxy_protocol) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log!(LogFileType::Server, format!("Error: {e}"));
+                    return true;
+                }
+            };
             poll.registry()
                 .register(&mut stream, connection_token, Interest::READABLE)
                 .expect("Failed to register new connection");
@@ -156,28 +627,519 @@ fn accept_connection<'a>(
 }
 
 fn handle_existing_connection(
+    registry: &Registry,
+    token: Token,
+    connections: &mut HashMap<Token, Connection>,
+) -> Option<io::Result<Outcome>> {
+    let connection = connections.get_mut(&token)?;
+    let waiting = connection.gateways.deadline().is_some();
+    let (stream, config) = (&mut connection.stream, &connection.config);
+
+    // Envoyer d'abord ce que le client n'a pas encore pu recevoir
+    if let Err(e) = stream.flush_output() {
+        return Some(Err(e));
+    }
+    if connection.closing {
+        return match stream.has_pending_output() {
+            true => Some(Ok(Outcome::Wait)),
+            false => Some(Ok(Outcome::Close)),
+        };
+    }
+    let gateways = &mut connection.gateways;
+    // La sortie du script, laissée dans son tube, peut reprendre
+    if let Some(process) = gateways.cgi.as_mut() {
+        let sink = Sink::Http1(stream);
+        if let Some(outcome) = resume_cgi_output(sink, process, registry, config) {
+            return Some(outcome);
+        }
+    }
+
+    if let Some(socket) = connection.websocket.as_mut() {
+        return Some(handle_websocket_event(stream, socket));
+    }
+    if let Some(events) = gateways.events.as_mut() {
+        return Some(handle_event_stream_input(stream, events));
+    }
+    // Les octets du client passent par le tunnel ouvert par le serveur mandaté
+    if let Some(request) = gateways.upstream.as_mut().filter(|r| r.is_tunnel()) {
+        return Some(handle_tunnel_input(stream, request, config));
+    }
+
+    // La requête a déjà été lue : la connexion attend la sortie de son script CGI
+    if waiting {
+        return None;
+    }
+
+    // Attendre l'en-tête PROXY, la fin de la poignée de main TLS et l'arrivée d'une requête
+    match stream.is_ready() {
+        Ok(true) => {}
+        Ok(false) => return Some(Ok(Outcome::Wait)),
+        Err(e) => return Some(Err(e)),
+    }
+
+    Some(handle_connection(stream, config))
+}
+
+// Fonction pour traiter les trames reçues sur une connexion HTTP/2 : les réponses produites au
+// fil de l'eau sont suivies sur leur flux, et celles qui attendaient le client reprennent
+fn serve_http2(
+    poll: &Poll,
+    token_id: &mut usize,
+    token: Token,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    let Some(connection) = connections.get_mut(&token) else {
+        return;
+    };
+    let Some(http2) = connection.http2.as_mut() else {
+        return;
+    };
+    let (stream, config) = (&mut connection.stream, &connection.config);
+
+    // Envoyer d'abord ce que le client n'a pas encore pu recevoir
+    let mut started = Vec::new();
+    let outcome = match stream.flush_output() {
+        Ok(()) => handle_http2_event(stream, http2, config, &mut started),
+        Err(e) => Err(e),
+    };
+    for (stream_id, outcome) in started {
+        settle_stream(
+            poll,
+            token_id,
+            token,
+            stream_id,
+            Ok(outcome),
+            connections,
+            backends,
+        );
+    }
+    resume_streams(poll, token_id, token, connections, backends);
+    settle(poll, token_id, token, outcome, connections, backends);
+}
+
+// Fonction pour relancer les réponses des flux HTTP/2 d'une connexion : celles que le client a
+// annulées sont arrêtées, et les scripts en pause ou les événements retenus reprennent une
+// fois le client rattrapé
+fn resume_streams(
+    poll: &Poll,
+    token_id: &mut usize,
+    token: Token,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    let Some(connection) = connections.get_mut(&token) else {
+        return;
+    };
+    let mut outcomes = Vec::new();
+    for (stream_id, owner) in connection.stream_owners() {
+        let Some((sink, gateways, config)) = connection.exchange(Some(stream_id)) else {
+            continue;
+        };
+        let outcome = match (gateways.cgi.as_mut(), gateways.events.as_mut()) {
+            _ if !sink.is_open() => Some(Ok(Outcome::Close)),
+            (Some(process), _) => resume_cgi_output(sink, process, poll.registry(), config),
+            (None, Some(events)) => Some(flush_event_stream(sink, events)),
+            (None, None) => None,
+        };
+        outcomes.extend(outcome.map(|outcome| (owner, outcome)));
+    }
+
+    for (owner, outcome) in outcomes {
+        settle(poll, token_id, owner, outcome, connections, backends);
+    }
+}
+
+// Fonction pour traiter un événement sur un tube CGI ou la connexion en amont d'une réponse
+fn handle_cgi_pipe(
+    poll: &Poll,
+    token: Token,
+    owner: Token,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &Backends,
+) -> Option<io::Result<Outcome>> {
+    let (connection_token, stream_id) = backends.exchange(owner);
+    let connection = connections.get_mut(&connection_token)?;
+    let (sink, gateways, config) = connection.exchange(stream_id)?;
+
+    match (gateways.cgi.as_mut(), gateways.upstream.as_mut()) {
+        (Some(process), _) => Some(handle_cgi_event(
+            sink,
+            process,
+            poll.registry(),
+            token,
+            config,
+        )),
+        (None, Some(request)) => Some(handle_upstream_event(sink, request, config)),
+        (None, None) => None,
+    }
+}
+
+// Fonction pour envoyer les événements publiés sur toutes les connexions qui en attendent
+fn flush_event_streams(
+    poll: &Poll,
+    token_id: &mut usize,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    let mut outcomes = Vec::new();
+    for (token, conn) in connections.iter_mut() {
+        if let Some(events) = conn.gateways.events.as_mut() {
+            let outcome = flush_event_stream(Sink::Http1(&mut conn.stream), events);
+            outcomes.push((*token, outcome));
+        }
+        for (stream_id, owner) in conn.stream_owners() {
+            let Some((sink, gateways, _)) = conn.exchange(Some(stream_id)) else {
+                continue;
+            };
+            if let Some(events) = gateways.events.as_mut() {
+                outcomes.push((owner, flush_event_stream(sink, events)));
+            }
+        }
+    }
+
+    for (owner, outcome) in outcomes {
+        settle(poll, token_id, owner, outcome, connections, backends);
+    }
+}
+
+// Fonction pour transmettre aux connexions ce que les serveurs FastCGI ont envoyé
+fn deliver(
+    poll: &Poll,
+    token_id: &mut usize,
+    deliveries: Vec<(Token, Delivery)>,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    for (owner, delivery) in deliveries {
+        let (token, stream_id) = backends.exchange(owner);
+        let Some(connection) = connections.get_mut(&token) else {
+            continue;
+        };
+        let Some((sink, gateways, config)) = connection.exchange(stream_id) else {
+            continue;
+        };
+        let Some(request) = gateways.fastcgi.as_mut() else {
+            continue;
+        };
+        let outcome = handle_fastcgi_output(sink, request, delivery, config);
+        settle(poll, token_id, owner, outcome, connections, backends);
+    }
+}
+
+// Fonction pour répondre à la place du script ou de la requête qui a dépassé sa durée
+fn expire(sink: Sink, gateways: &mut Gateways, config: &ServerConfig) -> io::Result<()> {
+    match (&gateways.cgi, &gateways.fastcgi, &mut gateways.upstream) {
+        (Some(process), _, _) => handle_cgi_timeout(sink, process, config),
+        (_, Some(request), _) => handle_fastcgi_timeout(sink, request, config),
+        (_, _, Some(request)) => handle_upstream_timeout(sink, request, config),
+        (None, None, None) => Ok(()),
+    }
+}
+
+// Fonction pour appliquer à une connexion le résultat du traitement d'un événement ; le jeton
+// peut aussi désigner la réponse d'un flux HTTP/2
+fn settle(
+    poll: &Poll,
+    token_id: &mut usize,
+    token: Token,
+    outcome: io::Result<Outcome>,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    if let (connection_token, Some(stream_id)) = backends.exchange(token) {
+        return settle_stream(
+            poll,
+            token_id,
+            connection_token,
+            stream_id,
+            outcome,
+            connections,
+            backends,
+        );
+    }
+
+    match outcome {
+        Ok(Outcome::Wait) => {
+            if let Some(connection) = connections.get_mut(&token) {
+                connection.last_activity = Instant::now();
+            }
+            return;
+        }
+        Ok(
+            outcome @ (Outcome::Cgi(_)
+            | Outcome::FastCgi(_)
+            | Outcome::Upstream(_)
+            | Outcome::EventStream(_)),
+        ) => {
+            // Un script remplace celui qui a demandé une redirection locale
+            stop_gateway(poll, token, connections, backends);
+
+            if let Some(connection) = connections.get_mut(&token) {
+                let gateways = &mut connection.gateways;
+                if let Some(failed) =
+                    attach_gateway(poll, token_id, token, outcome, gateways, backends)
+                {
+                    return deliver(poll, token_id, failed, connections, backends);
+                }
+            }
+        }
+        Ok(Outcome::WebSocket(socket)) => {
+            if let Some(connection) = connections.get_mut(&token) {
+                connection.last_activity = Instant::now();
+                connection.websocket = Some(socket);
+                return;
+            }
+        }
+        Ok(Outcome::Http2(http2)) => {
+            if let Some(connection) = connections.get_mut(&token) {
+                connection.last_activity = Instant::now();
+                connection.http2 = Some(http2);
+                // La préface du client, et la requête passée à HTTP/2, ont pu arriver avec la
+                // requête
+                return serve_http2(poll, token_id, token, connections, backends);
+            }
+        }
+        Ok(Outcome::KeepAlive) => {
+            // Passer aux requêtes que le client a envoyées pendant le traitement de la précédente
+            stop_gateway(poll, token, connections, backends);
+
+            if let Some(connection) = connections.get_mut(&token) {
+                let outcome = handle_connection(&mut connection.stream, &connection.config);
+                return settle(poll, token_id, token, outcome, connections, backends);
+            }
+        }
+        Ok(Outcome::Close) => {
+            // La fin de la réponse est envoyée avant la fermeture
+            let pending = connections.get(&token);
+            if pending.is_some_and(|conn| conn.stream.has_pending_output()) {
+                stop_gateway(poll, token, connections, backends);
+                if let Some(connection) = connections.get_mut(&token) {
+                    connection.last_activity = Instant::now();
+                    connection.closing = true;
+                    connection.websocket = None;
+                    connection.gateways.events = None;
+                    connection.http2 = None;
+                }
+                return;
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+            return; // Donc, nous gardons la connexion enregistrée et retournons
+        }
+        Err(e) => {
+            let client = connections
+                .get(&token)
+                .map(|conn| client_label(&conn.stream));
+            let client = client.unwrap_or_default();
+            log!(
+                LogFileType::Client,
+                format!("Error handling client {client}: {e}")
+            )
+        }
+    }
+
+    close_connection(poll, token, connections, backends);
+}
+
+// Fonction pour appliquer à la réponse d'un flux HTTP/2 le résultat du traitement d'un
+// événement ; une erreur d'écriture ferme toute la connexion
+fn settle_stream(
     poll: &Poll,
+    token_id: &mut usize,
     token: Token,
+    stream_id: u32,
+    outcome: io::Result<Outcome>,
     connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
 ) {
-    let connection = match connections.get_mut(&token) {
-        Some(connection) => connection,
-        None => return,
+    let outcome = match outcome {
+        Ok(outcome) => outcome,
+        Err(e) => return settle(poll, token_id, token, Err(e), connections, backends),
     };
+    let Some(connection) = connections.get_mut(&token) else {
+        return;
+    };
+    match outcome {
+        Outcome::Wait => {
+            connection.last_activity = Instant::now();
+            return;
+        }
+        Outcome::Cgi(_) | Outcome::FastCgi(_) | Outcome::Upstream(_) | Outcome::EventStream(_) => {
+            // Un script remplace celui qui a demandé une redirection locale ; une nouvelle
+            // réponse reçoit son propre jeton
+            let owner = match connection.streams.get_mut(&stream_id) {
+                Some((owner, gateways)) => {
+                    stop(poll, *owner, gateways, backends);
+                    *owner
+                }
+                None => {
+                    let owner = Token(*token_id);
+                    *token_id += 1;
+                    backends.streams.insert(owner, (token, stream_id));
+                    connection
+                        .streams
+                        .insert(stream_id, (owner, Gateways::default()));
+                    owner
+                }
+            };
+            if let Some((_, gateways)) = connection.streams.get_mut(&stream_id) {
+                if let Some(failed) =
+                    attach_gateway(poll, token_id, owner, outcome, gateways, backends)
+                {
+                    connection.last_activity = Instant::now();
+                    return deliver(poll, token_id, failed, connections, backends);
+                }
+            }
+        }
+        _ => {}
+    }
+    end_stream(poll, token, stream_id, connections, backends);
+}
 
-    if let Err(e) = crate::server::handle_connection(&mut connection.stream, &connection.config) {
-        match e.kind() {
-            ErrorKind::WouldBlock => {
-                return; // Donc, nous gardons la connexion enregistrée et retournons
+// Fonction pour surveiller ce qui produit désormais une réponse, désignée par `owner` auprès
+// des tubes et des serveurs FastCGI ; renvoie les réponses FastCGI déjà connues, ou `None` si
+// la surveillance n'a pas pu commencer
+fn attach_gateway(
+    poll: &Poll,
+    token_id: &mut usize,
+    owner: Token,
+    outcome: Outcome,
+    gateways: &mut Gateways,
+    backends: &mut Backends,
+) -> Option<Vec<(Token, Delivery)>> {
+    match outcome {
+        Outcome::Cgi(mut process) => {
+            match process.register(poll.registry(), token_id) {
+                Ok(tokens) => {
+                    backends
+                        .pipes
+                        .extend(tokens.into_iter().map(|pipe| (pipe, owner)));
+                    gateways.cgi = Some(process);
+                    return Some(Vec::new());
+                }
+                Err(e) => log!(
+                    LogFileType::Server,
+                    format!("Error registering CGI script: {e}")
+                ),
+            }
+            process.deregister(poll.registry());
+        }
+        Outcome::FastCgi(request) => {
+            let registry = poll.registry();
+            let failed = backends.fastcgi.submit(registry, token_id, owner, &request);
+            gateways.fastcgi = Some(request);
+            return Some(failed);
+        }
+        Outcome::Upstream(mut request) => {
+            match request.register(poll.registry(), token_id) {
+                Ok(socket) => {
+                    backends.pipes.insert(socket, owner);
+                    gateways.upstream = Some(request);
+                    return Some(Vec::new());
+                }
+                Err(e) => log!(
+                    LogFileType::Server,
+                    format!("Error registering gateway connection: {e}")
+                ),
             }
-            _ => log!(LogFileType::Client, format!("Error handling client: {e}")),
+            request.deregister(poll.registry());
         }
+        Outcome::EventStream(events) => {
+            // Les événements publiés avant le rattachement sont envoyés au réveil
+            events.attach(Arc::clone(&backends.waker));
+            if let Err(e) = backends.waker.wake() {
+                log!(LogFileType::Server, format!("Error waking event loop: {e}"));
+            }
+            gateways.events = Some(events);
+            return Some(Vec::new());
+        }
+        _ => {}
+    }
+    None
+}
+
+// Fonction pour désigner le client d'une connexion dans les journaux, avec le répartiteur de
+// charge par lequel il est passé
+fn client_label(stream: &ClientStream) -> String {
+    let client = stream.peer_addr().map(|address| address.to_string());
+    let client = client.unwrap_or_else(|_| "unknown".to_string());
+    match stream.proxy_addr() {
+        Some(proxy) => format!("{client} via {proxy}"),
+        None => client,
+    }
+}
+
+// Fonction pour arrêter le script CGI ou la requête FastCGI d'une connexion, ainsi que les
+// réponses de ses flux HTTP/2
+fn stop_gateway(
+    poll: &Poll,
+    token: Token,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    let Some(connection) = connections.get_mut(&token) else {
+        return;
+    };
+    stop(poll, token, &mut connection.gateways, backends);
+    for (_, (owner, mut gateways)) in std::mem::take(&mut connection.streams) {
+        stop(poll, owner, &mut gateways, backends);
+        backends.streams.remove(&owner);
+    }
+}
+
+// Fonction pour arrêter le script CGI ou la requête FastCGI d'une réponse
+fn stop(poll: &Poll, owner: Token, gateways: &mut Gateways, backends: &mut Backends) {
+    if let Some(mut process) = gateways.cgi.take() {
+        process.deregister(poll.registry());
+        backends.pipes.retain(|_, pipe_owner| *pipe_owner != owner);
+    }
+    if gateways.fastcgi.take().is_some() {
+        backends.fastcgi.abort(owner);
     }
+    if let Some(mut request) = gateways.upstream.take() {
+        request.deregister(poll.registry());
+        backends.pipes.retain(|_, pipe_owner| *pipe_owner != owner);
+    }
+}
 
-    poll.registry()
-        .deregister(&mut connection.stream)
-        .expect("Failed to deregister stream");
-    connections.remove(&token);
+// Fonction pour oublier la réponse d'un flux HTTP/2 ; le flux est annulé si elle n'est pas
+// terminée
+fn end_stream(
+    poll: &Poll,
+    token: Token,
+    stream_id: u32,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    let Some(connection) = connections.get_mut(&token) else {
+        return;
+    };
+    if let Some((owner, mut gateways)) = connection.streams.remove(&stream_id) {
+        stop(poll, owner, &mut gateways, backends);
+        backends.streams.remove(&owner);
+    }
+    if let Some(http2) = connection.http2.as_mut() {
+        // Une erreur d'écriture sera constatée au prochain événement de la connexion
+        let _ = cancel_http2_stream(&mut connection.stream, http2, stream_id);
+    }
+}
+
+// Fonction pour fermer une connexion, ainsi que son éventuel script CGI
+fn close_connection(
+    poll: &Poll,
+    token: Token,
+    connections: &mut HashMap<Token, Connection>,
+    backends: &mut Backends,
+) {
+    stop_gateway(poll, token, connections, backends);
+
+    if let Some(mut connection) = connections.remove(&token) {
+        connection.stream.close_notify();
+        poll.registry()
+            .deregister(&mut connection.stream)
+            .expect("Failed to deregister stream");
+    }
 }
 
 use crate::log;
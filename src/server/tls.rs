@@ -0,0 +1,733 @@
+use crate::server::redirections::redirect;
+use crate::server::{
+    get_route, parse_proxy_header, Addresses, ClientSocket, ServerConfig, PROXY_HEADER_MAX_SIZE,
+};
+use crate::type_aliases::{Bytes, Path, Port};
+use http::header::HOST;
+use http::{Method, Request, Response, StatusCode};
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{ProtocolVersion, RootCertStore, ServerConnection};
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use x509_parser::time::ASN1Time;
+
+/// # Tls
+///
+/// Port servi en HTTPS : certificats choisis selon le nom demandé par le client (SNI),
+/// version minimale du protocole, suites de chiffrement autorisées et port HTTP facultatif
+/// qui redirige vers ce port.
+#[derive(Clone, Debug)]
+pub struct Tls<'a> {
+    pub port: Port,
+    pub certificates: Vec<Certificate<'a>>, // Le premier sert aussi aux noms inconnus
+    pub min_version: TlsVersion,
+    pub cipher_suites: Option<Vec<&'a str>>, // Par exemple "TLS13_AES_256_GCM_SHA384"
+    pub redirect_from: Option<Port>,         // Port HTTP qui redirige vers HTTPS
+    pub client_ca_path: Option<Path<'a>>,    // Autorités qui signent les certificats clients
+}
+
+/// # Certificate
+///
+/// Chaîne de certificats et clé privée (fichiers PEM) servies pour les noms indiqués ;
+/// un nom peut commencer par `*.` pour couvrir un niveau de sous-domaines.
+#[derive(Clone, Debug)]
+pub struct Certificate<'a> {
+    pub server_names: Vec<&'a str>,
+    pub chain_path: Path<'a>,
+    pub key_path: Path<'a>,
+}
+
+/// # TlsVersion
+///
+/// Version minimale du protocole acceptée sur un port HTTPS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+/// # Transport
+///
+/// Protocole parlé sur un port d'écoute.
+#[derive(Clone, Debug)]
+pub enum Transport {
+    Plain,
+    Tls(Arc<rustls::ServerConfig>),
+    // Toutes les requêtes sont redirigées vers le port HTTPS indiqué
+    RedirectToHttps(Port),
+}
+
+/// # ClientAuth
+///
+/// Certificat client attendu par une route servie en HTTPS : sans ce réglage, le certificat
+/// éventuellement présenté n'est pas transmis à la route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    Optional,
+    Required,
+}
+
+/// # TlsSession
+///
+/// Paramètres négociés sur la connexion TLS qui a reçu la requête.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsSession {
+    pub protocol: String,
+    pub cipher: String,
+    pub server_name: Option<String>,
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+/// # ClientCertificate
+///
+/// Certificat présenté par le client et vérifié avec les autorités configurées sur le port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertificate {
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub issuer: String,
+    pub serial: String,      // En hexadécimal
+    pub fingerprint: String, // SHA-256 du certificat, en hexadécimal
+    pub not_before: String,
+    pub not_after: String,
+}
+
+// Certificats d'un port HTTPS, choisis selon le nom demandé par le client
+#[derive(Debug)]
+struct SniResolver {
+    certificates: Vec<(Vec<String>, Arc<CertifiedKey>)>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let requested = client_hello.server_name().map(str::to_ascii_lowercase);
+        let matching = requested.and_then(|name| {
+            self.certificates
+                .iter()
+                .find(|(names, _)| names.iter().any(|pattern| name_matches(pattern, &name)))
+        });
+        matching
+            .or(self.certificates.first())
+            .map(|(_, key)| Arc::clone(key))
+    }
+}
+
+// Fonction pour comparer un nom demandé à un nom de certificat, éventuellement générique
+fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+        None => pattern == name,
+    }
+}
+
+// Fonction pour construire la configuration rustls d'un port HTTPS
+pub fn tls_server_config(tls: &Tls) -> Result<Arc<rustls::ServerConfig>, String> {
+    let mut provider = ring::default_provider();
+    if let Some(names) = &tls.cipher_suites {
+        let unknown = names.iter().find(|name| {
+            !provider
+                .cipher_suites
+                .iter()
+                .any(|suite| suite.suite().as_str() == Some(**name))
+        });
+        if let Some(name) = unknown {
+            return Err(format!("unknown cipher suite {name}"));
+        }
+        provider.cipher_suites.retain(|suite| {
+            names
+                .iter()
+                .any(|name| suite.suite().as_str() == Some(name))
+        });
+    }
+
+    let versions: &[&rustls::SupportedProtocolVersion] = match tls.min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+
+    let certificates = tls
+        .certificates
+        .iter()
+        .map(|certificate| {
+            let names = certificate.server_names.iter();
+            let names = names.map(|name| name.to_ascii_lowercase()).collect();
+            Ok((names, Arc::new(load_certified_key(certificate, &provider)?)))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if certificates.is_empty() {
+        return Err("no certificate configured".to_string());
+    }
+
+    let provider = Arc::new(provider);
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(versions)
+        .map_err(|e| e.to_string())?;
+    let builder = match tls.client_ca_path {
+        Some(path) => builder.with_client_cert_verifier(client_verifier(path, provider)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(Arc::new(SniResolver { certificates }));
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+// Fonction pour vérifier les certificats clients avec les autorités d'un fichier PEM
+fn client_verifier(
+    path: &str,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    let authorities = rustls_pemfile::certs(&mut open_pem(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{path}: {e}"))?;
+    let mut roots = RootCertStore::empty();
+    if roots.add_parsable_certificates(authorities).0 == 0 {
+        return Err(format!("{path}: no certificate authority found"));
+    }
+
+    // Le certificat est demandé à tous les clients : chaque route décide s'il est exigé
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .allow_unauthenticated()
+        .build()
+        .map_err(|e| format!("{path}: {e}"))
+}
+
+fn open_pem(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("{path}: {e}"))
+}
+
+// Fonction pour charger une chaîne de certificats et sa clé privée
+fn load_certified_key(
+    certificate: &Certificate,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, String> {
+    let chain = rustls_pemfile::certs(&mut open_pem(certificate.chain_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {e}", certificate.chain_path))?;
+    if chain.is_empty() {
+        return Err(format!("{}: no certificate found", certificate.chain_path));
+    }
+    let key = rustls_pemfile::private_key(&mut open_pem(certificate.key_path)?)
+        .map_err(|e| format!("{}: {e}", certificate.key_path))?
+        .ok_or_else(|| format!("{}: no private key found", certificate.key_path))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| format!("{}: {e}", certificate.key_path))?;
+
+    Ok(CertifiedKey::new(chain, key))
+}
+
+// Fonction pour décrire le certificat vérifié du client
+fn client_certificate(der: &[u8]) -> Option<ClientCertificate> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+    let common_name = certificate.subject().iter_common_name().next();
+    let fingerprint = ::ring::digest::digest(&::ring::digest::SHA256, der);
+    let time = |time: ASN1Time| {
+        chrono::DateTime::from_timestamp(time.timestamp(), 0)
+            .map(|time| time.format("%b %e %H:%M:%S %Y GMT").to_string())
+            .unwrap_or_default()
+    };
+
+    Some(ClientCertificate {
+        subject: certificate.subject().to_string(),
+        common_name: common_name
+            .and_then(|name| name.as_str().ok())
+            .map(str::to_string),
+        issuer: certificate.issuer().to_string(),
+        serial: certificate.serial.to_str_radix(16).to_uppercase(),
+        fingerprint: fingerprint
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect(),
+        not_before: time(certificate.validity().not_before),
+        not_after: time(certificate.validity().not_after),
+    })
+}
+
+// Fonction pour appliquer à une requête l'exigence de certificat client de sa route
+pub fn check_client_certificate(
+    request: &mut Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<(), StatusCode> {
+    let route = get_route(request, config).ok();
+    let auth = route.and_then(|route| route.settings.and_then(|s| s.client_certificate));
+
+    let session = request.extensions_mut().get_mut::<TlsSession>();
+    let presented = session
+        .as_ref()
+        .is_some_and(|session| session.client_certificate.is_some());
+    match (auth, session) {
+        (Some(ClientAuth::Required), _) if !presented => Err(StatusCode::FORBIDDEN),
+        (None, Some(session)) => {
+            session.client_certificate = None;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+// Fonction pour rediriger une requête reçue en clair vers le port HTTPS
+pub fn https_redirect(
+    request: &Request<Bytes>,
+    port: Port,
+    config: &ServerConfig,
+) -> Response<Bytes> {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .map_or(config.host, without_port);
+    let authority = match port {
+        443 => host.to_string(),
+        port => format!("{host}:{port}"),
+    };
+    let target = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |target| target.as_str());
+
+    // Les autres méthodes doivent être répétées telles quelles sur le port HTTPS
+    let status = match *request.method() {
+        Method::GET | Method::HEAD => StatusCode::MOVED_PERMANENTLY,
+        _ => StatusCode::PERMANENT_REDIRECT,
+    };
+    redirect(
+        status,
+        config,
+        request.version(),
+        format!("https://{authority}{target}"),
+    )
+}
+
+// Fonction pour retirer le port d'un en-tête `Host`, y compris pour une adresse IPv6
+fn without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    }
+}
+
+/// # ClientStream
+///
+/// Connexion d'un client, en clair ou chiffrée : la poignée de main TLS avance au fil des
+/// événements de la boucle, sans bloquer.
+#[derive(Debug)]
+pub struct ClientStream {
+    socket: ClientSocket,
+    tls: Option<Box<ServerConnection>>,
+    https_redirect: Option<Port>,
+    // Octets reçus mais pas encore consommés, relus avant la connexion
+    unread: Bytes,
+    // Octets à envoyer que la connexion n'a pas encore acceptés, dans l'ordre
+    output: Bytes,
+    // L'en-tête PROXY du répartiteur de charge est attendu avant tout autre octet
+    expects_proxy_header: bool,
+    // Adresses d'origine transmises par l'en-tête PROXY
+    proxied: Option<Addresses>,
+}
+
+impl ClientStream {
+    // Fonction pour préparer la connexion selon le protocole du port d'écoute
+    pub fn new(
+        socket: ClientSocket,
+        transport: &Transport,
+        proxy_protocol: bool,
+    ) -> io::Result<Self> {
+        let (tls, https_redirect) = match transport {
+            Transport::Plain => (None, None),
+            Transport::Tls(config) => {
+                let connection =
+                    ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+                (Some(Box::new(connection)), None)
+            }
+            Transport::RedirectToHttps(port) => (None, Some(*port)),
+        };
+        Ok(Self {
+            socket,
+            tls,
+            https_redirect,
+            unread: Bytes::new(),
+            output: Bytes::new(),
+            expects_proxy_header: proxy_protocol,
+            proxied: None,
+        })
+    }
+
+    // Adresse du client, celle transmise par l'en-tête PROXY le cas échéant
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self.proxied {
+            Some(addresses) => Ok(addresses.remote),
+            None => self.socket.peer_addr(),
+        }
+    }
+
+    // Adresse à laquelle le client s'est connecté, celle transmise par l'en-tête PROXY le cas échéant
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self.proxied {
+            Some(addresses) => Ok(addresses.local),
+            None => self.socket.local_addr(),
+        }
+    }
+
+    // Adresse du répartiteur de charge qui a ouvert la connexion
+    pub fn proxy_addr(&self) -> Option<SocketAddr> {
+        self.proxied.and(self.socket.peer_addr().ok())
+    }
+
+    // Protocole applicatif négocié par ALPN pendant la poignée de main
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.tls.as_ref()?.alpn_protocol()
+    }
+
+    // Fonction pour lire les premiers octets reçus sans les consommer, en clair seulement
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.unread.is_empty() {
+            let n = buf.len().min(self.unread.len());
+            buf[..n].copy_from_slice(&self.unread[..n]);
+            return Ok(n);
+        }
+        match self.tls {
+            Some(_) => Ok(0),
+            None => self.socket.peek(buf),
+        }
+    }
+
+    // Fonction pour remettre des octets lus en tête de ce que la connexion renverra
+    pub fn unread(&mut self, bytes: &[u8]) {
+        self.unread.splice(..0, bytes.iter().copied());
+    }
+
+    // Vrai si une requête a commencé d'arriver sans être complète
+    pub fn has_unread(&self) -> bool {
+        !self.unread.is_empty()
+    }
+
+    // Fonction pour envoyer des octets au client sans bloquer : ceux que la connexion n'accepte
+    // pas encore sont conservés et envoyés quand elle redevient prête
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.extend(bytes);
+        self.flush_output()
+    }
+
+    // Fonction pour écrire les octets en attente tant que la connexion les accepte
+    pub fn flush_output(&mut self) -> io::Result<()> {
+        let mut output = std::mem::take(&mut self.output);
+        let mut written = 0;
+        let result = loop {
+            if written == output.len() {
+                break self.flush();
+            }
+            match self.write(&output[written..]) {
+                Ok(0) => break Err(ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        output.drain(..written);
+        self.output = output;
+        match result {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+
+    // Nombre d'octets en attente d'envoi
+    pub fn pending_output(&self) -> usize {
+        self.output.len()
+    }
+
+    // Vrai si des octets, ou des enregistrements TLS, attendent que la connexion redevienne prête
+    pub fn has_pending_output(&self) -> bool {
+        !self.output.is_empty() || self.tls.as_ref().is_some_and(|tls| tls.wants_write())
+    }
+
+    // Port HTTPS vers lequel les requêtes reçues sur cette connexion sont redirigées
+    pub fn https_redirect(&self) -> Option<Port> {
+        self.https_redirect
+    }
+
+    // Fonction pour lire l'en-tête PROXY et faire avancer la poignée de main ; vrai si une
+    // requête peut être lue
+    pub fn is_ready(&mut self) -> io::Result<bool> {
+        if self.expects_proxy_header && !self.receive_proxy_header()? {
+            return Ok(false);
+        }
+        let Some(tls) = self.tls.as_mut() else {
+            return Ok(true);
+        };
+        receive_tls(tls, &mut self.socket)?;
+        let state = tls
+            .process_new_packets()
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        Ok(!tls.is_handshaking()
+            && (state.plaintext_bytes_to_read() > 0 || state.peer_has_closed()))
+    }
+
+    // Fonction pour retirer l'en-tête PROXY du début de la connexion ; faux tant qu'il est incomplet
+    fn receive_proxy_header(&mut self) -> io::Result<bool> {
+        let mut buf = vec![0; PROXY_HEADER_MAX_SIZE];
+        let received = self.socket.peek(&mut buf)?;
+        if received == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let Some(header) = parse_proxy_header(&buf[..received])? else {
+            return Ok(false);
+        };
+        // Les octets de l'en-tête ont déjà été reçus : les consommer ne bloque pas
+        self.socket.read_exact(&mut buf[..header.length])?;
+        self.expects_proxy_header = false;
+        self.proxied = header.addresses;
+        Ok(true)
+    }
+
+    // Paramètres négociés, une fois la poignée de main terminée
+    pub fn tls_session(&self) -> Option<TlsSession> {
+        let tls = self.tls.as_ref().filter(|tls| !tls.is_handshaking())?;
+        let protocol = match tls.protocol_version()? {
+            ProtocolVersion::TLSv1_2 => "TLSv1.2".to_string(),
+            ProtocolVersion::TLSv1_3 => "TLSv1.3".to_string(),
+            version => format!("{version:?}"),
+        };
+        let suite = tls.negotiated_cipher_suite()?.suite();
+        let presented = tls.peer_certificates().and_then(|chain| chain.first());
+        Some(TlsSession {
+            protocol,
+            cipher: suite.as_str().map_or(format!("{suite:?}"), str::to_string),
+            server_name: tls.server_name().map(str::to_string),
+            client_certificate: presented.and_then(|der| client_certificate(der)),
+        })
+    }
+
+    // Fonction pour annoncer la fermeture au client avant de fermer la connexion
+    pub fn close_notify(&mut self) {
+        if let Some(tls) = self.tls.as_mut() {
+            tls.send_close_notify();
+            let _ = send_tls(tls, &mut self.socket);
+        }
+    }
+}
+
+// Fonction pour lire et déchiffrer les enregistrements disponibles ; vrai si des octets sont arrivés
+fn receive_tls(tls: &mut ServerConnection, socket: &mut ClientSocket) -> io::Result<bool> {
+    let mut received = false;
+    // rustls cesse de lire tant que les données déchiffrées n'ont pas été consommées
+    while tls.wants_read() {
+        match tls.read_tls(socket) {
+            Ok(0) => {
+                received = true;
+                break;
+            }
+            Ok(_) => received = true,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        if let Err(e) = tls.process_new_packets() {
+            // Envoyer l'alerte qui explique l'échec au client
+            let _ = send_tls(tls, socket);
+            return Err(io::Error::new(ErrorKind::InvalidData, e));
+        }
+    }
+    send_tls(tls, socket)?;
+    Ok(received)
+}
+
+// Fonction pour écrire les enregistrements en attente tant que la connexion les accepte ; les
+// autres restent dans rustls jusqu'à ce qu'elle redevienne prête
+fn send_tls(tls: &mut ServerConnection, socket: &mut ClientSocket) -> io::Result<()> {
+    while tls.wants_write() {
+        match tls.write_tls(socket) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Rendre d'abord les octets remis par `unread`
+        if !self.unread.is_empty() {
+            let n = buf.len().min(self.unread.len());
+            buf[..n].copy_from_slice(&self.unread[..n]);
+            self.unread.drain(..n);
+            return Ok(n);
+        }
+        let Some(tls) = self.tls.as_mut() else {
+            return self.socket.read(buf);
+        };
+
+        // Remplir le tampon autant que possible, comme une lecture sur la connexion en clair
+        let mut filled = 0;
+        while filled < buf.len() {
+            match tls.reader().read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    filled += n;
+                    continue;
+                }
+                // Le client a fermé la connexion sans `close_notify`
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+            match receive_tls(tls, &mut self.socket)? {
+                true => {}
+                false if filled > 0 => break,
+                false => return Err(ErrorKind::WouldBlock.into()),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(tls) = self.tls.as_mut() else {
+            return self.socket.write(buf);
+        };
+        loop {
+            let written = tls.writer().write(buf)?;
+            send_tls(tls, &mut self.socket)?;
+            if written > 0 || buf.is_empty() {
+                return Ok(written);
+            }
+            // Les enregistrements chiffrés remplissent le tampon de rustls
+            if tls.wants_write() {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(tls) = self.tls.as_mut() {
+            send_tls(tls, &mut self.socket)?;
+        }
+        self.socket.flush()
+    }
+}
+
+impl Source for ClientStream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.socket.register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.socket.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.socket.deregister(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_without_port() {
+        assert_eq!(without_port("example.com:8080"), "example.com");
+        assert_eq!(without_port("example.com"), "example.com");
+        assert_eq!(without_port("[::1]:8080"), "[::1]");
+        assert_eq!(without_port("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn wildcard_names() {
+        assert!(name_matches("example.com", "example.com"));
+        assert!(name_matches("*.example.com", "www.example.com"));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", "a.b.example.com"));
+        assert!(!name_matches("example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        let tls = Tls {
+            port: 8443,
+            certificates: vec![],
+            min_version: TlsVersion::Tls12,
+            cipher_suites: None,
+            redirect_from: None,
+            client_ca_path: None,
+        };
+        assert!(tls_server_config(&tls).is_err());
+
+        let tls = Tls {
+            cipher_suites: Some(vec!["TLS13_ROT13"]),
+            ..tls
+        };
+        assert_eq!(
+            tls_server_config(&tls).unwrap_err(),
+            "unknown cipher suite TLS13_ROT13"
+        );
+
+        let tls = Tls {
+            cipher_suites: None,
+            certificates: vec![Certificate {
+                server_names: vec!["localhost"],
+                chain_path: "missing/cert.pem",
+                key_path: "missing/key.pem",
+            }],
+            ..tls
+        };
+        assert!(tls_server_config(&tls)
+            .unwrap_err()
+            .starts_with("missing/cert.pem"));
+
+        let tls = Tls {
+            certificates: vec![Certificate {
+                server_names: vec!["localhost"],
+                chain_path: "tests/certs/localhost.pem",
+                key_path: "tests/certs/localhost.key",
+            }],
+            client_ca_path: Some("tests/certs/localhost.key"),
+            ..tls
+        };
+        assert_eq!(
+            tls_server_config(&tls).unwrap_err(),
+            "tests/certs/localhost.key: no certificate authority found"
+        );
+    }
+
+    #[test]
+    fn client_certificate_details() {
+        let mut pem = BufReader::new(File::open("tests/certs/client.pem").unwrap());
+        let der = rustls_pemfile::certs(&mut pem).next().unwrap().unwrap();
+        let certificate = client_certificate(&der).unwrap();
+
+        assert_eq!(certificate.subject, "O=Example Ops, CN=admin");
+        assert_eq!(certificate.common_name.as_deref(), Some("admin"));
+        assert_eq!(certificate.issuer, "CN=localhost test CA");
+        assert_eq!(certificate.fingerprint.len(), 64);
+        assert!(certificate.not_after.ends_with(" GMT"));
+        assert!(client_certificate(b"not a certificate").is_none());
+    }
+}
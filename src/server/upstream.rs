@@ -0,0 +1,512 @@
+use crate::log;
+use crate::log::*;
+use crate::server::route::Route;
+use crate::server::{
+    add_env_variables, gateway_script, Bytes, CgiEnv, CgiProgress, Lease, Proxy, ProxyRelay, Relay,
+    ServerConfig, StatusCode, BUFFER_SIZE, DEFAULT_CGI_TIMEOUT, WEBSOCKET_IDLE_TIMEOUT,
+};
+use http::Request;
+use mio::event::Source;
+use mio::net::{TcpStream, UnixStream};
+use mio::{Interest, Registry, Token};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+// Adresses TCP des serveurs d'application et des serveurs mandatés, résolues au démarrage :
+// la boucle d'événements n'interroge jamais le DNS
+static RESOLVED: Mutex<BTreeMap<String, SocketAddr>> = Mutex::new(BTreeMap::new());
+
+/// # Address
+///
+/// Adresse d'un serveur d'application local : `Tcp("127.0.0.1:9000")` ou
+/// `Unix("/run/php/php-fpm.sock")`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Address<'a> {
+    Tcp(&'a str),
+    Unix(&'a str),
+}
+
+/// # Gateway
+///
+/// Serveur d'application, ou serveurs HTTP mandatés, auxquels une route transmet ses requêtes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gateway<'a> {
+    FastCgi(Address<'a>),
+    Scgi(Address<'a>),
+    Uwsgi(Address<'a>),
+    Proxy(Proxy<'a>),
+}
+
+/// # Endpoint
+///
+/// Copie d'une `Address` conservée par les connexions ouvertes vers le serveur d'application.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Endpoint {
+    Tcp(String),
+    Unix(String),
+}
+
+impl From<&Address<'_>> for Endpoint {
+    fn from(address: &Address) -> Self {
+        match address {
+            Address::Tcp(address) => Endpoint::Tcp(address.to_string()),
+            Address::Unix(path) => Endpoint::Unix(path.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "{address}"),
+            Endpoint::Unix(path) => write!(f, "unix:{path}"),
+        }
+    }
+}
+
+// Fonction pour résoudre les adresses des serveurs auxquels les routes transmettent leurs
+// requêtes ; une adresse qui ne se résout pas rend ses requêtes en 502
+pub fn resolve_gateways(config: &ServerConfig) {
+    for route in &config.routes {
+        let addresses = match route.settings.as_ref().and_then(|s| s.gateway) {
+            Some(Gateway::FastCgi(address) | Gateway::Scgi(address) | Gateway::Uwsgi(address)) => {
+                vec![address]
+            }
+            Some(Gateway::Proxy(proxy)) => proxy.upstreams.iter().map(|u| u.address).collect(),
+            None => continue,
+        };
+        for address in addresses {
+            let Address::Tcp(host) = address else {
+                continue;
+            };
+            let resolved = host.to_socket_addrs().and_then(|mut addresses| {
+                addresses
+                    .next()
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "No address found"))
+            });
+            match resolved {
+                Ok(resolved) => {
+                    let mut cache = RESOLVED.lock().unwrap_or_else(PoisonError::into_inner);
+                    cache.insert(host.to_string(), resolved);
+                }
+                Err(e) => log!(
+                    LogFileType::Server,
+                    format!("Error resolving address {host}: {e}")
+                ),
+            }
+        }
+    }
+}
+
+// Fonction pour obtenir l'adresse résolue au démarrage ; une adresse IP est lue directement
+pub fn resolved_address(host: &str) -> io::Result<SocketAddr> {
+    if let Ok(address) = host.parse() {
+        return Ok(address);
+    }
+    let cache = RESOLVED.lock().unwrap_or_else(PoisonError::into_inner);
+    cache
+        .get(host)
+        .copied()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Unresolved address"))
+}
+
+/// # GatewayTarget
+///
+/// Script visé par une requête transmise à un serveur d'application, avec les variables
+/// méta-données qui lui sont passées et l'instant auquel la requête est abandonnée.
+#[derive(Debug)]
+pub struct GatewayTarget {
+    pub name: String,
+    pub env: CgiEnv,
+    pub deadline: Instant,
+}
+
+impl GatewayTarget {
+    // Fonction pour préparer les variables méta-données comme pour un script CGI
+    pub fn new(route: &Route, request: &Request<Bytes>, config: &ServerConfig) -> GatewayTarget {
+        let script = gateway_script(route, request.uri().path());
+        let mut env = CgiEnv::new();
+        add_env_variables(&mut env, request, config, route, &script);
+
+        let timeout = route
+            .settings
+            .as_ref()
+            .and_then(|settings| settings.cgi_timeout)
+            .unwrap_or(DEFAULT_CGI_TIMEOUT);
+
+        GatewayTarget {
+            name: script.name,
+            env,
+            deadline: Instant::now() + timeout,
+        }
+    }
+}
+
+/// # Exchange
+///
+/// Requête d'un client en attente de la réponse d'un serveur en amont.
+#[derive(Debug)]
+pub struct Exchange {
+    pub name: String,       // Script ou ressource demandée, pour les journaux
+    pub kind: &'static str, // Passerelle utilisée, pour les journaux
+    pub request: Request<Bytes>,
+    pub request_head: String,
+    pub deadline: Instant,
+}
+
+/// # Reader
+///
+/// Interprétation de la réponse d'un serveur en amont.
+#[derive(Debug)]
+pub enum Reader {
+    // Sortie au format CGI (SCGI, uwsgi). `status_line` conserve le début de la sortie tant
+    // que sa première ligne est incomplète.
+    Cgi {
+        status_line: Option<Bytes>,
+        relay: Relay,
+    },
+    // Réponse HTTP d'un serveur mandaté
+    Http(ProxyRelay),
+}
+
+impl Reader {
+    pub fn cgi() -> Reader {
+        Reader::Cgi {
+            status_line: Some(Bytes::new()),
+            relay: Relay::new(false),
+        }
+    }
+
+    fn has_responded(&self) -> bool {
+        match self {
+            Reader::Cgi { relay, .. } => relay.has_responded(),
+            Reader::Http(relay) => relay.has_responded(),
+        }
+    }
+
+    // Vrai si le serveur mandaté a accepté de changer de protocole
+    fn is_tunnel(&self) -> bool {
+        matches!(self, Reader::Http(relay) if relay.is_tunnel())
+    }
+
+    fn feed(
+        &mut self,
+        request: &Request<Bytes>,
+        output: Bytes,
+        closed: bool,
+        config: &ServerConfig,
+    ) -> Result<CgiProgress, StatusCode> {
+        match self {
+            Reader::Cgi { status_line, relay } => {
+                let output = normalize_status_line(status_line, output, closed);
+                relay.feed(request, output, closed, config)
+            }
+            Reader::Http(relay) => relay.feed(request, output, closed),
+        }
+    }
+}
+
+// Fonction pour remplacer une ligne de statut HTTP (`HTTP/1.1 200 OK`) par le champ
+// CGI `Status`, les applications uwsgi répondant généralement ainsi
+fn normalize_status_line(pending: &mut Option<Bytes>, output: Bytes, closed: bool) -> Bytes {
+    let Some(mut start) = pending.take() else {
+        return output;
+    };
+    start.extend(output);
+    if !start.contains(&b'\n') && !closed {
+        *pending = Some(start);
+        return Bytes::new();
+    }
+
+    if start.starts_with(b"HTTP/") {
+        if let Some(space) = start.iter().position(|&b| b == b' ') {
+            let mut normalized = b"Status:".to_vec();
+            normalized.extend(&start[space..]);
+            return normalized;
+        }
+    }
+    start
+}
+
+/// # UpstreamRequest
+///
+/// Requête d'un client transmise sur sa propre connexion à un serveur d'application
+/// (SCGI, uwsgi) ou à un serveur HTTP mandaté, dont la réponse est relayée au fil de l'eau.
+/// La réponse se termine à la fermeture de la connexion par le serveur.
+#[derive(Debug)]
+pub struct UpstreamRequest {
+    endpoint: Endpoint,
+    stream: UpstreamStream,
+    connected: bool,
+    outgoing: Bytes,
+    reader: Reader,
+    exchange: Exchange,
+    lease: Option<Lease>,
+}
+
+impl UpstreamRequest {
+    // Fonction pour ouvrir la connexion et préparer l'envoi de la requête
+    pub fn connect(
+        address: &Address,
+        outgoing: Bytes,
+        reader: Reader,
+        exchange: Exchange,
+    ) -> Result<UpstreamRequest, StatusCode> {
+        let endpoint = Endpoint::from(address);
+        let stream = UpstreamStream::connect(&endpoint).map_err(|e| {
+            log!(
+                LogFileType::Server,
+                format!("Error connecting to {endpoint}: {e}")
+            );
+            StatusCode::BAD_GATEWAY
+        })?;
+
+        Ok(UpstreamRequest {
+            endpoint,
+            stream,
+            connected: false,
+            outgoing,
+            reader,
+            exchange,
+            lease: None,
+        })
+    }
+
+    // Fonction pour rattacher la requête au serveur choisi parmi ceux d'une route mandatée
+    pub fn with_lease(mut self, lease: Lease) -> UpstreamRequest {
+        self.lease = Some(lease);
+        self
+    }
+
+    // Fonction pour signaler l'issue de la requête à la détection des pannes
+    pub fn report(&mut self, success: bool) {
+        if let Some(lease) = &mut self.lease {
+            lease.report(success);
+        }
+    }
+
+    // Fonction pour surveiller la connexion ; renvoie son jeton
+    pub fn register(&mut self, registry: &Registry, token_id: &mut usize) -> io::Result<Token> {
+        let token = Token(*token_id);
+        *token_id += 1;
+        let interests = Interest::READABLE | Interest::WRITABLE;
+        registry.register(&mut self.stream, token, interests)?;
+        Ok(token)
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) {
+        let _ = registry.deregister(&mut self.stream);
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    // Instant auquel la requête est abandonnée si la réponse n'est pas terminée
+    pub fn deadline(&self) -> Instant {
+        self.exchange.deadline
+    }
+
+    // Vrai si une partie de la réponse a déjà été envoyée au client
+    pub fn has_responded(&self) -> bool {
+        self.reader.has_responded()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.exchange.name
+    }
+
+    // Nom de la passerelle, pour les journaux
+    pub fn kind(&self) -> &'static str {
+        self.exchange.kind
+    }
+
+    // Vrai si la connexion est devenue un tunnel entre le client et le serveur (WebSocket)
+    pub fn is_tunnel(&self) -> bool {
+        self.reader.is_tunnel()
+    }
+
+    // Fonction pour ajouter des octets du client à ceux à envoyer au serveur
+    pub fn forward(&mut self, data: &[u8]) {
+        self.outgoing.extend(data);
+    }
+
+    pub fn request(&self) -> &Request<Bytes> {
+        &self.exchange.request
+    }
+
+    pub fn request_head(&self) -> &str {
+        &self.exchange.request_head
+    }
+
+    // Fonction pour envoyer la requête et relayer la réponse disponibles sur la connexion
+    pub fn handle_event(&mut self, config: &ServerConfig) -> Result<CgiProgress, StatusCode> {
+        let progress = match self.transfer() {
+            Ok((output, closed)) => {
+                let request = &self.exchange.request;
+                self.reader.feed(request, output, closed, config)
+            }
+            Err(e) => {
+                log!(
+                    LogFileType::Server,
+                    format!(
+                        "Error: server {} failed for {}: {e}",
+                        self.endpoint, self.exchange.name
+                    )
+                );
+                if self.has_responded() {
+                    // Fermer la connexion signale au client une réponse incomplète
+                    Ok(CgiProgress::Done(Bytes::new()))
+                } else {
+                    Err(StatusCode::BAD_GATEWAY)
+                }
+            }
+        };
+        match progress {
+            Err(_) => self.report(false),
+            Ok(_) if self.has_responded() => self.report(true),
+            Ok(_) => {}
+        }
+        // Un tunnel n'est fermé que s'il reste inactif
+        if self.is_tunnel() {
+            self.exchange.deadline = Instant::now() + WEBSOCKET_IDLE_TIMEOUT;
+        }
+        progress
+    }
+
+    // Fonction pour écrire la requête et lire la réponse ; vrai si le serveur a fermé
+    fn transfer(&mut self) -> io::Result<(Bytes, bool)> {
+        if !self.connected {
+            if !self.stream.is_connected()? {
+                return Ok((Bytes::new(), false));
+            }
+            self.connected = true;
+        }
+
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut output = Bytes::new();
+        let mut chunk = [0; BUFFER_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok((output, true)),
+                Ok(n) => output.extend(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok((output, false)),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// # UpstreamStream
+///
+/// Connexion non bloquante vers un serveur d'application, surveillée par la boucle d'événements.
+#[derive(Debug)]
+pub enum UpstreamStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl UpstreamStream {
+    // Fonction pour ouvrir la connexion ; elle est établie au premier événement WRITABLE
+    pub fn connect(endpoint: &Endpoint) -> io::Result<UpstreamStream> {
+        match endpoint {
+            Endpoint::Tcp(address) => {
+                let stream = TcpStream::connect(resolved_address(address)?)?;
+                stream.set_nodelay(true)?;
+                Ok(UpstreamStream::Tcp(stream))
+            }
+            Endpoint::Unix(path) => Ok(UpstreamStream::Unix(UnixStream::connect(path)?)),
+        }
+    }
+
+    // Fonction pour vérifier que la connexion est établie, ou renvoyer l'erreur de connexion
+    pub fn is_connected(&self) -> io::Result<bool> {
+        let (error, peer) = match self {
+            UpstreamStream::Tcp(stream) => (stream.take_error()?, stream.peer_addr().map(|_| ())),
+            UpstreamStream::Unix(stream) => (stream.take_error()?, stream.peer_addr().map(|_| ())),
+        };
+        if let Some(error) = error {
+            return Err(error);
+        }
+        match peer {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Read for UpstreamStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.read(buf),
+            UpstreamStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for UpstreamStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.write(buf),
+            UpstreamStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.flush(),
+            UpstreamStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Source for UpstreamStream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.register(registry, token, interests),
+            UpstreamStream::Unix(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.reregister(registry, token, interests),
+            UpstreamStream::Unix(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.deregister(registry),
+            UpstreamStream::Unix(stream) => stream.deregister(registry),
+        }
+    }
+}
@@ -0,0 +1,1277 @@
+use crate::log;
+use crate::log::*;
+use crate::server::path::{add_root_to_path, has_dot_segment, path_exists};
+use crate::server::{
+    content_type, Bytes, Method, Request, Response, Route, ServerConfig, StatusCode,
+};
+use chrono::{DateTime, Utc};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, HOST};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// # WEBDAV_METHODS
+///
+/// Méthodes WebDAV (RFC 4918) prises en charge sur les routes où `webdav` est activé.
+pub const WEBDAV_METHODS: [&str; 7] = [
+    "PROPFIND",
+    "PROPPATCH",
+    "MKCOL",
+    "COPY",
+    "MOVE",
+    "LOCK",
+    "UNLOCK",
+];
+
+const DAV: &str = "DAV:";
+
+// Fonction pour obtenir les méthodes WebDAV, à ajouter à `Route::methods`
+pub fn webdav_methods() -> Vec<Method> {
+    WEBDAV_METHODS
+        .iter()
+        .filter_map(|m| Method::from_bytes(m.as_bytes()).ok())
+        .collect()
+}
+
+// Fonction pour vérifier si une méthode appartient à WebDAV
+pub fn is_webdav_method(method: &Method) -> bool {
+    WEBDAV_METHODS.contains(&method.as_str())
+}
+
+// Fonction pour vérifier si WebDAV est activé pour une route
+pub fn is_webdav_route(route: &Route) -> bool {
+    route.settings.as_ref().is_some_and(|s| s.webdav)
+}
+
+// Fonction principale pour gérer les méthodes WebDAV
+pub fn handle_webdav(
+    route: &Route,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    if !is_webdav_route(route) {
+        log!(
+            LogFileType::Server,
+            format!("Not Implemented: {} (WebDAV disabled)", &req.method())
+        );
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+    check_path(req.uri().path())?;
+
+    let path = &add_root_to_path(route, req.uri().path());
+    match req.method().as_str() {
+        "PROPFIND" => propfind(req, config, path),
+        "PROPPATCH" => proppatch(req, config, path),
+        "MKCOL" => mkcol(req, config, path),
+        "COPY" => copy_or_move(req, config, path, false),
+        "MOVE" => copy_or_move(req, config, path, true),
+        "LOCK" => lock(req, config, path),
+        "UNLOCK" => unlock(req, config, path),
+        _ => Err(StatusCode::NOT_IMPLEMENTED),
+    }
+}
+
+// Fonction pour refuser un chemin qui sortirait de la racine de la route par `.` ou `..`
+pub fn check_path(path: &str) -> Result<(), StatusCode> {
+    if has_dot_segment(path) {
+        log!(
+            LogFileType::Server,
+            format!("Error: {} (dot segment in {path})", StatusCode::FORBIDDEN)
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+// Fonction pour refuser l'écriture sur une ressource verrouillée sans le jeton correspondant
+pub fn check_write_locks(route: &Route, req: &Request<Bytes>) -> Result<(), StatusCode> {
+    locks::check(&add_root_to_path(route, req.uri().path()), req)
+}
+
+// Fonction pour oublier les propriétés mortes d'une ressource supprimée
+pub fn forget_properties(route: &Route, req: &Request<Bytes>) {
+    properties::relocate(&add_root_to_path(route, req.uri().path()), None);
+}
+
+// Fonction pour construire une réponse WebDAV
+fn dav_response(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    status: StatusCode,
+    body: Option<String>,
+) -> http::response::Builder {
+    let mut resp = Response::builder()
+        .version(req.version())
+        .header(HOST, config.host)
+        .status(status);
+
+    if let Some(body) = body {
+        resp = resp
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .header(CONTENT_LENGTH, body.len());
+    } else {
+        resp = resp.header(CONTENT_LENGTH, 0);
+    }
+    resp
+}
+
+fn finish(
+    resp: http::response::Builder,
+    body: Option<String>,
+) -> Result<Response<Bytes>, StatusCode> {
+    resp.body(Bytes::from(body.unwrap_or_default()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Fonction pour construire une réponse 207 Multi-Status
+fn multistatus(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    responses: String,
+) -> Result<Response<Bytes>, StatusCode> {
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">{responses}</D:multistatus>"#
+    );
+    let resp = dav_response(req, config, StatusCode::MULTI_STATUS, Some(body.clone()));
+    finish(resp, Some(body))
+}
+
+fn status_line(status: StatusCode) -> String {
+    format!(
+        "HTTP/1.1 {} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+}
+
+// Profondeur demandée par l'en-tête `Depth`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Depth {
+    Zero,
+    One,
+    Infinity,
+}
+
+fn depth(req: &Request<Bytes>) -> Result<Depth, StatusCode> {
+    match req.headers().get("Depth").map(|h| h.to_str()) {
+        None => Ok(Depth::Infinity),
+        Some(Ok("0")) => Ok(Depth::Zero),
+        Some(Ok("1")) => Ok(Depth::One),
+        Some(Ok(d)) if d.eq_ignore_ascii_case("infinity") => Ok(Depth::Infinity),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// Propriétés demandées par le corps d'un PROPFIND
+enum PropFind {
+    AllProp,
+    PropName,
+    Prop(Vec<(String, String)>),
+}
+
+fn parse_propfind(body: &[u8]) -> Result<PropFind, StatusCode> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(PropFind::AllProp);
+    }
+
+    let root = xml::parse(body)
+        .filter(|e| e.is(DAV, "propfind"))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    if root.child(DAV, "propname").is_some() {
+        return Ok(PropFind::PropName);
+    }
+    match root.child(DAV, "prop") {
+        Some(prop) => Ok(PropFind::Prop(
+            prop.children
+                .iter()
+                .map(|p| (p.ns.clone(), p.name.clone()))
+                .collect(),
+        )),
+        None => Ok(PropFind::AllProp),
+    }
+}
+
+// Fonction pour gérer les requêtes PROPFIND
+fn propfind(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    path: &str,
+) -> Result<Response<Bytes>, StatusCode> {
+    let metadata = fs::metadata(path).map_err(|_| StatusCode::NOT_FOUND)?;
+    // Depth: infinity n'est pas pris en charge (RFC 4918, section 9.1)
+    let depth = match depth(req)? {
+        Depth::Infinity => return Err(StatusCode::FORBIDDEN),
+        depth => depth,
+    };
+    let request = parse_propfind(req.body())?;
+
+    let href = req.uri().path();
+    let mut responses = prop_response(href, path, &metadata, &request);
+
+    if depth == Depth::One && metadata.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .filter_map(|entry| entry.ok())
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().to_string();
+            let child_href = format!(
+                "{}/{}{}",
+                href.trim_end_matches('/'),
+                encode_href(&name),
+                if metadata.is_dir() { "/" } else { "" }
+            );
+            let child_path = format!("{}/{name}", path.trim_end_matches('/'));
+            responses.push_str(&prop_response(
+                &child_href,
+                &child_path,
+                &metadata,
+                &request,
+            ));
+        }
+    }
+
+    multistatus(req, config, responses)
+}
+
+// Fonction pour construire l'élément <D:response> d'une ressource
+fn prop_response(href: &str, path: &str, metadata: &fs::Metadata, request: &PropFind) -> String {
+    let mut all = live_properties(path, metadata)
+        .into_iter()
+        .map(|(name, value)| ((DAV.to_string(), name.to_string()), value))
+        .collect::<Vec<_>>();
+    all.extend(properties::get(path));
+
+    let mut found = String::new();
+    let mut missing = String::new();
+    match request {
+        PropFind::AllProp => all
+            .iter()
+            .for_each(|((ns, name), value)| found.push_str(&property_xml(ns, name, value))),
+        PropFind::PropName => all
+            .iter()
+            .for_each(|((ns, name), _)| found.push_str(&property_xml(ns, name, ""))),
+        PropFind::Prop(names) => {
+            for (ns, name) in names {
+                match all.iter().find(|((n, p), _)| n == ns && p == name) {
+                    Some((_, value)) => found.push_str(&property_xml(ns, name, value)),
+                    None => missing.push_str(&property_xml(ns, name, "")),
+                }
+            }
+        }
+    }
+
+    let mut propstats = propstat(&found, StatusCode::OK);
+    if !missing.is_empty() {
+        propstats.push_str(&propstat(&missing, StatusCode::NOT_FOUND));
+    }
+    format!(
+        "<D:response><D:href>{}</D:href>{propstats}</D:response>",
+        xml::escape(href)
+    )
+}
+
+fn propstat(props: &str, status: StatusCode) -> String {
+    format!(
+        "<D:propstat><D:prop>{props}</D:prop><D:status>{}</D:status></D:propstat>",
+        status_line(status)
+    )
+}
+
+// Fonction pour représenter une propriété en XML
+fn property_xml(ns: &str, name: &str, value: &str) -> String {
+    match ns {
+        DAV => format!("<D:{name}>{value}</D:{name}>"),
+        "" => format!(r#"<{name} xmlns="">{value}</{name}>"#),
+        ns => format!(
+            r#"<X:{name} xmlns:X="{}">{value}</X:{name}>"#,
+            xml::escape(ns)
+        ),
+    }
+}
+
+// Fonction pour calculer les propriétés vivantes à partir des métadonnées du système de fichiers
+fn live_properties(path: &str, metadata: &fs::Metadata) -> Vec<(&'static str, String)> {
+    let modified: DateTime<Utc> = metadata.modified().unwrap_or(SystemTime::now()).into();
+    let created: DateTime<Utc> = metadata.created().map(Into::into).unwrap_or(modified);
+    let name = Path::new(path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let mut props = vec![
+        ("creationdate", created.to_rfc3339()),
+        ("displayname", xml::escape(&name)),
+        (
+            "getlastmodified",
+            modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        ),
+        (
+            "getetag",
+            format!("\"{:x}-{:x}\"", metadata.len(), modified.timestamp()),
+        ),
+    ];
+
+    if metadata.is_dir() {
+        props.push(("resourcetype", "<D:collection/>".to_string()));
+    } else {
+        props.push(("resourcetype", String::new()));
+        props.push(("getcontentlength", metadata.len().to_string()));
+        props.push(("getcontenttype", content_type(path)));
+    }
+
+    props.push((
+        "supportedlock",
+        ["exclusive", "shared"]
+            .iter()
+            .map(|scope| {
+                format!(
+                    "<D:lockentry><D:lockscope><D:{scope}/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>"
+                )
+            })
+            .collect(),
+    ));
+    props.push(("lockdiscovery", locks::discovery(path)));
+    props
+}
+
+// Fonction pour gérer les requêtes PROPPATCH
+fn proppatch(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    path: &str,
+) -> Result<Response<Bytes>, StatusCode> {
+    fs::metadata(path).map_err(|_| StatusCode::NOT_FOUND)?;
+    locks::check(path, req)?;
+
+    let root = xml::parse(req.body())
+        .filter(|e| e.is(DAV, "propertyupdate"))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    // Instructions dans l'ordre du document : (namespace, nom) -> Some(valeur) ou None pour supprimer
+    let mut updates = Vec::new();
+    for instruction in &root.children {
+        let set = match (instruction.ns.as_str(), instruction.name.as_str()) {
+            (DAV, "set") => true,
+            (DAV, "remove") => false,
+            _ => continue,
+        };
+        for prop in instruction.children.iter().filter(|e| e.is(DAV, "prop")) {
+            for p in &prop.children {
+                let value = set.then(|| xml::escape(&p.text));
+                updates.push(((p.ns.clone(), p.name.clone()), value));
+            }
+        }
+    }
+
+    // Les propriétés vivantes sont protégées ; la mise à jour est atomique
+    let protected = updates.iter().any(|((ns, _), _)| ns == DAV);
+    let mut ok = String::new();
+    let mut forbidden = String::new();
+    let mut failed = String::new();
+
+    for ((ns, name), value) in updates {
+        let xml = property_xml(&ns, &name, "");
+        if ns == DAV {
+            forbidden.push_str(&xml);
+        } else if protected {
+            failed.push_str(&xml);
+        } else {
+            properties::set(path, (ns, name), value);
+            ok.push_str(&xml);
+        }
+    }
+
+    let mut propstats = String::new();
+    for (props, status) in [
+        (ok, StatusCode::OK),
+        (forbidden, StatusCode::FORBIDDEN),
+        (failed, StatusCode::FAILED_DEPENDENCY),
+    ] {
+        if !props.is_empty() {
+            propstats.push_str(&propstat(&props, status));
+        }
+    }
+
+    let responses = format!(
+        "<D:response><D:href>{}</D:href>{propstats}</D:response>",
+        xml::escape(req.uri().path())
+    );
+    multistatus(req, config, responses)
+}
+
+// Fonction pour gérer les requêtes MKCOL
+fn mkcol(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    path: &str,
+) -> Result<Response<Bytes>, StatusCode> {
+    if !req.body().is_empty() {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    if Path::new(path).exists() {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    if !parent_exists(path) {
+        return Err(StatusCode::CONFLICT);
+    }
+    locks::check(path, req)?;
+
+    fs::create_dir(path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    finish(dav_response(req, config, StatusCode::CREATED, None), None)
+}
+
+fn parent_exists(path: &str) -> bool {
+    Path::new(path.trim_end_matches('/'))
+        .parent()
+        .is_some_and(|parent| parent.is_dir())
+}
+
+// Fonction pour résoudre l'en-tête `Destination` en chemin sur le disque
+fn destination(req: &Request<Bytes>, config: &ServerConfig) -> Result<String, StatusCode> {
+    let uri = req
+        .headers()
+        .get("Destination")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<http::Uri>().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let path = uri.path();
+    check_path(path)?;
+
+    let (i, _) = path_exists(path, &config.routes).ok_or(StatusCode::FORBIDDEN)?;
+    let route = &config.routes[i];
+    if !is_webdav_route(route) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(add_root_to_path(route, path))
+}
+
+// Fonction pour gérer les requêtes COPY et MOVE
+fn copy_or_move(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    source: &str,
+    is_move: bool,
+) -> Result<Response<Bytes>, StatusCode> {
+    let metadata = fs::metadata(source).map_err(|_| StatusCode::NOT_FOUND)?;
+    let destination = destination(req, config)?;
+    let (source_trimmed, destination_trimmed) = (
+        source.trim_end_matches('/'),
+        destination.trim_end_matches('/'),
+    );
+
+    if source_trimmed == destination_trimmed
+        || destination_trimmed.starts_with(&format!("{source_trimmed}/"))
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let overwrite = req
+        .headers()
+        .get("Overwrite")
+        .is_none_or(|h| !h.as_bytes().eq_ignore_ascii_case(b"F"));
+    let existed = Path::new(&destination).exists();
+    if existed && !overwrite {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    }
+    if !parent_exists(&destination) {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    locks::check(&destination, req)?;
+    if is_move {
+        locks::check(source, req)?;
+    }
+
+    if existed {
+        remove(&destination).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    if is_move {
+        fs::rename(source, &destination).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        properties::relocate(source, Some(&destination));
+    } else {
+        // Une collection copiée avec `Depth: 0` ne reprend pas ses membres
+        let recursive = depth(req)? != Depth::Zero;
+        copy(
+            Path::new(source),
+            Path::new(&destination),
+            metadata.is_dir(),
+            recursive,
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        properties::copy(source, &destination);
+    }
+
+    let status = if existed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    };
+    finish(dav_response(req, config, status, None), None)
+}
+
+fn remove(path: &str) -> std::io::Result<()> {
+    if Path::new(path).is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn copy(source: &Path, destination: &Path, is_dir: bool, recursive: bool) -> std::io::Result<()> {
+    if !is_dir {
+        return fs::copy(source, destination).map(|_| ());
+    }
+
+    fs::create_dir(destination)?;
+    if recursive {
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            let is_dir = entry.file_type()?.is_dir();
+            copy(
+                &entry.path(),
+                &destination.join(entry.file_name()),
+                is_dir,
+                true,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Fonction pour gérer les requêtes LOCK
+fn lock(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    path: &str,
+) -> Result<Response<Bytes>, StatusCode> {
+    let timeout = locks::timeout(req);
+
+    // Un LOCK sans corps rafraîchit un verrou existant désigné par l'en-tête `If`
+    if req.body().iter().all(u8::is_ascii_whitespace) {
+        let body = locks::refresh(path, req, timeout).ok_or(StatusCode::PRECONDITION_FAILED)?;
+        let body = lock_discovery_body(&body);
+        let resp = dav_response(req, config, StatusCode::OK, Some(body.clone()));
+        return finish(resp, Some(body));
+    }
+
+    let info = xml::parse(req.body())
+        .filter(|e| e.is(DAV, "lockinfo"))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let exclusive = info
+        .child(DAV, "lockscope")
+        .is_some_and(|scope| scope.child(DAV, "exclusive").is_some());
+    let owner = info
+        .child(DAV, "owner")
+        .map(|owner| owner.text_content())
+        .unwrap_or_default();
+    let infinite = match depth(req)? {
+        Depth::Zero => false,
+        Depth::Infinity => true,
+        Depth::One => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    if locks::conflicts(path, infinite, exclusive) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    // Verrouiller une ressource inexistante crée une ressource vide
+    let existed = Path::new(path).exists();
+    if !existed {
+        if !parent_exists(path) {
+            return Err(StatusCode::CONFLICT);
+        }
+        fs::write(path, []).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    let (token, activelock) = locks::create(
+        path,
+        req.uri().path(),
+        exclusive,
+        infinite,
+        xml::escape(&owner),
+        timeout,
+    );
+
+    let status = if existed {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    let body = lock_discovery_body(&activelock);
+    let resp = dav_response(req, config, status, Some(body.clone()))
+        .header("Lock-Token", format!("<{token}>"));
+    finish(resp, Some(body))
+}
+
+fn lock_discovery_body(activelock: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?><D:prop xmlns:D="DAV:"><D:lockdiscovery>{activelock}</D:lockdiscovery></D:prop>"#
+    )
+}
+
+// Fonction pour gérer les requêtes UNLOCK
+fn unlock(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    path: &str,
+) -> Result<Response<Bytes>, StatusCode> {
+    let token = req
+        .headers()
+        .get("Lock-Token")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim().trim_start_matches('<').trim_end_matches('>'))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    if !locks::release(path, token) {
+        return Err(StatusCode::CONFLICT);
+    }
+    finish(
+        dav_response(req, config, StatusCode::NO_CONTENT, None),
+        None,
+    )
+}
+
+// Fonction pour encoder un nom de fichier dans une URL
+fn encode_href(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+mod properties {
+    use lazy_static::lazy_static;
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Mutex;
+
+    type Name = (String, String);
+
+    lazy_static! {
+        // Propriétés mortes (PROPPATCH), indexées par chemin sur le disque
+        static ref PROPERTIES: Mutex<HashMap<String, BTreeMap<Name, String>>> =
+            Mutex::new(HashMap::new());
+    }
+
+    fn key(path: &str) -> String {
+        path.trim_end_matches('/').to_string()
+    }
+
+    pub fn get(path: &str) -> Vec<(Name, String)> {
+        let properties = PROPERTIES.lock().unwrap_or_else(|e| e.into_inner());
+        properties
+            .get(&key(path))
+            .map(|props| props.clone().into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set(path: &str, name: Name, value: Option<String>) {
+        let mut properties = PROPERTIES.lock().unwrap_or_else(|e| e.into_inner());
+        let props = properties.entry(key(path)).or_default();
+        match value {
+            Some(value) => props.insert(name, value),
+            None => props.remove(&name),
+        };
+    }
+
+    // Déplacer (ou supprimer si `to` est `None`) les propriétés d'une ressource et de ses membres
+    pub fn relocate(from: &str, to: Option<&str>) {
+        let mut properties = PROPERTIES.lock().unwrap_or_else(|e| e.into_inner());
+        let from = key(from);
+        let moved = properties
+            .keys()
+            .filter(|k| **k == from || k.starts_with(&format!("{from}/")))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for k in moved {
+            let props = properties.remove(&k).unwrap_or_default();
+            if let Some(to) = to {
+                properties.insert(format!("{}{}", key(to), &k[from.len()..]), props);
+            }
+        }
+    }
+
+    pub fn copy(from: &str, to: &str) {
+        let mut properties = PROPERTIES.lock().unwrap_or_else(|e| e.into_inner());
+        let (from, to) = (key(from), key(to));
+        let copied = properties
+            .iter()
+            .filter(|(k, _)| **k == from || k.starts_with(&format!("{from}/")))
+            .map(|(k, v)| (format!("{to}{}", &k[from.len()..]), v.clone()))
+            .collect::<Vec<_>>();
+        properties.extend(copied);
+    }
+}
+
+mod locks {
+    use super::xml;
+    use crate::server::{Bytes, Request, StatusCode};
+    use lazy_static::lazy_static;
+    use rand::Rng;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    /// Durée maximale d'un verrou, appliquée aussi à `Timeout: Infinite`.
+    const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+
+    struct Lock {
+        path: String,
+        root: String,
+        exclusive: bool,
+        infinite: bool,
+        owner: String,
+        timeout: Duration,
+        expires: Instant,
+    }
+
+    lazy_static! {
+        // Verrous actifs, indexés par jeton
+        static ref LOCKS: Mutex<HashMap<String, Lock>> = Mutex::new(HashMap::new());
+    }
+
+    fn key(path: &str) -> &str {
+        path.trim_end_matches('/')
+    }
+
+    fn is_descendant(path: &str, ancestor: &str) -> bool {
+        key(path).starts_with(&format!("{}/", key(ancestor)))
+    }
+
+    // Un verrou s'applique à sa ressource et, en profondeur infinie, à tous ses membres
+    fn covers(lock: &Lock, path: &str) -> bool {
+        key(&lock.path) == key(path) || (lock.infinite && is_descendant(path, &lock.path))
+    }
+
+    fn active() -> std::sync::MutexGuard<'static, HashMap<String, Lock>> {
+        let mut locks = LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        locks.retain(|_, lock| lock.expires > now);
+        locks
+    }
+
+    pub fn timeout(req: &Request<Bytes>) -> Duration {
+        req.headers()
+            .get("Timeout")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split(',').next())
+            .and_then(|h| {
+                let h = h.trim();
+                h.get(..7)
+                    .filter(|prefix| prefix.eq_ignore_ascii_case("Second-"))
+                    .and_then(|_| h[7..].parse().ok())
+            })
+            .map_or(MAX_TIMEOUT, |s| Duration::from_secs(s).min(MAX_TIMEOUT))
+    }
+
+    // Vérifier que la requête présente le jeton de chaque verrou qui touche `path`
+    pub fn check(path: &str, req: &Request<Bytes>) -> Result<(), StatusCode> {
+        let if_header = req
+            .headers()
+            .get("If")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+
+        let locked = active().iter().any(|(token, lock)| {
+            (covers(lock, path) || is_descendant(&lock.path, path)) && !if_header.contains(token)
+        });
+
+        if locked {
+            Err(StatusCode::LOCKED)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn conflicts(path: &str, infinite: bool, exclusive: bool) -> bool {
+        active().values().any(|lock| {
+            (covers(lock, path) || (infinite && is_descendant(&lock.path, path)))
+                && (exclusive || lock.exclusive)
+        })
+    }
+
+    pub fn create(
+        path: &str,
+        root: &str,
+        exclusive: bool,
+        infinite: bool,
+        owner: String,
+        timeout: Duration,
+    ) -> (String, String) {
+        let mut rng = rand::thread_rng();
+        let token = format!(
+            "opaquelocktoken:{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            rng.gen::<u32>(),
+            rng.gen::<u16>(),
+            rng.gen::<u16>(),
+            rng.gen::<u16>(),
+            rng.gen::<u64>() & 0xffff_ffff_ffff
+        );
+
+        let lock = Lock {
+            path: path.to_string(),
+            root: root.to_string(),
+            exclusive,
+            infinite,
+            owner,
+            timeout,
+            expires: Instant::now() + timeout,
+        };
+        let xml = activelock(&token, &lock);
+        active().insert(token.clone(), lock);
+        (token, xml)
+    }
+
+    pub fn refresh(path: &str, req: &Request<Bytes>, timeout: Duration) -> Option<String> {
+        let if_header = req.headers().get("If")?.to_str().ok()?;
+        let mut locks = active();
+        let (token, lock) = locks
+            .iter_mut()
+            .find(|(token, lock)| if_header.contains(token.as_str()) && covers(lock, path))?;
+
+        lock.timeout = timeout;
+        lock.expires = Instant::now() + timeout;
+        Some(activelock(token, lock))
+    }
+
+    pub fn release(path: &str, token: &str) -> bool {
+        let mut locks = active();
+        match locks.get(token) {
+            Some(lock) if covers(lock, path) => locks.remove(token).is_some(),
+            _ => false,
+        }
+    }
+
+    // Contenu de la propriété `lockdiscovery`
+    pub fn discovery(path: &str) -> String {
+        active()
+            .iter()
+            .filter(|(_, lock)| covers(lock, path))
+            .map(|(token, lock)| activelock(token, lock))
+            .collect()
+    }
+
+    fn activelock(token: &str, lock: &Lock) -> String {
+        format!(
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:{}/></D:lockscope>\
+            <D:depth>{}</D:depth><D:owner>{}</D:owner><D:timeout>Second-{}</D:timeout>\
+            <D:locktoken><D:href>{token}</D:href></D:locktoken>\
+            <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+            if lock.exclusive {
+                "exclusive"
+            } else {
+                "shared"
+            },
+            if lock.infinite { "infinity" } else { "0" },
+            lock.owner,
+            lock.timeout.as_secs(),
+            xml::escape(&lock.root)
+        )
+    }
+}
+
+/// Analyseur XML minimal pour les corps de requêtes WebDAV, avec résolution des namespaces.
+pub mod xml {
+    use std::collections::HashMap;
+
+    #[derive(Debug, Default)]
+    pub struct Element {
+        pub ns: String,
+        pub name: String,
+        pub children: Vec<Element>,
+        pub text: String,
+    }
+
+    impl Element {
+        pub fn is(&self, ns: &str, name: &str) -> bool {
+            self.ns == ns && self.name == name
+        }
+
+        pub fn child(&self, ns: &str, name: &str) -> Option<&Element> {
+            self.children.iter().find(|c| c.is(ns, name))
+        }
+
+        // Texte de l'élément et de tous ses descendants
+        pub fn text_content(&self) -> String {
+            self.children
+                .iter()
+                .fold(self.text.trim().to_string(), |acc, c| {
+                    acc + &c.text_content()
+                })
+        }
+    }
+
+    pub fn escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    fn unescape(s: &str) -> String {
+        s.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+
+    // Fonction pour analyser un document et retourner son élément racine
+    pub fn parse(input: &[u8]) -> Option<Element> {
+        let input = std::str::from_utf8(input).ok()?;
+        // Pile des éléments ouverts, avec les préfixes de namespace en vigueur
+        let mut stack: Vec<(Element, String, HashMap<String, String>)> = Vec::new();
+        let mut rest = input;
+
+        loop {
+            let start = rest.find('<')?;
+            if let Some((element, _, _)) = stack.last_mut() {
+                element.text.push_str(&unescape(&rest[..start]));
+            }
+            rest = &rest[start..];
+
+            if rest.starts_with("<?") {
+                rest = &rest[rest.find("?>")? + 2..];
+            } else if rest.starts_with("<!--") {
+                rest = &rest[rest.find("-->")? + 3..];
+            } else if rest.starts_with("<!") {
+                rest = &rest[rest.find('>')? + 1..];
+            } else if let Some(tail) = rest.strip_prefix("</") {
+                let end = tail.find('>')?;
+                let (element, qname, _) = stack.pop()?;
+                if tail[..end].trim() != qname {
+                    return None;
+                }
+                rest = &tail[end + 1..];
+
+                match stack.last_mut() {
+                    Some((parent, _, _)) => parent.children.push(element),
+                    None => return Some(element),
+                }
+            } else {
+                let end = rest.find('>')?;
+                let self_closing = rest[..end].ends_with('/');
+                let tag = rest[1..end].trim_end_matches('/');
+                rest = &rest[end + 1..];
+
+                let mut namespaces = stack
+                    .last()
+                    .map(|(_, _, ns)| ns.clone())
+                    .unwrap_or_default();
+                let (qname, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+                for (key, value) in parse_attributes(attributes)? {
+                    if key == "xmlns" {
+                        namespaces.insert(String::new(), value);
+                    } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+                        namespaces.insert(prefix.to_string(), value);
+                    }
+                }
+
+                let (prefix, name) = qname.split_once(':').unwrap_or(("", qname));
+                let element = Element {
+                    ns: namespaces.get(prefix).cloned().unwrap_or_default(),
+                    name: name.to_string(),
+                    ..Default::default()
+                };
+
+                if !self_closing {
+                    stack.push((element, qname.to_string(), namespaces));
+                    continue;
+                }
+                match stack.last_mut() {
+                    Some((parent, _, _)) => parent.children.push(element),
+                    None => return Some(element),
+                }
+            }
+        }
+    }
+
+    fn parse_attributes(mut input: &str) -> Option<Vec<(String, String)>> {
+        let mut attributes = Vec::new();
+        loop {
+            input = input.trim_start();
+            if input.is_empty() {
+                return Some(attributes);
+            }
+            let (key, tail) = input.split_once('=')?;
+            let tail = tail.trim_start();
+            let quote = tail.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let end = tail[1..].find(quote)? + 1;
+            attributes.push((key.trim().to_string(), unescape(&tail[1..end])));
+            input = &tail[end + 1..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::config::route::Settings;
+    use crate::server::ListenOptions;
+    use std::time::Duration;
+
+    // `add_root_to_path` conserve le préfixe de la route
+    const ROOT: &str = "./target/webdav-test/dav";
+
+    fn config() -> ServerConfig<'static> {
+        ServerConfig {
+            host: "127.0.0.1",
+            ports: vec![],
+            bind_addresses: vec![],
+            listen_options: ListenOptions::default(),
+            custom_error_path: None,
+            body_size_limit: 1024,
+            trace_enabled: false,
+            cgi_max_processes: 16,
+            shutdown_grace_period: Duration::from_secs(30),
+            tls: vec![],
+            proxy_protocol: vec![],
+            unix_sockets: vec![],
+            routes: vec![Route {
+                url_path: "/dav",
+                methods: webdav_methods(),
+                handler: None,
+                settings: Some(Settings {
+                    http_redirections: None,
+                    redirect_status_code: None,
+                    root_path: Some("/target/webdav-test"),
+                    default_if_url_is_dir: None,
+                    default_if_request_is_dir: None,
+                    cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
+                    list_directory: false,
+                    webdav: true,
+                }),
+            }],
+        }
+    }
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> Request<Bytes> {
+        let mut req = Request::builder()
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(path);
+        for (key, value) in headers {
+            req = req.header(*key, *value);
+        }
+        req.body(Bytes::from(body)).unwrap()
+    }
+
+    fn send(req: Request<Bytes>) -> Result<Response<Bytes>, StatusCode> {
+        let config = config();
+        let route = config.routes[0].clone();
+        handle_webdav(&route, &req, &config)
+    }
+
+    #[test]
+    fn parse_namespaced_xml() {
+        let doc = br#"<?xml version="1.0"?>
+            <D:propfind xmlns:D="DAV:" xmlns:Z="urn:z">
+              <D:prop><D:getcontentlength/><Z:color>red &amp; blue</Z:color></D:prop>
+            </D:propfind>"#;
+        let root = xml::parse(doc).unwrap();
+        assert!(root.is(DAV, "propfind"));
+
+        let prop = root.child(DAV, "prop").unwrap();
+        assert!(prop.children[0].is(DAV, "getcontentlength"));
+        assert!(prop.children[1].is("urn:z", "color"));
+        assert_eq!(prop.children[1].text, "red & blue");
+
+        assert!(xml::parse(b"<a><b></a>").is_none());
+    }
+
+    #[test]
+    fn webdav_disabled_route() {
+        let mut config = config();
+        config.routes[0].settings.as_mut().unwrap().webdav = false;
+        let req = request("PROPFIND", "/dav", &[("Depth", "0")], "");
+        assert_eq!(
+            handle_webdav(&config.routes[0].clone(), &req, &config).unwrap_err(),
+            StatusCode::NOT_IMPLEMENTED
+        );
+    }
+
+    #[test]
+    fn dot_segments_stay_inside_the_root() {
+        let base = "./target/webdav-traversal";
+        let _ = fs::remove_dir_all(base);
+        fs::create_dir_all(format!("{base}/dav")).unwrap();
+        fs::write(format!("{base}/dav/a.txt"), "inside").unwrap();
+        fs::write(format!("{base}/outside.txt"), "outside").unwrap();
+
+        let mut config = config();
+        config.routes[0].settings.as_mut().unwrap().root_path = Some("/target/webdav-traversal");
+        let route = config.routes[0].clone();
+        let send = |req| handle_webdav(&route, &req, &config);
+
+        for (method, destination) in [
+            ("MOVE", "/dav/../outside.txt"),
+            ("COPY", "http://127.0.0.1/dav/%2e%2e/outside.txt"),
+        ] {
+            let req = request(method, "/dav/a.txt", &[("Destination", destination)], "");
+            assert_eq!(send(req).unwrap_err(), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(
+            send(request("MKCOL", "/dav/../col", &[], "")).unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        let delete = request("DELETE", "/dav/../outside.txt", &[], "");
+        assert_eq!(
+            crate::server::handle_method(&route, &delete, &config).unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        assert_eq!(
+            fs::read_to_string(format!("{base}/dav/a.txt")).unwrap(),
+            "inside"
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{base}/outside.txt")).unwrap(),
+            "outside"
+        );
+        assert!(!Path::new(&format!("{base}/col")).exists());
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn collections_properties_and_locks() {
+        let _ = fs::remove_dir_all("./target/webdav-test");
+        fs::create_dir_all(ROOT).unwrap();
+
+        // MKCOL
+        let resp = send(request("MKCOL", "/dav/col", &[], "")).unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(
+            send(request("MKCOL", "/dav/col", &[], "")).unwrap_err(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            send(request("MKCOL", "/dav/missing/col", &[], "")).unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        fs::write(format!("{ROOT}/col/a.txt"), "hello").unwrap();
+
+        // PROPFIND Depth 1
+        let resp = send(request("PROPFIND", "/dav/col", &[("Depth", "1")], "")).unwrap();
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let body = String::from_utf8(resp.body().clone()).unwrap();
+        assert!(body.contains("<D:href>/dav/col</D:href>"));
+        assert!(body.contains("<D:href>/dav/col/a.txt</D:href>"));
+        assert!(body.contains("<D:collection/>"));
+        assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert_eq!(
+            send(request("PROPFIND", "/dav/col", &[], "")).unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        // PROPPATCH puis PROPFIND d'une propriété précise
+        let update = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:z">
+            <D:set><D:prop><Z:color>red</Z:color></D:prop></D:set></D:propertyupdate>"#;
+        let resp = send(request("PROPPATCH", "/dav/col/a.txt", &[], update)).unwrap();
+        assert!(String::from_utf8_lossy(resp.body()).contains("200 OK"));
+
+        let find = r#"<D:propfind xmlns:D="DAV:" xmlns:Z="urn:z">
+            <D:prop><Z:color/><Z:size/></D:prop></D:propfind>"#;
+        let resp = send(request(
+            "PROPFIND",
+            "/dav/col/a.txt",
+            &[("Depth", "0")],
+            find,
+        ))
+        .unwrap();
+        let body = String::from_utf8(resp.body().clone()).unwrap();
+        assert!(body.contains(r#"<X:color xmlns:X="urn:z">red</X:color>"#));
+        assert!(body.contains("404 Not Found"));
+
+        // COPY et MOVE
+        let copy = request(
+            "COPY",
+            "/dav/col",
+            &[("Destination", "http://127.0.0.1/dav/copy")],
+            "",
+        );
+        assert_eq!(send(copy).unwrap().status(), StatusCode::CREATED);
+        assert!(Path::new(&format!("{ROOT}/copy/a.txt")).exists());
+
+        let no_overwrite = request(
+            "MOVE",
+            "/dav/copy/a.txt",
+            &[("Destination", "/dav/col/a.txt"), ("Overwrite", "F")],
+            "",
+        );
+        assert_eq!(
+            send(no_overwrite).unwrap_err(),
+            StatusCode::PRECONDITION_FAILED
+        );
+
+        let moved = request(
+            "MOVE",
+            "/dav/copy/a.txt",
+            &[("Destination", "/dav/b.txt")],
+            "",
+        );
+        assert_eq!(send(moved).unwrap().status(), StatusCode::CREATED);
+        assert!(!Path::new(&format!("{ROOT}/copy/a.txt")).exists());
+        assert!(Path::new(&format!("{ROOT}/b.txt")).exists());
+
+        // LOCK, écriture refusée sans jeton, puis UNLOCK
+        let info = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope>
+            <D:locktype><D:write/></D:locktype><D:owner>me</D:owner></D:lockinfo>"#;
+        let resp = send(request(
+            "LOCK",
+            "/dav/col",
+            &[("Timeout", "Second-60")],
+            info,
+        ))
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let token = resp.headers()["Lock-Token"].to_str().unwrap().to_string();
+        assert!(String::from_utf8_lossy(resp.body()).contains("Second-60"));
+
+        assert_eq!(
+            send(request("LOCK", "/dav/col/a.txt", &[("Depth", "0")], info)).unwrap_err(),
+            StatusCode::LOCKED
+        );
+        assert_eq!(
+            send(request("PROPPATCH", "/dav/col/a.txt", &[], update)).unwrap_err(),
+            StatusCode::LOCKED
+        );
+        let if_header = format!("({token})");
+        let resp = send(request(
+            "PROPPATCH",
+            "/dav/col/a.txt",
+            &[("If", &if_header)],
+            update,
+        ));
+        assert!(resp.is_ok());
+
+        assert_eq!(
+            send(request("UNLOCK", "/dav/col", &[("Lock-Token", &token)], ""))
+                .unwrap()
+                .status(),
+            StatusCode::NO_CONTENT
+        );
+        assert!(locks::discovery(&format!("{ROOT}/col")).is_empty());
+
+        fs::remove_dir_all("./target/webdav-test").unwrap();
+    }
+}
@@ -0,0 +1,511 @@
+use crate::server::{raw_header_values, Bytes, ServerConfig, StatusCode};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http::header::{HeaderMap, HeaderName, UPGRADE};
+use http::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION};
+use http::{Method, Request, Response, Version};
+use std::time::Duration;
+
+pub use frame::*;
+
+/// # WEBSOCKET_GUID
+///
+/// Identifiant concaténé à `Sec-WebSocket-Key` pour calculer `Sec-WebSocket-Accept` (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// # WEBSOCKET_IDLE_TIMEOUT
+///
+/// Durée au-delà de laquelle une connexion WebSocket, ou un tunnel établi par un proxy,
+/// qui n'a rien échangé est fermée.
+pub const WEBSOCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// # WebSocketHandler
+///
+/// Gestionnaire des connexions WebSocket d'une route, appelé à chaque événement ; il répond
+/// avec `WebSocket::send`, `ping` ou `close`.
+pub type WebSocketHandler = fn(socket: &mut WebSocket, event: WebSocketEvent);
+
+/// # Message
+///
+/// Message complet reçu ou envoyé, après réassemblage des fragments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+}
+
+/// # WebSocketEvent
+///
+/// Événements transmis au gestionnaire : ouverture, message, `pong` reçu, et fermeture
+/// avec son code (1006 si le client a coupé la connexion sans trame de fermeture).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WebSocketEvent {
+    Open,
+    Message(Message),
+    Pong(Bytes),
+    Close(u16, String),
+}
+
+/// # WebSocket
+///
+/// Connexion WebSocket établie avec un client : les trames reçues sont validées et
+/// réassemblées, les `ping` reçoivent leur `pong`, et les trames produites par le gestionnaire
+/// attendent d'être écrites par la boucle d'événements.
+#[derive(Debug)]
+pub struct WebSocket {
+    request: Request<Bytes>,
+    handler: WebSocketHandler,
+    max_message_size: usize,
+    buffer: Bytes,
+    message: Option<(u8, Bytes)>,
+    outgoing: Bytes,
+    close_sent: bool,
+    close_received: bool,
+    failed: bool,
+}
+
+impl WebSocket {
+    pub fn new(
+        request: Request<Bytes>,
+        handler: WebSocketHandler,
+        max_message_size: usize,
+    ) -> Self {
+        WebSocket {
+            request,
+            handler,
+            max_message_size,
+            buffer: Bytes::new(),
+            message: None,
+            outgoing: Bytes::new(),
+            close_sent: false,
+            close_received: false,
+            failed: false,
+        }
+    }
+
+    // Requête d'ouverture de la connexion
+    pub fn request(&self) -> &Request<Bytes> {
+        &self.request
+    }
+
+    // Fonction pour envoyer un message au client
+    pub fn send(&mut self, message: Message) {
+        match message {
+            Message::Text(text) => self.queue(TEXT, text.as_bytes()),
+            Message::Binary(data) => self.queue(BINARY, &data),
+        }
+    }
+
+    pub fn ping(&mut self, payload: &[u8]) {
+        self.queue(PING, &payload[..payload.len().min(MAX_CONTROL_PAYLOAD)]);
+    }
+
+    // Fonction pour commencer la fermeture ; la connexion est fermée à la réponse du client
+    pub fn close(&mut self, code: u16, reason: &str) {
+        if self.close_sent {
+            return;
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend(&reason.as_bytes()[..end]);
+        self.queue(CLOSE, &payload);
+        self.close_sent = true;
+    }
+
+    // Vrai lorsque la connexion peut être fermée
+    pub fn is_finished(&self) -> bool {
+        self.failed || (self.close_sent && self.close_received)
+    }
+
+    // Fonction pour prévenir le gestionnaire de l'ouverture de la connexion
+    pub fn open(&mut self) {
+        self.notify(WebSocketEvent::Open);
+    }
+
+    // Fonction pour traiter les octets reçus du client
+    pub fn receive(&mut self, data: &[u8]) {
+        self.buffer.extend(data);
+        while !self.close_received && !self.failed {
+            match parse_frame(&mut self.buffer, self.max_message_size) {
+                Ok(Some(frame)) => self.dispatch(frame),
+                Ok(None) => return,
+                Err(code) => self.fail(code),
+            }
+        }
+    }
+
+    // Fonction pour signaler que le client a fermé la connexion
+    pub fn peer_closed(&mut self) {
+        if !self.close_received && !self.failed {
+            self.close_received = true;
+            self.notify(WebSocketEvent::Close(ABNORMAL_CLOSURE, String::new()));
+        }
+    }
+
+    // Fonction pour récupérer les trames à écrire sur la connexion
+    pub fn take_outgoing(&mut self) -> Bytes {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    fn notify(&mut self, event: WebSocketEvent) {
+        let handler = self.handler;
+        handler(self, event);
+    }
+
+    fn queue(&mut self, opcode: u8, payload: &[u8]) {
+        // Plus rien n'est envoyé après la trame de fermeture
+        if !self.close_sent {
+            self.outgoing.extend(encode_frame(opcode, payload));
+        }
+    }
+
+    // Fonction pour fermer la connexion après une violation du protocole
+    fn fail(&mut self, code: u16) {
+        self.close(code, "");
+        self.failed = true;
+    }
+
+    fn dispatch(&mut self, frame: Frame) {
+        match frame.opcode {
+            CONTINUATION => {
+                let Some((_, data)) = self.message.as_mut() else {
+                    return self.fail(PROTOCOL_ERROR);
+                };
+                data.extend(frame.payload);
+                if data.len() > self.max_message_size {
+                    return self.fail(MESSAGE_TOO_BIG);
+                }
+                if frame.fin {
+                    if let Some((opcode, data)) = self.message.take() {
+                        self.deliver(opcode, data);
+                    }
+                }
+            }
+            TEXT | BINARY if self.message.is_some() => self.fail(PROTOCOL_ERROR),
+            TEXT | BINARY if frame.fin => self.deliver(frame.opcode, frame.payload),
+            TEXT | BINARY => self.message = Some((frame.opcode, frame.payload)),
+            PING => self.queue(PONG, &frame.payload),
+            PONG => self.notify(WebSocketEvent::Pong(frame.payload)),
+            CLOSE => match parse_close(&frame.payload) {
+                Ok((code, reason)) => {
+                    self.close_received = true;
+                    self.notify(WebSocketEvent::Close(code, reason));
+                    // Répondre avec le même code ; sans code, avec une trame vide
+                    if !self.close_sent {
+                        match code {
+                            NO_STATUS => self.queue(CLOSE, &[]),
+                            code => self.queue(CLOSE, &code.to_be_bytes()),
+                        }
+                        self.close_sent = true;
+                    }
+                }
+                Err(code) => self.fail(code),
+            },
+            _ => self.fail(PROTOCOL_ERROR),
+        }
+    }
+
+    fn deliver(&mut self, opcode: u8, data: Bytes) {
+        let message = match opcode {
+            TEXT => match String::from_utf8(data) {
+                Ok(text) => Message::Text(text),
+                Err(_) => return self.fail(INVALID_PAYLOAD),
+            },
+            _ => Message::Binary(data),
+        };
+        self.notify(WebSocketEvent::Message(message));
+    }
+}
+
+// Fonction pour vérifier si un en-tête contient une option (`Connection: keep-alive, Upgrade`)
+pub fn has_token(headers: &HeaderMap, name: HeaderName, token: &str) -> bool {
+    headers.get_all(name).iter().any(|value| {
+        value
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .any(|option| option.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+// Fonction pour vérifier si le client demande à changer de protocole sur la connexion
+pub fn is_upgrade_request(request: &Request<Bytes>) -> bool {
+    has_token(request.headers(), CONNECTION, "upgrade") && request.headers().contains_key(UPGRADE)
+}
+
+pub fn is_websocket_request(request: &Request<Bytes>) -> bool {
+    is_upgrade_request(request) && has_token(request.headers(), UPGRADE, "websocket")
+}
+
+// Fonction pour calculer `Sec-WebSocket-Accept` à partir de `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(WEBSOCKET_GUID.as_bytes());
+    STANDARD.encode(sha1.digest().bytes())
+}
+
+// Fonction pour accepter l'ouverture d'une connexion WebSocket (RFC 6455, section 4.2)
+pub fn websocket_handshake(
+    request: &Request<Bytes>,
+    handler: WebSocketHandler,
+    config: &ServerConfig,
+) -> Result<(Response<Bytes>, WebSocket), StatusCode> {
+    if request.method() != Method::GET || request.version() != Version::HTTP_11 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Le client doit recommencer avec une version acceptée, indiquée dans la réponse
+    if request
+        .headers()
+        .get(SEC_WEBSOCKET_VERSION)
+        .map(|v| v.as_bytes())
+        != Some(b"13")
+    {
+        return Err(StatusCode::UPGRADE_REQUIRED);
+    }
+    // La clé est sensible à la casse : elle est lue telle qu'elle a été reçue
+    let key = raw_header_values(request, &SEC_WEBSOCKET_KEY)
+        .into_iter()
+        .next()
+        .filter(|key| STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "Upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, accept_key(&key))
+        .body(Bytes::new())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let socket = WebSocket::new(request.clone(), handler, config.body_size_limit);
+    Ok((response, socket))
+}
+
+/// Trames WebSocket (RFC 6455, section 5) : codes d'opération, codes de fermeture,
+/// lecture des trames du client et écriture des trames du serveur.
+pub mod frame {
+    use crate::server::Bytes;
+
+    pub const CONTINUATION: u8 = 0x0;
+    pub const TEXT: u8 = 0x1;
+    pub const BINARY: u8 = 0x2;
+    pub const CLOSE: u8 = 0x8;
+    pub const PING: u8 = 0x9;
+    pub const PONG: u8 = 0xA;
+
+    pub const NORMAL_CLOSURE: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const NO_STATUS: u16 = 1005;
+    pub const ABNORMAL_CLOSURE: u16 = 1006;
+    pub const INVALID_PAYLOAD: u16 = 1007;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+    /// # MAX_CONTROL_PAYLOAD
+    ///
+    /// Taille maximale du contenu d'une trame de contrôle (`close`, `ping`, `pong`).
+    pub const MAX_CONTROL_PAYLOAD: usize = 125;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct Frame {
+        pub fin: bool,
+        pub opcode: u8,
+        pub payload: Bytes,
+    }
+
+    // Fonction pour extraire une trame complète du tampon ; renvoie le code de fermeture
+    // à envoyer si la trame est invalide
+    pub fn parse_frame(buffer: &mut Bytes, max_size: usize) -> Result<Option<Frame>, u16> {
+        if buffer.len() < 2 {
+            return Ok(None);
+        }
+        let (first, second) = (buffer[0], buffer[1]);
+        let fin = first & 0x80 != 0;
+        let opcode = first & 0x0F;
+        // Aucune extension n'est négociée : les bits réservés doivent être nuls
+        if first & 0x70 != 0 {
+            return Err(PROTOCOL_ERROR);
+        }
+        // Les trames du client sont toujours masquées
+        if second & 0x80 == 0 {
+            return Err(PROTOCOL_ERROR);
+        }
+
+        let (length, mut at) = match second & 0x7F {
+            126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+            127 if buffer.len() >= 10 => {
+                let length = u64::from_be_bytes(buffer[2..10].try_into().unwrap_or_default());
+                if length >> 63 != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                (length, 10)
+            }
+            126 | 127 => return Ok(None),
+            length => (length as u64, 2),
+        };
+        if opcode >= CLOSE && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(PROTOCOL_ERROR);
+        }
+        if length > max_size as u64 {
+            return Err(MESSAGE_TOO_BIG);
+        }
+
+        let length = length as usize;
+        if buffer.len() < at + 4 + length {
+            return Ok(None);
+        }
+        let mask = [buffer[at], buffer[at + 1], buffer[at + 2], buffer[at + 3]];
+        at += 4;
+        let payload = buffer[at..at + length]
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4])
+            .collect();
+        buffer.drain(..at + length);
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    // Fonction pour encoder une trame complète du serveur, qui n'est pas masquée
+    pub fn encode_frame(opcode: u8, payload: &[u8]) -> Bytes {
+        let mut out = vec![0x80 | opcode];
+        match payload.len() {
+            length if length < 126 => out.push(length as u8),
+            length if length <= u16::MAX as usize => {
+                out.push(126);
+                out.extend((length as u16).to_be_bytes());
+            }
+            length => {
+                out.push(127);
+                out.extend((length as u64).to_be_bytes());
+            }
+        }
+        out.extend(payload);
+        out
+    }
+
+    // Fonction pour lire le code et la raison d'une trame de fermeture
+    pub fn parse_close(payload: &[u8]) -> Result<(u16, String), u16> {
+        match payload {
+            [] => Ok((NO_STATUS, String::new())),
+            [_] => Err(PROTOCOL_ERROR),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+                    return Err(PROTOCOL_ERROR);
+                }
+                let reason = String::from_utf8(reason.to_vec()).map_err(|_| INVALID_PAYLOAD)?;
+                Ok((code, reason))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Bytes {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut out = encode_frame(opcode, payload);
+        let start = out.len() - payload.len();
+        out[0] = if fin { out[0] } else { out[0] & 0x7F };
+        out[1] |= 0x80;
+        let masked = payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]);
+        let masked: Bytes = masked.collect();
+        out.truncate(start);
+        out.extend(mask);
+        out.extend(masked);
+        out
+    }
+
+    fn echo(socket: &mut WebSocket, event: WebSocketEvent) {
+        match event {
+            WebSocketEvent::Message(message) => socket.send(message),
+            WebSocketEvent::Close(..) | WebSocketEvent::Open | WebSocketEvent::Pong(_) => {}
+        }
+    }
+
+    fn echo_socket() -> WebSocket {
+        WebSocket::new(Request::new(Bytes::new()), echo, 1024)
+    }
+
+    #[test]
+    fn accept_key_of_the_rfc_sample() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frames_are_unmasked_and_validated() {
+        let mut buffer = masked(true, TEXT, b"Hello");
+        let partial = buffer.split_off(4);
+        assert_eq!(parse_frame(&mut buffer, 1024), Ok(None));
+        buffer.extend(partial);
+        let frame = parse_frame(&mut buffer, 1024).unwrap().unwrap();
+        assert_eq!((frame.fin, frame.opcode), (true, TEXT));
+        assert_eq!(frame.payload, b"Hello");
+        assert!(buffer.is_empty());
+
+        let mut long = masked(true, BINARY, &[7; 300]);
+        assert_eq!(&long[1..4], [0x80 | 126, 1, 44]);
+        assert_eq!(
+            parse_frame(&mut long, 1024).unwrap().unwrap().payload,
+            [7; 300]
+        );
+
+        let mut unmasked = encode_frame(TEXT, b"Hello");
+        assert_eq!(parse_frame(&mut unmasked, 1024), Err(PROTOCOL_ERROR));
+        let mut fragmented_ping = masked(false, PING, b"");
+        assert_eq!(parse_frame(&mut fragmented_ping, 1024), Err(PROTOCOL_ERROR));
+        let mut too_big = masked(true, BINARY, &[0; 2000]);
+        assert_eq!(parse_frame(&mut too_big, 1024), Err(MESSAGE_TOO_BIG));
+    }
+
+    #[test]
+    fn fragments_are_reassembled_around_control_frames() {
+        let mut socket = echo_socket();
+        let mut data = masked(false, TEXT, b"Hel");
+        data.extend(masked(true, PING, b"p"));
+        data.extend(masked(true, CONTINUATION, b"lo"));
+        socket.receive(&data);
+
+        let mut expected = encode_frame(PONG, b"p");
+        expected.extend(encode_frame(TEXT, b"Hello"));
+        assert_eq!(socket.take_outgoing(), expected);
+        assert!(!socket.is_finished());
+    }
+
+    #[test]
+    fn protocol_errors_close_the_connection() {
+        let mut socket = echo_socket();
+        socket.receive(&masked(true, CONTINUATION, b"x"));
+        assert_eq!(socket.take_outgoing(), encode_frame(CLOSE, &[0x03, 0xEA]));
+        assert!(socket.is_finished());
+
+        let mut socket = echo_socket();
+        socket.receive(&masked(true, TEXT, &[0xC3, 0x28]));
+        assert_eq!(socket.take_outgoing(), encode_frame(CLOSE, &[0x03, 0xEF]));
+    }
+
+    #[test]
+    fn close_is_echoed() {
+        let mut socket = echo_socket();
+        let mut payload = NORMAL_CLOSURE.to_be_bytes().to_vec();
+        payload.extend(b"done");
+        socket.receive(&masked(true, CLOSE, &payload));
+        assert_eq!(socket.take_outgoing(), encode_frame(CLOSE, &[0x03, 0xE8]));
+        assert!(socket.is_finished());
+
+        assert_eq!(parse_close(&[0x03, 0xED]), Err(PROTOCOL_ERROR));
+        assert_eq!(parse_close(&[]), Ok((NO_STATUS, String::new())));
+    }
+}
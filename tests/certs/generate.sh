@@ -0,0 +1,33 @@
+#!/bin/sh
+# Regenerates the test certificate authority and the certificates it signs.
+set -e
+cd "$(dirname "$0")"
+
+openssl req -x509 -new -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+    -keyout ca.key -out ca.pem -days 36500 -subj "/CN=localhost test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign"
+
+# leaf <file> <subject> <extended key usage> [subject alternative names]
+leaf() {
+    openssl req -new -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+        -keyout "$1.key" -out "$1.csr" -subj "$2"
+    {
+        echo "basicConstraints=critical,CA:FALSE"
+        echo "keyUsage=critical,digitalSignature"
+        echo "extendedKeyUsage=$3"
+        [ -n "$4" ] && echo "subjectAltName=$4"
+    } > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA ca.pem -CAkey ca.key -CAcreateserial \
+        -out "$1.pem" -days 36500 -extfile "$1.ext"
+    rm "$1.csr" "$1.ext"
+}
+
+leaf localhost /CN=localhost serverAuth "DNS:localhost,IP:127.0.0.1"
+leaf alt /CN=alt.test serverAuth "DNS:alt.test,DNS:*.alt.test"
+leaf client "/O=Example Ops/CN=admin" clientAuth
+rm -f ca.srl
+
+# A client certificate signed by no trusted authority
+openssl req -x509 -new -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+    -keyout rogue.key -out rogue.pem -days 36500 -subj "/CN=rogue" \
+    -addext "basicConstraints=critical,CA:FALSE" -addext "extendedKeyUsage=clientAuth"
@@ -2,7 +2,7 @@ use lazy_static::lazy_static;
 use localhost::log;
 use localhost::log::{init_logs, LogFileType};
 use localhost::server::{content_type, start};
-use localhost::server_config::server_config;
+use localhost::server::config::server_config;
 use reqwest::blocking::Client;
 use reqwest::header::CONTENT_TYPE;
 use std::fs::File;
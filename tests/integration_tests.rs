@@ -1,9 +1,8 @@
 use common::setup;
-use reqwest;
 
 mod common;
 mod test_config {
-    use localhost::server_config::server_config;
+    use localhost::server::config::server_config;
     #[test]
     fn test_fields() {
         let configs = server_config();
@@ -232,8 +231,7 @@ mod test_handle_client {
                 .header(TRANSFER_ENCODING, "chunked")
                 .body(body);
 
-            let response = request_builder.send().unwrap();
-            response
+            request_builder.send().unwrap()
         }
 
         mod get {
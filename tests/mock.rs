@@ -1,15 +1,15 @@
 use http::{Method, Request, StatusCode};
+use localhost::server::route::{Route, Settings};
 use localhost::server::Cgi;
-use localhost::server_config::route::{Route, Settings};
-use localhost::server_config::ServerConfig;
+use localhost::server::{ListenOptions, ServerConfig};
 use localhost::type_aliases::Bytes;
 use std::collections::HashMap;
-
-#[allow(dead_code)]
+use std::time::Duration;
 
 // Mock functions and data for testing
+#[allow(dead_code)]
 pub fn mock_route() -> Route<'static> {
-    let route = Route {
+    Route {
         methods: vec![
             Method::GET,
             Method::OPTIONS,
@@ -24,12 +24,10 @@ pub fn mock_route() -> Route<'static> {
         url_path: "/",
         handler: None,
         settings: None,
-    };
-    route
+    }
 }
 
 #[allow(dead_code)]
-
 pub fn mock_request(
     method: Method,
     path: &str,
@@ -48,11 +46,19 @@ pub fn mock_request(
 }
 
 pub fn mock_server_config() -> ServerConfig<'static> {
-    let config = ServerConfig {
+    ServerConfig {
         host: "127.0.0.1",
         ports: vec![8080],
+        bind_addresses: vec![],
+        listen_options: ListenOptions::default(),
         custom_error_path: None,
         body_size_limit: 10024,
+        trace_enabled: true,
+        cgi_max_processes: 16,
+        shutdown_grace_period: Duration::from_secs(5),
+        tls: vec![],
+        proxy_protocol: vec![],
+        unix_sockets: vec![],
         routes: vec![
             Route {
                 url_path: "/cgi",
@@ -65,12 +71,22 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: Some(HashMap::from([
-                        ("js", Cgi::JavaScript),
+                        ("js", Cgi::JAVASCRIPT),
                         ("php", Cgi::PHP),
-                        ("py", Cgi::Python),
-                        ("rb", Cgi::Ruby),
+                        ("py", Cgi::PYTHON),
+                        ("rb", Cgi::RUBY),
+                        ("cgi", Cgi::Executable),
                     ])),
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -99,7 +115,16 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -122,7 +147,16 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -145,7 +179,16 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -168,7 +211,16 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -191,7 +243,16 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
             Route {
@@ -205,10 +266,18 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     default_if_url_is_dir: None,
                     default_if_request_is_dir: None,
                     cgi_def: None,
+                    cgi_dirs: None,
+                    cgi_pass_env: None,
+                    cgi_timeout: None,
+                    cgi_sandbox: None,
+                    gateway: None,
+                    websocket: None,
+                    event_stream: None,
+                    client_certificate: None,
                     list_directory: false,
+                    webdav: false,
                 }),
             },
         ],
-    };
-    config
+    }
 }
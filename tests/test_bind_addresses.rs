@@ -0,0 +1,116 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{start, Addresses, BindAddress, ListenOptions, ServerConfig};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+const PORT: u16 = 8106;
+const DUAL_STACK_PORT: u16 = 8107;
+
+static SERVER: Once = Once::new();
+
+// Answers with the host name of the server and the address of the client
+fn addresses(
+    request: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let remote = match request.extensions().get::<Addresses>() {
+        Some(Addresses { remote, .. }) => remote.ip().to_string(),
+        None => "none".to_string(),
+    };
+    let body = format!("host={} remote={remote}", config.host);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-length", body.len())
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn route() -> Route<'static> {
+    Route {
+        url_path: "/addresses",
+        methods: vec![Method::GET],
+        handler: Some(addresses),
+        settings: None,
+    }
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        // Le nom annoncé n'a pas besoin de se résoudre en une adresse d'écoute
+        let mut config = mock_server_config();
+        config.host = "www.example.test";
+        config.ports = vec![PORT];
+        config.bind_addresses = vec![
+            BindAddress {
+                ip: "127.0.0.1",
+                v6_only: None,
+            },
+            BindAddress {
+                ip: "::1",
+                v6_only: Some(true),
+            },
+        ];
+        config.routes.push(route());
+
+        let mut dual_stack = mock_server_config();
+        dual_stack.ports = vec![DUAL_STACK_PORT];
+        dual_stack.bind_addresses = vec![BindAddress {
+            ip: "::",
+            v6_only: Some(false),
+        }];
+        dual_stack.listen_options = ListenOptions {
+            backlog: 16,
+            reuse_port: true,
+            nodelay: true,
+            ..Default::default()
+        };
+        dual_stack.routes.push(route());
+
+        thread::spawn(move || start(vec![config, dual_stack]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+// Sends a request to the address and returns the body of the response
+fn get(host: &str, port: u16) -> String {
+    let mut stream = TcpStream::connect((host, port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(b"GET /addresses HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    response.split("\r\n\r\n").nth(1).unwrap().to_string()
+}
+
+#[test]
+fn every_bind_address_is_served() {
+    setup();
+    assert_eq!(
+        get("127.0.0.1", PORT),
+        "host=www.example.test remote=127.0.0.1"
+    );
+    assert_eq!(get("::1", PORT), "host=www.example.test remote=::1");
+}
+
+#[test]
+fn dual_stack_accepts_ipv4_clients() {
+    setup();
+    assert_eq!(get("::1", DUAL_STACK_PORT), "host=127.0.0.1 remote=::1");
+    // Les clients IPv4 arrivent avec une adresse IPv6 qui correspond à leur adresse IPv4
+    assert_eq!(
+        get("127.0.0.1", DUAL_STACK_PORT),
+        "host=127.0.0.1 remote=::ffff:127.0.0.1"
+    );
+}
@@ -1,8 +1,23 @@
 mod mock;
 
 use http::StatusCode;
-use localhost::server::execute_cgi_script;
+use localhost::server::{
+    add_env_variables, execute_cgi_script, is_cgi_request, start, Cgi, CgiEnv, CgiSandbox, Script,
+};
 use mock::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::process::Command;
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PORT: u16 = 8109;
+
+static SERVER: Once = Once::new();
 
 #[test]
 fn test_get() {
@@ -13,6 +28,24 @@ fn test_get() {
     }
 }
 
+// Run with `cargo test -- --ignored` where every interpreter is installed
+#[test]
+#[ignore = "needs the node, php, python3 and ruby interpreters"]
+fn each_extension_runs_its_interpreter() {
+    let conf = &mock_server_config();
+    for path in [
+        "javascript.js",
+        "php.php",
+        "python.py",
+        "ruby.rb",
+        "hello.cgi",
+    ] {
+        let req = &mock_request(http::Method::GET, &format!("/cgi/{path}"), None, None);
+        let resp = execute_cgi_script(req, conf).unwrap();
+        assert_eq!(resp.body(), b"Hello world!\n", "{path}");
+    }
+}
+
 #[test]
 fn test_invalid_route() {
     let conf = &mock_server_config();
@@ -50,3 +83,261 @@ fn add_headers_to_request() {
     let req = &mock_request(http::Method::GET, "/cgi/php.php", None, Some(headers));
     assert!(execute_cgi_script(req, conf).is_ok());
 }
+
+#[test]
+fn body_on_stdin_and_per_child_environment() {
+    let conf = &mock_server_config();
+    let req = &mock_request(
+        http::Method::GET,
+        "/cgi/echo.py/extra/path?a=1",
+        Some("name=value"),
+        Some(vec![("X-Test", "yes"), ("Authorization", "Basic c2VjcmV0")]),
+    );
+    let resp = execute_cgi_script(req, conf).unwrap();
+    let output = String::from_utf8(resp.body().clone()).unwrap();
+
+    for variable in [
+        "GATEWAY_INTERFACE=CGI/1.1",
+        "QUERY_STRING=a=1",
+        "SCRIPT_NAME=/cgi/echo.py",
+        "PATH_INFO=/extra/path",
+        "CONTENT_LENGTH=10",
+        "HTTP_X_TEST=yes",
+        "AUTH_TYPE=Basic",
+        "SERVER_PROTOCOL=HTTP/1.1",
+    ] {
+        assert!(
+            output.contains(variable),
+            "{variable} missing from {output}"
+        );
+    }
+    let cwd = std::env::current_dir().unwrap();
+    let translated = format!("PATH_TRANSLATED={}/extra/path\n", cwd.display());
+    assert!(
+        output.contains(&translated),
+        "{translated} missing from {output}"
+    );
+    // Credentials are not forwarded, and the body is read from stdin
+    assert!(!output.contains("c2VjcmV0"));
+    assert!(output.ends_with("\nname=value"));
+
+    // PATH_TRANSLATED follows the root of the route
+    let mut route = conf.routes[0].clone();
+    route.settings.as_mut().unwrap().root_path = Some("/files");
+    let script = Script {
+        name: "/cgi/echo.py".to_string(),
+        filename: "./files/cgi/echo.py".to_string(),
+        path_info: Some("/extra/path".to_string()),
+        extension: "py".to_string(),
+    };
+    let mut env = CgiEnv::new();
+    add_env_variables(&mut env, req, conf, &route, &script);
+    let expected = cwd.join("files/extra/path").display().to_string();
+    assert_eq!(env["PATH_TRANSLATED"], expected);
+
+    // Nothing leaks into the server's own environment
+    assert!(std::env::var("QUERY_STRING").is_err());
+}
+
+#[test]
+fn output_headers_are_parsed() {
+    let conf = &mock_server_config();
+    let req = &mock_request(http::Method::GET, "/cgi/python.py", None, None);
+    let resp = execute_cgi_script(req, conf).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["Content-Type"], "text/html");
+    assert_eq!(resp.body(), b"Hello world!\n");
+}
+
+#[test]
+fn process_limit() {
+    let mut conf = mock_server_config();
+    conf.cgi_max_processes = 0;
+    let req = &mock_request(http::Method::GET, "/cgi/python.py", None, None);
+
+    assert!(execute_cgi_script(req, &conf).is_err_and(|e| e == StatusCode::SERVICE_UNAVAILABLE));
+}
+
+#[test]
+fn interpreter_mapping_and_allowed_directories() {
+    let mut conf = mock_server_config();
+    let settings = conf.routes[0].settings.as_mut().unwrap();
+    settings.cgi_def = Some(HashMap::from([(
+        "py",
+        Cgi::Interpreter {
+            program: "python3",
+            args: &["-S"],
+        },
+    )]));
+    let req = &mock_request(http::Method::GET, "/cgi/python.py", None, None);
+    assert!(execute_cgi_script(req, &conf).is_ok());
+
+    // Scripts outside the allowlisted directories are refused
+    let settings = conf.routes[0].settings.as_mut().unwrap();
+    settings.cgi_dirs = Some(vec!["./cgi/bin"]);
+    assert!(execute_cgi_script(req, &conf).is_err_and(|e| e == StatusCode::FORBIDDEN));
+}
+
+#[test]
+fn cgi_enabled_by_cgi_def() {
+    let conf = mock_server_config();
+    let cgi_route = &conf.routes[0];
+    assert!(is_cgi_request(cgi_route, "/cgi/python.py/extra"));
+    assert!(!is_cgi_request(cgi_route, "/cgi/missing.py"));
+
+    // A path containing /cgi/ is not a script on a route without `cgi_def`
+    let plain_route = &conf.routes[1];
+    assert!(!is_cgi_request(plain_route, "/cgi/python.py"));
+}
+
+#[test]
+fn sandboxed_scripts() {
+    let mut conf = mock_server_config();
+    conf.routes[0].settings.as_mut().unwrap().cgi_sandbox = Some(CgiSandbox {
+        cpu_time: Some(Duration::from_secs(5)),
+        memory: Some(256 * 1024 * 1024),
+        open_files: Some(32),
+        ..CgiSandbox::default()
+    });
+
+    // A descriptor without FD_CLOEXEC, as a listener inherited by the script would be
+    let file = File::open("Cargo.toml").unwrap();
+    let leaked = unsafe { libc::dup(file.as_raw_fd()) };
+
+    let req = &mock_request(http::Method::GET, "/cgi/limits.cgi", None, None);
+    let resp = execute_cgi_script(req, &conf).unwrap();
+    let output = String::from_utf8(resp.body().clone()).unwrap();
+    unsafe { libc::close(leaked) };
+
+    let (limits, fds) = output.split_once('\n').unwrap();
+    // The script runs with the route's limits, from its own directory
+    assert!(limits.starts_with("32 5 262144 /"), "{limits}");
+    assert!(limits.ends_with("/cgi"), "{limits}");
+    assert!(!fds.lines().any(|fd| fd == leaked.to_string()), "{fds}");
+}
+
+#[test]
+fn scripts_past_their_timeout_are_stopped() {
+    let mut conf = mock_server_config();
+    conf.routes[0].settings.as_mut().unwrap().cgi_timeout = Some(Duration::from_millis(500));
+    let req = &mock_request(http::Method::GET, "/cgi/stream.cgi", None, None);
+
+    assert!(execute_cgi_script(req, &conf).is_err_and(|e| e == StatusCode::GATEWAY_TIMEOUT));
+}
+
+// Starts a server whose scripts are stopped after two seconds
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![PORT];
+        config.routes[0].settings.as_mut().unwrap().cgi_timeout = Some(Duration::from_secs(2));
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+// Sends a request for the script to the server
+fn request(path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).unwrap();
+    stream
+}
+
+fn read_all(mut stream: TcpStream) -> String {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn output_is_streamed_while_the_script_runs() {
+    setup();
+    let mut stream = request("/cgi/stream.cgi");
+
+    // The script waits a second before its last line: the first one arrives before
+    let mut received = String::new();
+    let mut buf = [0; 1024];
+    while !received.contains("first") {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "{received}");
+        received.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+    assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{received}");
+    assert!(
+        received.contains("transfer-encoding: chunked\r\n"),
+        "{received}"
+    );
+    assert!(!received.contains("second"), "{received}");
+
+    let rest = read_all(stream);
+    assert!(rest.ends_with("7\r\nsecond\n\r\n0\r\n\r\n"), "{rest}");
+}
+
+#[test]
+fn a_client_that_does_not_read_only_holds_up_its_own_script() {
+    setup();
+    let mut lagging = request("/cgi/large.cgi");
+    thread::sleep(Duration::from_millis(300));
+
+    // Other clients are served while the output of the script waits for its client
+    let started = Instant::now();
+    let response = read_all(request("/cgi/hello.cgi"));
+    assert!(response.contains("Hello world!\n"), "{response}");
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let mut received = Vec::new();
+    lagging.read_to_end(&mut received).unwrap();
+    assert!(received.len() > 16 << 20, "{}", received.len());
+    assert!(received.ends_with(b"\r\n0\r\n\r\n"));
+}
+
+#[test]
+fn output_kept_before_the_response_is_limited() {
+    setup();
+    // The body of a local redirect is discarded, so the server stops keeping it at some point
+    let response = read_all(request("/cgi/flood.cgi"));
+    assert!(response.starts_with("HTTP/1.1 502 "), "{response}");
+
+    let pgrep = Command::new("pgrep")
+        .args(["-f", "^/bin/sh /.*/cgi/flood\\.cgi$"])
+        .output();
+    assert!(!pgrep.unwrap().status.success());
+}
+
+#[test]
+fn slow_scripts_get_a_gateway_timeout() {
+    setup();
+    let started = Instant::now();
+    let response = read_all(request("/cgi/slow.cgi"));
+    assert!(response.starts_with("HTTP/1.1 504 "), "{response}");
+    assert!(started.elapsed() >= Duration::from_secs(2));
+
+    // The script and the processes it started are stopped together
+    let running = |pattern: &str| {
+        let pgrep = Command::new("pgrep").args(["-f", pattern]).output();
+        pgrep.unwrap().status.success()
+    };
+    let script = "^/bin/sh /.*/cgi/slow\\.cgi$";
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while (running(script) || running("^sleep 30$")) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!running(script));
+    assert!(!running("^sleep 30$"));
+}
+
+#[test]
+fn stderr_is_logged() {
+    setup();
+    let marker = format!("marker-{}", std::process::id());
+    let response = read_all(request(&format!("/cgi/stderr.cgi?{marker}")));
+    assert!(response.contains("done\n"), "{response}");
+
+    let log = fs::read_to_string("src/log/log_files/server.log").unwrap();
+    assert!(log.contains(&format!("CGI script /cgi/stderr.cgi: warning: {marker}")));
+}
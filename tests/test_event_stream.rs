@@ -0,0 +1,165 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{start, Event, EventSink, ServerConfig};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+const ADDRESS: &str = "127.0.0.1:8095";
+
+static SERVER: Once = Once::new();
+static SUBSCRIBERS: Mutex<Vec<EventSink>> = Mutex::new(Vec::new());
+
+// Greets the client, resuming after the last event it received, and subscribes it
+fn subscribe(_: &Request<Bytes>, sink: EventSink) {
+    let greeting = match sink.last_event_id() {
+        Some(id) => format!("resumed after {id}"),
+        None => "hello".to_string(),
+    };
+    sink.send(&Event {
+        event: Some("greeting"),
+        data: &greeting,
+        id: Some("0"),
+        retry: None,
+    });
+    SUBSCRIBERS.lock().unwrap().push(sink);
+}
+
+// Sends three events from a timer thread, then closes the stream
+fn countdown(_: &Request<Bytes>, sink: EventSink) {
+    thread::spawn(move || {
+        for n in (1..=3).rev() {
+            thread::sleep(Duration::from_millis(50));
+            sink.send(&Event::data(&n.to_string()));
+        }
+        sink.close();
+    });
+}
+
+// Publishes the request body to every subscriber still connected
+fn publish(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let data = String::from_utf8_lossy(request.body()).to_string();
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.retain(|sink| sink.send(&Event::data(&data)));
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Bytes::new())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![8095];
+
+        let mut settings = config.routes[0].settings.clone().unwrap();
+        settings.cgi_def = None;
+        settings.event_stream = Some(subscribe);
+        config.routes.push(Route {
+            url_path: "/events",
+            methods: vec![Method::GET],
+            handler: None,
+            settings: Some(settings.clone()),
+        });
+        settings.event_stream = Some(countdown);
+        config.routes.push(Route {
+            url_path: "/countdown",
+            methods: vec![Method::GET],
+            handler: None,
+            settings: Some(settings),
+        });
+        config.routes.push(Route {
+            url_path: "/publish",
+            methods: vec![Method::POST],
+            handler: Some(publish),
+            settings: None,
+        });
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+// Opens an event stream and returns the response head
+fn open(path: &str, headers: &str) -> (BufReader<TcpStream>, String) {
+    let mut stream = TcpStream::connect(ADDRESS).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let head = format!("GET {path} HTTP/1.1\r\nHost: {ADDRESS}\r\n{headers}\r\n");
+    stream.write_all(head.as_bytes()).unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let head = read_block(&mut reader, "\r\n");
+    (reader, head)
+}
+
+// Reads lines until an empty one
+fn read_block(reader: &mut BufReader<TcpStream>, end: &str) -> String {
+    let mut block = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap() == 0 || line == end {
+            return block;
+        }
+        block.push_str(&line);
+    }
+}
+
+fn publish_message(data: &str) {
+    let mut stream = TcpStream::connect(ADDRESS).unwrap();
+    let request = format!(
+        "POST /publish HTTP/1.1\r\nHost: {ADDRESS}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{data}",
+        data.len()
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 204"), "{response}");
+}
+
+#[test]
+fn events_are_published_from_other_connections() {
+    setup();
+    let (mut reader, head) = open("/events", "Accept: text/event-stream\r\n");
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    assert!(head.contains("content-type: text/event-stream\r\n"));
+    assert!(head.contains("cache-control: no-cache\r\n"));
+    assert_eq!(
+        read_block(&mut reader, "\n"),
+        "event: greeting\nid: 0\ndata: hello\n"
+    );
+
+    publish_message("first line\nsecond line");
+    assert_eq!(
+        read_block(&mut reader, "\n"),
+        "data: first line\ndata: second line\n"
+    );
+}
+
+#[test]
+fn last_event_id_is_given_on_reconnect() {
+    setup();
+    let (mut reader, _) = open("/events", "Last-Event-ID: Abc-42\r\n");
+    assert_eq!(
+        read_block(&mut reader, "\n"),
+        "event: greeting\nid: 0\ndata: resumed after Abc-42\n"
+    );
+}
+
+#[test]
+fn handler_closes_the_stream() {
+    setup();
+    let (mut reader, head) = open("/countdown", "");
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    let mut body = String::new();
+    reader.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "data: 3\n\ndata: 2\n\ndata: 1\n\n");
+}
@@ -0,0 +1,128 @@
+mod mock;
+
+use http::Method;
+use localhost::server::route::Route;
+use localhost::server::{
+    decode_pairs, encode_pairs, start, Address, Gateway, Record, END_REQUEST, GET_VALUES,
+    GET_VALUES_RESULT, PARAMS, STDERR, STDIN, STDOUT,
+};
+use mock::*;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+const HOST: &str = "http://127.0.0.1:8091";
+
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static SERVER: Once = Once::new();
+
+// Stand-in FastCGI application: echoes SCRIPT_NAME, PATH_INFO and the request body
+fn application(mut stream: TcpStream) {
+    let mut buffer = Vec::new();
+    let mut params = Vec::new();
+    let mut body = Vec::new();
+    let mut chunk = [0; 4096];
+
+    loop {
+        while let Some(record) = Record::parse(&mut buffer).unwrap() {
+            let mut out = Vec::new();
+            match record.kind {
+                GET_VALUES => {
+                    let values = encode_pairs([("FCGI_MPXS_CONNS", "0")]);
+                    Record::new(GET_VALUES_RESULT, 0, values).encode(&mut out);
+                }
+                PARAMS => params.extend(record.content),
+                STDIN if !record.content.is_empty() => body.extend(record.content),
+                STDIN => {
+                    let env: HashMap<_, _> = decode_pairs(&params).into_iter().collect();
+                    let reply = format!(
+                        "Content-Type: text/plain\r\nX-Method: {}\r\n\r\n{} {} {}",
+                        env["REQUEST_METHOD"],
+                        env["SCRIPT_NAME"],
+                        env.get("PATH_INFO").map(String::as_str).unwrap_or("-"),
+                        String::from_utf8_lossy(&body),
+                    );
+                    let id = record.request_id;
+                    Record::new(STDOUT, id, reply.into_bytes()).encode(&mut out);
+                    Record::new(STDERR, id, b"served\n".to_vec()).encode(&mut out);
+                    Record::new(END_REQUEST, id, vec![0; 8]).encode(&mut out);
+                    params.clear();
+                    body.clear();
+                }
+                _ => {}
+            }
+            stream.write_all(&out).unwrap();
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buffer.extend(&chunk[..n]),
+        }
+    }
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: &'static str = Box::leak(listener.local_addr().unwrap().to_string().into());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || application(stream));
+            }
+        });
+
+        // A port on which nothing listens
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let unreachable: &'static str = Box::leak(closed.local_addr().unwrap().to_string().into());
+        drop(closed);
+
+        let mut config = mock_server_config();
+        config.ports = vec![8091];
+        for (url_path, address) in [("/app", address), ("/down", unreachable)] {
+            let mut settings = config.routes[0].settings.clone().unwrap();
+            settings.cgi_def = None;
+            settings.gateway = Some(Gateway::FastCgi(Address::Tcp(address)));
+            config.routes.push(Route {
+                url_path,
+                methods: vec![Method::GET, Method::POST],
+                handler: None,
+                settings: Some(settings),
+            });
+        }
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+#[test]
+fn requests_are_relayed_over_a_kept_connection() {
+    setup();
+    let client = reqwest::blocking::Client::new();
+
+    let resp = client.get(format!("{HOST}/app/index.php")).send().unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-method"], "GET");
+    assert_eq!(resp.text().unwrap(), "/app/index.php - ");
+
+    let resp = client
+        .post(format!("{HOST}/app/index.php"))
+        .body("name=value")
+        .send()
+        .unwrap();
+    assert_eq!(resp.headers()["x-method"], "POST");
+    assert_eq!(resp.text().unwrap(), "/app/index.php - name=value");
+
+    // Both requests used the same connection to the application server
+    assert_eq!(CONNECTIONS.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn unreachable_server() {
+    setup();
+    let resp = reqwest::blocking::get(format!("{HOST}/down/index.php")).unwrap();
+    assert_eq!(resp.status(), 502);
+}
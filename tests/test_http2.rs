@@ -0,0 +1,378 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{
+    encode_header, start, Certificate, Event, EventSink, HpackDecoder, ServerConfig, Tls,
+    TlsVersion, HTTP2_PREFACE,
+};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use rustls::crypto::ring;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::Duration;
+
+const PORT: u16 = 8100;
+const HTTPS_PORT: u16 = 8101;
+
+static SERVER: Once = Once::new();
+
+// Describes the request as the route received it
+fn echo(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let header = |name| {
+        let value = request.headers().get(name);
+        value.and_then(|value| value.to_str().ok()).unwrap_or("-")
+    };
+    let body = format!(
+        "{} {} {:?} host={} cookie={} length={}",
+        request.method(),
+        request.uri(),
+        request.version(),
+        header("host"),
+        header("cookie"),
+        request.body().len()
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/plain")
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Answers with a body larger than the default flow control window
+fn large(_: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let body: Bytes = (0..100_000).map(|i| (i % 251) as u8).collect();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-length", body.len())
+        .body(body)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Sends three events from a timer thread, then closes the stream
+fn countdown(_: &Request<Bytes>, sink: EventSink) {
+    thread::spawn(move || {
+        for n in (1..=3).rev() {
+            thread::sleep(Duration::from_millis(50));
+            sink.send(&Event::data(&n.to_string()));
+        }
+        sink.close();
+    });
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![PORT];
+        config.body_size_limit = 200_000;
+        config.tls = vec![Tls {
+            port: HTTPS_PORT,
+            certificates: vec![Certificate {
+                server_names: vec!["localhost"],
+                chain_path: "tests/certs/localhost.pem",
+                key_path: "tests/certs/localhost.key",
+            }],
+            min_version: TlsVersion::Tls12,
+            cipher_suites: None,
+            redirect_from: None,
+            client_ca_path: None,
+        }];
+        for (url_path, handler) in [("/echo", echo as _), ("/large", large as _)] {
+            config.routes.push(Route {
+                url_path,
+                methods: vec![Method::GET, Method::HEAD, Method::POST],
+                handler: Some(handler),
+                settings: None,
+            });
+        }
+        let mut settings = config.routes[0].settings.clone().unwrap();
+        settings.cgi_def = None;
+        settings.event_stream = Some(countdown);
+        config.routes.push(Route {
+            url_path: "/countdown",
+            methods: vec![Method::GET],
+            handler: None,
+            settings: Some(settings),
+        });
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+fn client() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn prior_knowledge_requests() {
+    setup();
+    let response = client()
+        .get(format!("http://127.0.0.1:{PORT}/echo?x=1"))
+        .header("cookie", "a=1")
+        .send()
+        .unwrap();
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    assert_eq!(response.headers()["content-type"], "text/plain");
+    assert_eq!(
+        response.text().unwrap(),
+        format!("GET /echo?x=1 HTTP/2.0 host=127.0.0.1:{PORT} cookie=a=1 length=0")
+    );
+
+    // Les fichiers statiques sont servis comme en HTTP/1.1
+    let http1 = reqwest::blocking::get(format!("http://127.0.0.1:{PORT}/index.html")).unwrap();
+    let http2 = client()
+        .get(format!("http://127.0.0.1:{PORT}/index.html"))
+        .send()
+        .unwrap();
+    assert_eq!(http2.status(), http1.status());
+    assert_eq!(http2.bytes().unwrap(), http1.bytes().unwrap());
+
+    let missing = client()
+        .get(format!("http://127.0.0.1:{PORT}/missing.html"))
+        .send()
+        .unwrap();
+    assert_eq!(missing.status().as_u16(), 404);
+}
+
+#[test]
+fn streams_are_multiplexed_with_flow_control() {
+    setup();
+    let client = client();
+    let requests: Vec<_> = (0..8)
+        .map(|i| {
+            let client = client.clone();
+            thread::spawn(move || {
+                let url = format!("http://127.0.0.1:{PORT}/echo");
+                let body = vec![b'x'; 70_000 + i];
+                client.post(url).body(body).send().unwrap().text().unwrap()
+            })
+        })
+        .collect();
+    for (i, request) in requests.into_iter().enumerate() {
+        let text = request.join().unwrap();
+        assert!(text.ends_with(&format!("length={}", 70_000 + i)), "{text}");
+    }
+
+    let body = client
+        .get(format!("http://127.0.0.1:{PORT}/large"))
+        .send()
+        .unwrap()
+        .bytes()
+        .unwrap();
+    assert_eq!(body.len(), 100_000);
+    assert!(body.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+
+    let head = client
+        .head(format!("http://127.0.0.1:{PORT}/large"))
+        .send()
+        .unwrap();
+    assert_eq!(head.headers()["content-length"], "100000");
+    assert!(head.bytes().unwrap().is_empty());
+}
+
+#[test]
+fn oversized_bodies_are_rejected() {
+    setup();
+    let response = client()
+        .post(format!("http://127.0.0.1:{PORT}/echo"))
+        .body(vec![0; 200_001])
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 413);
+}
+
+// Writes a frame
+fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Bytes {
+    let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.extend([kind, flags]);
+    frame.extend(stream_id.to_be_bytes());
+    frame.extend(payload);
+    frame
+}
+
+// Reads the next frame: type, flags, stream and payload
+fn read_frame(stream: &mut impl Read) -> (u8, u8, u32, Bytes) {
+    let mut head = [0; 9];
+    stream.read_exact(&mut head).unwrap();
+    let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).unwrap();
+    let id = u32::from_be_bytes([head[5], head[6], head[7], head[8]]);
+    (head[3], head[4], id, payload)
+}
+
+// Reads frames until the end of a stream; returns the response headers and body
+fn read_response(stream: &mut impl Read, stream_id: u32) -> (Vec<(String, String)>, Bytes) {
+    let mut decoder = HpackDecoder::default();
+    let (mut headers, mut body) = (Vec::new(), Bytes::new());
+    loop {
+        let (kind, flags, id, payload) = read_frame(stream);
+        match kind {
+            0x1 if id == stream_id => {
+                for (name, value) in decoder.decode(&payload, usize::MAX).unwrap().unwrap() {
+                    let name = String::from_utf8(name).unwrap();
+                    headers.push((name, String::from_utf8(value).unwrap()));
+                }
+            }
+            0x0 if id == stream_id => body.extend(payload),
+            0x3 if id == stream_id => {
+                let code = u32::from_be_bytes(payload.try_into().unwrap());
+                headers.push(("reset".to_string(), code.to_string()));
+                return (headers, body);
+            }
+            _ => continue,
+        }
+        if flags & 0x1 != 0 {
+            return (headers, body);
+        }
+    }
+}
+
+// Sends the client preface and a GET request on stream 1
+fn send_get(stream: &mut impl Write, path: &str) {
+    let mut block = Bytes::new();
+    for (name, value) in [
+        (":method", "GET"),
+        (":scheme", "https"),
+        (":path", path),
+        (":authority", "localhost"),
+    ] {
+        encode_header(&mut block, name.as_bytes(), value.as_bytes());
+    }
+    let mut bytes = HTTP2_PREFACE.to_vec();
+    bytes.extend(frame(0x4, 0, 0, &[]));
+    bytes.extend(frame(0x1, 0x5, 1, &block));
+    stream.write_all(&bytes).unwrap();
+}
+
+#[test]
+fn upgrade_from_http1() {
+    setup();
+    let mut stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let request =
+        "GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+                   Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n";
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+    assert!(head.contains("upgrade: h2c\r\n"));
+
+    let mut preface = HTTP2_PREFACE.to_vec();
+    preface.extend(frame(0x4, 0, 0, &[]));
+    stream.write_all(&preface).unwrap();
+    let (headers, body) = read_response(&mut stream, 1);
+    assert_eq!(headers[0], (":status".to_string(), "200".to_string()));
+    assert_eq!(body, b"GET /echo HTTP/1.1 host=localhost cookie=- length=0");
+}
+
+#[test]
+fn alpn_negotiates_http2() {
+    setup();
+    let mut roots = RootCertStore::empty();
+    let mut pem = BufReader::new(File::open("tests/certs/ca.pem").unwrap());
+    for certificate in rustls_pemfile::certs(&mut pem) {
+        roots.add(certificate.unwrap()).unwrap();
+    }
+    let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    let name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(Arc::new(config), name).unwrap();
+    let tcp = TcpStream::connect(("127.0.0.1", HTTPS_PORT)).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut tls = StreamOwned::new(connection, tcp);
+    send_get(&mut tls, "/echo");
+    assert_eq!(tls.conn.alpn_protocol(), Some(&b"h2"[..]));
+
+    let (headers, body) = read_response(&mut tls, 1);
+    assert_eq!(headers[0], (":status".to_string(), "200".to_string()));
+    assert_eq!(body, b"GET /echo HTTP/2.0 host=localhost cookie=- length=0");
+}
+
+#[test]
+fn scripts_are_relayed_on_their_stream() {
+    setup();
+    let url = format!("http://127.0.0.1:{PORT}/cgi/hello.cgi");
+    let http1 = reqwest::blocking::get(&url).unwrap();
+    let http2 = client().get(&url).send().unwrap();
+    assert_eq!(http2.version(), reqwest::Version::HTTP_2);
+    assert_eq!(http2.status(), http1.status());
+    assert_eq!(http2.headers()["content-type"], "text/html");
+    assert_eq!(http2.text().unwrap(), http1.text().unwrap());
+
+    // Une sortie plus grande que les fenêtres du client attend son crédit
+    let response = client()
+        .get(format!("http://127.0.0.1:{PORT}/cgi/large.cgi"))
+        .send()
+        .unwrap();
+    let body = response.bytes().unwrap();
+    assert_eq!(body.len(), 16 * 1024 * 1024);
+    assert!(body.iter().all(|&b| b == 0));
+}
+
+#[test]
+fn upgraded_scripts_are_decoded_from_chunks() {
+    setup();
+    let mut stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let request =
+        "GET /cgi/stream.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+                   Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n";
+    let mut bytes = request.as_bytes().to_vec();
+    bytes.extend(HTTP2_PREFACE);
+    bytes.extend(frame(0x4, 0, 0, &[]));
+    stream.write_all(&bytes).unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    assert!(head.starts_with(b"HTTP/1.1 101"));
+    let (headers, body) = read_response(&mut stream, 1);
+    assert_eq!(headers[0], (":status".to_string(), "200".to_string()));
+    assert!(!headers.iter().any(|(name, _)| name == "transfer-encoding"));
+    assert_eq!(body, b"first\nsecond\n");
+}
+
+#[test]
+fn event_streams_are_sent_as_data_frames() {
+    setup();
+    let mut stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    send_get(&mut stream, "/countdown");
+    let (headers, body) = read_response(&mut stream, 1);
+    assert_eq!(headers[0], (":status".to_string(), "200".to_string()));
+    let content_type = ("content-type".to_string(), "text/event-stream".to_string());
+    assert!(headers.contains(&content_type), "{headers:?}");
+    assert_eq!(body, b"data: 3\n\ndata: 2\n\ndata: 1\n\n");
+}
@@ -15,9 +15,10 @@ mod test_misc {
     #[test]
     fn test_method_is_allowed() {
         let route = mock_route();
+        let config = mock_server_config();
         // Iterate through all the methods, and assert that they are allowed.
         for method in &route.methods {
-            assert!(method_is_allowed(method, &route));
+            assert!(method_is_allowed(method, &route, &config));
         }
 
         // 10 random invalid methods
@@ -30,7 +31,8 @@ mod test_misc {
 
             assert!(!method_is_allowed(
                 &Method::from_bytes(s.as_bytes()).unwrap(),
-                &route
+                &route,
+                &config
             ));
         }
     }
@@ -140,6 +142,30 @@ mod test_options {
         for method in &route.methods {
             assert!(allowed_methods.contains(&method.to_string().to_ascii_uppercase()))
         }
+        // PATCH is allowed, so the accepted patch formats are advertised
+        assert!(response.headers().contains_key("Accept-Patch"));
+    }
+}
+
+mod test_server_options {
+    use super::*;
+    use localhost::server::safe::server_options;
+    #[test]
+    fn test_options_asterisk() {
+        let config = mock_server_config();
+        let request = mock_request(Method::OPTIONS, "", None, None);
+
+        let response = server_options(&request, &config).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Every method allowed on at least one route is advertised
+        let allow = response.headers().get("Allow").unwrap().to_str().unwrap();
+        for method in ["OPTIONS", "GET", "HEAD", "PUT", "PATCH", "DELETE"] {
+            assert!(allow.contains(method));
+        }
+        assert!(response.headers().contains_key("Accept-Patch"));
+        assert!(response.headers().contains_key("Accept-Ranges"));
+        assert!(!response.headers().contains_key("DAV"));
     }
 }
 
@@ -164,20 +190,35 @@ mod test_trace {
         let result = handle_method(&route, &request, &config);
         assert!(result.is_ok());
         let response = result.unwrap();
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "message/http"
+        );
 
-        // Use debug representation to reconstruct the request string
-        let request_representation = format!("{:?}\r\n", request)
-            .lines()
-            .filter(|line| !line.contains("Cookie:") && !line.contains("Authorization:"))
-            .collect::<Vec<_>>()
-            .join("\r\n");
-
-        // Convert response body to String for comparison
+        // The body is the request in message/http wire format, with sensitive headers redacted
         let response_body_str = String::from_utf8(response.body().clone())
             .expect("Failed to convert response body to String");
+        assert!(response_body_str.starts_with("TRACE http://localhost:8080/test.txt HTTP/1.1\r\n"));
+        assert!(response_body_str.contains("max-forwards: 10\r\n"));
+        assert!(response_body_str.contains("cookie: [redacted]\r\n"));
+        assert!(response_body_str.contains("authorization: [redacted]\r\n"));
+        assert!(!response_body_str.contains("test_cookie"));
+        assert!(response_body_str.ends_with("\r\n\r\n"));
+    }
 
-        // Check that the response body matches the reconstructed request
-        assert_eq!(response_body_str, request_representation);
+    #[test]
+    fn test_handle_method_trace_disabled() {
+        let mut config = mock_server_config();
+        config.trace_enabled = false;
+        let route = mock_route();
+        let request = mock_request(Method::TRACE, "/test.txt", None, None);
+
+        assert!(!method_is_allowed(&Method::TRACE, &route, &config));
+        let response = handle_method(&route, &request, &config).unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let allow = response.headers().get("Allow").unwrap().to_str().unwrap();
+        assert!(allow.contains("GET"));
+        assert!(!allow.contains("TRACE"));
     }
 }
 
@@ -240,15 +281,40 @@ mod test_patch {
         let put_result = handle_method(&route, &put_request, &config);
         assert!(put_result.is_ok());
 
-        // Step 2: Modify the file content using PATCH
-        let patch_request =
-            mock_request(Method::PATCH, test_file_path, Some(modified_content), None);
+        // Step 2: Modify the file content using a byte-range PATCH
+        let patch_request = mock_request(
+            Method::PATCH,
+            test_file_path,
+            Some(modified_content),
+            Some(vec![
+                ("Content-Type", "application/octet-stream"),
+                ("Content-Range", "bytes 0-15/16"),
+            ]),
+        );
         let body = match handle_method(&route, &patch_request, &config) {
             Ok(resp) => resp.body().clone(),
             _ => panic!(),
         };
         // Assert that the content is now updated
         assert_eq!(Bytes::from(modified_content), body);
+
+        // Step 3: Unsupported patch formats are rejected with the accepted formats
+        let patch_request = mock_request(
+            Method::PATCH,
+            test_file_path,
+            Some("<xml/>"),
+            Some(vec![("Content-Type", "application/xml")]),
+        );
+        let response = handle_method(&route, &patch_request, &config).unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(response
+            .headers()
+            .get("Accept-Patch")
+            .is_some_and(|formats| formats
+                .to_str()
+                .unwrap()
+                .contains("application/json-patch+json")));
+
         // Clean up: remove the test file
         let file_path = format!("./files{}", test_file_path);
         fs::remove_file(file_path).expect("Failed to remove test file");
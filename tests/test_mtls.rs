@@ -0,0 +1,163 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{
+    start, Certificate, ClientAuth, ServerConfig, Tls, TlsSession, TlsVersion,
+};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::Duration;
+
+const PORT: u16 = 8099;
+
+static SERVER: Once = Once::new();
+
+// Describes the client certificate the route received
+fn whoami(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let session = request.extensions().get::<TlsSession>();
+    let body = match session.and_then(|session| session.client_certificate.as_ref()) {
+        Some(certificate) => format!(
+            "{} | {} | {}",
+            certificate.subject, certificate.issuer, certificate.fingerprint
+        ),
+        None => "anonymous".to_string(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![];
+        config.tls = vec![Tls {
+            port: PORT,
+            certificates: vec![Certificate {
+                server_names: vec!["localhost"],
+                chain_path: "tests/certs/localhost.pem",
+                key_path: "tests/certs/localhost.key",
+            }],
+            min_version: TlsVersion::Tls12,
+            cipher_suites: None,
+            redirect_from: None,
+            client_ca_path: Some("tests/certs/ca.pem"),
+        }];
+
+        let mut settings = config.routes[0].settings.clone().unwrap();
+        settings.cgi_def = None;
+        for (url_path, auth) in [
+            ("/admin", Some(ClientAuth::Required)),
+            ("/account", Some(ClientAuth::Optional)),
+            ("/public", None),
+        ] {
+            settings.client_certificate = auth;
+            config.routes.push(Route {
+                url_path,
+                methods: vec![Method::GET],
+                handler: Some(whoami),
+                settings: Some(settings.clone()),
+            });
+        }
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+fn pem_certificates(path: &str) -> Vec<CertificateDer<'static>> {
+    let mut pem = BufReader::new(File::open(path).unwrap());
+    rustls_pemfile::certs(&mut pem)
+        .map(Result::unwrap)
+        .collect()
+}
+
+// Sends a GET request, presenting the named client certificate if any
+fn get(path: &str, client: Option<&str>) -> std::io::Result<String> {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(pem_certificates("tests/certs/ca.pem"));
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match client {
+        Some(name) => {
+            let chain = pem_certificates(&format!("tests/certs/{name}.pem"));
+            let mut key = BufReader::new(File::open(format!("tests/certs/{name}.key")).unwrap());
+            let key = rustls_pemfile::private_key(&mut key).unwrap().unwrap();
+            builder.with_client_auth_cert(chain, key).unwrap()
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(Arc::new(config), name).unwrap();
+    let tcp = TcpStream::connect(("127.0.0.1", PORT))?;
+    tcp.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut tls = StreamOwned::new(connection, tcp);
+    let request =
+        format!("GET {path} HTTP/1.1\r\nHost: localhost:{PORT}\r\nConnection: close\r\n\r\n");
+    tls.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    tls.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").unwrap().1
+}
+
+#[test]
+fn required_certificate() {
+    setup();
+    let response = get("/admin", Some("client")).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    let fingerprint = ::ring::digest::digest(
+        &::ring::digest::SHA256,
+        &pem_certificates("tests/certs/client.pem")[0],
+    );
+    let fingerprint: String = fingerprint
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    assert_eq!(
+        body(&response),
+        format!("O=Example Ops, CN=admin | CN=localhost test CA | {fingerprint}")
+    );
+
+    let response = get("/admin", None).unwrap();
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+}
+
+#[test]
+fn optional_certificate() {
+    setup();
+    let response = get("/account", None).unwrap();
+    assert_eq!(body(&response), "anonymous");
+    let response = get("/account", Some("client")).unwrap();
+    assert!(body(&response).starts_with("O=Example Ops, CN=admin | "));
+}
+
+#[test]
+fn certificate_is_hidden_from_other_routes() {
+    setup();
+    let response = get("/public", Some("client")).unwrap();
+    assert_eq!(body(&response), "anonymous");
+}
+
+#[test]
+fn untrusted_certificate_is_rejected() {
+    setup();
+    assert!(get("/account", Some("rogue")).is_err());
+}
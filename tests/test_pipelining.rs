@@ -0,0 +1,190 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{start, ServerConfig};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+const PORT: u16 = 8102;
+
+static SERVER: Once = Once::new();
+
+// Answers with the method, the path and the body of the request
+fn echo(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let body = format!(
+        "{} {} {}",
+        request.method(),
+        request.uri(),
+        String::from_utf8_lossy(request.body())
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-length", body.len())
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![PORT];
+        config.routes.push(Route {
+            url_path: "/echo",
+            methods: vec![Method::GET, Method::HEAD, Method::POST],
+            handler: Some(echo),
+            settings: None,
+        });
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+fn connect() -> TcpStream {
+    let stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+// Reads the status line and the headers of a response
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+// Reads one response delimited by its content-length; returns the head and the body
+fn read_response(stream: &mut TcpStream) -> (String, String) {
+    let head = read_head(stream);
+    let length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length: "))
+        .map_or(0, |length| length.parse().unwrap());
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).unwrap();
+    (head, String::from_utf8(body).unwrap())
+}
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    setup();
+    let mut stream = connect();
+    let requests = "GET /echo?1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                    POST /echo?2 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+                    POST /echo?3 HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    3\r\nabc\r\n0\r\n\r\n\
+                    HEAD /echo?4 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                    GET /missing.html HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                    GET /echo?6 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    stream.write_all(requests.as_bytes()).unwrap();
+
+    let mut bodies = Vec::new();
+    for i in 0..6 {
+        // La réponse à HEAD annonce la longueur du corps sans l'envoyer
+        let (head, body) = match i {
+            3 => (read_head(&mut stream), String::new()),
+            _ => read_response(&mut stream),
+        };
+        let status = head.split(' ').nth(1).unwrap().to_string();
+        bodies.push((status, body));
+    }
+    assert_eq!(bodies[0], ("200".into(), "GET /echo?1 ".into()));
+    assert_eq!(bodies[1], ("200".into(), "POST /echo?2 hello".into()));
+    assert_eq!(bodies[2], ("200".into(), "POST /echo?3 abc".into()));
+    assert_eq!(bodies[3].0, "200");
+    assert_eq!(bodies[4].0, "404");
+    assert_eq!(bodies[5], ("200".into(), "GET /echo?6 ".into()));
+
+    // `Connection: close` termine la connexion après la dernière réponse
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn requests_split_across_segments() {
+    setup();
+    let mut stream = connect();
+    let request = "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789";
+    let (first, second) = request.split_at(20);
+    stream.write_all(first.as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    stream.write_all(&second.as_bytes()[..30]).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    // La fin du corps arrive avec le début de la requête suivante
+    let next = format!("{}GET /echo?next HTTP/1.1\r\n", &second[30..]);
+    stream.write_all(next.as_bytes()).unwrap();
+    assert_eq!(read_response(&mut stream).1, "POST /echo 0123456789");
+    thread::sleep(Duration::from_millis(100));
+    stream.write_all(b"Host: localhost\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut stream).1, "GET /echo?next ");
+}
+
+#[test]
+fn smuggled_bodies_are_not_read_as_requests() {
+    setup();
+    for (encoding, status) in [
+        ("gzip, chunked", "501 Not Implemented"),
+        ("chunked, identity", "501 Not Implemented"),
+        ("chunked\r\nContent-Length: 48", "400 Bad Request"),
+    ] {
+        let mut stream = connect();
+        let request = format!(
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: {encoding}\r\n\r\n\
+             0\r\n\r\nGET /echo?smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+            "{response}"
+        );
+        assert!(!response.contains("smuggled"), "{response}");
+    }
+}
+
+#[test]
+fn http10_connections_close_unless_kept_alive() {
+    setup();
+    let mut stream = connect();
+    let request = "GET /echo HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
+    stream.write_all(request.as_bytes()).unwrap();
+    let (head, _) = read_response(&mut stream);
+    assert!(head.contains("connection: keep-alive\r\n"), "{head}");
+
+    stream.write_all(b"GET /echo HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.contains(" 200 OK\r\n"), "{response}");
+    assert!(!response.contains("connection: keep-alive"));
+}
+
+#[test]
+fn idle_connections_are_closed() {
+    setup();
+    let mut stream = connect();
+    stream
+        .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    read_response(&mut stream);
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
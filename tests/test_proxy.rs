@@ -0,0 +1,250 @@
+mod mock;
+
+use http::Method;
+use localhost::server::route::Route;
+use localhost::server::{
+    start, Address, Balance, Ejection, Gateway, HealthCheck, Proxy, ServerConfig, Upstream,
+};
+use mock::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+const HOST: &str = "http://127.0.0.1:8093";
+
+static SERVER: Once = Once::new();
+
+fn read_head(reader: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        if reader.read_line(&mut head).unwrap() == 0 {
+            return None;
+        }
+    }
+    Some(head)
+}
+
+// Stand-in upstream: echoes the request it received as a chunked body
+fn upstream(stream: TcpStream) {
+    let mut reader = BufReader::new(stream);
+    let Some(head) = read_head(&mut reader) else {
+        return;
+    };
+    let length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length: "))
+        .map_or(0, |len| len.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    let echo = format!("{head}{}", String::from_utf8_lossy(&body));
+    let reply = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nKeep-Alive: timeout=5\r\n\
+         Transfer-Encoding: chunked\r\n\r\n{:X}\r\n{echo}\r\n0\r\n\r\n",
+        echo.len()
+    );
+    reader.get_mut().write_all(reply.as_bytes()).unwrap();
+}
+
+// Stand-in upstream that never answers
+fn silent(stream: TcpStream) {
+    thread::sleep(Duration::from_secs(5));
+    drop(stream);
+}
+
+// Stand-in upstream that answers with its name, and fails its health check when sick
+fn named(name: &'static str, sick: bool) -> impl Fn(TcpStream) + Send + Copy + 'static {
+    move |stream| {
+        let mut reader = BufReader::new(stream);
+        let Some(head) = read_head(&mut reader) else {
+            return;
+        };
+        let status = match head.starts_with("GET /health ") {
+            true if sick => "503 Service Unavailable",
+            _ => "200 OK",
+        };
+        let reply = format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{name}",
+            name.len()
+        );
+        reader.get_mut().write_all(reply.as_bytes()).unwrap();
+    }
+}
+
+fn serve(handler: impl Fn(TcpStream) + Send + Copy + 'static) -> &'static str {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = Box::leak(listener.local_addr().unwrap().to_string().into());
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || handler(stream));
+        }
+    });
+    address
+}
+
+fn add_route(config: &mut ServerConfig<'static>, url_path: &'static str, proxy: Proxy<'static>) {
+    let mut settings = config.routes[0].settings.clone().unwrap();
+    settings.cgi_def = None;
+    settings.gateway = Some(Gateway::Proxy(proxy));
+    config.routes.push(Route {
+        url_path,
+        methods: vec![Method::GET, Method::POST],
+        handler: None,
+        settings: Some(settings),
+    });
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        // A port on which nothing listens
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let unreachable: &'static str = Box::leak(closed.local_addr().unwrap().to_string().into());
+        drop(closed);
+
+        let mut config = mock_server_config();
+        config.ports = vec![8093];
+        for (url_path, address, timeout) in [
+            ("/api", serve(upstream), None),
+            ("/down", unreachable, None),
+            ("/slow", serve(silent), Some(Duration::from_secs(1))),
+        ] {
+            let upstreams = Box::leak(Box::new([Upstream::new(Address::Tcp(address))]));
+            let proxy = Proxy {
+                upstreams,
+                balance: Balance::RoundRobin,
+                ejection: None,
+                health_check: None,
+                path: Some("/v1"),
+                timeout,
+            };
+            add_route(&mut config, url_path, proxy);
+        }
+
+        let pool = |addresses: &[&'static str]| -> &'static [Upstream<'static>] {
+            let upstreams = addresses.iter().map(|a| Upstream::new(Address::Tcp(a)));
+            Box::leak(upstreams.collect())
+        };
+        let balanced = Proxy {
+            upstreams: pool(&[serve(named("one", false)), unreachable]),
+            balance: Balance::RoundRobin,
+            ejection: Some(Ejection {
+                max_fails: 1,
+                duration: Duration::from_secs(60),
+            }),
+            health_check: None,
+            path: None,
+            timeout: None,
+        };
+        add_route(&mut config, "/pool", balanced);
+        add_route(
+            &mut config,
+            "/checked",
+            Proxy {
+                upstreams: pool(&[serve(named("two", false)), serve(named("sick", true))]),
+                ejection: None,
+                health_check: Some(HealthCheck {
+                    path: "/health",
+                    interval: Duration::from_millis(100),
+                    timeout: Duration::from_secs(1),
+                }),
+                ..balanced
+            },
+        );
+        add_route(
+            &mut config,
+            "/sticky",
+            Proxy {
+                upstreams: pool(&[
+                    serve(named("a", false)),
+                    serve(named("b", false)),
+                    serve(named("c", false)),
+                ]),
+                balance: Balance::CookieHash("session"),
+                ..balanced
+            },
+        );
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+#[test]
+fn request_is_forwarded() {
+    setup();
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .post(format!("{HOST}/api/users?id=1"))
+        .header("X-Forwarded-For", "203.0.113.1")
+        .body("name=value")
+        .send()
+        .unwrap();
+
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("keep-alive").is_none());
+    let echo = resp.text().unwrap();
+    assert!(
+        echo.starts_with("POST /v1/users?id=1 HTTP/1.1\r\n"),
+        "{echo}"
+    );
+    assert!(echo.contains("x-forwarded-for: 203.0.113.1, 127.0.0.1\r\n"));
+    assert!(echo.contains("forwarded: for=127.0.0.1;proto=http;host=\"127.0.0.1:8093\"\r\n"));
+    assert!(echo.contains("connection: close\r\n"));
+    assert!(echo.ends_with("\r\n\r\nname=value"));
+}
+
+#[test]
+fn upstream_failures() {
+    setup();
+    let resp = reqwest::blocking::get(format!("{HOST}/down/")).unwrap();
+    assert_eq!(resp.status(), 502);
+
+    let resp = reqwest::blocking::get(format!("{HOST}/slow/")).unwrap();
+    assert_eq!(resp.status(), 504);
+    let log = std::fs::read_to_string("src/log/log_files/server.log").unwrap();
+    assert!(
+        log.contains("Error: proxied request /v1/ timed out"),
+        "{log}"
+    );
+}
+
+#[test]
+fn failing_upstream_is_ejected() {
+    setup();
+    let statuses: Vec<_> = (0..6)
+        .map(|_| reqwest::blocking::get(format!("{HOST}/pool/")).unwrap())
+        .map(|resp| (resp.status().as_u16(), resp.text().unwrap()))
+        .collect();
+    // Only the first request sent to the unreachable server fails
+    let failures = statuses.iter().filter(|(status, _)| *status == 502).count();
+    assert!(failures <= 1, "{statuses:?}");
+    assert!(statuses[2..]
+        .iter()
+        .all(|(status, body)| *status == 200 && body == "one"));
+}
+
+#[test]
+fn unhealthy_upstream_is_skipped() {
+    setup();
+    thread::sleep(Duration::from_millis(300));
+    for _ in 0..4 {
+        let resp = reqwest::blocking::get(format!("{HOST}/checked/")).unwrap();
+        assert_eq!(resp.text().unwrap(), "two");
+    }
+}
+
+#[test]
+fn cookie_pins_the_upstream() {
+    setup();
+    let client = reqwest::blocking::Client::new();
+    let names: Vec<_> = (0..5)
+        .map(|_| {
+            let resp = client
+                .get(format!("{HOST}/sticky/"))
+                .header("Cookie", "session=42");
+            resp.send().unwrap().text().unwrap()
+        })
+        .collect();
+    assert!(names.iter().all(|name| *name == names[0]), "{names:?}");
+}
@@ -0,0 +1,176 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{start, Addresses, Certificate, ServerConfig, Tls, TlsVersion};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use rustls::crypto::ring;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::{Ipv6Addr, TcpStream};
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::Duration;
+
+const PORT: u16 = 8103;
+const HTTPS_PORT: u16 = 8104;
+
+static SERVER: Once = Once::new();
+
+// Answers with the addresses the request was received with
+fn addresses(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let body = match request.extensions().get::<Addresses>() {
+        Some(Addresses { remote, local }) => format!("remote={remote} local={local}"),
+        None => "none".to_string(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-length", body.len())
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![PORT];
+        config.tls = vec![Tls {
+            port: HTTPS_PORT,
+            certificates: vec![Certificate {
+                server_names: vec!["localhost"],
+                chain_path: "tests/certs/localhost.pem",
+                key_path: "tests/certs/localhost.key",
+            }],
+            min_version: TlsVersion::Tls12,
+            cipher_suites: None,
+            redirect_from: None,
+            client_ca_path: None,
+        }];
+        config.proxy_protocol = vec![PORT, HTTPS_PORT];
+        config.routes.push(Route {
+            url_path: "/addresses",
+            methods: vec![Method::GET],
+            handler: Some(addresses),
+            settings: None,
+        });
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+fn connect() -> TcpStream {
+    let stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+// Sends the bytes and returns everything the server answers
+fn exchange(mut stream: TcpStream, bytes: &[u8]) -> String {
+    stream.write_all(bytes).unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    String::from_utf8(response).unwrap()
+}
+
+const REQUEST: &[u8] = b"GET /addresses HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+#[test]
+fn v1_header_sets_the_client_address() {
+    setup();
+    let mut bytes = b"PROXY TCP4 203.0.113.7 198.51.100.1 51000 443\r\n".to_vec();
+    bytes.extend(REQUEST);
+    let response = exchange(connect(), &bytes);
+    assert!(
+        response.ends_with("remote=203.0.113.7:51000 local=198.51.100.1:443"),
+        "{response}"
+    );
+
+    // UNKNOWN keeps the addresses of the connection
+    let mut bytes = b"PROXY UNKNOWN\r\n".to_vec();
+    bytes.extend(REQUEST);
+    let response = exchange(connect(), &bytes);
+    assert!(
+        response.contains("remote=127.0.0.1:")
+            && response.contains(&format!("local=127.0.0.1:{PORT}")),
+        "{response}"
+    );
+}
+
+// Writes a PROXY protocol v2 header for a TCP over IPv6 connection
+fn v2_header() -> Bytes {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x24".to_vec();
+    for address in ["2001:db8::7", "2001:db8::1"] {
+        header.extend(address.parse::<Ipv6Addr>().unwrap().octets());
+    }
+    header.extend(40_000u16.to_be_bytes());
+    header.extend(80u16.to_be_bytes());
+    header
+}
+
+#[test]
+fn v2_header_reaches_cgi_scripts() {
+    setup();
+    let mut stream = connect();
+    let header = v2_header();
+    // L'en-tête peut arriver en plusieurs segments
+    stream.write_all(&header[..10]).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut bytes = header[10..].to_vec();
+    bytes.extend(b"GET /cgi/echo.py HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let response = exchange(stream, &bytes);
+    for variable in [
+        "REMOTE_ADDR=2001:db8::7\n",
+        "REMOTE_PORT=40000\n",
+        "SERVER_ADDR=2001:db8::1\n",
+        "SERVER_PORT=80\n",
+    ] {
+        assert!(
+            response.contains(variable),
+            "{variable} missing from {response}"
+        );
+    }
+}
+
+#[test]
+fn connections_without_header_are_closed() {
+    setup();
+    let response = exchange(connect(), REQUEST);
+    assert!(response.is_empty(), "{response}");
+}
+
+#[test]
+fn header_precedes_the_tls_handshake() {
+    setup();
+    let mut roots = RootCertStore::empty();
+    let mut pem = BufReader::new(File::open("tests/certs/ca.pem").unwrap());
+    for certificate in rustls_pemfile::certs(&mut pem) {
+        roots.add(certificate.unwrap()).unwrap();
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let mut tcp = TcpStream::connect(("127.0.0.1", HTTPS_PORT)).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    tcp.write_all(b"PROXY TCP4 203.0.113.9 198.51.100.1 52000 443\r\n")
+        .unwrap();
+
+    let name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(Arc::new(config), name).unwrap();
+    let mut tls = StreamOwned::new(connection, tcp);
+    tls.write_all(REQUEST).unwrap();
+    let mut response = String::new();
+    let _ = tls.read_to_string(&mut response);
+    assert!(
+        response.ends_with("remote=203.0.113.9:52000 local=198.51.100.1:443"),
+        "{response}"
+    );
+}
@@ -1,7 +1,7 @@
 mod mock;
 
 use http::{StatusCode, Version};
-use localhost::server::content_type;
+use localhost::server::{content_type, format_response, without_body};
 use localhost::server::informational::informational;
 use localhost::server::redirections::redirect;
 use mock::*;
@@ -77,3 +77,21 @@ fn test_content_type() {
         assert_eq!(content_type(input), expected);
     }
 }
+
+#[test]
+fn test_without_body() {
+    let resp = http::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html")
+        .body(b"<h1>Hello</h1>".to_vec())
+        .unwrap();
+    let resp = without_body(resp);
+
+    // HEAD keeps every header of the GET response, including its Content-Length
+    assert!(resp.body().is_empty());
+    assert_eq!(resp.headers()["Content-Type"], "text/html");
+    assert_eq!(resp.headers()["Content-Length"], "14");
+    assert!(String::from_utf8(format_response(resp))
+        .unwrap()
+        .ends_with("content-length: 14\r\n\r\n"));
+}
@@ -33,7 +33,7 @@ mod test_get_route {
         let config = &mock_server_config();
         let route = get_route(req, config);
 
-        assert!(route.is_err_and(|(code, path)| { code == StatusCode::NOT_FOUND && path == "" }));
+        assert!(route.is_err_and(|(code, path)| { code == StatusCode::NOT_FOUND && path.is_empty() }));
     }
 
     #[test]
@@ -42,7 +42,9 @@ mod test_get_route {
         let config = &mock_server_config();
         let route = get_route(req, config);
 
-        assert!(route
-            .is_err_and(|(code, path)| { code == StatusCode::METHOD_NOT_ALLOWED && path == "" }));
+        // The allowed methods are returned for the `Allow` header, HEAD being implied by GET
+        assert!(route.is_err_and(|(code, allow)| {
+            code == StatusCode::METHOD_NOT_ALLOWED && allow == "GET, HEAD"
+        }));
     }
 }
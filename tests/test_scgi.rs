@@ -0,0 +1,161 @@
+mod mock;
+
+use http::Method;
+use localhost::server::route::Route;
+use localhost::server::{start, Address, Gateway};
+use mock::*;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+const HOST: &str = "http://127.0.0.1:8092";
+
+static SERVER: Once = Once::new();
+
+fn read_exact(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+    let mut buffer = vec![0; len];
+    stream.read_exact(&mut buffer).unwrap();
+    buffer
+}
+
+fn read_until(stream: &mut TcpStream, delimiter: u8) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut byte = [0];
+    while stream.read(&mut byte).unwrap() == 1 && byte[0] != delimiter {
+        buffer.push(byte[0]);
+    }
+    buffer
+}
+
+// Stand-in SCGI application: answers with CGI header fields
+fn scgi_application(mut stream: TcpStream) {
+    let len: usize = String::from_utf8(read_until(&mut stream, b':'))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let headers = read_exact(&mut stream, len + 1);
+    let fields: Vec<_> = headers[..len].split(|&b| b == 0).collect();
+    let env: HashMap<_, _> = fields
+        .chunks_exact(2)
+        .map(|pair| {
+            (
+                String::from_utf8_lossy(pair[0]),
+                String::from_utf8_lossy(pair[1]),
+            )
+        })
+        .collect();
+    // CONTENT_LENGTH comes first, even without a body
+    assert_eq!(fields[0], b"CONTENT_LENGTH");
+    let body = read_exact(&mut stream, env["CONTENT_LENGTH"].parse().unwrap());
+
+    let reply = format!(
+        "Status: 200 OK\r\nContent-Type: text/plain\r\n\r\nscgi {} {}",
+        env["SCRIPT_NAME"],
+        String::from_utf8_lossy(&body)
+    );
+    stream.write_all(reply.as_bytes()).unwrap();
+}
+
+// Stand-in uwsgi application: answers with an HTTP status line
+fn uwsgi_application(mut stream: TcpStream) {
+    let header = read_exact(&mut stream, 4);
+    let size = u16::from_le_bytes([header[1], header[2]]) as usize;
+    let vars = read_exact(&mut stream, size);
+
+    let mut env = HashMap::new();
+    let mut at = 0;
+    while at < vars.len() {
+        let mut field = || {
+            let len = u16::from_le_bytes([vars[at], vars[at + 1]]) as usize;
+            at += 2 + len;
+            String::from_utf8_lossy(&vars[at - len..at]).to_string()
+        };
+        let (name, value) = (field(), field());
+        env.insert(name, value);
+    }
+    let length = env
+        .get("CONTENT_LENGTH")
+        .map_or(0, |len| len.parse().unwrap());
+    let body = read_exact(&mut stream, length);
+
+    let reply = format!(
+        "HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\n\r\nuwsgi {} {}",
+        env["REQUEST_METHOD"],
+        String::from_utf8_lossy(&body)
+    );
+    stream.write_all(reply.as_bytes()).unwrap();
+}
+
+fn serve(application: fn(TcpStream)) -> &'static str {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = Box::leak(listener.local_addr().unwrap().to_string().into());
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || application(stream));
+        }
+    });
+    address
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![8092];
+        for (url_path, gateway) in [
+            (
+                "/scgi",
+                Gateway::Scgi(Address::Tcp(serve(scgi_application))),
+            ),
+            (
+                "/uwsgi",
+                Gateway::Uwsgi(Address::Tcp(serve(uwsgi_application))),
+            ),
+        ] {
+            let mut settings = config.routes[0].settings.clone().unwrap();
+            settings.cgi_def = None;
+            settings.gateway = Some(gateway);
+            config.routes.push(Route {
+                url_path,
+                methods: vec![Method::GET, Method::POST],
+                handler: None,
+                settings: Some(settings),
+            });
+        }
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+#[test]
+fn scgi_request() {
+    setup();
+    let resp = reqwest::blocking::get(format!("{HOST}/scgi/app")).unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().unwrap(), "scgi /scgi/app ");
+
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .post(format!("{HOST}/scgi/app"))
+        .body("a=b")
+        .send()
+        .unwrap();
+    assert_eq!(resp.text().unwrap(), "scgi /scgi/app a=b");
+}
+
+#[test]
+fn uwsgi_request() {
+    setup();
+    let client = reqwest::blocking::Client::new();
+    let resp = client
+        .post(format!("{HOST}/uwsgi/app"))
+        .body("a=b")
+        .send()
+        .unwrap();
+    // The status line of the application is turned into a CGI `Status` field
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers()["content-type"], "text/plain");
+    assert_eq!(resp.text().unwrap(), "uwsgi POST a=b");
+}
@@ -0,0 +1,107 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{start, ServerConfig};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PORT: u16 = 8108;
+
+// Answers with the method, the path and the body of the request
+fn echo(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let body = format!(
+        "{} {} {}",
+        request.method(),
+        request.uri(),
+        String::from_utf8_lossy(request.body())
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-length", body.len())
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn connect() -> TcpStream {
+    let stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+fn read_all(stream: &mut TcpStream) -> String {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+// The signal reaches the whole test process: this file holds a single test
+#[test]
+fn sigterm_lets_requests_finish_then_stops() {
+    let mut config = mock_server_config();
+    config.ports = vec![PORT];
+    config.shutdown_grace_period = Duration::from_secs(1);
+    config.routes.push(Route {
+        url_path: "/echo",
+        methods: vec![Method::GET, Method::POST],
+        handler: Some(echo),
+        settings: None,
+    });
+    let server = thread::spawn(move || start(vec![config]));
+    thread::sleep(Duration::from_millis(500));
+
+    // A persistent connection waiting for its next request
+    let mut idle = connect();
+    idle.write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = [0; 1024];
+    assert!(idle.read(&mut response).unwrap() > 0);
+
+    // A request whose body has not been received entirely
+    let mut upload = connect();
+    upload
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\nConnection: close\r\n\r\n01234")
+        .unwrap();
+
+    // A CGI script that outlasts the grace period
+    let mut script = connect();
+    script
+        .write_all(b"GET /cgi/slow.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let stopping = Instant::now();
+    unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+    thread::sleep(Duration::from_millis(200));
+
+    // New connections are refused and idle ones are closed
+    assert!(TcpStream::connect(("127.0.0.1", PORT)).is_err());
+    assert_eq!(read_all(&mut idle), "");
+
+    // The request in progress completes
+    upload.write_all(b"56789").unwrap();
+    let response = read_all(&mut upload);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("POST /echo 0123456789"), "{response}");
+
+    // The script is stopped at the end of the grace period, then the server stops
+    let response = read_all(&mut script);
+    assert!(response.starts_with("HTTP/1.1 504 "), "{response}");
+    server.join().unwrap();
+    let elapsed = stopping.elapsed();
+    assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(3), "{elapsed:?}");
+
+    let running = Command::new("pgrep")
+        .args(["-f", "^/bin/sh /.*/cgi/slow\\.cgi$"])
+        .output()
+        .unwrap();
+    assert!(!running.status.success());
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Ports of the default configuration of the binary
+const PORTS: [u16; 2] = [8080, 8081];
+
+// Both tests use the same ports
+static PORTS_LOCK: Mutex<()> = Mutex::new(());
+
+// Running server: the process started by the test and the lines it prints
+struct Server {
+    child: Child,
+    lines: Receiver<String>,
+    upgraded: Option<i32>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(pid) = self.upgraded {
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Starts the binary the way systemd does: the sockets from descriptor 3, LISTEN_FDS and
+// LISTEN_PID set to the pid of the new process
+fn spawn(listeners: &[TcpListener]) -> Server {
+    let fds: Vec<RawFd> = listeners.iter().map(|l| l.as_raw_fd()).collect();
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "LISTEN_PID=$$ exec \"$0\""])
+        .arg(env!("CARGO_BIN_EXE_localhost"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("LISTEN_FDS", fds.len().to_string())
+        .stdout(Stdio::piped());
+    unsafe {
+        command.pre_exec(move || {
+            for (target, fd) in (3..).zip(&fds) {
+                // dup2 keeps FD_CLOEXEC when the socket already has the target number
+                if libc::dup2(*fd, target) == -1 || libc::fcntl(target, libc::F_SETFD, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().unwrap();
+
+    let (sender, lines) = mpsc::channel();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    thread::spawn(move || {
+        for line in stdout.lines().map_while(Result::ok) {
+            let _ = sender.send(line);
+        }
+    });
+    Server {
+        child,
+        lines,
+        upgraded: None,
+    }
+}
+
+fn bind() -> Vec<TcpListener> {
+    PORTS
+        .iter()
+        .map(|port| TcpListener::bind(("127.0.0.1", *port)).unwrap())
+        .collect()
+}
+
+// Sends a request on the connection and returns the status line of the response
+fn request(stream: &mut TcpStream, close: bool) -> String {
+    let connection = if close { "close" } else { "keep-alive" };
+    let request =
+        format!("GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: {connection}\r\n\r\n");
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = [0; 4096];
+    let received = stream.read(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response[..received]).to_string();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+fn connect(port: u16) -> TcpStream {
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+// Waits for a line printed by the server that starts with the prefix
+fn wait_for_line(server: &Server, prefix: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        match server.lines.recv_timeout(timeout) {
+            Ok(line) if line.starts_with(prefix) => return line,
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    panic!("the server did not print {prefix:?}");
+}
+
+// Waits for the process started by the server for the upgrade, found by the variable naming
+// its parent in its initial environment: it is still there once the parent has exited
+fn wait_for_upgrade(server: &Server) -> i32 {
+    let variable = format!("LOCALHOST_UPGRADE_PID={}", server.child.id());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        for entry in fs::read_dir("/proc").unwrap().map_while(Result::ok) {
+            let Ok(environ) = fs::read(entry.path().join("environ")) else {
+                continue;
+            };
+            if environ
+                .split(|&byte| byte == 0)
+                .any(|v| v == variable.as_bytes())
+            {
+                return entry.file_name().to_str().unwrap().parse().unwrap();
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("the server did not start a new process");
+}
+
+#[test]
+fn serves_inherited_sockets() {
+    let _lock = PORTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // The test keeps its own copies: binding the ports again would fail
+    let listeners = bind();
+    let server = spawn(&listeners);
+    for port in PORTS {
+        wait_for_line(&server, &format!("Server listening on 127.0.0.1:{port}"));
+    }
+
+    for port in PORTS {
+        let status = request(&mut connect(port), true);
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+}
+
+#[test]
+fn upgrade_hands_the_sockets_over() {
+    let _lock = PORTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let listeners = bind();
+    let mut server = spawn(&listeners);
+    drop(listeners);
+    wait_for_line(&server, "Server listening on 127.0.0.1:8081");
+
+    // A persistent connection to the old process
+    let mut idle = connect(PORTS[0]);
+    assert_eq!(request(&mut idle, false), "HTTP/1.1 404 Not Found");
+
+    unsafe { libc::kill(server.child.id() as i32, libc::SIGUSR2) };
+    let pid = wait_for_upgrade(&server);
+    server.upgraded = Some(pid);
+
+    // The old process stops once the new one accepts connections
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = server.child.try_wait().unwrap() {
+            break status;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "the old process is still running"
+        );
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success(), "{status}");
+
+    // Its idle connection was closed, and the new process answers on the same ports
+    let mut rest = Vec::new();
+    assert_eq!(idle.read_to_end(&mut rest).unwrap(), 0);
+    for port in PORTS {
+        assert_eq!(request(&mut connect(port), true), "HTTP/1.1 404 Not Found");
+    }
+    assert_eq!(unsafe { libc::kill(pid, 0) }, 0);
+}
@@ -0,0 +1,230 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{start, Certificate, ServerConfig, Tls, TlsSession, TlsVersion};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use rustls::crypto::ring;
+use rustls::pki_types::ServerName;
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned, SupportedProtocolVersion,
+};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HTTPS_PORT: u16 = 8096;
+const REDIRECT_PORT: u16 = 8097;
+const STRICT_PORT: u16 = 8098;
+const LARGE_BODY: usize = 16 << 20;
+
+static SERVER: Once = Once::new();
+
+// Describes the TLS session the request was received on
+fn session(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let session = request.extensions().get::<TlsSession>();
+    let body = match session {
+        Some(session) => format!(
+            "{} {} {}",
+            session.protocol,
+            session.cipher,
+            session.server_name.as_deref().unwrap_or("-")
+        ),
+        None => "plain".to_string(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Answers with a body larger than what the socket and rustls can buffer
+fn large(_: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-length", LARGE_BODY)
+        .body(vec![b'a'; LARGE_BODY])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn certificates() -> Vec<Certificate<'static>> {
+    vec![
+        Certificate {
+            server_names: vec!["localhost"],
+            chain_path: "tests/certs/localhost.pem",
+            key_path: "tests/certs/localhost.key",
+        },
+        Certificate {
+            server_names: vec!["alt.test", "*.alt.test"],
+            chain_path: "tests/certs/alt.pem",
+            key_path: "tests/certs/alt.key",
+        },
+    ]
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![];
+        config.tls = vec![
+            Tls {
+                port: HTTPS_PORT,
+                certificates: certificates(),
+                min_version: TlsVersion::Tls12,
+                cipher_suites: None,
+                redirect_from: Some(REDIRECT_PORT),
+                client_ca_path: None,
+            },
+            Tls {
+                port: STRICT_PORT,
+                certificates: certificates(),
+                min_version: TlsVersion::Tls13,
+                cipher_suites: Some(vec!["TLS13_CHACHA20_POLY1305_SHA256"]),
+                redirect_from: None,
+                client_ca_path: None,
+            },
+        ];
+        config.routes.push(Route {
+            url_path: "/session",
+            methods: vec![Method::GET],
+            handler: Some(session),
+            settings: None,
+        });
+        config.routes.push(Route {
+            url_path: "/large",
+            methods: vec![Method::GET],
+            handler: Some(large),
+            settings: None,
+        });
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+fn client_config(versions: &[&'static SupportedProtocolVersion]) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    let mut pem = BufReader::new(File::open("tests/certs/ca.pem").unwrap());
+    for certificate in rustls_pemfile::certs(&mut pem) {
+        roots.add(certificate.unwrap()).unwrap();
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+// Sends a GET request over TLS and returns the response with the certificate the server chose
+fn https_get(
+    port: u16,
+    server_name: &str,
+    versions: &[&'static SupportedProtocolVersion],
+) -> std::io::Result<(String, Vec<u8>)> {
+    let name = ServerName::try_from(server_name.to_string()).unwrap();
+    let connection = ClientConnection::new(client_config(versions), name).unwrap();
+    let tcp = TcpStream::connect(("127.0.0.1", port))?;
+    tcp.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut tls = StreamOwned::new(connection, tcp);
+    let request =
+        format!("GET /session HTTP/1.1\r\nHost: {server_name}:{port}\r\nConnection: close\r\n\r\n");
+    tls.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    tls.read_to_string(&mut response)?;
+
+    let certificate = tls.conn.peer_certificates().unwrap()[0].to_vec();
+    Ok((response, certificate))
+}
+
+fn certificate_der(path: &str) -> Vec<u8> {
+    let mut pem = BufReader::new(File::open(path).unwrap());
+    let certificate = rustls_pemfile::certs(&mut pem).next().unwrap().unwrap();
+    certificate.to_vec()
+}
+
+#[test]
+fn request_over_tls() {
+    setup();
+    let versions = rustls::DEFAULT_VERSIONS;
+    let (response, certificate) = https_get(HTTPS_PORT, "localhost", versions).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("\r\n\r\nTLSv1.3 TLS13_AES_256_GCM_SHA384 localhost"));
+    assert_eq!(certificate, certificate_der("tests/certs/localhost.pem"));
+
+    let versions = &[&rustls::version::TLS12];
+    let (response, _) = https_get(HTTPS_PORT, "localhost", versions).unwrap();
+    assert!(response.contains("\r\n\r\nTLSv1.2 "), "{response}");
+}
+
+#[test]
+fn certificate_is_chosen_by_server_name() {
+    setup();
+    let versions = rustls::DEFAULT_VERSIONS;
+    for name in ["alt.test", "www.alt.test"] {
+        let (response, certificate) = https_get(HTTPS_PORT, name, versions).unwrap();
+        assert!(response.ends_with(&format!(" {name}")), "{response}");
+        assert_eq!(certificate, certificate_der("tests/certs/alt.pem"));
+    }
+}
+
+#[test]
+fn minimum_version_and_cipher_suites() {
+    setup();
+    let (response, _) = https_get(STRICT_PORT, "localhost", rustls::DEFAULT_VERSIONS).unwrap();
+    assert!(response.ends_with("TLSv1.3 TLS13_CHACHA20_POLY1305_SHA256 localhost"));
+
+    let versions = &[&rustls::version::TLS12];
+    assert!(https_get(STRICT_PORT, "localhost", versions).is_err());
+}
+
+#[test]
+fn plain_requests_are_redirected() {
+    setup();
+    let mut stream = TcpStream::connect(("127.0.0.1", REDIRECT_PORT)).unwrap();
+    let request = "GET /session?full=1 HTTP/1.1\r\nHost: localhost:8097\r\n\r\n";
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 301"), "{response}");
+    assert!(response.contains("location: https://localhost:8096/session?full=1\r\n"));
+
+    // Plain HTTP is not answered on the HTTPS port
+    let mut stream = TcpStream::connect(("127.0.0.1", HTTPS_PORT)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    assert!(!response.starts_with(b"HTTP/"));
+}
+
+#[test]
+fn a_client_that_does_not_read_does_not_hold_up_the_others() {
+    setup();
+    let name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(client_config(rustls::DEFAULT_VERSIONS), name).unwrap();
+    let tcp = TcpStream::connect(("127.0.0.1", HTTPS_PORT)).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut lagging = StreamOwned::new(connection, tcp);
+    let request = "GET /large HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    lagging.write_all(request.as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let started = Instant::now();
+    let (response, _) = https_get(HTTPS_PORT, "localhost", rustls::DEFAULT_VERSIONS).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let mut response = Vec::new();
+    lagging.read_to_end(&mut response).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+    assert!(response.ends_with(&[b'a'; 1024]));
+    assert!(response.len() > LARGE_BODY);
+}
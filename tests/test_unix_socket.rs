@@ -0,0 +1,149 @@
+mod mock;
+
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::route::Route;
+use localhost::server::{start, Addresses, ServerConfig, UnixSocket};
+use localhost::type_aliases::Bytes;
+use mock::*;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+const PORT: u16 = 8105;
+const SOCKET_PATH: &str = "/tmp/localhost-test-unix.sock";
+const PROXY_SOCKET_PATH: &str = "/tmp/localhost-test-unix-proxy.sock";
+const ABSTRACT_NAME: &str = "localhost-test-unix";
+
+static SERVER: Once = Once::new();
+
+// Answers with the addresses the request was received with
+fn addresses(request: &Request<Bytes>, _: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let body = match request.extensions().get::<Addresses>() {
+        Some(Addresses { remote, local }) => format!("remote={remote} local={local}"),
+        None => "none".to_string(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-length", body.len())
+        .body(body.into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![PORT];
+        config.unix_sockets = vec![
+            UnixSocket {
+                path: SOCKET_PATH,
+                mode: Some(0o660),
+                ..Default::default()
+            },
+            UnixSocket {
+                path: concat!("@", "localhost-test-unix"),
+                ..Default::default()
+            },
+            UnixSocket {
+                path: PROXY_SOCKET_PATH,
+                proxy_protocol: true,
+                ..Default::default()
+            },
+        ];
+        config.routes.push(Route {
+            url_path: "/addresses",
+            methods: vec![Method::GET],
+            handler: Some(addresses),
+            settings: None,
+        });
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+// Sends the bytes and returns everything the server answers
+fn exchange<S: Read + Write>(mut stream: S, bytes: &[u8]) -> String {
+    stream.write_all(bytes).unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    String::from_utf8(response).unwrap()
+}
+
+fn connect(path: &str) -> UnixStream {
+    let stream = UnixStream::connect(path).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+const REQUEST: &[u8] = b"GET /addresses HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+#[test]
+fn filesystem_socket_serves_the_routes() {
+    setup();
+    let metadata = std::fs::metadata(SOCKET_PATH).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+
+    // Les sockets Unix n'ont pas d'adresse IP à transmettre
+    let response = exchange(connect(SOCKET_PATH), REQUEST);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nnone"), "{response}");
+
+    // Les routes sont les mêmes que sur le port TCP
+    let request = b"GET /cgi/echo.py HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let response = exchange(connect(SOCKET_PATH), request);
+    assert!(response.contains("REQUEST_METHOD=GET\n"), "{response}");
+    let tcp = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    let response = exchange(tcp, REQUEST);
+    assert!(response.contains("remote=127.0.0.1:"), "{response}");
+}
+
+#[test]
+fn abstract_socket_serves_the_routes() {
+    setup();
+    let address = SocketAddr::from_abstract_name(ABSTRACT_NAME).unwrap();
+    let stream = UnixStream::connect_addr(&address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let response = exchange(stream, REQUEST);
+    assert!(response.ends_with("\r\n\r\nnone"), "{response}");
+}
+
+#[test]
+fn keep_alive_over_unix_socket() {
+    setup();
+    let mut stream = connect(SOCKET_PATH);
+    for _ in 0..2 {
+        stream
+            .write_all(b"GET /addresses HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = [0; 1024];
+        let received = stream.read(&mut response).unwrap();
+        let response = String::from_utf8_lossy(&response[..received]);
+        assert!(response.ends_with("\r\n\r\nnone"), "{response}");
+    }
+}
+
+#[test]
+fn proxy_header_over_unix_socket() {
+    setup();
+    let mut bytes = b"PROXY TCP4 203.0.113.7 198.51.100.1 51000 443\r\n".to_vec();
+    bytes.extend(REQUEST);
+    let response = exchange(connect(PROXY_SOCKET_PATH), &bytes);
+    assert!(
+        response.ends_with("remote=203.0.113.7:51000 local=198.51.100.1:443"),
+        "{response}"
+    );
+
+    // Sans en-tête PROXY, la connexion est fermée
+    let response = exchange(connect(PROXY_SOCKET_PATH), REQUEST);
+    assert!(response.is_empty(), "{response}");
+}
@@ -0,0 +1,176 @@
+mod mock;
+
+use http::Method;
+use localhost::server::route::Route;
+use localhost::server::{
+    start, Address, Balance, Gateway, Message, Proxy, Upstream, WebSocket, WebSocketEvent, BINARY,
+    CLOSE, CONTINUATION, PING, PONG, TEXT,
+};
+use mock::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+const ADDRESS: &str = "127.0.0.1:8094";
+
+static SERVER: Once = Once::new();
+static UPSTREAMS: [Upstream; 1] = [Upstream::new(Address::Tcp(ADDRESS))];
+
+// Greets the client, echoes its messages and closes the connection on "bye"
+fn echo(socket: &mut WebSocket, event: WebSocketEvent) {
+    match event {
+        WebSocketEvent::Open => socket.send(Message::Text("welcome".to_string())),
+        WebSocketEvent::Message(Message::Text(text)) if text == "bye" => socket.close(1000, "bye"),
+        WebSocketEvent::Message(message) => socket.send(message),
+        WebSocketEvent::Pong(_) | WebSocketEvent::Close(..) => {}
+    }
+}
+
+fn setup() {
+    SERVER.call_once(|| {
+        let mut config = mock_server_config();
+        config.ports = vec![8094];
+
+        let mut settings = config.routes[0].settings.clone().unwrap();
+        settings.cgi_def = None;
+        settings.websocket = Some(echo);
+        config.routes.push(Route {
+            url_path: "/ws",
+            methods: vec![Method::GET],
+            handler: None,
+            settings: Some(settings.clone()),
+        });
+
+        // The proxy route tunnels to the WebSocket route of the same server
+        settings.websocket = None;
+        settings.gateway = Some(Gateway::Proxy(Proxy {
+            upstreams: &UPSTREAMS,
+            balance: Balance::RoundRobin,
+            ejection: None,
+            health_check: None,
+            path: Some("/ws"),
+            timeout: None,
+        }));
+        config.routes.push(Route {
+            url_path: "/tunnel",
+            methods: vec![Method::GET],
+            handler: None,
+            settings: Some(settings),
+        });
+
+        thread::spawn(move || start(vec![config]));
+        thread::sleep(Duration::from_millis(500));
+    });
+}
+
+// Sends the opening handshake and returns the response head
+fn handshake(path: &str, version: &str) -> (BufReader<TcpStream>, String) {
+    let mut stream = TcpStream::connect(ADDRESS).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let head = format!(
+        "GET {path} HTTP/1.1\r\nHost: {ADDRESS}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: {version}\r\n\r\n"
+    );
+    stream.write_all(head.as_bytes()).unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        if reader.read_line(&mut head).unwrap() == 0 {
+            break;
+        }
+    }
+    (reader, head)
+}
+
+fn connect(path: &str) -> BufReader<TcpStream> {
+    let (mut reader, head) = handshake(path, "13");
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+    assert!(head.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert_eq!(read_frame(&mut reader), (true, TEXT, b"welcome".to_vec()));
+    reader
+}
+
+fn send_frame(reader: &mut BufReader<TcpStream>, fin: bool, opcode: u8, payload: &[u8]) {
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![(fin as u8) << 7 | opcode, 0x80 | payload.len() as u8];
+    frame.extend(mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    reader.get_mut().write_all(&frame).unwrap();
+}
+
+fn read_frame(reader: &mut BufReader<TcpStream>) -> (bool, u8, Vec<u8>) {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header).unwrap();
+    assert_eq!(header[1] & 0x80, 0, "server frames are not masked");
+    let mut payload = vec![0; (header[1] & 0x7F) as usize];
+    reader.read_exact(&mut payload).unwrap();
+    (header[0] & 0x80 != 0, header[0] & 0x0F, payload)
+}
+
+fn conversation(path: &str) {
+    let mut reader = connect(path);
+
+    send_frame(&mut reader, true, TEXT, b"hello");
+    assert_eq!(read_frame(&mut reader), (true, TEXT, b"hello".to_vec()));
+    send_frame(&mut reader, true, PING, b"ping");
+    assert_eq!(read_frame(&mut reader), (true, PONG, b"ping".to_vec()));
+
+    // A fragmented message, interleaved with a control frame
+    send_frame(&mut reader, false, BINARY, &[1, 2]);
+    send_frame(&mut reader, true, PING, b"");
+    send_frame(&mut reader, true, CONTINUATION, &[3]);
+    assert_eq!(read_frame(&mut reader), (true, PONG, vec![]));
+    assert_eq!(read_frame(&mut reader), (true, BINARY, vec![1, 2, 3]));
+
+    // The handler starts the closing handshake
+    send_frame(&mut reader, true, TEXT, b"bye");
+    assert_eq!(
+        read_frame(&mut reader),
+        (true, CLOSE, b"\x03\xe8bye".to_vec())
+    );
+    send_frame(&mut reader, true, CLOSE, b"\x03\xe8");
+    assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+}
+
+#[test]
+fn echo_conversation() {
+    setup();
+    conversation("/ws/chat");
+}
+
+#[test]
+fn conversation_through_a_proxy_route() {
+    setup();
+    conversation("/tunnel/chat");
+}
+
+#[test]
+fn protocol_violations_close_the_connection() {
+    setup();
+    // Client frames must be masked
+    let mut reader = connect("/ws/chat");
+    reader
+        .get_mut()
+        .write_all(&[0x81, 0x02, b'h', b'i'])
+        .unwrap();
+    assert_eq!(read_frame(&mut reader), (true, CLOSE, b"\x03\xea".to_vec()));
+    assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+
+    // Text messages must be valid UTF-8
+    let mut reader = connect("/ws/chat");
+    send_frame(&mut reader, true, TEXT, &[0xC3, 0x28]);
+    assert_eq!(read_frame(&mut reader), (true, CLOSE, b"\x03\xef".to_vec()));
+}
+
+#[test]
+fn unsupported_version() {
+    setup();
+    let (_, head) = handshake("/ws/chat", "8");
+    assert!(head.starts_with("HTTP/1.1 426"), "{head}");
+    assert!(head.contains("sec-websocket-version: 13\r\n"));
+}